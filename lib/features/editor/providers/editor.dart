@@ -1,6 +1,7 @@
 import 'dart:math';
 
 import 'package:rei/bridge/rust/api/buffer.dart';
+import 'package:rei/bridge/rust/api/clipboard.dart';
 import 'package:rei/bridge/rust/api/cursor.dart';
 import 'package:rei/bridge/rust/api/selection.dart';
 import 'package:rei/features/editor/models/state.dart';
@@ -23,6 +24,12 @@ final activeEditorProvider = Provider<EditorState>((ref) {
   return ref.watch(editorProvider(activeTab.path));
 });
 
+// Recent copies, shared by every editor so pasting keeps line-wise and
+// per-cursor copies intact across tabs.
+final clipboardHistoryProvider = Provider<ClipboardHistory>((ref) {
+  return ClipboardHistory(capacity: 50);
+});
+
 @Riverpod(keepAlive: true)
 class Editor extends _$Editor {
   @override
@@ -323,14 +330,44 @@ class Editor extends _$Editor {
     );
   }
 
-  String getSelectedText() {
-    final normalized = state.selection.normalized();
+  // The selection to copy from or paste over. An empty one stands for the
+  // cursor, which copies its whole line.
+  Selection _clipboardSelection() {
+    if (state.selection.isEmpty()) {
+      return Selection(start: state.cursor, end: state.cursor);
+    }
+    return state.selection;
+  }
 
-    return getTextInRange(
-      normalized.start.row,
-      normalized.start.column,
-      normalized.end.row,
-      normalized.end.column,
+  ClipboardContents copy() {
+    return state.buffer.copy(selections: [_clipboardSelection()]);
+  }
+
+  ClipboardContents cut() {
+    final (contents, cursors) = state.buffer.cut(
+      selections: [_clipboardSelection()],
+    );
+
+    state = state.copyWith(
+      buffer: state.buffer,
+      cursor: cursors.first,
+      selection: Selection.default_(),
+    );
+    _syncToTab();
+    return contents;
+  }
+
+  void paste(ClipboardContents contents) {
+    final cursors = state.buffer.paste(
+      selections: [_clipboardSelection()],
+      contents: contents,
+    );
+
+    state = state.copyWith(
+      buffer: state.buffer,
+      cursor: cursors.first,
+      selection: Selection.default_(),
     );
+    _syncToTab();
   }
 }
@@ -6,6 +6,7 @@ import 'package:flutter/material.dart' hide Tab;
 import 'package:flutter/services.dart';
 import 'package:flutter_hooks/flutter_hooks.dart';
 import 'package:hooks_riverpod/hooks_riverpod.dart';
+import 'package:rei/bridge/rust/api/clipboard.dart';
 import 'package:rei/bridge/rust/api/cursor.dart';
 import 'package:rei/features/editor/models/char_offset.dart';
 import 'package:rei/features/editor/models/editor_padding.dart';
@@ -169,10 +170,21 @@ class EditorWidget extends HookConsumerWidget {
     isDragging.value = false;
   }
 
-  void _handlePaste(EditorState state, Editor notifier) async {
+  void _handleCopy(ClipboardContents contents, WidgetRef ref) {
+    final text = contents.text();
+    if (text.isEmpty) return;
+
+    ref.read(clipboardHistoryProvider).push(contents: contents);
+    Clipboard.setData(ClipboardData(text: text));
+  }
+
+  void _handlePaste(Editor notifier, WidgetRef ref) async {
     final text = (await Clipboard.getData(Clipboard.kTextPlain))?.text;
     if (text != null && text.isNotEmpty) {
-      notifier.insert(text);
+      // Reuse the matching history entry to keep how it was copied.
+      notifier.paste(
+        ref.read(clipboardHistoryProvider).resolve(systemText: text),
+      );
     }
   }
 
@@ -207,28 +219,21 @@ class EditorWidget extends HookConsumerWidget {
       // Cut
       case LogicalKeyboardKey.keyX:
         if (isSuperPressed) {
-          final text = notifier.getSelectedText();
-          if (text.isNotEmpty) {
-            Clipboard.setData(ClipboardData(text: text));
-          }
-          notifier.deleteSelection();
+          _handleCopy(notifier.cut(), ref);
           handled = true;
         }
 
       // Copy
       case LogicalKeyboardKey.keyC:
         if (isSuperPressed) {
-          final text = notifier.getSelectedText();
-          if (text.isNotEmpty) {
-            Clipboard.setData(ClipboardData(text: text));
-          }
+          _handleCopy(notifier.copy(), ref);
           handled = true;
         }
 
       // Paste
       case LogicalKeyboardKey.keyV:
         if (isSuperPressed) {
-          _handlePaste(state, notifier);
+          _handlePaste(notifier, ref);
           handled = true;
         }
 
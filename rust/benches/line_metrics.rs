@@ -0,0 +1,75 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust_lib_rei::api::buffer::Buffer;
+
+const LINE_COUNTS: [usize; 3] = [10_000, 100_000, 1_000_000];
+
+fn buffer_with_lines(line_count: usize) -> Buffer {
+    let text: String = (0..line_count)
+        .map(|row| format!("{}\n", "x".repeat(row % 120)))
+        .collect();
+
+    Buffer::from(text)
+}
+
+// Each iteration undoes its own edit so the buffer stays the same size. The
+// time per edit should stay flat as the number of lines after it grows.
+fn edits_near_top(c: &mut Criterion) {
+    let mut group = c.benchmark_group("edits_near_top");
+
+    for line_count in LINE_COUNTS {
+        let mut buffer = buffer_with_lines(line_count);
+
+        group.bench_with_input(
+            BenchmarkId::new("split_and_join_line", line_count),
+            &line_count,
+            |b, _| {
+                b.iter(|| {
+                    let (row, column) = buffer.insert(1, 0, "\n".to_string());
+                    black_box(buffer.remove_char(row, column));
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("type_and_delete_char", line_count),
+            &line_count,
+            |b, _| {
+                b.iter(|| {
+                    let (row, column) = buffer.insert(1, 0, "a".to_string());
+                    black_box(buffer.remove_char(row, column));
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// Shrinking and restoring the longest line forces the maximum to be found
+// again among every other line.
+fn max_line_length_after_edit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("max_line_length_after_edit");
+
+    for line_count in LINE_COUNTS {
+        let mut buffer = buffer_with_lines(line_count);
+        let longest_row = 119;
+
+        group.bench_with_input(
+            BenchmarkId::from_parameter(line_count),
+            &line_count,
+            |b, _| {
+                b.iter(|| {
+                    buffer.remove_range(longest_row, 0, longest_row, 100);
+                    black_box(buffer.max_line_length());
+                    buffer.insert(longest_row, 0, "x".repeat(100));
+                    black_box(buffer.max_line_length());
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, edits_near_top, max_line_length_after_edit);
+criterion_main!(benches);
@@ -0,0 +1,457 @@
+use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use super::buffer::Buffer;
+
+// Which side of an insertion made exactly at the anchor it stays on. A left
+// anchor stays before the inserted text, a right anchor moves after it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Bias {
+    Left,
+    Right,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Anchor {
+    pub id: u64,
+}
+
+const NIL: u32 = u32::MAX;
+
+#[derive(Clone, Copy)]
+struct Node {
+    left: u32,
+    right: u32,
+    parent: u32,
+    priority: u32,
+    bias: Bias,
+    // The byte offset, once the shifts pending on the node's ancestors are
+    // applied.
+    offset: usize,
+    // A shift not yet applied to the node's descendants.
+    shift: isize,
+}
+
+// Anchors are kept in a treap ordered by byte offset. An edit moves every
+// anchor after it with one pending shift on the subtree holding them, so only
+// the anchors inside the replaced range are visited one by one. Parent links
+// let an anchor be resolved by walking up to the root, adding the shifts still
+// pending above it.
+pub(crate) struct AnchorSet {
+    nodes: Vec<Node>,
+    free: Vec<u32>,
+    root: u32,
+    node_of: HashMap<u64, u32>,
+    next_id: u64,
+}
+
+impl Default for AnchorSet {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: NIL,
+            node_of: HashMap::new(),
+            next_id: 0,
+        }
+    }
+}
+
+impl AnchorSet {
+    pub fn insert(&mut self, offset: usize, bias: Bias) -> Anchor {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let idx = self.alloc(offset, bias);
+        let (before, after) = self.split(self.root, offset, true);
+        let merged = self.merge(before, idx);
+        let root = self.merge(merged, after);
+        self.set_root(root);
+        self.node_of.insert(id, idx);
+
+        Anchor { id }
+    }
+
+    pub fn offset(&self, anchor: Anchor) -> Option<usize> {
+        let &idx = self.node_of.get(&anchor.id)?;
+        let mut offset = self.nodes[idx as usize].offset as isize;
+        let mut parent = self.nodes[idx as usize].parent;
+
+        while parent != NIL {
+            offset += self.nodes[parent as usize].shift;
+            parent = self.nodes[parent as usize].parent;
+        }
+
+        Some(offset as usize)
+    }
+
+    pub fn remove(&mut self, anchor: Anchor) -> bool {
+        let Some(idx) = self.node_of.remove(&anchor.id) else {
+            return false;
+        };
+
+        // The shifts pending above the node apply to its children alike, so
+        // they can take its place as they are.
+        self.push(idx);
+        let Node {
+            left,
+            right,
+            parent,
+            ..
+        } = self.nodes[idx as usize];
+        let merged = self.merge(left, right);
+
+        if parent == NIL {
+            self.set_root(merged);
+        } else {
+            let parent_node = &mut self.nodes[parent as usize];
+            if parent_node.left == idx {
+                parent_node.left = merged;
+            } else {
+                parent_node.right = merged;
+            }
+            self.set_parent(merged, parent);
+        }
+
+        self.free.push(idx);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.node_of.len()
+    }
+
+    // Moves anchors to account for `start..old_end` being replaced by text
+    // ending at `new_end`. Anchors inside the replaced range collapse to the
+    // side of the new text given by their bias.
+    pub fn apply_edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        let (before, rest) = self.split(self.root, start, false);
+        // An anchor at the end of a deletion stays after it, while one at the
+        // point of a pure insertion goes where its bias says.
+        let (inside, after) = self.split(rest, old_end, old_end == start);
+
+        let mut collapsed = Vec::new();
+        self.collect(inside, &mut collapsed);
+        collapsed.sort_by_key(|&idx| self.nodes[idx as usize].bias == Bias::Right);
+        for &idx in &collapsed {
+            let node = &mut self.nodes[idx as usize];
+            node.offset = match node.bias {
+                Bias::Left => start,
+                Bias::Right => new_end,
+            };
+        }
+        let inside = self.build(&collapsed);
+
+        self.shift(after, new_end as isize - old_end as isize);
+        let merged = self.merge(before, inside);
+        let root = self.merge(merged, after);
+        self.set_root(root);
+    }
+
+    fn alloc(&mut self, offset: usize, bias: Bias) -> u32 {
+        let node = Node {
+            left: NIL,
+            right: NIL,
+            parent: NIL,
+            priority: rand::random(),
+            bias,
+            offset,
+            shift: 0,
+        };
+
+        match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx as usize] = node;
+                idx
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as u32
+            }
+        }
+    }
+
+    fn set_root(&mut self, idx: u32) {
+        self.root = idx;
+        self.set_parent(idx, NIL);
+    }
+
+    fn set_parent(&mut self, idx: u32, parent: u32) {
+        if idx != NIL {
+            self.nodes[idx as usize].parent = parent;
+        }
+    }
+
+    fn shift(&mut self, idx: u32, delta: isize) {
+        if idx != NIL && delta != 0 {
+            let node = &mut self.nodes[idx as usize];
+            node.offset = (node.offset as isize + delta) as usize;
+            node.shift += delta;
+        }
+    }
+
+    fn push(&mut self, idx: u32) {
+        let Node {
+            left, right, shift, ..
+        } = self.nodes[idx as usize];
+        if shift != 0 {
+            self.shift(left, shift);
+            self.shift(right, shift);
+            self.nodes[idx as usize].shift = 0;
+        }
+    }
+
+    // The subtree's nodes in offset order, with every pending shift applied.
+    fn collect(&mut self, idx: u32, out: &mut Vec<u32>) {
+        if idx == NIL {
+            return;
+        }
+
+        self.push(idx);
+        let Node { left, right, .. } = self.nodes[idx as usize];
+        self.collect(left, out);
+        out.push(idx);
+        self.collect(right, out);
+    }
+
+    // Relinks nodes already in offset order into a subtree in linear time,
+    // keeping the rightmost path on a stack as in `LineMetrics::build`.
+    fn build(&mut self, order: &[u32]) -> u32 {
+        let mut stack: Vec<u32> = Vec::new();
+
+        for &idx in order {
+            let priority = self.nodes[idx as usize].priority;
+            let mut last = NIL;
+
+            while let Some(&top) = stack.last() {
+                if self.nodes[top as usize].priority >= priority {
+                    break;
+                }
+                stack.pop();
+                last = top;
+            }
+
+            self.nodes[idx as usize].left = last;
+            self.nodes[idx as usize].right = NIL;
+            self.set_parent(last, idx);
+            if let Some(&top) = stack.last() {
+                self.nodes[top as usize].right = idx;
+                self.set_parent(idx, top);
+            }
+            stack.push(idx);
+        }
+
+        stack.first().copied().unwrap_or(NIL)
+    }
+
+    // Splits the subtree into the anchors before `key`, or up to and
+    // including it when `inclusive`, and the rest.
+    fn split(&mut self, idx: u32, key: usize, inclusive: bool) -> (u32, u32) {
+        if idx == NIL {
+            return (NIL, NIL);
+        }
+
+        self.push(idx);
+        let node = self.nodes[idx as usize];
+
+        if node.offset < key || (inclusive && node.offset == key) {
+            let (left, right) = self.split(node.right, key, inclusive);
+            self.nodes[idx as usize].right = left;
+            self.set_parent(left, idx);
+            (idx, right)
+        } else {
+            let (left, right) = self.split(node.left, key, inclusive);
+            self.nodes[idx as usize].left = right;
+            self.set_parent(right, idx);
+            (left, idx)
+        }
+    }
+
+    fn merge(&mut self, left: u32, right: u32) -> u32 {
+        if left == NIL {
+            return right;
+        }
+        if right == NIL {
+            return left;
+        }
+
+        if self.nodes[left as usize].priority > self.nodes[right as usize].priority {
+            self.push(left);
+            let merged = self.merge(self.nodes[left as usize].right, right);
+            self.nodes[left as usize].right = merged;
+            self.set_parent(merged, left);
+            left
+        } else {
+            self.push(right);
+            let merged = self.merge(left, self.nodes[right as usize].left);
+            self.nodes[right as usize].left = merged;
+            self.set_parent(merged, right);
+            right
+        }
+    }
+}
+
+impl Buffer {
+    #[frb(sync, type_64bit_int)]
+    pub fn create_anchor(&mut self, row: usize, column: usize, bias: Bias) -> Anchor {
+        let offset = self
+            .row_column_to_idx(row, column)
+            .min(self.rope().byte_len());
+
+        self.anchors_mut().insert(offset, bias)
+    }
+
+    // The anchor's current position, or `None` once it has been dropped.
+    #[frb(sync, type_64bit_int)]
+    pub fn resolve_anchor(&self, anchor: Anchor) -> Option<(usize, usize)> {
+        let offset = self.anchors().offset(anchor)?;
+        Some(self.idx_to_row_column(offset))
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn resolve_anchors(&self, anchors: Vec<Anchor>) -> Vec<Option<(usize, usize)>> {
+        anchors
+            .into_iter()
+            .map(|anchor| self.resolve_anchor(anchor))
+            .collect()
+    }
+
+    #[frb(sync)]
+    pub fn drop_anchor(&mut self, anchor: Anchor) -> bool {
+        self.anchors_mut().remove(anchor)
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn anchor_count(&self) -> usize {
+        self.anchors().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    fn anchors_at(buffer: &mut Buffer, row: usize, column: usize) -> (Anchor, Anchor) {
+        (
+            buffer.create_anchor(row, column, Bias::Left),
+            buffer.create_anchor(row, column, Bias::Right),
+        )
+    }
+
+    #[test]
+    fn bias_decides_the_side_of_an_insert_at_the_anchor() {
+        let mut buffer = Buffer::from("abcd".to_string());
+        let (left, right) = anchors_at(&mut buffer, 0, 2);
+        let (start_left, start_right) = anchors_at(&mut buffer, 0, 0);
+        let (end_left, end_right) = anchors_at(&mut buffer, 0, 4);
+
+        buffer.insert(0, 2, "XY".to_string());
+        assert_eq!(buffer.resolve_anchor(left), Some((0, 2)));
+        assert_eq!(buffer.resolve_anchor(right), Some((0, 4)));
+
+        buffer.insert(0, 0, "\n".to_string());
+        assert_eq!(buffer.resolve_anchor(start_left), Some((0, 0)));
+        assert_eq!(buffer.resolve_anchor(start_right), Some((1, 0)));
+        assert_eq!(buffer.resolve_anchor(right), Some((1, 4)));
+
+        buffer.insert(1, 6, "!".to_string());
+        assert_eq!(buffer.resolve_anchor(end_left), Some((1, 6)));
+        assert_eq!(buffer.resolve_anchor(end_right), Some((1, 7)));
+    }
+
+    #[test]
+    fn deletes_collapse_anchors_inside_and_shift_those_after() {
+        let mut buffer = Buffer::from("abcdefgh".to_string());
+        let (start_left, start_right) = anchors_at(&mut buffer, 0, 2);
+        let (inside_left, inside_right) = anchors_at(&mut buffer, 0, 4);
+        let (end_left, end_right) = anchors_at(&mut buffer, 0, 6);
+        let after = buffer.create_anchor(0, 7, Bias::Left);
+        let before = buffer.create_anchor(0, 1, Bias::Right);
+
+        buffer.replace_range(0, 2, 0, 6, String::new());
+        assert_eq!(buffer.to_string(), "abgh");
+        for anchor in [start_left, start_right, inside_left, inside_right] {
+            assert_eq!(buffer.resolve_anchor(anchor), Some((0, 2)));
+        }
+        // At the end of the deleted range, both stay after it.
+        assert_eq!(buffer.resolve_anchor(end_left), Some((0, 2)));
+        assert_eq!(buffer.resolve_anchor(end_right), Some((0, 2)));
+        assert_eq!(buffer.resolve_anchor(after), Some((0, 3)));
+        assert_eq!(buffer.resolve_anchor(before), Some((0, 1)));
+
+        // Replacing a range puts collapsed anchors on either side of the text.
+        let (left, right) = anchors_at(&mut buffer, 0, 2);
+        buffer.replace_range(0, 1, 0, 3, "XYZ".to_string());
+        assert_eq!(buffer.resolve_anchor(left), Some((0, 1)));
+        assert_eq!(buffer.resolve_anchor(right), Some((0, 4)));
+    }
+
+    #[test]
+    fn dropped_anchors_no_longer_resolve() {
+        let mut buffer = Buffer::from("abc".to_string());
+        let first = buffer.create_anchor(0, 1, Bias::Left);
+        let second = buffer.create_anchor(0, 2, Bias::Left);
+
+        assert!(buffer.drop_anchor(first));
+        assert!(!buffer.drop_anchor(first));
+        buffer.insert(0, 0, "x".to_string());
+        assert_eq!(buffer.resolve_anchor(first), None);
+        assert_eq!(buffer.resolve_anchor(second), Some((0, 3)));
+        assert_eq!(buffer.anchor_count(), 1);
+    }
+
+    // Compares against shifting every anchor, as a flat list would.
+    #[test]
+    fn matches_a_linear_model_under_random_edits() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut set = AnchorSet::default();
+        let mut model: Vec<(Anchor, usize, Bias)> = Vec::new();
+        let mut len = 1000;
+
+        for _ in 0..3000 {
+            match rng.random_range(0..4) {
+                0 => {
+                    let offset = rng.random_range(0..=len);
+                    let bias = if rng.random() {
+                        Bias::Left
+                    } else {
+                        Bias::Right
+                    };
+                    model.push((set.insert(offset, bias), offset, bias));
+                }
+                1 if !model.is_empty() => {
+                    let (anchor, _, _) = model.swap_remove(rng.random_range(0..model.len()));
+                    assert!(set.remove(anchor));
+                }
+                _ => {
+                    let start = rng.random_range(0..=len);
+                    let old_end = rng.random_range(start..=len.min(start + 20));
+                    let new_end = start + rng.random_range(0..20);
+                    set.apply_edit(start, old_end, new_end);
+                    len = len - (old_end - start) + (new_end - start);
+
+                    for (_, offset, bias) in &mut model {
+                        if *offset < start {
+                            continue;
+                        }
+                        *offset = if *offset > old_end || (*offset == old_end && old_end > start) {
+                            *offset - old_end + new_end
+                        } else if *bias == Bias::Left {
+                            start
+                        } else {
+                            new_end
+                        };
+                    }
+                }
+            }
+        }
+
+        assert_eq!(set.len(), model.len());
+        for (anchor, offset, _) in model {
+            assert_eq!(set.offset(anchor), Some(offset));
+        }
+    }
+}
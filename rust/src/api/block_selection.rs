@@ -0,0 +1,411 @@
+use flutter_rust_bridge::frb;
+
+use super::buffer::Buffer;
+use super::clipboard::ClipboardContents;
+use super::cursor::Cursor;
+use super::selection::Selection;
+use super::text::{byte_column, floor_char_boundary, visual_width};
+
+// A rectangle of rows and visual columns, where a tab reaches the next
+// multiple of the tab width. The anchor is where the selection started and
+// the head is where it is being extended to. Equal columns make a zero-width
+// block, which edits like one cursor per row.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSelection {
+    pub anchor_row: usize,
+    pub anchor_column: usize,
+    pub head_row: usize,
+    pub head_column: usize,
+}
+
+impl BlockSelection {
+    #[frb(sync, type_64bit_int)]
+    pub fn new(
+        anchor_row: usize,
+        anchor_column: usize,
+        head_row: usize,
+        head_column: usize,
+    ) -> Self {
+        Self {
+            anchor_row,
+            anchor_column,
+            head_row,
+            head_column,
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn start_row(&self) -> usize {
+        self.anchor_row.min(self.head_row)
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn end_row(&self) -> usize {
+        self.anchor_row.max(self.head_row)
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn left_column(&self) -> usize {
+        self.anchor_column.min(self.head_column)
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn right_column(&self) -> usize {
+        self.anchor_column.max(self.head_column)
+    }
+
+    #[frb(sync)]
+    pub fn is_empty(&self) -> bool {
+        self.anchor_column == self.head_column
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn contains(&self, row: usize) -> bool {
+        self.start_row() <= row && row <= self.end_row()
+    }
+
+    // The same rows, zero-width at `column`.
+    fn collapsed(&self, column: usize) -> Self {
+        Self::new(self.anchor_row, column, self.head_row, column)
+    }
+}
+
+// Where the block falls on one row, in byte columns. A row too short to reach
+// the block's left edge has `padding`, the spaces needed to get there.
+struct BlockSpan {
+    row: usize,
+    start: usize,
+    end: usize,
+    padding: usize,
+}
+
+impl Buffer {
+    #[frb(sync, type_64bit_int)]
+    pub fn visual_column(&self, row: usize, column: usize, tab_width: usize) -> usize {
+        let line = self.line(row);
+        visual_width(&line[..floor_char_boundary(&line, column)], tab_width)
+    }
+
+    // The block between two cursors, e.g. from an alt-drag.
+    #[frb(sync, type_64bit_int)]
+    pub fn block_selection(
+        &self,
+        anchor: Cursor,
+        head: Cursor,
+        tab_width: usize,
+    ) -> BlockSelection {
+        BlockSelection::new(
+            anchor.row,
+            self.visual_column(anchor.row, anchor.column, tab_width),
+            head.row,
+            self.visual_column(head.row, head.column, tab_width),
+        )
+    }
+
+    // One selection per row of the block, ending on the head's side, for
+    // drawing the block or turning it into multiple cursors. A tab only partly
+    // inside the block is selected whole. Short rows get an empty selection at
+    // their end.
+    #[frb(sync, type_64bit_int)]
+    pub fn block_selections(&self, block: BlockSelection, tab_width: usize) -> Vec<Selection> {
+        let head_is_left = block.head_column < block.anchor_column;
+        self.block_spans(&block, tab_width)
+            .into_iter()
+            .map(|span| {
+                let start = Cursor::new(span.row, span.start, span.start);
+                let end = Cursor::new(span.row, span.end, span.end);
+                if head_is_left {
+                    Selection::new(end, start)
+                } else {
+                    Selection::new(start, end)
+                }
+            })
+            .collect()
+    }
+
+    // One fragment per row, so pasting into as many cursors or another block
+    // of as many rows puts each back on its own row.
+    #[frb(sync, type_64bit_int)]
+    pub fn copy_block(&self, block: BlockSelection, tab_width: usize) -> ClipboardContents {
+        let fragments = self
+            .block_spans(&block, tab_width)
+            .into_iter()
+            .map(|span| self.line(span.row)[span.start..span.end].to_string())
+            .collect();
+
+        ClipboardContents {
+            fragments,
+            line_wise: false,
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn cut_block(
+        &mut self,
+        block: BlockSelection,
+        tab_width: usize,
+    ) -> (ClipboardContents, BlockSelection) {
+        let contents = self.copy_block(block, tab_width);
+        let block = self.delete_block(block, false, tab_width);
+        (contents, block)
+    }
+
+    // Deletes the block's columns on every row. A zero-width block deletes the
+    // character before each cursor, or after it when `forward`; rows too short
+    // to reach the block are left alone.
+    #[frb(sync, type_64bit_int)]
+    pub fn delete_block(
+        &mut self,
+        block: BlockSelection,
+        forward: bool,
+        tab_width: usize,
+    ) -> BlockSelection {
+        let spans: Vec<BlockSpan> = self
+            .block_spans(&block, tab_width)
+            .into_iter()
+            .filter(|span| span.padding == 0)
+            .filter_map(|span| {
+                if !block.is_empty() {
+                    return Some(span);
+                }
+
+                let line = self.line(span.row);
+                let (start, end) = if forward {
+                    let next = line[span.start..].chars().next()?;
+                    (span.start, span.start + next.len_utf8())
+                } else {
+                    let previous = line[..span.start].chars().next_back()?;
+                    (span.start - previous.len_utf8(), span.start)
+                };
+                Some(BlockSpan { start, end, ..span })
+            })
+            .collect();
+
+        let Some(first) = spans.first() else {
+            return block.collapsed(block.left_column());
+        };
+        let (row, column) = (first.row, first.start);
+        let fragments = vec![String::new(); spans.len()];
+        self.replace_spans(&spans, &fragments);
+
+        block.collapsed(self.visual_column(row, column, tab_width))
+    }
+
+    // Types `text` on every row, replacing the block's columns and padding
+    // short rows with spaces, and returns the zero-width block after it.
+    #[frb(sync, type_64bit_int)]
+    pub fn insert_block(
+        &mut self,
+        block: BlockSelection,
+        text: String,
+        tab_width: usize,
+    ) -> BlockSelection {
+        let spans = self.block_spans(&block, tab_width);
+        let fragments = vec![text; spans.len()];
+        let cursors = self.replace_spans(&spans, &fragments);
+        let Some(first) = cursors.first() else {
+            return block;
+        };
+
+        let column = self.visual_column(first.row, first.column, tab_width);
+        block.collapsed(column)
+    }
+
+    // Pastes one line per row starting at the block's top row: the block's
+    // fragments when there is one per row, the same text on every row when
+    // it is a single line, or otherwise the lines of the text as a rectangle,
+    // adding rows at the end of the buffer if it runs past. Returns a cursor
+    // after each pasted line.
+    #[frb(sync, type_64bit_int)]
+    pub fn paste_block(
+        &mut self,
+        block: BlockSelection,
+        contents: ClipboardContents,
+        tab_width: usize,
+    ) -> Vec<Cursor> {
+        let rows = block.end_row() - block.start_row() + 1;
+        let lines: Vec<String> = if contents.fragments.len() > 1 && !contents.line_wise {
+            contents.fragments.clone()
+        } else {
+            let text = contents.text();
+            let text = text.strip_suffix('\n').unwrap_or(&text);
+            text.split('\n').map(str::to_string).collect()
+        };
+        let lines = if lines.len() == 1 {
+            vec![lines[0].clone(); rows]
+        } else {
+            lines
+        };
+
+        let last_row = self.last_row();
+        let end_row = block.start_row() + rows.max(lines.len()) - 1;
+        let mut spans = self.block_spans(
+            &BlockSelection::new(
+                block.start_row(),
+                block.anchor_column,
+                end_row.min(last_row),
+                block.head_column,
+            ),
+            tab_width,
+        );
+        // Rows below the block only receive text, without replacing any.
+        for span in spans.iter_mut().filter(|span| span.row > block.end_row()) {
+            span.end = span.start;
+        }
+
+        let mut fragments: Vec<String> = (0..spans.len())
+            .map(|idx| lines.get(idx).cloned().unwrap_or_default())
+            .collect();
+        if end_row > last_row {
+            // Rows past the end of the buffer are appended, padded out to the
+            // block's left edge.
+            let end = self.line_len(last_row);
+            let left = block.left_column();
+            for line in lines.iter().skip(spans.len()) {
+                spans.push(BlockSpan {
+                    row: last_row,
+                    start: end,
+                    end,
+                    padding: 0,
+                });
+                fragments.push(format!("\n{}{line}", " ".repeat(left)));
+            }
+        }
+
+        self.replace_spans(&spans, &fragments)
+    }
+
+    fn block_spans(&self, block: &BlockSelection, tab_width: usize) -> Vec<BlockSpan> {
+        let (left, right) = (block.left_column(), block.right_column());
+
+        (block.start_row()..=block.end_row().min(self.last_row()))
+            .map(|row| {
+                let line = self.line(row);
+                let width = visual_width(&line, tab_width);
+                if width < left {
+                    return BlockSpan {
+                        row,
+                        start: line.len(),
+                        end: line.len(),
+                        padding: left - width,
+                    };
+                }
+
+                let start = byte_column(&line, left, tab_width, false);
+                let end = if right > left {
+                    byte_column(&line, right, tab_width, true)
+                } else {
+                    start
+                };
+                BlockSpan {
+                    row,
+                    start,
+                    end,
+                    padding: 0,
+                }
+            })
+            .collect()
+    }
+
+    // Replaces each span with its fragment, after any padding the span needs,
+    // and returns the cursor after each.
+    fn replace_spans(&mut self, spans: &[BlockSpan], fragments: &[String]) -> Vec<Cursor> {
+        let ranges = spans
+            .iter()
+            .map(|span| {
+                let line_start = self.byte_of_line(span.row);
+                (line_start + span.start, line_start + span.end)
+            })
+            .collect();
+        let fragments: Vec<String> = spans
+            .iter()
+            .zip(fragments)
+            .map(|(span, fragment)| {
+                // Short rows are only padded when something goes after it.
+                let padding = if fragment.is_empty() { 0 } else { span.padding };
+                format!("{}{fragment}", " ".repeat(padding))
+            })
+            .collect();
+
+        self.apply_byte_edits(ranges, &fragments, |start, _, text| start + text.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(cursors: &[Cursor]) -> Vec<(usize, usize)> {
+        cursors.iter().map(|c| (c.row, c.column)).collect()
+    }
+
+    #[test]
+    fn copies_and_cuts_whole_tabs_and_short_rows() {
+        let mut buffer = Buffer::from("a\tbc\nabcdefg\nab".to_string());
+        let block = BlockSelection::new(0, 2, 2, 5);
+
+        let contents = buffer.copy_block(block, 4);
+        assert_eq!(contents.fragments, ["\tb", "cde", ""]);
+        assert!(!contents.line_wise);
+
+        let (cut, block) = buffer.cut_block(block, 4);
+        assert_eq!(cut.fragments, contents.fragments);
+        assert_eq!(buffer.to_string(), "ac\nabfg\nab");
+        // Collapsed where the tab started on the first row.
+        assert_eq!(block, BlockSelection::new(0, 1, 2, 1));
+    }
+
+    #[test]
+    fn pastes_one_fragment_per_row_padding_short_rows() {
+        let mut buffer = Buffer::from("abcd\nx\nabcd".to_string());
+        let contents = ClipboardContents {
+            fragments: vec!["1".to_string(), "2".to_string(), "3".to_string()],
+            line_wise: false,
+        };
+
+        let cursors = buffer.paste_block(BlockSelection::new(0, 3, 2, 3), contents, 4);
+        assert_eq!(buffer.to_string(), "abc1d\nx  2\nabc3d");
+        assert_eq!(positions(&cursors), [(0, 4), (1, 4), (2, 4)]);
+
+        // A single line goes on every row, replacing the block.
+        let cursors = buffer.paste_block(
+            BlockSelection::new(0, 0, 1, 1),
+            ClipboardContents::plain("-".to_string()),
+            4,
+        );
+        assert_eq!(buffer.to_string(), "-bc1d\n-  2\nabc3d");
+        assert_eq!(positions(&cursors), [(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn pastes_past_the_end_of_the_buffer() {
+        let mut buffer = Buffer::from("ab\ncd".to_string());
+        let cursors = buffer.paste_block(
+            BlockSelection::new(1, 1, 1, 1),
+            ClipboardContents::plain("X\nY\nZ\n".to_string()),
+            4,
+        );
+        assert_eq!(buffer.to_string(), "ab\ncXd\n Y\n Z");
+        assert_eq!(positions(&cursors), [(1, 2), (2, 2), (3, 2)]);
+    }
+
+    #[test]
+    fn zero_width_blocks_edit_like_a_cursor_per_row() {
+        let mut buffer = Buffer::from("\tx\nabcdef\nab".to_string());
+        let block = BlockSelection::new(0, 4, 2, 4);
+
+        let block = buffer.insert_block(block, "|".to_string(), 4);
+        assert_eq!(buffer.to_string(), "\t|x\nabcd|ef\nab  |");
+        assert_eq!(block, BlockSelection::new(0, 5, 2, 5));
+
+        let block = buffer.delete_block(block, false, 4);
+        assert_eq!(buffer.to_string(), "\tx\nabcdef\nab  ");
+        assert_eq!(block, BlockSelection::new(0, 4, 2, 4));
+
+        // Rows too short to reach the block are left alone.
+        let mut buffer = Buffer::from("abcdef\nab".to_string());
+        buffer.delete_block(BlockSelection::new(0, 4, 1, 4), true, 4);
+        assert_eq!(buffer.to_string(), "abcdf\nab");
+    }
+}
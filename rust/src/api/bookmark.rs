@@ -0,0 +1,397 @@
+use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use super::anchor::{Anchor, Bias};
+use super::buffer::Buffer;
+use super::session::write_atomically;
+
+// A bookmarked position. Named bookmarks act as vim-style marks: setting one
+// again moves it rather than adding another.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bookmark {
+    pub name: Option<String>,
+    pub row: usize,
+    pub column: usize,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BookmarkLocation {
+    pub path: String,
+    pub bookmark: Bookmark,
+}
+
+#[derive(Clone)]
+pub(crate) struct BookmarkEntry {
+    name: Option<String>,
+    anchor: Anchor,
+}
+
+impl Buffer {
+    // Removes the bookmarks on `row` if it has any, and otherwise adds an
+    // unnamed one at the start of the row. Returns whether the row is now
+    // bookmarked.
+    #[frb(sync, type_64bit_int)]
+    pub fn toggle_bookmark(&mut self, row: usize) -> bool {
+        let on_row: Vec<Anchor> = self
+            .bookmark_entries()
+            .iter()
+            .filter(|entry| entry.name.is_none())
+            .map(|entry| entry.anchor)
+            .filter(|&anchor| self.resolve_anchor(anchor).map(|(r, _)| r) == Some(row))
+            .collect();
+
+        if !on_row.is_empty() {
+            for anchor in on_row {
+                self.remove_bookmark_anchor(anchor);
+            }
+            return false;
+        }
+
+        let anchor = self.create_anchor(row, 0, Bias::Right);
+        self.bookmark_entries_mut()
+            .push(BookmarkEntry { name: None, anchor });
+        true
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn set_mark(&mut self, name: String, row: usize, column: usize) {
+        self.remove_mark(name.clone());
+
+        let anchor = self.create_anchor(row, column, Bias::Right);
+        self.bookmark_entries_mut().push(BookmarkEntry {
+            name: Some(name),
+            anchor,
+        });
+    }
+
+    #[frb(sync)]
+    pub fn remove_mark(&mut self, name: String) -> bool {
+        let anchor = self
+            .bookmark_entries()
+            .iter()
+            .find(|entry| entry.name.as_deref() == Some(name.as_str()))
+            .map(|entry| entry.anchor);
+
+        match anchor {
+            Some(anchor) => {
+                self.remove_bookmark_anchor(anchor);
+                true
+            }
+            None => false,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn mark(&self, name: String) -> Option<Bookmark> {
+        self.bookmarks()
+            .into_iter()
+            .find(|bookmark| bookmark.name.as_deref() == Some(name.as_str()))
+    }
+
+    #[frb(sync)]
+    pub fn clear_bookmarks(&mut self) {
+        let anchors: Vec<Anchor> = self
+            .bookmark_entries()
+            .iter()
+            .map(|entry| entry.anchor)
+            .collect();
+
+        for anchor in anchors {
+            self.remove_bookmark_anchor(anchor);
+        }
+    }
+
+    // Bookmarks in buffer order.
+    #[frb(sync)]
+    pub fn bookmarks(&self) -> Vec<Bookmark> {
+        let mut bookmarks: Vec<Bookmark> = self
+            .bookmark_entries()
+            .iter()
+            .filter_map(|entry| {
+                let (row, column) = self.resolve_anchor(entry.anchor)?;
+                Some(Bookmark {
+                    name: entry.name.clone(),
+                    row,
+                    column,
+                })
+            })
+            .collect();
+
+        bookmarks.sort_by_key(|bookmark| (bookmark.row, bookmark.column));
+        bookmarks
+    }
+
+    // The first bookmark on a row after `row`, wrapping around to the top.
+    #[frb(sync, type_64bit_int)]
+    pub fn next_bookmark(&self, row: usize) -> Option<Bookmark> {
+        let bookmarks = self.bookmarks();
+        bookmarks
+            .iter()
+            .find(|bookmark| bookmark.row > row)
+            .or(bookmarks.first())
+            .cloned()
+    }
+
+    // The last bookmark on a row before `row`, wrapping around to the bottom.
+    #[frb(sync, type_64bit_int)]
+    pub fn previous_bookmark(&self, row: usize) -> Option<Bookmark> {
+        let bookmarks = self.bookmarks();
+        bookmarks
+            .iter()
+            .rev()
+            .find(|bookmark| bookmark.row < row)
+            .or(bookmarks.last())
+            .cloned()
+    }
+
+    fn remove_bookmark_anchor(&mut self, anchor: Anchor) {
+        self.bookmark_entries_mut()
+            .retain(|entry| entry.anchor != anchor);
+        self.drop_anchor(anchor);
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredBookmark {
+    name: Option<String>,
+    row: usize,
+    column: usize,
+    // The bookmarked line, used to find it again if the file was changed
+    // outside the editor.
+    line: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BookmarkFile {
+    version: u32,
+    files: BTreeMap<String, Vec<StoredBookmark>>,
+}
+
+// Bookmarks for every file, including ones that are not open. Open buffers
+// are recorded into the store as their bookmarks change, so listing reflects
+// each buffer as of its last `record`.
+#[frb(opaque)]
+pub struct BookmarkStore {
+    dir: PathBuf,
+    files: BTreeMap<String, Vec<StoredBookmark>>,
+}
+
+impl BookmarkStore {
+    const FILE_NAME: &'static str = "bookmarks.json";
+    const FORMAT_VERSION: u32 = 1;
+    // How far from its saved row a bookmark's line is searched for.
+    const RELOCATE_DISTANCE: usize = 100;
+
+    // Creates a store with the bookmarks saved in `data_dir`, if any.
+    pub fn load(data_dir: String) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(data_dir);
+        let path = dir.join(Self::FILE_NAME);
+
+        let files = if path.exists() {
+            let file: BookmarkFile = serde_json::from_slice(&fs::read(path)?)?;
+            file.files
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self { dir, files })
+    }
+
+    #[frb(sync)]
+    pub fn record(&mut self, path: String, buffer: &Buffer) {
+        let stored: Vec<StoredBookmark> = buffer
+            .bookmarks()
+            .into_iter()
+            .map(|bookmark| StoredBookmark {
+                line: buffer.line(bookmark.row),
+                name: bookmark.name,
+                row: bookmark.row,
+                column: bookmark.column,
+            })
+            .collect();
+
+        if stored.is_empty() {
+            self.files.remove(&path);
+        } else {
+            self.files.insert(path, stored);
+        }
+    }
+
+    // Adds the bookmarks saved for `path` to a freshly opened buffer.
+    #[frb(sync)]
+    pub fn apply(&self, path: String, buffer: &mut Buffer) {
+        let Some(stored) = self.files.get(&path) else {
+            return;
+        };
+
+        for bookmark in stored {
+            let row = relocate(buffer, bookmark);
+            let column = bookmark.column.min(buffer.line(row).len());
+
+            match &bookmark.name {
+                Some(name) => buffer.set_mark(name.clone(), row, column),
+                None => {
+                    if !buffer
+                        .bookmarks()
+                        .iter()
+                        .any(|b| b.row == row && b.name.is_none())
+                    {
+                        buffer.toggle_bookmark(row);
+                    }
+                }
+            }
+        }
+    }
+
+    #[frb(sync)]
+    pub fn rename(&mut self, old_path: String, new_path: String) {
+        if let Some(stored) = self.files.remove(&old_path) {
+            self.files.insert(new_path, stored);
+        }
+    }
+
+    #[frb(sync)]
+    pub fn remove(&mut self, path: String) {
+        self.files.remove(&path);
+    }
+
+    // Every bookmark, grouped by file in path order.
+    #[frb(sync)]
+    pub fn all_bookmarks(&self) -> Vec<BookmarkLocation> {
+        self.files
+            .iter()
+            .flat_map(|(path, stored)| {
+                stored.iter().map(|bookmark| BookmarkLocation {
+                    path: path.clone(),
+                    bookmark: Bookmark {
+                        name: bookmark.name.clone(),
+                        row: bookmark.row,
+                        column: bookmark.column,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    // Finds a named mark in any file, as for vim's uppercase marks.
+    #[frb(sync)]
+    pub fn find_mark(&self, name: String) -> Option<BookmarkLocation> {
+        self.all_bookmarks()
+            .into_iter()
+            .find(|location| location.bookmark.name.as_deref() == Some(name.as_str()))
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let file = BookmarkFile {
+            version: Self::FORMAT_VERSION,
+            files: self.files.clone(),
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        write_atomically(
+            &self.dir.join(Self::FILE_NAME),
+            &serde_json::to_vec_pretty(&file)?,
+        )
+    }
+}
+
+// The saved row if its line is unchanged, otherwise the nearest row holding
+// the saved line, falling back to the saved row clamped to the buffer.
+fn relocate(buffer: &Buffer, bookmark: &StoredBookmark) -> usize {
+    let last_row = buffer.last_row();
+    let row = bookmark.row.min(last_row);
+    if buffer.line(row) == bookmark.line {
+        return row;
+    }
+
+    (1..=BookmarkStore::RELOCATE_DISTANCE)
+        .flat_map(|distance| [row.checked_sub(distance), Some(row + distance)])
+        .flatten()
+        .filter(|&candidate| candidate <= last_row)
+        .find(|&candidate| buffer.line(candidate) == bookmark.line)
+        .unwrap_or(row)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(buffer: &Buffer) -> Vec<(Option<String>, usize, usize)> {
+        buffer
+            .bookmarks()
+            .into_iter()
+            .map(|bookmark| (bookmark.name, bookmark.row, bookmark.column))
+            .collect()
+    }
+
+    #[test]
+    fn bookmarks_follow_edits_and_wrap_around() {
+        let mut buffer = Buffer::from("a\nb\nc\nd\n".to_string());
+        assert!(buffer.toggle_bookmark(1));
+        assert!(buffer.toggle_bookmark(3));
+        buffer.set_mark("x".to_string(), 2, 1);
+
+        buffer.insert(0, 0, "new\n".to_string());
+        assert_eq!(
+            rows(&buffer),
+            [(None, 2, 0), (Some("x".to_string()), 3, 1), (None, 4, 0)]
+        );
+
+        assert_eq!(buffer.next_bookmark(4).unwrap().row, 2);
+        assert_eq!(buffer.previous_bookmark(2).unwrap().row, 4);
+        assert_eq!(buffer.next_bookmark(2).unwrap().row, 3);
+
+        // Toggling a row leaves the named mark on it alone.
+        buffer.toggle_bookmark(3);
+        assert!(!buffer.toggle_bookmark(3));
+        assert_eq!(buffer.mark("x".to_string()).unwrap().row, 3);
+
+        // Setting a mark again moves it.
+        buffer.set_mark("x".to_string(), 0, 0);
+        assert_eq!(buffer.bookmarks().len(), 3);
+        assert_eq!(buffer.mark("x".to_string()).unwrap().row, 0);
+
+        assert!(buffer.remove_mark("x".to_string()));
+        assert!(!buffer.remove_mark("x".to_string()));
+        buffer.clear_bookmarks();
+        assert!(buffer.bookmarks().is_empty());
+        assert!(buffer.next_bookmark(0).is_none());
+    }
+
+    #[test]
+    fn stored_bookmarks_find_their_line_again() {
+        let dir = std::env::temp_dir().join(format!("rei-bookmark-test-{}", std::process::id()));
+        let data_dir = dir.to_string_lossy().into_owned();
+
+        let mut buffer = Buffer::from("fn a() {}\nfn b() {}\nfn c() {}\n".to_string());
+        buffer.toggle_bookmark(1);
+        buffer.set_mark("C".to_string(), 2, 3);
+        let mut store = BookmarkStore::load(data_dir.clone()).unwrap();
+        store.record("/p/lib.rs".to_string(), &buffer);
+        store.save().unwrap();
+
+        let mut store = BookmarkStore::load(data_dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let mark = store.find_mark("C".to_string()).unwrap();
+        assert_eq!((mark.path.as_str(), mark.bookmark.row), ("/p/lib.rs", 2));
+
+        // The file gained lines outside the editor.
+        let mut reopened =
+            Buffer::from("// header\n\nfn a() {}\nfn b() {}\nfn c() {}\n".to_string());
+        store.apply("/p/lib.rs".to_string(), &mut reopened);
+        assert_eq!(
+            rows(&reopened),
+            [(None, 3, 0), (Some("C".to_string()), 4, 3)]
+        );
+
+        store.rename("/p/lib.rs".to_string(), "/p/main.rs".to_string());
+        assert_eq!(store.all_bookmarks()[0].path, "/p/main.rs");
+        store.record("/p/main.rs".to_string(), &Buffer::new());
+        assert!(store.all_bookmarks().is_empty());
+    }
+}
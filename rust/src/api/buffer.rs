@@ -1,88 +1,177 @@
 use crop::Rope;
 use flutter_rust_bridge::frb;
 use rand::Rng;
-use std::collections::{BTreeMap, BTreeSet};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::ops::Range;
+
+use super::anchor::AnchorSet;
+use super::bookmark::BookmarkEntry;
+use super::changes::{BufferChange, ChangeLog};
+use super::encoding::Encoding;
+use super::large_file::{BufferFeatures, LargeFileThresholds};
+use super::line_metrics::LineMetrics;
+
+// A replacement of the text between two positions. Positions refer to the
+// buffer as it was before any edit in the same batch was applied.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub start_row: usize,
+    pub start_column: usize,
+    pub end_row: usize,
+    pub end_column: usize,
+    pub text: String,
+}
 
 #[frb(type_64bit_int)]
 pub struct Buffer {
     text: Rope,
     pub version: usize,
-    line_lengths: BTreeMap<usize, usize>,
-    // (length, line_index)
-    length_index_set: BTreeSet<(usize, usize)>,
+    // Identifies the buffer in the session and swap files, which cannot key
+    // on the path since untitled buffers have none and a file can be open
+    // twice.
+    id: u64,
+    line_metrics: LineMetrics,
+    features: BufferFeatures,
+    // Without line length tracking, the longest line seen when loading or
+    // editing stands in for `max_line_length`.
+    longest_line_seen: usize,
+    change_log: ChangeLog,
+    anchors: AnchorSet,
+    bookmarks: Vec<BookmarkEntry>,
+    // Set when the file was not valid in its encoding and some bytes were
+    // replaced while loading, so saving would not write back what was read.
+    lossy: bool,
+    encoding: Encoding,
 }
 
 impl Buffer {
     #[frb(sync)]
     pub fn new() -> Self {
-        let mut line_lengths = BTreeMap::new();
-        let mut length_index_set = BTreeSet::new();
-
-        line_lengths.insert(0, 0);
-        length_index_set.insert((0, 0));
-
         let version: u32 = rand::rng().random();
 
         Self {
             text: Rope::new(),
             version: version as usize,
-            line_lengths,
-            length_index_set,
+            id: rand::rng().random(),
+            line_metrics: LineMetrics::from_lengths([0]),
+            features: BufferFeatures::default(),
+            longest_line_seen: 0,
+            change_log: ChangeLog::default(),
+            anchors: AnchorSet::default(),
+            bookmarks: Vec::new(),
+            lossy: false,
+            encoding: Encoding::Utf8,
         }
     }
 
     #[frb(sync)]
     pub fn from(text: String) -> Self {
-        let mut line_lengths = BTreeMap::new();
-        let mut length_index_set = BTreeSet::new();
+        let features = BufferFeatures::for_size(text.len() as u64, &LargeFileThresholds::default());
+        let longest_line = if features.line_length_tracking {
+            0
+        } else {
+            text.lines().map(str::len).max().unwrap_or(0)
+        };
+
+        Self::from_rope(Rope::from(text), features, longest_line)
+    }
 
-        for (i, line) in text.lines().enumerate() {
-            let line_length = line.len();
+    pub(crate) fn from_rope(text: Rope, features: BufferFeatures, longest_line: usize) -> Self {
+        let line_metrics = if features.line_length_tracking {
+            // The row after a trailing newline, or the only row of an empty
+            // buffer, is empty and not yielded by `lines`.
+            let ends_with_newline = text.byte_len() == 0 || text.byte(text.byte_len() - 1) == b'\n';
+            let lengths = text.lines().map(|line| line.byte_len());
 
-            line_lengths.insert(i, line_length);
-            length_index_set.insert((line_length, i));
-        }
+            LineMetrics::from_lengths(lengths.chain(ends_with_newline.then_some(0)))
+        } else {
+            LineMetrics::from_lengths([])
+        };
 
         let version: u32 = rand::rng().random();
 
         Self {
-            text: Rope::from(text),
+            text,
             version: version as usize,
-            line_lengths,
-            length_index_set,
+            id: rand::rng().random(),
+            line_metrics,
+            features,
+            longest_line_seen: longest_line,
+            change_log: ChangeLog::default(),
+            anchors: AnchorSet::default(),
+            bookmarks: Vec::new(),
+            lossy: false,
+            encoding: Encoding::Utf8,
         }
     }
 
     #[frb(sync, type_64bit_int)]
-    pub fn insert(&mut self, row: usize, column: usize, text: String) -> (usize, usize) {
-        let char_idx = self.row_column_to_idx(row, column);
-        self.text.insert(char_idx, &text);
-        self.version += 1;
+    pub fn id(&self) -> u64 {
+        self.id
+    }
 
-        let new_idx = char_idx + text.chars().count();
-        let (new_row, new_column) = self.idx_to_row_column(new_idx);
+    // Gives a restored buffer the id it was saved with.
+    #[frb(sync, type_64bit_int)]
+    pub fn set_id(&mut self, id: u64) {
+        self.id = id;
+    }
 
-        self.update_line_lengths_range(row, new_row);
+    #[frb(sync)]
+    pub fn features(&self) -> BufferFeatures {
+        self.features
+    }
 
-        (new_row, new_column)
+    #[frb(sync)]
+    pub fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+
+    // Allows saving a lossy buffer, once the user has agreed to write the
+    // replacement characters in place of the bytes that could not be read.
+    #[frb(sync)]
+    pub fn clear_lossy(&mut self) {
+        self.lossy = false;
+    }
+
+    pub(crate) fn set_lossy(&mut self, lossy: bool) {
+        self.lossy = lossy;
+    }
+
+    #[frb(sync)]
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    // The encoding the next save writes, e.g. after the user picks another.
+    #[frb(sync)]
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        self.encoding = encoding;
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn insert(&mut self, row: usize, column: usize, text: String) -> (usize, usize) {
+        let idx = self.row_column_to_idx(row, column);
+
+        self.replace_bytes(idx..idx, &text)
     }
 
     #[frb(sync, type_64bit_int)]
     pub fn remove_char(&mut self, row: usize, column: usize) -> (usize, usize) {
-        let char_idx = self.row_column_to_idx(row, column);
-        if char_idx == 0 {
+        let idx = self.row_column_to_idx(row, column);
+        if idx == 0 {
             return (row, column);
         }
 
-        self.text.delete(char_idx - 1..char_idx);
-        self.version += 1;
-
-        let new_idx = char_idx - 1;
-        let (new_row, new_column) = self.idx_to_row_column(new_idx);
+        let char_len = self
+            .text
+            .byte_slice(..idx)
+            .chars()
+            .next_back()
+            .map_or(1, char::len_utf8);
 
-        self.update_line_lengths_range(new_row.min(row), new_row.max(row));
-
-        (new_row, new_column)
+        self.replace_bytes(idx - char_len..idx, "")
     }
 
     #[frb(sync, type_64bit_int)]
@@ -102,63 +191,79 @@ impl Buffer {
             end_idx = self.text.byte_len();
         }
 
-        self.text.delete(start_idx..end_idx);
-        self.version += 1;
-
-        if should_clear {
-            self.line_lengths.clear();
-            self.length_index_set.clear();
-
-            if self.text.byte_len() == 0 {
-                self.line_lengths.insert(0, 0);
-                self.length_index_set.insert((0, 0));
-            }
-        } else {
-            self.rebuild_line_lengths_from(start_row);
-        }
+        self.replace_bytes(start_idx..end_idx, "");
 
         (start_row, start_column)
     }
 
-    fn update_line_lengths_range(&mut self, start_row: usize, end_row: usize) {
-        for row in start_row..=end_row {
-            self.update_single_line_length(row);
-        }
-    }
-
-    fn update_single_line_length(&mut self, row: usize) {
-        let new_length = self.actual_line_len(row);
-
-        if let Some(&old_length) = self.line_lengths.get(&row) {
-            if old_length != new_length {
-                self.length_index_set.remove(&(old_length, row));
-                self.length_index_set.insert((new_length, row));
-            }
-        } else {
-            self.length_index_set.insert((new_length, row));
-        }
+    #[frb(sync, type_64bit_int)]
+    pub fn replace_range(
+        &mut self,
+        start_row: usize,
+        start_column: usize,
+        end_row: usize,
+        end_column: usize,
+        text: String,
+    ) -> (usize, usize) {
+        let start_idx = self.row_column_to_idx(start_row, start_column);
+        let end_idx = self.row_column_to_idx(end_row, end_column);
 
-        self.line_lengths.insert(row, new_length);
+        self.replace_bytes(start_idx..end_idx, &text)
     }
 
-    fn rebuild_line_lengths_from(&mut self, start_row: usize) {
-        let keys_to_remove: Vec<usize> = self
-            .line_lengths
-            .range(start_row..)
-            .map(|(&row, &length)| {
-                self.length_index_set.remove(&(length, row));
-                row
+    // Applies non-overlapping edits from the bottom of the buffer up, so each
+    // edit's positions stay valid while the others are applied.
+    #[frb(sync)]
+    pub fn apply_edits(&mut self, edits: Vec<TextEdit>) {
+        let mut ranges: Vec<(Range<usize>, String)> = edits
+            .into_iter()
+            .map(|edit| {
+                let start_idx = self.row_column_to_idx(edit.start_row, edit.start_column);
+                let end_idx = self.row_column_to_idx(edit.end_row, edit.end_column);
+                (start_idx..end_idx, edit.text)
             })
             .collect();
+        ranges.sort_by_key(|(range, _)| range.start);
 
-        for row in keys_to_remove {
-            self.line_lengths.remove(&row);
+        for (range, text) in ranges.into_iter().rev() {
+            self.replace_bytes(range, &text);
         }
+    }
+
+    // Replaces `range` with `text` as a single edit and returns the position at
+    // the end of the inserted text. Only the rows the edit spans are measured;
+    // the rows after it shift in the line metrics without being revisited.
+    pub(crate) fn replace_bytes(&mut self, range: Range<usize>, text: &str) -> (usize, usize) {
+        let (start_row, _) = self.idx_to_row_column(range.start);
+        let (old_end_row, _) = self.idx_to_row_column(range.end);
+
+        self.text.replace(range.clone(), text);
+        self.version += 1;
 
-        let line_count = self.line_count();
-        for row in start_row..line_count {
-            self.update_single_line_length(row);
+        let new_end = range.start + text.len();
+        let (new_row, new_column) = self.idx_to_row_column(new_end);
+
+        self.anchors.apply_edit(range.start, range.end, new_end);
+        self.change_log.push(BufferChange {
+            version: self.version,
+            start_byte: range.start,
+            old_end_byte: range.end,
+            new_end_byte: new_end,
+            start_row,
+            old_end_row,
+            new_end_row: new_row,
+        });
+        let lengths = (start_row..=new_row).map(|row| self.actual_line_len(row));
+
+        if self.features.line_length_tracking {
+            let lengths: Vec<usize> = lengths.collect();
+            self.line_metrics
+                .splice(start_row..old_end_row + 1, lengths);
+        } else {
+            self.longest_line_seen = lengths.fold(self.longest_line_seen, usize::max);
         }
+
+        (new_row, new_column)
     }
 
     #[frb(sync, type_64bit_int)]
@@ -277,7 +382,11 @@ impl Buffer {
 
     #[frb(sync, type_64bit_int)]
     pub fn line_len(&self, row: usize) -> usize {
-        self.line_lengths.get(&row).unwrap_or(&0).clone()
+        if !self.features.line_length_tracking {
+            return self.actual_line_len(row);
+        }
+
+        self.line_metrics.get(row).unwrap_or(0)
     }
 
     fn actual_line_len(&self, row: usize) -> usize {
@@ -288,16 +397,58 @@ impl Buffer {
         }
     }
 
+    #[frb(sync, type_64bit_int)]
+    pub fn line(&self, row: usize) -> String {
+        if row >= self.line_count() {
+            String::new()
+        } else {
+            self.text.line(row).to_string()
+        }
+    }
+
     #[frb(sync)]
     pub fn to_string(&self) -> String {
         self.text.to_string()
     }
 
+    pub(crate) fn change_log(&self) -> &ChangeLog {
+        &self.change_log
+    }
+
+    pub(crate) fn anchors(&self) -> &AnchorSet {
+        &self.anchors
+    }
+
+    pub(crate) fn anchors_mut(&mut self) -> &mut AnchorSet {
+        &mut self.anchors
+    }
+
+    pub(crate) fn bookmark_entries(&self) -> &[BookmarkEntry] {
+        &self.bookmarks
+    }
+
+    pub(crate) fn bookmark_entries_mut(&mut self) -> &mut Vec<BookmarkEntry> {
+        &mut self.bookmarks
+    }
+
+    pub(crate) fn rope(&self) -> &Rope {
+        &self.text
+    }
+
+    pub(crate) fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        for chunk in self.text.chunks() {
+            writer.write_all(&self.encoding.encode(chunk)?)?;
+        }
+
+        writer.flush()
+    }
+
     #[frb(sync, type_64bit_int)]
     pub fn max_line_length(&self) -> usize {
-        self.length_index_set
-            .last()
-            .map(|(length, _)| *length)
-            .unwrap_or(0)
+        if !self.features.line_length_tracking {
+            return self.longest_line_seen;
+        }
+
+        self.line_metrics.max()
     }
 }
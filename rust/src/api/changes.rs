@@ -0,0 +1,122 @@
+use flutter_rust_bridge::frb;
+use std::collections::VecDeque;
+
+use super::buffer::Buffer;
+
+// One edit to a buffer. Bytes and rows before the edit are unchanged; rows
+// after `old_end_row` moved to follow `new_end_row`.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferChange {
+    // The version the buffer reached by applying this change.
+    pub version: usize,
+    pub start_byte: usize,
+    pub old_end_byte: usize,
+    pub new_end_byte: usize,
+    pub start_row: usize,
+    pub old_end_row: usize,
+    pub new_end_row: usize,
+}
+
+impl BufferChange {
+    // Whether rows after the change moved, so anything keyed by row past
+    // `old_end_row` needs to be shifted.
+    #[frb(sync)]
+    pub fn shifts_rows(&self) -> bool {
+        self.old_end_row != self.new_end_row
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ChangeLog {
+    changes: VecDeque<BufferChange>,
+}
+
+impl ChangeLog {
+    // Enough to cover the edits made between two frames, or a large batch
+    // such as a formatter run, without growing with the buffer's history.
+    const CAPACITY: usize = 4096;
+
+    pub fn push(&mut self, change: BufferChange) {
+        if self.changes.len() == Self::CAPACITY {
+            self.changes.pop_front();
+        }
+        self.changes.push_back(change);
+    }
+
+    fn since(&self, version: usize, current_version: usize) -> Option<Vec<BufferChange>> {
+        if version == current_version {
+            return Some(Vec::new());
+        }
+
+        let oldest = self.changes.front()?;
+        if version > current_version || version + 1 < oldest.version {
+            return None;
+        }
+
+        Some(
+            self.changes
+                .iter()
+                .filter(|change| change.version > version)
+                .copied()
+                .collect(),
+        )
+    }
+}
+
+impl Buffer {
+    // The changes made after `version`, oldest first. Returns `None` when they
+    // are no longer retained, or `version` is not one this buffer had, in
+    // which case the caller should treat the whole buffer as changed.
+    #[frb(sync, type_64bit_int)]
+    pub fn changes_since(&self, version: usize) -> Option<Vec<BufferChange>> {
+        self.change_log().since(version, self.version)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_rows_and_bytes_of_each_edit() {
+        let mut buffer = Buffer::from("ab\ncd\n".to_string());
+        let version = buffer.version;
+        buffer.replace_range(0, 1, 1, 1, "x\ny\nz".to_string());
+
+        let changes = buffer.changes_since(version).unwrap();
+        assert_eq!(
+            changes,
+            [BufferChange {
+                version: version + 1,
+                start_byte: 1,
+                old_end_byte: 4,
+                new_end_byte: 6,
+                start_row: 0,
+                old_end_row: 1,
+                new_end_row: 2,
+            }]
+        );
+        assert!(changes[0].shifts_rows());
+        assert_eq!(buffer.changes_since(buffer.version), Some(Vec::new()));
+        assert_eq!(buffer.changes_since(buffer.version + 1), None);
+    }
+
+    #[test]
+    fn changes_are_lost_once_the_log_overflows() {
+        let mut buffer = Buffer::new();
+        let version = buffer.version;
+        for _ in 0..ChangeLog::CAPACITY {
+            buffer.insert(0, 0, "a".to_string());
+        }
+        let changes = buffer.changes_since(version).unwrap();
+        assert_eq!(changes.len(), ChangeLog::CAPACITY);
+        assert_eq!(changes[0].version, version + 1);
+
+        buffer.insert(0, 0, "a".to_string());
+        assert_eq!(buffer.changes_since(version), None);
+        let changes = buffer.changes_since(version + 1).unwrap();
+        assert_eq!(changes.len(), ChangeLog::CAPACITY);
+        assert_eq!(changes.last().unwrap().version, buffer.version);
+    }
+}
@@ -0,0 +1,261 @@
+use flutter_rust_bridge::frb;
+use std::collections::{BTreeSet, VecDeque};
+
+use super::buffer::Buffer;
+use super::cursor::Cursor;
+use super::selection::Selection;
+
+// What was copied, one fragment per cursor. Line-wise contents come from a copy
+// with an empty selection and paste as whole lines above the cursor.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ClipboardContents {
+    pub fragments: Vec<String>,
+    pub line_wise: bool,
+}
+
+impl ClipboardContents {
+    #[frb(sync)]
+    pub fn plain(text: String) -> Self {
+        Self {
+            fragments: vec![text],
+            line_wise: false,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn text(&self) -> String {
+        if self.line_wise {
+            self.fragments.concat()
+        } else {
+            self.fragments.join("\n")
+        }
+    }
+}
+
+#[frb(opaque)]
+pub struct ClipboardHistory {
+    entries: VecDeque<ClipboardContents>,
+    capacity: usize,
+}
+
+impl ClipboardHistory {
+    #[frb(sync, type_64bit_int)]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn push(&mut self, contents: ClipboardContents) {
+        self.entries.retain(|entry| entry != &contents);
+        self.entries.push_front(contents);
+        self.entries.truncate(self.capacity);
+    }
+
+    #[frb(sync)]
+    pub fn entries(&self) -> Vec<ClipboardContents> {
+        self.entries.iter().cloned().collect()
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn get(&self, index: usize) -> Option<ClipboardContents> {
+        self.entries.get(index).cloned()
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[frb(sync)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[frb(sync)]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // The system clipboard only carries text, so reuse the latest entry when it
+    // still matches to keep its line-wise and per-cursor information.
+    #[frb(sync)]
+    pub fn resolve(&self, system_text: String) -> ClipboardContents {
+        match self.entries.front() {
+            Some(latest) if latest.text() == system_text => latest.clone(),
+            _ => ClipboardContents::plain(system_text),
+        }
+    }
+}
+
+// Each selection stands for one cursor, positioned at its `end`.
+impl Buffer {
+    #[frb(sync)]
+    pub fn copy(&self, selections: Vec<Selection>) -> ClipboardContents {
+        if selections.iter().all(Selection::is_empty) {
+            let fragments = selections
+                .iter()
+                .map(|selection| format!("{}\n", self.line(selection.end.row)))
+                .collect();
+
+            return ClipboardContents {
+                fragments,
+                line_wise: true,
+            };
+        }
+
+        let fragments = selections
+            .iter()
+            .map(|selection| {
+                let normalized = selection.normalized();
+                self.text_in_range(
+                    normalized.start.row,
+                    normalized.start.column,
+                    normalized.end.row,
+                    normalized.end.column,
+                )
+            })
+            .collect();
+
+        ClipboardContents {
+            fragments,
+            line_wise: false,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn cut(&mut self, selections: Vec<Selection>) -> (ClipboardContents, Vec<Cursor>) {
+        let contents = self.copy(selections.clone());
+
+        let cursors = if contents.line_wise {
+            self.delete_rows_for(&selections)
+        } else {
+            let fragments = vec![String::new(); selections.len()];
+            self.replace_selections(&selections, &fragments)
+        };
+
+        (contents, cursors)
+    }
+
+    // Returns the cursor for each selection, in the order they were given.
+    #[frb(sync)]
+    pub fn paste(
+        &mut self,
+        selections: Vec<Selection>,
+        contents: ClipboardContents,
+    ) -> Vec<Cursor> {
+        let fragments: Vec<String> =
+            if contents.fragments.len() == selections.len() && selections.len() > 1 {
+                contents.fragments.clone()
+            } else {
+                vec![contents.text(); selections.len()]
+            };
+
+        if contents.line_wise && selections.iter().all(Selection::is_empty) {
+            return self.insert_lines_above(&selections, &fragments);
+        }
+
+        self.replace_selections(&selections, &fragments)
+    }
+
+    fn replace_selections(
+        &mut self,
+        selections: &[Selection],
+        fragments: &[String],
+    ) -> Vec<Cursor> {
+        let ranges: Vec<(usize, usize)> = selections
+            .iter()
+            .map(|selection| {
+                let normalized = selection.normalized();
+                (
+                    self.row_column_to_idx(normalized.start.row, normalized.start.column),
+                    self.row_column_to_idx(normalized.end.row, normalized.end.column),
+                )
+            })
+            .collect();
+
+        self.apply_byte_edits(ranges, fragments, |start, _, text| start + text.len())
+    }
+
+    fn insert_lines_above(
+        &mut self,
+        selections: &[Selection],
+        fragments: &[String],
+    ) -> Vec<Cursor> {
+        let ranges: Vec<(usize, usize)> = selections
+            .iter()
+            .map(|selection| {
+                let line_start = self.byte_of_line(selection.end.row);
+                let cursor_idx = self.row_column_to_idx(selection.end.row, selection.end.column);
+                (line_start, cursor_idx)
+            })
+            .collect();
+
+        // The cursor stays on its own line, which has moved down past the paste.
+        self.apply_byte_edits(
+            ranges
+                .iter()
+                .map(|&(line_start, _)| (line_start, line_start))
+                .collect(),
+            fragments,
+            |start, idx, text| start + text.len() + (ranges[idx].1 - ranges[idx].0),
+        )
+    }
+
+    // Applies one replacement per range from the bottom up, then maps each
+    // range to its resulting cursor with `cursor_at(shifted_start, index, text)`.
+    pub(crate) fn apply_byte_edits(
+        &mut self,
+        ranges: Vec<(usize, usize)>,
+        fragments: &[String],
+        cursor_at: impl Fn(usize, usize, &str) -> usize,
+    ) -> Vec<Cursor> {
+        let mut order: Vec<usize> = (0..ranges.len()).collect();
+        order.sort_by_key(|&idx| ranges[idx].0);
+
+        for &idx in order.iter().rev() {
+            let (start, end) = ranges[idx];
+            self.replace_bytes(start..end, &fragments[idx]);
+        }
+
+        let mut shifted = vec![0; ranges.len()];
+        let mut delta: isize = 0;
+        for &idx in &order {
+            let (start, end) = ranges[idx];
+            shifted[idx] = start.saturating_add_signed(delta);
+            delta += fragments[idx].len() as isize - (end - start) as isize;
+        }
+
+        (0..ranges.len())
+            .map(|idx| {
+                let cursor_idx = cursor_at(shifted[idx], idx, &fragments[idx]);
+                let (row, column) = self.idx_to_row_column(cursor_idx);
+                Cursor::new(row, column, column)
+            })
+            .collect()
+    }
+
+    fn delete_rows_for(&mut self, selections: &[Selection]) -> Vec<Cursor> {
+        let rows: BTreeSet<usize> = selections
+            .iter()
+            .map(|selection| selection.end.row)
+            .collect();
+
+        for &row in rows.iter().rev() {
+            self.delete_lines(Cursor::new(row, 0, 0), Selection::default());
+        }
+
+        selections
+            .iter()
+            .map(|selection| {
+                let deleted_above = rows.range(..selection.end.row).count();
+                let row = (selection.end.row - deleted_above).min(self.last_row());
+                let column = selection.end.column.min(self.line(row).len());
+                Cursor::new(row, column, column)
+            })
+            .collect()
+    }
+}
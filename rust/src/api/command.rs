@@ -0,0 +1,1077 @@
+use anyhow::{anyhow, bail};
+use flutter_rust_bridge::frb;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, VecDeque};
+
+use super::buffer::Buffer;
+use super::clipboard::ClipboardContents;
+use super::cursor::Cursor;
+use super::editorconfig::Indentation;
+use super::line_ops::SortKind;
+use super::macros::MacroStep;
+use super::selection::Selection;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    Boolean,
+    Integer,
+    String,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct CommandArg {
+    pub name: String,
+    pub kind: ArgKind,
+    pub required: bool,
+    pub description: String,
+}
+
+// A command as shown in the palette. Host commands are run by the Flutter
+// side, e.g. because they open a dialog; invoking one only validates its
+// arguments and records it as used.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct CommandInfo {
+    pub id: String,
+    pub title: String,
+    pub category: String,
+    pub args: Vec<CommandArg>,
+    pub host: bool,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone)]
+pub struct CommandResult {
+    pub cursor: Cursor,
+    pub selection: Selection,
+    // False for host commands, which the caller still has to run.
+    pub handled: bool,
+    // What `editor.copy` and `editor.cut` put on the clipboard.
+    pub clipboard: Option<ClipboardContents>,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug)]
+pub struct CommandMatch {
+    pub command: CommandInfo,
+    // `Category: Title`, as matched against the query.
+    pub label: String,
+    // Char indices into `label` that matched, for highlighting.
+    pub matched_indices: Vec<usize>,
+    pub score: i64,
+}
+
+// The editor state a command acts on besides the buffer.
+struct Target {
+    cursor: Cursor,
+    selection: Selection,
+    clipboard: Option<ClipboardContents>,
+    indentation: Indentation,
+    // Set by a motion that could not move, such as moving up on the first
+    // row. Macro playback stops there, as it would on an error.
+    blocked: bool,
+}
+
+struct Args(Map<String, Value>);
+
+impl Args {
+    fn bool(&self, name: &str) -> bool {
+        self.0.get(name).and_then(Value::as_bool).unwrap_or(false)
+    }
+
+    fn usize(&self, name: &str) -> Option<usize> {
+        self.0
+            .get(name)
+            .and_then(Value::as_u64)
+            .map(|value| value as usize)
+    }
+
+    fn str(&self, name: &str) -> Option<&str> {
+        self.0.get(name).and_then(Value::as_str)
+    }
+}
+
+type Handler = fn(&mut Buffer, &mut Target, &Args) -> anyhow::Result<()>;
+
+struct Entry {
+    info: CommandInfo,
+    handler: Option<Handler>,
+}
+
+// (name, kind, required, description)
+type ArgSpec = (&'static str, ArgKind, bool, &'static str);
+
+const NO_ARGS: &[ArgSpec] = &[];
+const MOVE_TO_ARGS: &[ArgSpec] = &[
+    ("row", ArgKind::Integer, true, "Row to move to"),
+    (
+        "column",
+        ArgKind::Integer,
+        false,
+        "Byte column, clamped to the line",
+    ),
+    ("select", ArgKind::Boolean, false, "Extend the selection"),
+];
+const SELECT_LINE_ARGS: &[ArgSpec] = &[(
+    "row",
+    ArgKind::Integer,
+    false,
+    "Row to select, defaulting to the cursor's",
+)];
+const TEXT_ARGS: &[ArgSpec] = &[("text", ArgKind::String, true, "Text to insert")];
+const PASTE_ARGS: &[ArgSpec] = &[
+    ("text", ArgKind::String, true, "Text to paste"),
+    (
+        "lineWise",
+        ArgKind::Boolean,
+        false,
+        "Paste as whole lines above the cursor, as copied without a selection",
+    ),
+];
+const DUPLICATE_ARGS: &[ArgSpec] = &[(
+    "up",
+    ArgKind::Boolean,
+    false,
+    "Keep the cursor on the original lines",
+)];
+const SORT_ARGS: &[ArgSpec] = &[
+    (
+        "kind",
+        ArgKind::String,
+        false,
+        "`lexical`, `numeric` or `caseInsensitive`",
+    ),
+    ("unique", ArgKind::Boolean, false, "Drop duplicate lines"),
+];
+const SAVE_AS_ARGS: &[ArgSpec] = &[("path", ArgKind::String, false, "Where to save")];
+
+// (id, title, category, args, handler); a missing handler marks a host command.
+type CommandSpec = (
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static [ArgSpec],
+    Option<Handler>,
+);
+
+const BUILTIN_COMMANDS: &[CommandSpec] = &[
+    (
+        "editor.moveLeft",
+        "Move Left",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, false, move_left)),
+    ),
+    (
+        "editor.moveRight",
+        "Move Right",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, false, move_right)),
+    ),
+    (
+        "editor.moveUp",
+        "Move Up",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| vertical_motion(b, t, false, move_up)),
+    ),
+    (
+        "editor.moveDown",
+        "Move Down",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| vertical_motion(b, t, false, move_down)),
+    ),
+    (
+        "editor.moveToLineStart",
+        "Move to Line Start",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, false, line_start)),
+    ),
+    (
+        "editor.moveToLineEnd",
+        "Move to Line End",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, false, line_end)),
+    ),
+    (
+        "editor.moveToDocumentStart",
+        "Move to Document Start",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, false, document_start)),
+    ),
+    (
+        "editor.moveToDocumentEnd",
+        "Move to Document End",
+        "Cursor",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, false, document_end)),
+    ),
+    (
+        "editor.moveTo",
+        "Go to Position",
+        "Cursor",
+        MOVE_TO_ARGS,
+        Some(move_to),
+    ),
+    (
+        "editor.selectLeft",
+        "Select Left",
+        "Selection",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, true, move_left)),
+    ),
+    (
+        "editor.selectRight",
+        "Select Right",
+        "Selection",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, true, move_right)),
+    ),
+    (
+        "editor.selectUp",
+        "Select Up",
+        "Selection",
+        NO_ARGS,
+        Some(|b, t, _| vertical_motion(b, t, true, move_up)),
+    ),
+    (
+        "editor.selectDown",
+        "Select Down",
+        "Selection",
+        NO_ARGS,
+        Some(|b, t, _| vertical_motion(b, t, true, move_down)),
+    ),
+    (
+        "editor.selectToLineStart",
+        "Select to Line Start",
+        "Selection",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, true, line_start)),
+    ),
+    (
+        "editor.selectToLineEnd",
+        "Select to Line End",
+        "Selection",
+        NO_ARGS,
+        Some(|b, t, _| motion(b, t, true, line_end)),
+    ),
+    (
+        "editor.selectAll",
+        "Select All",
+        "Selection",
+        NO_ARGS,
+        Some(select_all),
+    ),
+    (
+        "editor.selectLine",
+        "Select Line",
+        "Selection",
+        SELECT_LINE_ARGS,
+        Some(select_line),
+    ),
+    (
+        "editor.clearSelection",
+        "Clear Selection",
+        "Selection",
+        NO_ARGS,
+        Some(|_, t, _| {
+            t.selection = Selection::default();
+            Ok(())
+        }),
+    ),
+    (
+        "editor.insert",
+        "Insert Text",
+        "Edit",
+        TEXT_ARGS,
+        Some(|b, t, args| {
+            insert(b, t, args.str("text").unwrap_or_default());
+            Ok(())
+        }),
+    ),
+    (
+        "editor.newline",
+        "Insert Line Break",
+        "Edit",
+        NO_ARGS,
+        Some(|b, t, _| {
+            insert(b, t, "\n");
+            Ok(())
+        }),
+    ),
+    (
+        "editor.deleteLeft",
+        "Delete Left",
+        "Edit",
+        NO_ARGS,
+        Some(delete_left),
+    ),
+    (
+        "editor.deleteSelection",
+        "Delete Selection",
+        "Edit",
+        NO_ARGS,
+        Some(|b, t, _| {
+            delete_selection(b, t);
+            Ok(())
+        }),
+    ),
+    ("editor.copy", "Copy", "Edit", NO_ARGS, Some(copy)),
+    ("editor.cut", "Cut", "Edit", NO_ARGS, Some(cut)),
+    ("editor.paste", "Paste", "Edit", PASTE_ARGS, Some(paste)),
+    (
+        "editor.duplicateLines",
+        "Duplicate Lines",
+        "Lines",
+        DUPLICATE_ARGS,
+        Some(|b, t, args| {
+            let result = b.duplicate_lines(t.cursor, t.selection, args.bool("up"));
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.moveLinesUp",
+        "Move Lines Up",
+        "Lines",
+        NO_ARGS,
+        Some(|b, t, _| {
+            let result = b.move_lines_up(t.cursor, t.selection);
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.moveLinesDown",
+        "Move Lines Down",
+        "Lines",
+        NO_ARGS,
+        Some(|b, t, _| {
+            let result = b.move_lines_down(t.cursor, t.selection);
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.joinLines",
+        "Join Lines",
+        "Lines",
+        NO_ARGS,
+        Some(|b, t, _| {
+            let result = b.join_lines(t.cursor, t.selection);
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.deleteLines",
+        "Delete Lines",
+        "Lines",
+        NO_ARGS,
+        Some(|b, t, _| {
+            let result = b.delete_lines(t.cursor, t.selection);
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.sortLines",
+        "Sort Lines",
+        "Lines",
+        SORT_ARGS,
+        Some(sort_lines),
+    ),
+    (
+        "editor.reverseLines",
+        "Reverse Lines",
+        "Lines",
+        NO_ARGS,
+        Some(|b, t, _| {
+            let result = b.reverse_lines(t.cursor, t.selection);
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.indentLines",
+        "Indent Lines",
+        "Lines",
+        NO_ARGS,
+        Some(|b, t, _| {
+            let result = b.indent_lines(t.cursor, t.selection, t.indentation);
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.outdentLines",
+        "Outdent Lines",
+        "Lines",
+        NO_ARGS,
+        Some(|b, t, _| {
+            let result = b.outdent_lines(t.cursor, t.selection, t.indentation);
+            (t.cursor, t.selection) = (result.cursor, result.selection);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.trimTrailingWhitespace",
+        "Trim Trailing Whitespace",
+        "Whitespace",
+        NO_ARGS,
+        Some(|b, t, _| {
+            b.trim_trailing_whitespace();
+            clamp_target(b, t);
+            Ok(())
+        }),
+    ),
+    (
+        "editor.ensureFinalNewline",
+        "Ensure Final Newline",
+        "Whitespace",
+        NO_ARGS,
+        Some(|b, _, _| {
+            b.ensure_final_newline();
+            Ok(())
+        }),
+    ),
+    ("file.save", "Save", "File", NO_ARGS, None),
+    ("file.saveAs", "Save As…", "File", SAVE_AS_ARGS, None),
+    (
+        "explorer.selectPrevious",
+        "Select Previous File",
+        "Explorer",
+        NO_ARGS,
+        None,
+    ),
+    (
+        "explorer.selectNext",
+        "Select Next File",
+        "Explorer",
+        NO_ARGS,
+        None,
+    ),
+    (
+        "explorer.collapse",
+        "Collapse Folder",
+        "Explorer",
+        NO_ARGS,
+        None,
+    ),
+    (
+        "explorer.expand",
+        "Expand Folder",
+        "Explorer",
+        NO_ARGS,
+        None,
+    ),
+];
+
+// Every editor command by id, with the ones invoked most recently ranked
+// first in searches.
+#[frb(opaque)]
+pub struct CommandRegistry {
+    entries: Vec<Entry>,
+    index: HashMap<String, usize>,
+    recent: VecDeque<String>,
+    recording: Option<Vec<MacroStep>>,
+    indentation: Indentation,
+}
+
+impl CommandRegistry {
+    const RECENT_CAPACITY: usize = 20;
+
+    #[frb(sync)]
+    pub fn new() -> Self {
+        let mut registry = Self {
+            entries: Vec::new(),
+            index: HashMap::new(),
+            recent: VecDeque::new(),
+            recording: None,
+            indentation: Indentation::default(),
+        };
+
+        for &(id, title, category, args, handler) in BUILTIN_COMMANDS {
+            let info = CommandInfo {
+                id: id.to_string(),
+                title: title.to_string(),
+                category: category.to_string(),
+                args: args
+                    .iter()
+                    .map(|&(name, kind, required, description)| CommandArg {
+                        name: name.to_string(),
+                        kind,
+                        required,
+                        description: description.to_string(),
+                    })
+                    .collect(),
+                host: handler.is_none(),
+            };
+            registry.add(info, handler);
+        }
+
+        registry
+    }
+
+    // Adds a command implemented on the Flutter side.
+    #[frb(sync)]
+    pub fn register_host_command(&mut self, info: CommandInfo) -> anyhow::Result<()> {
+        if self.index.contains_key(&info.id) {
+            bail!("command `{}` is already registered", info.id);
+        }
+
+        self.add(CommandInfo { host: true, ..info }, None);
+        Ok(())
+    }
+
+    #[frb(sync)]
+    pub fn command(&self, id: String) -> Option<CommandInfo> {
+        self.index.get(&id).map(|&i| self.entries[i].info.clone())
+    }
+
+    #[frb(sync)]
+    pub fn commands(&self) -> Vec<CommandInfo> {
+        self.entries
+            .iter()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    // Runs `id` with `args`, a JSON object (or an empty string for no
+    // arguments), checked against the command's argument list.
+    #[frb(sync)]
+    pub fn invoke(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        selection: Selection,
+        id: String,
+        args: String,
+    ) -> anyhow::Result<CommandResult> {
+        let (result, _) = self.run(buffer, cursor, selection, &id, &args)?;
+
+        self.recent.retain(|recent| *recent != id);
+        self.recent.push_front(id);
+        self.recent.truncate(Self::RECENT_CAPACITY);
+
+        Ok(result)
+    }
+
+    // Commands whose label contains the query's characters in order, best
+    // first. An empty query lists recently used commands first, then the
+    // rest by label.
+    #[frb(sync, type_64bit_int)]
+    pub fn search(&self, query: String, limit: usize) -> Vec<CommandMatch> {
+        let mut matches: Vec<CommandMatch> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let label = format!("{}: {}", entry.info.category, entry.info.title);
+                let (score, matched_indices) = fuzzy_match(&query, &label)?;
+                // Recency orders an empty query outright, and otherwise only
+                // breaks near ties between matches.
+                let recency = self
+                    .recent
+                    .iter()
+                    .position(|id| *id == entry.info.id)
+                    .map_or(0, |position| (Self::RECENT_CAPACITY - position) as i64);
+                let recency = if query.trim().is_empty() {
+                    recency
+                } else {
+                    recency / 4
+                };
+
+                Some(CommandMatch {
+                    command: entry.info.clone(),
+                    label,
+                    matched_indices,
+                    score: score + recency,
+                })
+            })
+            .collect();
+
+        matches.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.label.cmp(&b.label)));
+        matches.truncate(limit);
+        matches
+    }
+
+    // Recently invoked command ids, most recent first, for saving between
+    // sessions.
+    #[frb(sync)]
+    pub fn recent_commands(&self) -> Vec<String> {
+        self.recent.iter().cloned().collect()
+    }
+
+    #[frb(sync)]
+    pub fn set_recent_commands(&mut self, ids: Vec<String>) {
+        self.recent = ids
+            .into_iter()
+            .filter(|id| self.index.contains_key(id))
+            .take(Self::RECENT_CAPACITY)
+            .collect();
+    }
+
+    // Runs a command without ranking it as recently used, also returning
+    // whether it was a motion that could not move. Commands run in Rust are
+    // added to the macro being recorded, if any.
+    pub(crate) fn run(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        selection: Selection,
+        id: &str,
+        args: &str,
+    ) -> anyhow::Result<(CommandResult, bool)> {
+        let entry = self
+            .index
+            .get(id)
+            .map(|&i| &self.entries[i])
+            .ok_or_else(|| anyhow!("unknown command `{id}`"))?;
+        let parsed = parse_args(&entry.info, args)?;
+
+        let mut target = Target {
+            cursor,
+            selection,
+            clipboard: None,
+            indentation: self.indentation,
+            blocked: false,
+        };
+        let handled = match entry.handler {
+            Some(handler) => {
+                handler(buffer, &mut target, &parsed)?;
+                true
+            }
+            None => false,
+        };
+
+        if let Some(steps) = self.recording.as_mut().filter(|_| handled) {
+            steps.push(MacroStep {
+                command: id.to_string(),
+                args: args.to_string(),
+            });
+        }
+
+        let result = CommandResult {
+            cursor: target.cursor,
+            selection: target.selection,
+            handled,
+            clipboard: target.clipboard,
+        };
+        Ok((result, target.blocked))
+    }
+
+    // What the indent commands add and remove, e.g. from EditorConfig.
+    #[frb(sync)]
+    pub fn set_indentation(&mut self, indentation: Indentation) {
+        self.indentation = indentation;
+    }
+
+    pub(crate) fn recording(&self) -> Option<&[MacroStep]> {
+        self.recording.as_deref()
+    }
+
+    pub(crate) fn recording_mut(&mut self) -> &mut Option<Vec<MacroStep>> {
+        &mut self.recording
+    }
+
+    pub(crate) fn is_host_command(&self, id: &str) -> bool {
+        self.index
+            .get(id)
+            .is_some_and(|&i| self.entries[i].handler.is_none())
+    }
+
+    fn add(&mut self, info: CommandInfo, handler: Option<Handler>) {
+        self.index.insert(info.id.clone(), self.entries.len());
+        self.entries.push(Entry { info, handler });
+    }
+}
+
+impl Default for CommandRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_args(info: &CommandInfo, args: &str) -> anyhow::Result<Args> {
+    let map = if args.trim().is_empty() {
+        Map::new()
+    } else {
+        match serde_json::from_str(args)? {
+            Value::Object(map) => map,
+            _ => bail!("arguments for `{}` must be a JSON object", info.id),
+        }
+    };
+
+    for name in map.keys() {
+        if !info.args.iter().any(|arg| arg.name == *name) {
+            bail!("`{}` has no argument `{name}`", info.id);
+        }
+    }
+
+    for arg in &info.args {
+        let valid = match (map.get(&arg.name), arg.kind) {
+            (None, _) => !arg.required,
+            (Some(Value::Bool(_)), ArgKind::Boolean) => true,
+            (Some(value), ArgKind::Integer) => value.is_u64(),
+            (Some(Value::String(_)), ArgKind::String) => true,
+            _ => false,
+        };
+        if !valid {
+            bail!(
+                "`{}` expects `{}` to be {:?}{}",
+                info.id,
+                arg.name,
+                arg.kind,
+                if arg.required { "" } else { " or omitted" }
+            );
+        }
+    }
+
+    Ok(Args(map))
+}
+
+// Scores `label` against `query` case-insensitively, choosing the alignment
+// of query characters that scores best. Matches at word starts and runs of
+// consecutive matches score higher; skipped characters cost a little.
+pub(crate) fn fuzzy_match(query: &str, label: &str) -> Option<(i64, Vec<usize>)> {
+    const WORD_START: i64 = 10;
+    const CONSECUTIVE: i64 = 5;
+
+    let query: Vec<char> = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let label: Vec<char> = label.chars().collect();
+    let lower: Vec<char> = label
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    // best[i][j]: the best score for the first `i + 1` query characters with
+    // the last one matched at label index `j`, and where the one before went.
+    let mut best: Vec<Vec<Option<(i64, usize)>>> = vec![vec![None; label.len()]; query.len()];
+    for (i, &q) in query.iter().enumerate() {
+        for j in (i..label.len()).filter(|&j| lower[j] == q) {
+            let word_start = j == 0 || !label[j - 1].is_alphanumeric();
+            let gain = 1 + if word_start { WORD_START } else { 0 };
+
+            best[i][j] = if i == 0 {
+                Some((gain - (j as i64).min(10) / 2, 0))
+            } else {
+                (i - 1..j)
+                    .filter_map(|k| {
+                        let (score, _) = best[i - 1][k]?;
+                        let bonus = if k + 1 == j {
+                            CONSECUTIVE
+                        } else {
+                            -((j - k - 1) as i64).min(10) / 2
+                        };
+                        Some((score + gain + bonus, k))
+                    })
+                    .max_by_key(|&(score, _)| score)
+            };
+        }
+    }
+
+    if query.is_empty() {
+        return Some((0, Vec::new()));
+    }
+
+    let last = query.len() - 1;
+    let (mut j, &(score, _)) = best[last]
+        .iter()
+        .enumerate()
+        .filter_map(|(j, cell)| Some((j, cell.as_ref()?)))
+        .max_by_key(|(_, (score, _))| *score)?;
+
+    let mut indices = vec![0; query.len()];
+    for i in (0..=last).rev() {
+        indices[i] = j;
+        j = best[i][j].map_or(0, |(_, previous)| previous);
+    }
+
+    Some((score, indices))
+}
+
+// Like `motion`, but blocked unless the row changes, so that moving down on the
+// last row stops a macro even though it moves to the end of the line.
+fn vertical_motion(
+    buffer: &mut Buffer,
+    target: &mut Target,
+    extend: bool,
+    moved: fn(&Buffer, Cursor) -> Cursor,
+) -> anyhow::Result<()> {
+    let row = target.cursor.row;
+    motion(buffer, target, extend, moved)?;
+    target.blocked = target.cursor.row == row;
+    Ok(())
+}
+
+fn motion(
+    buffer: &mut Buffer,
+    target: &mut Target,
+    extend: bool,
+    moved: fn(&Buffer, Cursor) -> Cursor,
+) -> anyhow::Result<()> {
+    let cursor = moved(buffer, target.cursor);
+    target.blocked = cursor == target.cursor;
+    move_cursor(target, cursor, extend);
+    Ok(())
+}
+
+// Moves the cursor, either extending the selection from where it was or
+// clearing it.
+fn move_cursor(target: &mut Target, cursor: Cursor, extend: bool) {
+    target.selection = if !extend {
+        Selection::default()
+    } else if target.selection.is_empty() {
+        Selection::new(target.cursor, cursor)
+    } else {
+        Selection::new(target.selection.start, cursor)
+    };
+    target.cursor = cursor;
+}
+
+fn at(row: usize, column: usize) -> Cursor {
+    Cursor::new(row, column, column)
+}
+
+fn move_left(buffer: &Buffer, cursor: Cursor) -> Cursor {
+    if cursor.column > 0 {
+        let line = buffer.line(cursor.row);
+        let column = line[..cursor.column.min(line.len())]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i);
+        at(cursor.row, column)
+    } else if cursor.row > 0 {
+        at(cursor.row - 1, buffer.line_len(cursor.row - 1))
+    } else {
+        cursor
+    }
+}
+
+fn move_right(buffer: &Buffer, cursor: Cursor) -> Cursor {
+    let line = buffer.line(cursor.row);
+    if cursor.column < line.len() {
+        let column = line[cursor.column..]
+            .chars()
+            .next()
+            .map_or(line.len(), |c| cursor.column + c.len_utf8());
+        at(cursor.row, column)
+    } else if cursor.row < buffer.last_row() {
+        at(cursor.row + 1, 0)
+    } else {
+        cursor
+    }
+}
+
+fn move_up(buffer: &Buffer, cursor: Cursor) -> Cursor {
+    if cursor.row == 0 {
+        return Cursor::default();
+    }
+    vertical(buffer, cursor, cursor.row - 1)
+}
+
+fn move_down(buffer: &Buffer, cursor: Cursor) -> Cursor {
+    if cursor.row == buffer.last_row() {
+        return document_end(buffer, cursor);
+    }
+    vertical(buffer, cursor, cursor.row + 1)
+}
+
+// The cursor on `row` at its sticky column, clamped to the line.
+fn vertical(buffer: &Buffer, cursor: Cursor, row: usize) -> Cursor {
+    let line = buffer.line(row);
+    let mut column = cursor.sticky_column.min(line.len());
+    while !line.is_char_boundary(column) {
+        column -= 1;
+    }
+    Cursor::new(row, column, cursor.sticky_column)
+}
+
+fn line_start(_: &Buffer, cursor: Cursor) -> Cursor {
+    at(cursor.row, 0)
+}
+
+fn line_end(buffer: &Buffer, cursor: Cursor) -> Cursor {
+    at(cursor.row, buffer.line_len(cursor.row))
+}
+
+fn document_start(_: &Buffer, _: Cursor) -> Cursor {
+    Cursor::default()
+}
+
+fn document_end(buffer: &Buffer, _: Cursor) -> Cursor {
+    let row = buffer.last_row();
+    at(row, buffer.line_len(row))
+}
+
+fn move_to(buffer: &mut Buffer, target: &mut Target, args: &Args) -> anyhow::Result<()> {
+    let row = args.usize("row").unwrap_or_default().min(buffer.last_row());
+    let column = args.usize("column").unwrap_or_default();
+    let cursor = vertical(buffer, Cursor::new(row, column, column), row);
+    move_cursor(target, at(cursor.row, cursor.column), args.bool("select"));
+    Ok(())
+}
+
+fn select_all(buffer: &mut Buffer, target: &mut Target, _: &Args) -> anyhow::Result<()> {
+    let end = document_end(buffer, target.cursor);
+    target.selection = Selection::new(Cursor::default(), end);
+    target.cursor = end;
+    Ok(())
+}
+
+fn select_line(buffer: &mut Buffer, target: &mut Target, args: &Args) -> anyhow::Result<()> {
+    let row = args
+        .usize("row")
+        .unwrap_or(target.cursor.row)
+        .min(buffer.last_row());
+    target.selection = Selection::new(at(row, 0), line_end(buffer, at(row, 0)));
+    Ok(())
+}
+
+fn delete_selection(buffer: &mut Buffer, target: &mut Target) {
+    let normalized = target.selection.normalized();
+    let (row, column) = buffer.replace_range(
+        normalized.start.row,
+        normalized.start.column,
+        normalized.end.row,
+        normalized.end.column,
+        String::new(),
+    );
+    target.cursor = at(row, column);
+    target.selection = Selection::default();
+}
+
+fn insert(buffer: &mut Buffer, target: &mut Target, text: &str) {
+    if !target.selection.is_empty() {
+        delete_selection(buffer, target);
+    }
+
+    let (row, column) = buffer.insert(target.cursor.row, target.cursor.column, text.to_string());
+    target.cursor = at(row, column);
+}
+
+fn delete_left(buffer: &mut Buffer, target: &mut Target, _: &Args) -> anyhow::Result<()> {
+    if !target.selection.is_empty() {
+        delete_selection(buffer, target);
+    } else if target.cursor.row > 0 || target.cursor.column > 0 {
+        let (row, column) = buffer.remove_char(target.cursor.row, target.cursor.column);
+        target.cursor = at(row, column);
+    }
+    Ok(())
+}
+
+// The selection to copy or cut: the current one, or the cursor's line.
+fn clipboard_selection(target: &Target) -> Selection {
+    if target.selection.is_empty() {
+        Selection::new(target.cursor, target.cursor)
+    } else {
+        target.selection
+    }
+}
+
+fn copy(buffer: &mut Buffer, target: &mut Target, _: &Args) -> anyhow::Result<()> {
+    target.clipboard = Some(buffer.copy(vec![clipboard_selection(target)]));
+    Ok(())
+}
+
+fn cut(buffer: &mut Buffer, target: &mut Target, _: &Args) -> anyhow::Result<()> {
+    let (contents, cursors) = buffer.cut(vec![clipboard_selection(target)]);
+    target.clipboard = Some(contents);
+    target.cursor = cursors.first().copied().unwrap_or(target.cursor);
+    target.selection = Selection::default();
+    Ok(())
+}
+
+fn paste(buffer: &mut Buffer, target: &mut Target, args: &Args) -> anyhow::Result<()> {
+    let contents = ClipboardContents {
+        line_wise: args.bool("lineWise"),
+        ..ClipboardContents::plain(args.str("text").unwrap_or_default().to_string())
+    };
+    let cursors = buffer.paste(vec![clipboard_selection(target)], contents);
+    target.cursor = cursors.first().copied().unwrap_or(target.cursor);
+    target.selection = Selection::default();
+    Ok(())
+}
+
+fn sort_lines(buffer: &mut Buffer, target: &mut Target, args: &Args) -> anyhow::Result<()> {
+    let kind = match args.str("kind").unwrap_or("lexical") {
+        "lexical" => SortKind::Lexical,
+        "numeric" => SortKind::Numeric,
+        "caseInsensitive" => SortKind::CaseInsensitive,
+        other => bail!("unknown sort kind `{other}`"),
+    };
+
+    let result = buffer.sort_lines(target.cursor, target.selection, kind, args.bool("unique"));
+    (target.cursor, target.selection) = (result.cursor, result.selection);
+    Ok(())
+}
+
+// Keeps the cursor and selection inside lines that may have been shortened.
+fn clamp_target(buffer: &Buffer, target: &mut Target) {
+    let clamp = |cursor: Cursor| {
+        let row = cursor.row.min(buffer.last_row());
+        at(row, cursor.column.min(buffer.line_len(row)))
+    };
+
+    target.cursor = clamp(target.cursor);
+    if !target.selection.is_empty() {
+        target.selection =
+            Selection::new(clamp(target.selection.start), clamp(target.selection.end));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invoke(buffer: &mut Buffer, selection: Selection, id: &str, args: &str) -> CommandResult {
+        CommandRegistry::new()
+            .invoke(
+                buffer,
+                selection.end,
+                selection,
+                id.to_string(),
+                args.to_string(),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn delete_selection_from_first_to_last_row_keeps_the_rest() {
+        let selection = Selection::new(at(0, 1), at(1, 1));
+
+        let mut buffer = Buffer::from("abc\ndef".to_string());
+        let result = invoke(&mut buffer, selection, "editor.deleteSelection", "");
+        assert_eq!(buffer.to_string(), "aef");
+        assert!(result.cursor == at(0, 1));
+
+        let mut buffer = Buffer::from("abc\ndef".to_string());
+        invoke(&mut buffer, selection, "editor.insert", r#"{"text": "X"}"#);
+        assert_eq!(buffer.to_string(), "aXef");
+    }
+
+    #[test]
+    fn paste_keeps_line_wise_contents_line_wise() {
+        let cursor = Selection::new(at(1, 2), at(1, 2));
+
+        let mut buffer = Buffer::from("abc\ndef".to_string());
+        let result = invoke(&mut buffer, cursor, "editor.copy", "");
+        let copied = result.clipboard.unwrap();
+        assert!(copied.line_wise);
+
+        let args = serde_json::json!({ "text": copied.text(), "lineWise": copied.line_wise });
+        let result = invoke(&mut buffer, cursor, "editor.paste", &args.to_string());
+        assert_eq!(buffer.to_string(), "abc\ndef\ndef");
+        assert!(result.cursor == at(2, 2));
+    }
+}
@@ -0,0 +1,453 @@
+use flutter_rust_bridge::frb;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::buffer::Buffer;
+use super::command::fuzzy_match;
+use super::cursor::Cursor;
+use super::text::floor_char_boundary;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompletionKind {
+    Word,
+    File,
+    Directory,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompletionItem {
+    pub label: String,
+    // What replaces the text from `start_column` to the cursor.
+    pub insert_text: String,
+    pub kind: CompletionKind,
+    pub score: i64,
+    // Char indices into `label` that matched, for highlighting.
+    pub matched_indices: Vec<usize>,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompletionList {
+    // Where the text being completed starts on the cursor's row.
+    pub start_column: usize,
+    pub items: Vec<CompletionItem>,
+}
+
+// The identifiers in one open buffer, kept per row so an edit only rescans
+// the rows it touched.
+struct Document {
+    file_path: Option<String>,
+    word_characters: String,
+    // `None` for rows changed since they were last scanned, whose words are
+    // already taken out of `counts`.
+    rows: Vec<Option<Vec<String>>>,
+    counts: HashMap<String, usize>,
+    version: usize,
+}
+
+impl Document {
+    fn is_word_char(&self, c: char) -> bool {
+        c.is_alphanumeric() || c == '_' || self.word_characters.contains(c)
+    }
+
+    fn scan(&mut self, buffer: &Buffer) {
+        let rows = buffer.line_count_with_trailing_newline().max(1);
+        self.rows = vec![None; rows];
+        self.counts.clear();
+        self.rescan(buffer, 0, rows - 1);
+    }
+
+    fn update(&mut self, buffer: &Buffer) {
+        if buffer.version == self.version {
+            return;
+        }
+        let Some(changes) = buffer.changes_since(self.version) else {
+            self.scan(buffer);
+            self.version = buffer.version;
+            return;
+        };
+
+        let mut dirty: Option<(usize, usize)> = None;
+        for change in changes {
+            let old_end = change.old_end_row.min(self.rows.len().saturating_sub(1));
+            for row in change.start_row..=old_end {
+                if let Some(words) = self.rows.get_mut(row).and_then(Option::take) {
+                    for word in words {
+                        self.remove_word(word);
+                    }
+                }
+            }
+
+            let inserted = change.new_end_row - change.start_row + 1;
+            let start = change.start_row.min(self.rows.len());
+            let removed = (old_end + 1)
+                .saturating_sub(start)
+                .min(self.rows.len() - start);
+            self.rows
+                .splice(start..start + removed, std::iter::repeat_n(None, inserted));
+
+            // Keep the dirty range in step with the rows moved by this change.
+            let shift = |row: usize| {
+                if row > change.old_end_row {
+                    row + change.new_end_row - change.old_end_row
+                } else if row >= change.start_row {
+                    change.new_end_row
+                } else {
+                    row
+                }
+            };
+            dirty = Some(match dirty {
+                Some((lo, hi)) => (lo.min(change.start_row), shift(hi).max(change.new_end_row)),
+                None => (change.start_row, change.new_end_row),
+            });
+        }
+
+        if let Some((lo, hi)) = dirty {
+            let hi = hi.min(self.rows.len().saturating_sub(1));
+            self.rescan(buffer, lo, hi);
+        }
+        self.version = buffer.version;
+    }
+
+    fn rescan(&mut self, buffer: &Buffer, start_row: usize, end_row: usize) {
+        for row in start_row..=end_row {
+            if self.rows.get(row).is_none_or(Option::is_some) {
+                continue;
+            }
+
+            let line = buffer.line(row);
+            let words = words(&line, |c| self.is_word_char(c));
+            for word in &words {
+                *self.counts.entry(word.clone()).or_default() += 1;
+            }
+            self.rows[row] = Some(words);
+        }
+    }
+
+    fn remove_word(&mut self, word: String) {
+        if let Some(count) = self.counts.get_mut(&word) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&word);
+            }
+        }
+    }
+}
+
+// Words worth completing: at least two characters and not starting with a
+// digit.
+fn words(line: &str, is_word_char: impl Fn(char) -> bool) -> Vec<String> {
+    line.split(|c: char| !is_word_char(c))
+        .filter(|word| word.chars().nth(1).is_some())
+        .filter(|word| !word.starts_with(|c: char| c.is_ascii_digit()))
+        .map(str::to_string)
+        .collect()
+}
+
+// Completion from the words in open buffers and from paths on disk, for when
+// no language server is available.
+#[frb(opaque)]
+#[derive(Default)]
+pub struct CompletionIndex {
+    documents: HashMap<String, Document>,
+}
+
+impl CompletionIndex {
+    // Bonus for candidates from the buffer being completed over those only in
+    // other buffers.
+    const SAME_DOCUMENT: i64 = 8;
+
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Starts indexing `buffer` as `document`, any key the caller uses for the
+    // buffer. `word_characters` are characters besides letters, digits and
+    // `_` that belong to words, as in `LanguageInfo`.
+    #[frb(sync)]
+    pub fn open(
+        &mut self,
+        document: String,
+        file_path: Option<String>,
+        buffer: &Buffer,
+        word_characters: String,
+    ) {
+        let mut indexed = Document {
+            file_path,
+            word_characters,
+            rows: Vec::new(),
+            counts: HashMap::new(),
+            version: buffer.version,
+        };
+        indexed.scan(buffer);
+        self.documents.insert(document, indexed);
+    }
+
+    // Brings the index for `document` up to date with `buffer`, rescanning
+    // only the rows edited since the last update.
+    #[frb(sync)]
+    pub fn update(&mut self, document: String, buffer: &Buffer) {
+        if let Some(indexed) = self.documents.get_mut(&document) {
+            indexed.update(buffer);
+        }
+    }
+
+    #[frb(sync)]
+    pub fn set_file_path(&mut self, document: String, file_path: Option<String>) {
+        if let Some(indexed) = self.documents.get_mut(&document) {
+            indexed.file_path = file_path;
+        }
+    }
+
+    #[frb(sync)]
+    pub fn close(&mut self, document: String) {
+        self.documents.remove(&document);
+    }
+
+    // Candidates for the text before `cursor`: file and directory names when
+    // it is inside a string that looks like a relative path, and otherwise
+    // words from every open buffer fuzzy-matching the word being typed.
+    #[frb(sync, type_64bit_int)]
+    pub fn complete(
+        &mut self,
+        document: String,
+        buffer: &Buffer,
+        cursor: Cursor,
+        limit: usize,
+    ) -> CompletionList {
+        self.update(document.clone(), buffer);
+
+        let line = buffer.line(cursor.row);
+        let column = floor_char_boundary(&line, cursor.column);
+        let before = &line[..column];
+
+        let file_path = self
+            .documents
+            .get(&document)
+            .and_then(|indexed| indexed.file_path.as_deref());
+        if let Some(list) = complete_path(before, file_path, limit) {
+            return list;
+        }
+
+        self.complete_word(&document, before, limit)
+    }
+
+    fn complete_word(&self, document: &str, before: &str, limit: usize) -> CompletionList {
+        let current = self.documents.get(document);
+        let is_word_char = |c: char| {
+            current.map_or(c.is_alphanumeric() || c == '_', |indexed| {
+                indexed.is_word_char(c)
+            })
+        };
+
+        let start_column = before
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_word_char(c))
+            .last()
+            .map_or(before.len(), |(i, _)| i);
+        let prefix = &before[start_column..];
+        if prefix.is_empty() {
+            return CompletionList {
+                start_column,
+                items: Vec::new(),
+            };
+        }
+
+        let mut candidates: HashMap<&str, (usize, bool)> = HashMap::new();
+        for (key, indexed) in &self.documents {
+            for (word, &count) in &indexed.counts {
+                let entry = candidates.entry(word).or_default();
+                entry.0 += count;
+                entry.1 |= key == document;
+            }
+        }
+
+        let mut items: Vec<CompletionItem> = candidates
+            .into_iter()
+            .filter(|(word, _)| *word != prefix)
+            .filter_map(|(word, (count, in_document))| {
+                let (score, matched_indices) = fuzzy_match(prefix, word)?;
+                // Matches must start at the word's first character.
+                if matched_indices.first() != Some(&0) {
+                    return None;
+                }
+
+                let bonus = if in_document { Self::SAME_DOCUMENT } else { 0 };
+                let frequency = (count as i64).min(10) / 2;
+                Some(CompletionItem {
+                    label: word.to_string(),
+                    insert_text: word.to_string(),
+                    kind: CompletionKind::Word,
+                    score: score + bonus + frequency,
+                    matched_indices,
+                })
+            })
+            .collect();
+        sort_and_truncate(&mut items, limit);
+
+        CompletionList {
+            start_column,
+            items,
+        }
+    }
+}
+
+// Completes the file name at the end of an unterminated string literal such
+// as `"./src/ma`, relative to the directory of `file_path`.
+fn complete_path(before: &str, file_path: Option<&str>, limit: usize) -> Option<CompletionList> {
+    let content_start = open_string_start(before)?;
+    let typed = &before[content_start..];
+    let looks_relative = typed.starts_with("./")
+        || typed.starts_with("../")
+        || (typed.contains('/') && !typed.starts_with('/') && !typed.contains("://"));
+    if !looks_relative {
+        return None;
+    }
+
+    let base = Path::new(file_path?).parent()?;
+    let slash = typed.rfind('/')?;
+    let (dir, name) = (&typed[..=slash], &typed[slash + 1..]);
+    let entries = fs::read_dir(base.join(dir)).ok()?;
+
+    let mut items: Vec<CompletionItem> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            // Hidden files only when asked for.
+            if file_name.starts_with('.') && !name.starts_with('.') {
+                return None;
+            }
+
+            let (score, matched_indices) = fuzzy_match(name, &file_name)?;
+            let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            Some(CompletionItem {
+                insert_text: if is_dir {
+                    format!("{file_name}/")
+                } else {
+                    file_name.clone()
+                },
+                label: file_name,
+                kind: if is_dir {
+                    CompletionKind::Directory
+                } else {
+                    CompletionKind::File
+                },
+                score,
+                matched_indices,
+            })
+        })
+        .collect();
+    sort_and_truncate(&mut items, limit);
+
+    Some(CompletionList {
+        start_column: content_start + slash + 1,
+        items,
+    })
+}
+
+// The byte offset just after the opening quote of a string still open at the
+// end of `before`, honouring backslash escapes.
+fn open_string_start(before: &str) -> Option<usize> {
+    let mut open: Option<(char, usize)> = None;
+    let mut escaped = false;
+
+    for (i, c) in before.char_indices() {
+        match open {
+            _ if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some((quote, _)) if c == quote => open = None,
+            None if matches!(c, '"' | '\'' | '`') => open = Some((c, i + 1)),
+            _ => {}
+        }
+    }
+
+    open.map(|(_, start)| start)
+}
+
+fn sort_and_truncate(items: &mut Vec<CompletionItem>, limit: usize) {
+    items.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then_with(|| a.label.len().cmp(&b.label.len()))
+            .then_with(|| a.label.cmp(&b.label))
+    });
+    items.truncate(limit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    fn document(buffer: &Buffer) -> Document {
+        let mut document = Document {
+            file_path: None,
+            word_characters: String::new(),
+            rows: Vec::new(),
+            counts: HashMap::new(),
+            version: buffer.version,
+        };
+        document.scan(buffer);
+        document
+    }
+
+    fn assert_up_to_date(indexed: &mut Document, buffer: &Buffer) {
+        indexed.update(buffer);
+        let fresh = document(buffer);
+        assert_eq!(indexed.counts, fresh.counts, "{:?}", buffer.to_string());
+        assert_eq!(indexed.rows, fresh.rows);
+    }
+
+    #[test]
+    fn updates_match_a_fresh_scan_after_multi_line_edits() {
+        let mut buffer = Buffer::from("alpha beta\ngamma\n\ndelta alpha\n".to_string());
+        let mut indexed = document(&buffer);
+
+        buffer.insert(1, 2, "x one\ntwo\nthree y".to_string());
+        assert_up_to_date(&mut indexed, &buffer);
+
+        buffer.replace_range(0, 6, 3, 2, String::new());
+        assert_up_to_date(&mut indexed, &buffer);
+
+        // Several edits between updates, each moving the rows of the last.
+        buffer.insert(0, 0, "first\nsecond\n".to_string());
+        buffer.replace_range(3, 0, 4, 0, "joined ".to_string());
+        buffer.insert(buffer.last_row(), 0, "tail\nend".to_string());
+        assert_up_to_date(&mut indexed, &buffer);
+    }
+
+    #[test]
+    fn updates_match_a_fresh_scan_after_random_edits() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(49);
+        let mut buffer = Buffer::from("fn main() {\n    let x = y;\n}\n".to_string());
+        let mut indexed = document(&buffer);
+        let pieces = ["", "\n", "word", "a b\nc d", "\n\nfoo\n", " bar_baz "];
+
+        for step in 0..300 {
+            let len = buffer.to_string().len();
+            let start = rng.random_range(0..=len);
+            let end = rng.random_range(start..=len.min(start + 15));
+            buffer.replace_bytes(start..end, pieces[rng.random_range(0..pieces.len())]);
+            if step % 3 == 0 {
+                assert_up_to_date(&mut indexed, &buffer);
+            }
+        }
+        assert_up_to_date(&mut indexed, &buffer);
+    }
+
+    #[test]
+    fn completes_other_words_but_not_the_one_being_typed() {
+        let buffer = Buffer::from("alphabet alpine\nalp".to_string());
+        let mut index = CompletionIndex::new();
+        index.open("a".to_string(), None, &buffer, String::new());
+
+        let list = index.complete_word("a", "alp", 10);
+        assert_eq!(list.start_column, 0);
+        let labels: Vec<_> = list.items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, ["alpine", "alphabet"]);
+    }
+}
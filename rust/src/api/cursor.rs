@@ -1,7 +1,8 @@
 use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
 
 #[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Cursor {
     pub row: usize,
     pub column: usize,
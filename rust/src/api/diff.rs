@@ -0,0 +1,249 @@
+use std::ops::Range;
+
+use super::buffer::TextEdit;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+// Line-level edits turning `old` into `new`. Rows are offset by `row_offset`
+// so a slice of a buffer can be diffed in place.
+pub(crate) fn line_edits(old: &str, new: &str, row_offset: usize) -> Vec<TextEdit> {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+
+    // Without a trailing newline the last line ends mid-row rather than at the
+    // start of the next one.
+    let position = |line: usize| match old_lines.last() {
+        Some(last) if line == old_lines.len() && !last.ends_with('\n') => {
+            (row_offset + line - 1, last.len())
+        }
+        _ => (row_offset + line, 0),
+    };
+
+    diff_lines(&old_lines, &new_lines)
+        .into_iter()
+        .map(|hunk| {
+            let (start_row, start_column) = position(hunk.old.start);
+            let (end_row, end_column) = position(hunk.old.end);
+
+            TextEdit {
+                start_row,
+                start_column,
+                end_row,
+                end_column,
+                text: new_lines[hunk.new].concat(),
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    myers(old_middle, new_middle)
+        .into_iter()
+        .map(|hunk| Hunk {
+            old: hunk.old.start + prefix..hunk.old.end + prefix,
+            new: hunk.new.start + prefix..hunk.new.end + prefix,
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+// Past this many inserted and deleted lines the diff gives up and replaces
+// the whole changed range. The search costs O((n + m) * D) time and keeps
+// O(D^2) of trace, which a change touching every line of a large file, such
+// as a re-indent, would otherwise blow up.
+const MAX_EDIT_DISTANCE: isize = 1024;
+
+fn myers(old: &[&str], new: &[&str]) -> Vec<Hunk> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m).min(MAX_EDIT_DISTANCE);
+    if n + m == 0 {
+        return Vec::new();
+    }
+
+    let offset = max as usize + 1;
+    let mut v = vec![0isize; 2 * offset + 1];
+    // Before step `d`, the furthest x reached on diagonals `-d..=d`, which is
+    // all the backtrack reads from that step.
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut found = false;
+
+    'search: for d in 0..=max {
+        trace.push(v[offset - d as usize..=offset + d as usize].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset as isize) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+
+    if !found {
+        return vec![Hunk {
+            old: 0..old.len(),
+            new: 0..new.len(),
+        }];
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, window) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| window[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { at(prev_k) };
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+
+        if d > 0 {
+            ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+        }
+
+        x = prev_x;
+        y = prev_y;
+    }
+
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut old_idx, mut new_idx) = (0, 0);
+    let mut in_hunk = false;
+
+    for op in ops.into_iter().rev() {
+        if op == Op::Equal {
+            old_idx += 1;
+            new_idx += 1;
+            in_hunk = false;
+            continue;
+        }
+
+        if !in_hunk {
+            hunks.push(Hunk {
+                old: old_idx..old_idx,
+                new: new_idx..new_idx,
+            });
+            in_hunk = true;
+        }
+
+        let hunk = hunks.last_mut().unwrap();
+        if op == Op::Delete {
+            old_idx += 1;
+            hunk.old.end = old_idx;
+        } else {
+            new_idx += 1;
+            hunk.new.end = new_idx;
+        }
+    }
+
+    hunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(old: &[&str], new: &[&str], hunks: &[Hunk]) -> Vec<String> {
+        let mut result = Vec::new();
+        let mut old_idx = 0;
+        for hunk in hunks {
+            result.extend(old[old_idx..hunk.old.start].iter().map(|s| s.to_string()));
+            result.extend(new[hunk.new.clone()].iter().map(|s| s.to_string()));
+            old_idx = hunk.old.end;
+        }
+        result.extend(old[old_idx..].iter().map(|s| s.to_string()));
+        result
+    }
+
+    #[test]
+    fn hunks_turn_old_into_new() {
+        let cases: &[(&str, &str)] = &[
+            ("", "a b"),
+            ("a b c", ""),
+            ("a b c d e", "a x c e f"),
+            ("a b a b a", "b a b a b"),
+            ("x y z", "x y z"),
+        ];
+        for (old, new) in cases {
+            let old: Vec<&str> = old.split_whitespace().collect();
+            let new: Vec<&str> = new.split_whitespace().collect();
+            assert_eq!(apply(&old, &new, &diff_lines(&old, &new)), new);
+        }
+
+        let old = ["a", "b", "c", "d"];
+        let new = ["a", "c", "d", "e"];
+        assert_eq!(
+            diff_lines(&old, &new),
+            vec![
+                Hunk {
+                    old: 1..2,
+                    new: 1..1
+                },
+                Hunk {
+                    old: 4..4,
+                    new: 3..4
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn large_rewrites_become_one_replacement() {
+        let old: Vec<String> = (0..10_000).map(|i| format!("line {i}\n")).collect();
+        let new: Vec<String> = old.iter().map(|line| format!("    {line}")).collect();
+        let old: Vec<&str> = old.iter().map(String::as_str).collect();
+        let new: Vec<&str> = new.iter().map(String::as_str).collect();
+
+        let hunks = diff_lines(&old, &new);
+        assert_eq!(
+            hunks,
+            vec![Hunk {
+                old: 0..10_000,
+                new: 0..10_000
+            }]
+        );
+    }
+}
@@ -0,0 +1,512 @@
+use flutter_rust_bridge::frb;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use super::encoding::Encoding;
+use super::save::SaveActions;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentStyle {
+    Space,
+    Tab,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+// How one level of indentation is written. `tab_width` is how wide a tab
+// displays, which is also how much indentation one tab removes.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Indentation {
+    pub style: IndentStyle,
+    pub size: usize,
+    pub tab_width: usize,
+}
+
+impl Default for Indentation {
+    #[frb(sync)]
+    fn default() -> Self {
+        Self {
+            style: IndentStyle::Space,
+            size: 4,
+            tab_width: 4,
+        }
+    }
+}
+
+impl Indentation {
+    #[frb(sync)]
+    pub fn unit(&self) -> String {
+        match self.style {
+            IndentStyle::Space => " ".repeat(self.size.max(1)),
+            IndentStyle::Tab => "\t".to_string(),
+        }
+    }
+}
+
+// Properties from the `.editorconfig` files that apply to a path. Unset
+// properties leave the editor's own settings in place.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EditorConfigProperties {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub end_of_line: Option<LineEnding>,
+    pub charset: Option<String>,
+    pub trim_trailing_whitespace: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+}
+
+impl EditorConfigProperties {
+    // `fallback` with the properties that are set applied over it.
+    #[frb(sync)]
+    pub fn indentation(&self, fallback: Indentation) -> Indentation {
+        let style = self.indent_style.unwrap_or(fallback.style);
+        // Per the spec, `indent_size` and `tab_width` default to each other.
+        let tab_width = self
+            .tab_width
+            .or(self.indent_size)
+            .unwrap_or(fallback.tab_width);
+        let size = match (self.indent_size, style) {
+            (Some(size), _) => size,
+            (None, IndentStyle::Tab) => tab_width,
+            (None, IndentStyle::Space) => fallback.size,
+        };
+
+        Indentation {
+            style,
+            size,
+            tab_width,
+        }
+    }
+
+    // The encoding to open and save the file with, when `charset` names one.
+    #[frb(sync)]
+    pub fn encoding(&self) -> Option<Encoding> {
+        Encoding::from_charset(self.charset.clone()?)
+    }
+
+    #[frb(sync)]
+    pub fn save_actions(&self, fallback: SaveActions) -> SaveActions {
+        SaveActions {
+            trim_trailing_whitespace: self
+                .trim_trailing_whitespace
+                .unwrap_or(fallback.trim_trailing_whitespace),
+            ensure_final_newline: self
+                .insert_final_newline
+                .unwrap_or(fallback.ensure_final_newline),
+            line_ending: self.end_of_line.or(fallback.line_ending),
+            byte_order_mark: match self.charset.as_deref() {
+                Some("utf-8-bom") => Some(true),
+                Some("utf-8" | "latin1") => Some(false),
+                _ => fallback.byte_order_mark,
+            },
+            ..fallback
+        }
+    }
+}
+
+// Resolves the properties for `path` from the `.editorconfig` files in its
+// directory and each parent, stopping after one that declares `root = true`.
+// Closer files take precedence, as do later sections within a file.
+pub fn resolve_editorconfig(path: String) -> anyhow::Result<EditorConfigProperties> {
+    // Sections match against the full path, so a relative one is resolved
+    // against the working directory first.
+    let path = std::path::absolute(&path)?;
+    let path = path.as_path();
+    let target = normalize(path);
+
+    let mut files = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let config = dir.join(".editorconfig");
+        if !config.is_file() {
+            continue;
+        }
+
+        let file = ConfigFile::parse(&fs::read_to_string(&config)?);
+        let root = file.root;
+        files.push((normalize(dir), file));
+        if root {
+            break;
+        }
+    }
+
+    let mut values: HashMap<String, String> = HashMap::new();
+    for (dir, file) in files.iter().rev() {
+        for section in &file.sections {
+            if !section_matches(&section.glob, dir, &target) {
+                continue;
+            }
+            for (key, value) in &section.properties {
+                values.insert(key.clone(), value.clone());
+            }
+        }
+    }
+
+    Ok(properties(&values))
+}
+
+struct Section {
+    glob: String,
+    properties: Vec<(String, String)>,
+}
+
+struct ConfigFile {
+    root: bool,
+    sections: Vec<Section>,
+}
+
+impl ConfigFile {
+    fn parse(contents: &str) -> Self {
+        let mut file = ConfigFile {
+            root: false,
+            sections: Vec::new(),
+        };
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                file.sections.push(Section {
+                    glob: glob.to_string(),
+                    properties: Vec::new(),
+                });
+                continue;
+            }
+
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim().to_string();
+
+            match file.sections.last_mut() {
+                Some(section) => section.properties.push((key, value)),
+                None if key == "root" => file.root = value.eq_ignore_ascii_case("true"),
+                None => {}
+            }
+        }
+
+        file
+    }
+}
+
+fn properties(values: &HashMap<String, String>) -> EditorConfigProperties {
+    // Values are case-insensitive, and `unset` clears a property.
+    let get = |key: &str| {
+        values
+            .get(key)
+            .map(|value| value.to_lowercase())
+            .filter(|value| value != "unset")
+    };
+    let number = |key: &str| get(key).and_then(|value| value.parse::<usize>().ok());
+    let flag = |key: &str| match get(key)?.as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    };
+
+    let tab_width = number("tab_width");
+    let indent_size = match get("indent_size").as_deref() {
+        Some("tab") => tab_width,
+        _ => number("indent_size"),
+    };
+
+    EditorConfigProperties {
+        indent_style: match get("indent_style").as_deref() {
+            Some("space") => Some(IndentStyle::Space),
+            Some("tab") => Some(IndentStyle::Tab),
+            _ => None,
+        },
+        indent_size,
+        tab_width,
+        end_of_line: match get("end_of_line").as_deref() {
+            Some("lf") => Some(LineEnding::Lf),
+            Some("crlf") => Some(LineEnding::Crlf),
+            Some("cr") => Some(LineEnding::Cr),
+            _ => None,
+        },
+        charset: get("charset"),
+        trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+        insert_final_newline: flag("insert_final_newline"),
+    }
+}
+
+fn normalize(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+// Globs without a `/` match file names at any depth below the file's
+// directory; others match paths relative to it.
+fn section_matches(glob: &str, dir: &str, target: &str) -> bool {
+    let Some(relative) = target.strip_prefix(dir).and_then(|r| r.strip_prefix('/')) else {
+        return false;
+    };
+
+    let (glob, anchored) = match glob.strip_prefix('/') {
+        Some(glob) => (glob, true),
+        None => (glob, glob.contains('/')),
+    };
+    let prefix = if anchored { "^" } else { "^(?:.*/)?" };
+
+    let mut ranges = Vec::new();
+    let regex = format!("{prefix}{}$", glob_to_regex(glob, &mut ranges));
+    let Ok(regex) = Regex::new(&regex) else {
+        return false;
+    };
+    let Some(captures) = regex.captures(relative) else {
+        return false;
+    };
+
+    // Each numeric range is the capture group of the same index, and only
+    // matches when the number it captured is inside the range.
+    ranges.iter().enumerate().all(|(idx, (low, high))| {
+        captures.get(idx + 1).is_none_or(|number| {
+            number
+                .as_str()
+                .parse::<i64>()
+                .is_ok_and(|number| (*low..=*high).contains(&number))
+        })
+    })
+}
+
+// Translates EditorConfig glob syntax: `*`, `**`, `?`, `[...]`, `[!...]`,
+// `{a,b}` and numeric ranges `{1..3}`. A numeric range becomes a capture group
+// matching any integer, and its bounds are pushed onto `ranges` to be checked
+// after matching.
+fn glob_to_regex(glob: &str, ranges: &mut Vec<(i64, i64)>) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                regex.push_str(".*");
+                i += 1;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(len) if len > 0 => {
+                    let class: String = chars[i + 1..i + 1 + len].iter().collect();
+                    let class = match class.strip_prefix('!') {
+                        Some(rest) => format!("^{rest}"),
+                        None => class,
+                    };
+                    regex.push('[');
+                    regex.push_str(&class.replace('\\', "\\\\").replace('[', "\\["));
+                    regex.push(']');
+                    i += len + 1;
+                }
+                _ => regex.push_str("\\["),
+            },
+            '{' => match closing_brace(&chars, i) {
+                Some(end) => {
+                    let inner: String = chars[i + 1..end].iter().collect();
+                    regex.push_str(&brace_to_regex(&inner, ranges));
+                    i = end;
+                }
+                None => regex.push_str("\\{"),
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+
+    regex
+}
+
+fn closing_brace(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, &c) in chars.iter().enumerate().skip(open) {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+fn brace_to_regex(inner: &str, ranges: &mut Vec<(i64, i64)>) -> String {
+    if let Some((start, end)) = inner.split_once("..") {
+        if let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) {
+            ranges.push((start.min(end), start.max(end)));
+            return "([+-]?\\d+)".to_string();
+        }
+    }
+
+    let alternatives = split_alternatives(inner);
+    if alternatives.len() < 2 {
+        return format!("\\{{{}\\}}", glob_to_regex(inner, ranges));
+    }
+
+    let alternatives: Vec<String> = alternatives
+        .iter()
+        .map(|a| glob_to_regex(a, ranges))
+        .collect();
+    format!("(?:{})", alternatives.join("|"))
+}
+
+// Splits on commas that are not inside nested braces.
+fn split_alternatives(inner: &str) -> Vec<String> {
+    let mut alternatives = vec![String::new()];
+    let mut depth = 0;
+
+    for c in inner.chars() {
+        match c {
+            ',' if depth == 0 => {
+                alternatives.push(String::new());
+                continue;
+            }
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        alternatives.last_mut().unwrap().push(c);
+    }
+
+    alternatives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::buffer::Buffer;
+    use crate::api::large_file::LargeFileThresholds;
+    use crate::api::save::SavePipeline;
+
+    #[test]
+    fn numeric_ranges_compare_the_number() {
+        assert!(section_matches("file{1..3}.txt", "/p", "/p/file2.txt"));
+        assert!(section_matches("file{3..1}.txt", "/p", "/p/file3.txt"));
+        assert!(!section_matches("file{1..3}.txt", "/p", "/p/file4.txt"));
+        assert!(section_matches("v{-5..5}", "/p", "/p/v-2"));
+        assert!(!section_matches("v{-5..5}", "/p", "/p/vx"));
+
+        // Wide ranges are not expanded, so they match as quickly.
+        assert!(section_matches("f{0..20000000}", "/p", "/p/f19999999"));
+        assert!(!section_matches("f{0..20000000}", "/p", "/p/f20000001"));
+        assert!(!section_matches(
+            "f{0..20000000}",
+            "/p",
+            "/p/f99999999999999999999"
+        ));
+    }
+
+    #[test]
+    fn charset_is_used_to_open_and_save() {
+        let dir =
+            std::env::temp_dir().join(format!("rei-editorconfig-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(".editorconfig"),
+            "root = true\n[*.txt]\ncharset = latin1\n",
+        )
+        .unwrap();
+        let path = dir.join("notes.txt");
+        fs::write(&path, b"caf\xe9\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let properties = resolve_editorconfig(path.clone()).unwrap();
+        let encoding = properties.encoding().unwrap();
+        assert_eq!(encoding, Encoding::Latin1);
+
+        let mut buffer =
+            Buffer::open(path.clone(), LargeFileThresholds::default(), encoding).unwrap();
+        assert_eq!(buffer.to_string(), "caf\u{e9}\n");
+        assert!(!buffer.is_lossy());
+
+        let pipeline = SavePipeline::new(properties.save_actions(SaveActions::default()));
+        buffer.save(path.clone(), &pipeline).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_working_directory() {
+        let dir = std::env::current_dir()
+            .unwrap()
+            .join(format!("rei-editorconfig-relative-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(".editorconfig"),
+            "root = true\n[*.rs]\nindent_size = 2\n",
+        )
+        .unwrap();
+
+        let relative = Path::new(dir.file_name().unwrap()).join("main.rs");
+        let properties = resolve_editorconfig(relative.to_string_lossy().into_owned());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(properties.unwrap().indent_size, Some(2));
+    }
+
+    #[test]
+    fn indent_size_falls_back_to_tab_width_only_for_tabs() {
+        let fallback = Indentation {
+            style: IndentStyle::Space,
+            size: 4,
+            tab_width: 8,
+        };
+        let spaces = EditorConfigProperties {
+            indent_style: Some(IndentStyle::Space),
+            tab_width: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(spaces.indentation(fallback).size, 4);
+        assert_eq!(spaces.indentation(fallback).tab_width, 3);
+
+        let tabs = EditorConfigProperties {
+            indent_style: Some(IndentStyle::Tab),
+            tab_width: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(tabs.indentation(fallback).size, 3);
+
+        // `indent_size = tab` takes the tab width, and `tab_width` defaults
+        // to a numeric `indent_size`.
+        let values = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+        let tab_sized = properties(&values(&[("indent_size", "tab"), ("tab_width", "6")]));
+        assert_eq!(tab_sized.indentation(fallback).size, 6);
+        let sized = properties(&values(&[("indent_size", "2")]));
+        assert_eq!(sized.indentation(fallback).tab_width, 2);
+    }
+}
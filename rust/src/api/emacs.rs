@@ -0,0 +1,839 @@
+use crop::Rope;
+use flutter_rust_bridge::frb;
+use std::collections::VecDeque;
+
+use super::anchor::{Anchor, Bias};
+use super::buffer::Buffer;
+use super::cursor::Cursor;
+use super::selection::Selection;
+use super::text::repeat_motion;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EmacsCommand {
+    ForwardChar,
+    BackwardChar,
+    NextLine,
+    PreviousLine,
+    BeginningOfLine,
+    EndOfLine,
+    ForwardWord,
+    BackwardWord,
+    BeginningOfBuffer,
+    EndOfBuffer,
+    SetMark,
+    ExchangePointAndMark,
+    MarkWholeBuffer,
+    KeyboardQuit,
+    DeleteChar,
+    DeleteBackwardChar,
+    KillLine,
+    KillWord,
+    BackwardKillWord,
+    KillRegion,
+    CopyRegion,
+    Yank,
+    YankPop,
+    TransposeChars,
+    TransposeWords,
+    TransposeLines,
+    Newline,
+    OpenLine,
+    Tab,
+    SelfInsert(char),
+}
+
+// Key sequences in `kbd` notation, with prefix keys such as `C-x` implied by
+// the longer sequences that start with them.
+const BINDINGS: &[(&str, EmacsCommand)] = &[
+    ("C-f", EmacsCommand::ForwardChar),
+    ("<right>", EmacsCommand::ForwardChar),
+    ("C-b", EmacsCommand::BackwardChar),
+    ("<left>", EmacsCommand::BackwardChar),
+    ("C-n", EmacsCommand::NextLine),
+    ("<down>", EmacsCommand::NextLine),
+    ("C-p", EmacsCommand::PreviousLine),
+    ("<up>", EmacsCommand::PreviousLine),
+    ("C-a", EmacsCommand::BeginningOfLine),
+    ("<home>", EmacsCommand::BeginningOfLine),
+    ("C-e", EmacsCommand::EndOfLine),
+    ("<end>", EmacsCommand::EndOfLine),
+    ("M-f", EmacsCommand::ForwardWord),
+    ("M-b", EmacsCommand::BackwardWord),
+    ("M-<", EmacsCommand::BeginningOfBuffer),
+    ("M->", EmacsCommand::EndOfBuffer),
+    ("C-SPC", EmacsCommand::SetMark),
+    ("C-@", EmacsCommand::SetMark),
+    ("C-x C-x", EmacsCommand::ExchangePointAndMark),
+    ("C-x h", EmacsCommand::MarkWholeBuffer),
+    ("C-g", EmacsCommand::KeyboardQuit),
+    ("C-d", EmacsCommand::DeleteChar),
+    ("<delete>", EmacsCommand::DeleteChar),
+    ("DEL", EmacsCommand::DeleteBackwardChar),
+    ("C-k", EmacsCommand::KillLine),
+    ("M-d", EmacsCommand::KillWord),
+    ("M-DEL", EmacsCommand::BackwardKillWord),
+    ("C-w", EmacsCommand::KillRegion),
+    ("M-w", EmacsCommand::CopyRegion),
+    ("C-y", EmacsCommand::Yank),
+    ("M-y", EmacsCommand::YankPop),
+    ("C-t", EmacsCommand::TransposeChars),
+    ("M-t", EmacsCommand::TransposeWords),
+    ("C-x C-t", EmacsCommand::TransposeLines),
+    ("RET", EmacsCommand::Newline),
+    ("C-m", EmacsCommand::Newline),
+    ("C-o", EmacsCommand::OpenLine),
+    ("TAB", EmacsCommand::Tab),
+];
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone)]
+pub struct EmacsOutcome {
+    pub cursor: Cursor,
+    // The region between mark and point while the mark is active.
+    pub selection: Selection,
+    pub mark_active: bool,
+    // The prefix typed so far, such as `C-x`, for the echo area.
+    pub pending_keys: String,
+    // Set when the last sequence fed had no binding, like emacs's
+    // "C-x C-q is undefined".
+    pub undefined_keys: Option<String>,
+}
+
+// Killed text, most recent first. Consecutive kills are merged into one
+// entry, as emacs does for repeated `C-k`.
+#[frb(opaque)]
+pub struct KillRing {
+    entries: VecDeque<String>,
+    capacity: usize,
+}
+
+impl KillRing {
+    #[frb(sync, type_64bit_int)]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    // Adds text killed outside the editor, such as the system clipboard.
+    #[frb(sync)]
+    pub fn push(&mut self, text: String) {
+        if text.is_empty() {
+            return;
+        }
+
+        self.entries.push_front(text);
+        self.entries.truncate(self.capacity);
+    }
+
+    #[frb(sync)]
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.iter().cloned().collect()
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn get(&self, index: usize) -> Option<String> {
+        self.entries.get(index % self.entries.len().max(1)).cloned()
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[frb(sync)]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn append(&mut self, text: &str, backward: bool) {
+        match self.entries.front_mut() {
+            Some(front) if backward => front.insert_str(0, text),
+            Some(front) => front.push_str(text),
+            None => self.push(text.to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LastCommand {
+    Other,
+    Kill,
+    // The yanked text spans `start..end`, taken from kill ring `index`.
+    Yank {
+        start: usize,
+        end: usize,
+        index: usize,
+    },
+}
+
+// Emacs-style editing for one buffer. The mark is kept as an anchor in that
+// buffer, so it follows edits made before it.
+#[frb(opaque)]
+pub struct EmacsState {
+    kill_ring: KillRing,
+    mark: Option<Anchor>,
+    mark_active: bool,
+    pending: Vec<String>,
+    // `C-u` multiplies the argument by four; digits typed after it replace it.
+    argument: Option<usize>,
+    argument_digits: Option<usize>,
+    last_command: LastCommand,
+}
+
+impl EmacsState {
+    const KILL_RING_CAPACITY: usize = 120;
+    // The largest prefix argument, so repeated `C-u` or long digit strings
+    // cannot overflow and inserted repeats stay bounded.
+    const MAX_ARGUMENT: usize = 1 << 20;
+
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            kill_ring: KillRing::new(Self::KILL_RING_CAPACITY),
+            mark: None,
+            mark_active: false,
+            pending: Vec::new(),
+            argument: None,
+            argument_digits: None,
+            last_command: LastCommand::Other,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn kill_ring(&self) -> Vec<String> {
+        self.kill_ring.entries()
+    }
+
+    #[frb(sync)]
+    pub fn push_kill(&mut self, text: String) {
+        self.kill_ring.push(text);
+        self.last_command = LastCommand::Other;
+    }
+
+    // Feeds keys in `kbd` notation, e.g. `C-a C-k C-k` or `C-x C-t`. Words
+    // that are not key names are typed character by character.
+    #[frb(sync)]
+    pub fn feed(&mut self, buffer: &mut Buffer, cursor: Cursor, keys: String) -> EmacsOutcome {
+        let mut cursor = cursor;
+        let mut undefined_keys = None;
+
+        for key in parse_keys(&keys) {
+            match self.handle_key(buffer, cursor, key) {
+                Ok(next) => cursor = next,
+                Err(sequence) => undefined_keys = Some(sequence),
+            }
+        }
+
+        let mark = self
+            .mark
+            .and_then(|mark| buffer.resolve_anchor(mark))
+            .map(|(row, column)| Cursor::new(row, column, column));
+        let selection = match mark {
+            Some(mark) if self.mark_active => Selection::new(mark, cursor),
+            _ => Selection::default(),
+        };
+
+        EmacsOutcome {
+            cursor,
+            selection,
+            mark_active: self.mark_active,
+            pending_keys: self.pending.join(" "),
+            undefined_keys,
+        }
+    }
+
+    fn handle_key(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        key: String,
+    ) -> Result<Cursor, String> {
+        if self.pending.is_empty() {
+            if key == "C-u" {
+                let argument = self.argument.unwrap_or(1).saturating_mul(4);
+                self.argument = Some(argument.min(Self::MAX_ARGUMENT));
+                self.argument_digits = None;
+                return Ok(cursor);
+            }
+
+            // `M-<digit>` starts an argument; plain digits only continue one.
+            let digit = key
+                .strip_prefix("M-")
+                .or(self.argument.is_some().then_some(key.as_str()))
+                .and_then(|digit| digit.parse::<usize>().ok())
+                .filter(|&digit| digit < 10);
+            if let Some(digit) = digit {
+                self.argument.get_or_insert(1);
+                let digits = self
+                    .argument_digits
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit);
+                self.argument_digits = Some(digits.min(Self::MAX_ARGUMENT));
+                return Ok(cursor);
+            }
+        }
+
+        self.pending.push(key);
+        let sequence = self.pending.join(" ");
+
+        let command = match lookup(&sequence) {
+            Lookup::Command(command) => command,
+            Lookup::Prefix => return Ok(cursor),
+            Lookup::Undefined => {
+                self.pending.clear();
+                self.argument = None;
+                self.argument_digits = None;
+                return Err(sequence);
+            }
+        };
+
+        let count = self.argument_digits.or(self.argument);
+        self.pending.clear();
+        self.argument = None;
+        self.argument_digits = None;
+
+        Ok(self.execute(buffer, cursor, command, count))
+    }
+
+    fn execute(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        command: EmacsCommand,
+        count: Option<usize>,
+    ) -> Cursor {
+        let times = count.unwrap_or(1);
+        let last_command = std::mem::replace(&mut self.last_command, LastCommand::Other);
+        let point = offset_of(buffer, cursor);
+        let text = buffer.rope().clone();
+
+        match command {
+            EmacsCommand::ForwardChar => {
+                let offset = repeat_motion(point, times, |offset| next_char(&text, offset));
+                cursor_at(buffer, offset)
+            }
+            EmacsCommand::BackwardChar => {
+                let offset = repeat_motion(point, times, |offset| previous_char(&text, offset));
+                cursor_at(buffer, offset)
+            }
+            EmacsCommand::NextLine | EmacsCommand::PreviousLine => {
+                let row = if command == EmacsCommand::NextLine {
+                    cursor.row.saturating_add(times).min(buffer.last_row())
+                } else {
+                    cursor.row.saturating_sub(times)
+                };
+                let line = buffer.line(row);
+                let mut column = cursor.sticky_column.min(line.len());
+                while !line.is_char_boundary(column) {
+                    column -= 1;
+                }
+                Cursor::new(row, column, cursor.sticky_column)
+            }
+            EmacsCommand::BeginningOfLine => Cursor::new(cursor.row, 0, 0),
+            EmacsCommand::EndOfLine => {
+                let column = buffer.line(cursor.row).len();
+                Cursor::new(cursor.row, column, usize::MAX)
+            }
+            EmacsCommand::ForwardWord => {
+                let offset = repeat_motion(point, times, |offset| forward_word(&text, offset));
+                cursor_at(buffer, offset)
+            }
+            EmacsCommand::BackwardWord => {
+                let offset = repeat_motion(point, times, |offset| backward_word(&text, offset));
+                cursor_at(buffer, offset)
+            }
+            EmacsCommand::BeginningOfBuffer | EmacsCommand::EndOfBuffer => {
+                if !self.mark_active {
+                    self.set_mark(buffer, point, false);
+                }
+                let offset = if command == EmacsCommand::BeginningOfBuffer {
+                    0
+                } else {
+                    text.byte_len()
+                };
+                cursor_at(buffer, offset)
+            }
+            EmacsCommand::SetMark => {
+                self.set_mark(buffer, point, true);
+                cursor
+            }
+            EmacsCommand::ExchangePointAndMark => match self.mark_offset(buffer) {
+                Some(mark) => {
+                    self.set_mark(buffer, point, true);
+                    cursor_at(buffer, mark)
+                }
+                None => cursor,
+            },
+            EmacsCommand::MarkWholeBuffer => {
+                self.set_mark(buffer, text.byte_len(), true);
+                cursor_at(buffer, 0)
+            }
+            EmacsCommand::KeyboardQuit => {
+                self.mark_active = false;
+                cursor
+            }
+            EmacsCommand::DeleteChar => {
+                let end = repeat_motion(point, times, |offset| next_char(&text, offset));
+                self.delete(buffer, point..end)
+            }
+            EmacsCommand::DeleteBackwardChar => {
+                let start = repeat_motion(point, times, |offset| previous_char(&text, offset));
+                self.delete(buffer, start..point)
+            }
+            EmacsCommand::KillLine => {
+                // With an argument, kill that many whole lines including
+                // their line breaks; otherwise up to the end of the line, or
+                // the line break itself at the end of a line.
+                let end = match count {
+                    Some(lines) => {
+                        let row = cursor.row.saturating_add(lines);
+                        if row > buffer.last_row() {
+                            text.byte_len()
+                        } else {
+                            buffer.byte_of_line(row)
+                        }
+                    }
+                    None => {
+                        let line = buffer.line(cursor.row);
+                        let line_end = buffer.byte_of_line(cursor.row) + line.len();
+                        if point < line_end && !line[cursor.column..].trim().is_empty() {
+                            line_end
+                        } else {
+                            next_char(&text, line_end.max(point))
+                        }
+                    }
+                };
+                self.kill(buffer, point..end, false, last_command)
+            }
+            EmacsCommand::KillWord => {
+                let end = repeat_motion(point, times, |offset| forward_word(&text, offset));
+                self.kill(buffer, point..end, false, last_command)
+            }
+            EmacsCommand::BackwardKillWord => {
+                let start = repeat_motion(point, times, |offset| backward_word(&text, offset));
+                self.kill(buffer, start..point, true, last_command)
+            }
+            EmacsCommand::KillRegion => match self.mark_offset(buffer) {
+                Some(mark) => {
+                    let range = mark.min(point)..mark.max(point);
+                    self.kill(buffer, range, mark > point, last_command)
+                }
+                None => cursor,
+            },
+            EmacsCommand::CopyRegion => {
+                if let Some(mark) = self.mark_offset(buffer) {
+                    let range = mark.min(point)..mark.max(point);
+                    self.kill_ring.push(text.byte_slice(range).to_string());
+                    self.mark_active = false;
+                }
+                cursor
+            }
+            EmacsCommand::Yank => {
+                let index = count.map_or(0, |count| count.saturating_sub(1));
+                let Some(yanked) = self.kill_ring.get(index) else {
+                    return cursor;
+                };
+
+                self.set_mark(buffer, point, false);
+                let (row, column) = buffer.replace_bytes(point..point, &yanked);
+                self.last_command = LastCommand::Yank {
+                    start: point,
+                    end: point + yanked.len(),
+                    index,
+                };
+                Cursor::new(row, column, column)
+            }
+            EmacsCommand::YankPop => {
+                let LastCommand::Yank { start, end, index } = last_command else {
+                    return cursor;
+                };
+
+                let index = (index + times) % self.kill_ring.len().max(1);
+                let Some(yanked) = self.kill_ring.get(index) else {
+                    return cursor;
+                };
+
+                let (row, column) = buffer.replace_bytes(start..end, &yanked);
+                self.last_command = LastCommand::Yank {
+                    start,
+                    end: start + yanked.len(),
+                    index,
+                };
+                Cursor::new(row, column, column)
+            }
+            EmacsCommand::TransposeChars => self.transpose_chars(buffer, cursor, point),
+            EmacsCommand::TransposeWords => {
+                let end2 = forward_word(&text, point);
+                let start2 = backward_word(&text, end2);
+                let start1 = backward_word(&text, start2);
+                let end1 = forward_word(&text, start1);
+                if start1 == start2 || end1 > start2 {
+                    return cursor;
+                }
+
+                self.mark_active = false;
+                let swapped = format!(
+                    "{}{}{}",
+                    text.byte_slice(start2..end2),
+                    text.byte_slice(end1..start2),
+                    text.byte_slice(start1..end1)
+                );
+                let (row, column) = buffer.replace_bytes(start1..end2, &swapped);
+                Cursor::new(row, column, column)
+            }
+            EmacsCommand::TransposeLines => {
+                if cursor.row == 0 || cursor.row > buffer.last_row() {
+                    return cursor;
+                }
+
+                self.mark_active = false;
+                buffer.move_lines_up(cursor, Selection::default());
+                let row = (cursor.row + 1).min(buffer.last_row());
+                Cursor::new(row, 0, 0)
+            }
+            EmacsCommand::Newline => self.insert(buffer, point, &"\n".repeat(times)),
+            EmacsCommand::OpenLine => {
+                self.insert(buffer, point, &"\n".repeat(times));
+                cursor
+            }
+            EmacsCommand::Tab => self.insert(buffer, point, "\t"),
+            EmacsCommand::SelfInsert(c) => self.insert(buffer, point, &c.to_string().repeat(times)),
+        }
+    }
+
+    fn set_mark(&mut self, buffer: &mut Buffer, offset: usize, active: bool) {
+        if let Some(mark) = self.mark.take() {
+            buffer.drop_anchor(mark);
+        }
+
+        let cursor = cursor_at(buffer, offset);
+        self.mark = Some(buffer.create_anchor(cursor.row, cursor.column, Bias::Left));
+        self.mark_active = active;
+    }
+
+    fn mark_offset(&self, buffer: &Buffer) -> Option<usize> {
+        let (row, column) = buffer.resolve_anchor(self.mark?)?;
+        Some(buffer.row_column_to_idx(row, column))
+    }
+
+    fn insert(&mut self, buffer: &mut Buffer, offset: usize, text: &str) -> Cursor {
+        self.mark_active = false;
+        let (row, column) = buffer.replace_bytes(offset..offset, text);
+        Cursor::new(row, column, column)
+    }
+
+    fn delete(&mut self, buffer: &mut Buffer, range: std::ops::Range<usize>) -> Cursor {
+        self.mark_active = false;
+        let start = range.start;
+        buffer.replace_bytes(range, "");
+        cursor_at(buffer, start)
+    }
+
+    // Kills `range`, merging with the previous kill when the last command
+    // was also a kill. Backward kills are prepended.
+    fn kill(
+        &mut self,
+        buffer: &mut Buffer,
+        range: std::ops::Range<usize>,
+        backward: bool,
+        last_command: LastCommand,
+    ) -> Cursor {
+        let killed = buffer.rope().byte_slice(range.clone()).to_string();
+        if last_command == LastCommand::Kill {
+            self.kill_ring.append(&killed, backward);
+        } else {
+            self.kill_ring.push(killed);
+        }
+
+        let cursor = self.delete(buffer, range);
+        self.last_command = LastCommand::Kill;
+        cursor
+    }
+
+    // Swaps the characters around point and moves past them. At the end of
+    // a line, the two characters before point are swapped instead.
+    fn transpose_chars(&mut self, buffer: &mut Buffer, cursor: Cursor, point: usize) -> Cursor {
+        let text = buffer.rope();
+        let line_end = buffer.byte_of_line(cursor.row) + buffer.line(cursor.row).len();
+
+        let middle = if point == line_end {
+            previous_char(text, point)
+        } else {
+            point
+        };
+        let start = previous_char(text, middle);
+        let end = next_char(text, middle);
+        if start == middle || middle == end {
+            return cursor;
+        }
+
+        let swapped = format!(
+            "{}{}",
+            text.byte_slice(middle..end),
+            text.byte_slice(start..middle)
+        );
+
+        self.mark_active = false;
+        let (row, column) = buffer.replace_bytes(start..end, &swapped);
+        Cursor::new(row, column, column)
+    }
+}
+
+impl Default for EmacsState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+enum Lookup {
+    Command(EmacsCommand),
+    Prefix,
+    Undefined,
+}
+
+fn lookup(sequence: &str) -> Lookup {
+    if let Some(&(_, command)) = BINDINGS.iter().find(|(keys, _)| *keys == sequence) {
+        return Lookup::Command(command);
+    }
+
+    let prefix = format!("{sequence} ");
+    if BINDINGS.iter().any(|(keys, _)| keys.starts_with(&prefix)) {
+        return Lookup::Prefix;
+    }
+
+    let mut chars = sequence.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if !c.is_control() => Lookup::Command(EmacsCommand::SelfInsert(c)),
+        _ if sequence == "SPC" => Lookup::Command(EmacsCommand::SelfInsert(' ')),
+        _ => Lookup::Undefined,
+    }
+}
+
+// Splits `kbd` notation into keys. Modifier prefixes are normalized to `C-`
+// then `M-`, `<backspace>` and `<return>` are the same keys as `DEL` and `RET`,
+// and other words are typed one character at a time.
+fn parse_keys(keys: &str) -> Vec<String> {
+    const NAMES: &[&str] = &[
+        "RET", "SPC", "TAB", "DEL", "ESC", "<left>", "<right>", "<up>", "<down>", "<home>",
+        "<end>", "<delete>",
+    ];
+
+    let mut parsed = Vec::new();
+    for word in keys.split_whitespace() {
+        let (mut ctrl, mut meta) = (false, false);
+        let mut rest = word;
+        loop {
+            if let Some(stripped) = rest.strip_prefix("C-").filter(|s| !s.is_empty()) {
+                ctrl = true;
+                rest = stripped;
+            } else if let Some(stripped) = rest.strip_prefix("M-").filter(|s| !s.is_empty()) {
+                meta = true;
+                rest = stripped;
+            } else {
+                break;
+            }
+        }
+
+        let rest = match rest {
+            "<backspace>" => "DEL",
+            "<return>" => "RET",
+            "<tab>" => "TAB",
+            rest => rest,
+        };
+
+        if !ctrl && !meta && rest.chars().count() > 1 && !NAMES.contains(&rest) {
+            parsed.extend(rest.chars().map(String::from));
+            continue;
+        }
+
+        let mut key = String::new();
+        if ctrl {
+            key.push_str("C-");
+        }
+        if meta {
+            key.push_str("M-");
+        }
+        key.push_str(rest);
+        parsed.push(key);
+    }
+
+    parsed
+}
+
+fn offset_of(buffer: &Buffer, cursor: Cursor) -> usize {
+    buffer
+        .row_column_to_idx(cursor.row, cursor.column)
+        .min(buffer.rope().byte_len())
+}
+
+fn cursor_at(buffer: &Buffer, offset: usize) -> Cursor {
+    let (row, column) = buffer.idx_to_row_column(offset);
+    Cursor::new(row, column, column)
+}
+
+fn next_char(text: &Rope, offset: usize) -> usize {
+    if offset >= text.byte_len() {
+        return text.byte_len();
+    }
+
+    text.byte_slice(offset..)
+        .chars()
+        .next()
+        .map_or(offset, |c| offset + c.len_utf8())
+}
+
+fn previous_char(text: &Rope, offset: usize) -> usize {
+    text.byte_slice(..offset)
+        .chars()
+        .next_back()
+        .map_or(offset, |c| offset - c.len_utf8())
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+fn forward_word(text: &Rope, offset: usize) -> usize {
+    let mut offset = offset;
+    let mut seen_word = false;
+
+    for c in text.byte_slice(offset..).chars() {
+        if is_word_char(c) {
+            seen_word = true;
+        } else if seen_word {
+            break;
+        }
+        offset += c.len_utf8();
+    }
+
+    offset
+}
+
+fn backward_word(text: &Rope, offset: usize) -> usize {
+    let mut offset = offset;
+    let mut seen_word = false;
+
+    for c in text.byte_slice(..offset).chars().rev() {
+        if is_word_char(c) {
+            seen_word = true;
+        } else if seen_word {
+            break;
+        }
+        offset -= c.len_utf8();
+    }
+
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `keys` over `text` with point at `at`, and returns the resulting
+    // text and point.
+    fn feed(
+        state: &mut EmacsState,
+        text: &str,
+        at: (usize, usize),
+        keys: &str,
+    ) -> (String, (usize, usize)) {
+        let mut buffer = Buffer::from(text.to_string());
+        let outcome = state.feed(&mut buffer, Cursor::new(at.0, at.1, at.1), keys.to_string());
+        (
+            buffer.to_string(),
+            (outcome.cursor.row, outcome.cursor.column),
+        )
+    }
+
+    #[test]
+    fn consecutive_line_kills_accumulate() {
+        let mut state = EmacsState::new();
+        let result = feed(&mut state, "one\ntwo\nthree", (0, 0), "C-k C-k C-k");
+        assert_eq!(result, ("\nthree".to_string(), (0, 0)));
+        assert_eq!(state.kill_ring(), vec!["one\ntwo"]);
+    }
+
+    #[test]
+    fn kills_separated_by_a_motion_stay_apart() {
+        let mut state = EmacsState::new();
+        feed(&mut state, "one\ntwo", (0, 0), "C-k C-n C-a C-k");
+        assert_eq!(state.kill_ring(), vec!["two", "one"]);
+    }
+
+    #[test]
+    fn yank_pop_replaces_the_yank() {
+        let mut state = EmacsState::new();
+        state.push_kill("first".to_string());
+        state.push_kill("second".to_string());
+
+        assert_eq!(feed(&mut state, "x", (0, 1), "C-y").0, "xsecond");
+        assert_eq!(
+            feed(&mut state, "x", (0, 1), "C-y M-y"),
+            ("xfirst".to_string(), (0, 6))
+        );
+        // `M-y` only follows a yank.
+        assert_eq!(feed(&mut state, "x", (0, 1), "C-y C-f M-y").0, "xsecond");
+    }
+
+    #[test]
+    fn region_kill_and_transpose() {
+        let mut state = EmacsState::new();
+        assert_eq!(
+            feed(&mut state, "abcd", (0, 0), "C-SPC C-f C-f C-w").0,
+            "cd"
+        );
+        assert_eq!(state.kill_ring(), vec!["ab"]);
+        assert_eq!(
+            feed(&mut state, "ab", (0, 1), "C-t"),
+            ("ba".to_string(), (0, 2))
+        );
+        assert_eq!(feed(&mut state, "a\nb", (1, 0), "C-x C-t").0, "b\na");
+    }
+
+    #[test]
+    fn prefix_arguments_repeat_commands() {
+        let mut state = EmacsState::new();
+        assert_eq!(feed(&mut state, "", (0, 0), "C-u x").0, "xxxx");
+        assert_eq!(feed(&mut state, "", (0, 0), "C-u C-u x").0, "x".repeat(16));
+        assert_eq!(feed(&mut state, "", (0, 0), "M-3 x").0, "xxx");
+        assert_eq!(feed(&mut state, "", (0, 0), "C-u 1 2 x").0, "x".repeat(12));
+        assert_eq!(feed(&mut state, "a\nb\nc", (0, 0), "C-u 2 C-k").0, "c");
+    }
+
+    #[test]
+    fn reports_pending_and_undefined_keys() {
+        let mut buffer = Buffer::from("abc".to_string());
+        let mut state = EmacsState::new();
+        let outcome = state.feed(&mut buffer, Cursor::default(), "C-x".to_string());
+        assert_eq!(outcome.pending_keys, "C-x");
+        let outcome = state.feed(&mut buffer, Cursor::default(), "C-q".to_string());
+        assert_eq!(outcome.undefined_keys.as_deref(), Some("C-x C-q"));
+    }
+
+    #[test]
+    fn huge_arguments_are_clamped() {
+        let mut state = EmacsState::new();
+        let many_c_u = "C-u ".repeat(100);
+        assert_eq!(
+            feed(&mut state, "abc\ndef", (0, 0), &format!("{many_c_u} C-f")).1,
+            (1, 3)
+        );
+        assert_eq!(
+            feed(&mut state, "abc\ndef", (0, 0), &format!("{many_c_u} C-n")).1,
+            (1, 0)
+        );
+        assert_eq!(
+            feed(&mut state, "abc\ndef", (0, 0), &format!("{many_c_u} C-k")).0,
+            ""
+        );
+
+        let digits = "9 ".repeat(50);
+        let (text, _) = feed(&mut state, "", (0, 0), &format!("M-9 {digits} x"));
+        assert_eq!(text.len(), EmacsState::MAX_ARGUMENT);
+        let (text, _) = feed(&mut state, "", (0, 0), &format!("{many_c_u} x"));
+        assert_eq!(text.len(), EmacsState::MAX_ARGUMENT);
+    }
+}
@@ -0,0 +1,170 @@
+use flutter_rust_bridge::frb;
+use std::borrow::Cow;
+use std::io;
+
+// How a file's bytes map to text. A UTF-8 byte order mark is kept in the text
+// as U+FEFF, and so is a UTF-16 one, so each is written back as it was read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Latin1,
+    Utf16Be,
+    Utf16Le,
+}
+
+impl Encoding {
+    // The encoding for an EditorConfig `charset` value.
+    #[frb(sync)]
+    pub fn from_charset(charset: String) -> Option<Self> {
+        match charset.to_lowercase().as_str() {
+            "utf-8" | "utf-8-bom" => Some(Self::Utf8),
+            "latin1" => Some(Self::Latin1),
+            "utf-16be" => Some(Self::Utf16Be),
+            "utf-16le" => Some(Self::Utf16Le),
+            _ => None,
+        }
+    }
+
+    // Decodes as much of `bytes` as forms whole characters, returning the
+    // text, the number of bytes consumed and whether anything invalid was
+    // replaced. A character split across chunks is left for the next chunk,
+    // unless this is the last one.
+    pub(crate) fn decode_chunk(self, bytes: &[u8], is_last: bool) -> (Cow<'_, str>, usize, bool) {
+        match self {
+            Self::Utf8 => decode_utf8(bytes, is_last),
+            Self::Latin1 => (
+                bytes.iter().map(|&b| b as char).collect(),
+                bytes.len(),
+                false,
+            ),
+            Self::Utf16Be => decode_utf16(bytes, is_last, u16::from_be_bytes),
+            Self::Utf16Le => decode_utf16(bytes, is_last, u16::from_le_bytes),
+        }
+    }
+
+    pub(crate) fn encode<'a>(self, text: &'a str) -> io::Result<Cow<'a, [u8]>> {
+        let bytes = match self {
+            Self::Utf8 => return Ok(text.as_bytes().into()),
+            Self::Latin1 => {
+                let mut bytes = Vec::with_capacity(text.len());
+                for c in text.chars() {
+                    match u8::try_from(c) {
+                        Ok(byte) => bytes.push(byte),
+                        Err(_) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("{c:?} cannot be written as latin1"),
+                            ))
+                        }
+                    }
+                }
+                bytes
+            }
+            Self::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Self::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        };
+
+        Ok(bytes.into())
+    }
+}
+
+// The text is only owned when something was replaced.
+fn decode_utf8(bytes: &[u8], is_last: bool) -> (Cow<'_, str>, usize, bool) {
+    let err = match std::str::from_utf8(bytes) {
+        Ok(text) => return (text.into(), bytes.len(), false),
+        Err(err) => err,
+    };
+
+    let valid = err.valid_up_to();
+    // Safety: `from_utf8` validated this prefix.
+    let prefix = unsafe { std::str::from_utf8_unchecked(&bytes[..valid]) };
+
+    match err.error_len() {
+        None if !is_last && valid > 0 => (prefix.into(), valid, false),
+        Some(len) => (format!("{prefix}\u{FFFD}").into(), valid + len, true),
+        None => (format!("{prefix}\u{FFFD}").into(), bytes.len(), true),
+    }
+}
+
+fn decode_utf16(
+    bytes: &[u8],
+    is_last: bool,
+    unit: fn([u8; 2]) -> u16,
+) -> (Cow<'_, str>, usize, bool) {
+    let mut units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| unit([pair[0], pair[1]]))
+        .collect();
+    let mut consumed = units.len() * 2;
+    // A high surrogate at the end of a chunk pairs with the next one.
+    if !is_last && units.len() > 1 && units.last().is_some_and(|u| (0xD800..0xDC00).contains(u)) {
+        units.pop();
+        consumed -= 2;
+    }
+
+    let mut lossy = false;
+    let mut text: String = char::decode_utf16(units)
+        .map(|c| {
+            c.unwrap_or_else(|_| {
+                lossy = true;
+                char::REPLACEMENT_CHARACTER
+            })
+        })
+        .collect();
+    if is_last && bytes.len() % 2 == 1 {
+        text.push(char::REPLACEMENT_CHARACTER);
+        consumed += 1;
+        lossy = true;
+    }
+
+    (text.into(), consumed, lossy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(encoding: Encoding, bytes: &[u8], chunk: usize) -> (String, bool) {
+        let (mut text, mut offset, mut lossy) = (String::new(), 0, false);
+        while offset < bytes.len() {
+            let end = (offset + chunk).min(bytes.len());
+            let (decoded, consumed, replaced) =
+                encoding.decode_chunk(&bytes[offset..end], end == bytes.len());
+            text.push_str(&decoded);
+            offset += consumed;
+            lossy |= replaced;
+        }
+        (text, lossy)
+    }
+
+    #[test]
+    fn round_trips_each_encoding_across_chunks() {
+        let text = "\u{feff}caf\u{e9} \u{1F600}\n";
+        for encoding in [Encoding::Utf16Be, Encoding::Utf16Le] {
+            let bytes = encoding.encode(text).unwrap();
+            assert_eq!(decode(encoding, &bytes, 5), (text.to_string(), false));
+        }
+
+        let latin1 = Encoding::Latin1.encode("caf\u{e9}\n").unwrap();
+        assert_eq!(latin1.as_ref(), b"caf\xe9\n");
+        assert_eq!(
+            decode(Encoding::Latin1, &latin1, 2),
+            ("caf\u{e9}\n".to_string(), false)
+        );
+        assert!(Encoding::Latin1.encode("\u{1F600}").is_err());
+    }
+
+    #[test]
+    fn invalid_input_is_replaced_and_reported() {
+        assert_eq!(
+            decode(Encoding::Utf8, b"caf\xe9\n", 2),
+            ("caf\u{FFFD}\n".to_string(), true)
+        );
+        // An unpaired surrogate, then an odd trailing byte.
+        assert_eq!(
+            decode(Encoding::Utf16Le, &[0x00, 0xD8, 0x61, 0x00, 0x62], 4),
+            ("\u{FFFD}a\u{FFFD}".to_string(), true)
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use anyhow::{anyhow, bail};
+use flutter_rust_bridge::frb;
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::buffer::{Buffer, TextEdit};
+use super::diff::line_edits;
+use super::save::Formatter;
+use super::snapshot::BufferSnapshot;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug)]
+pub struct FormatDiagnostic {
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+    pub row: Option<usize>,
+    pub column: Option<usize>,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug)]
+pub struct FormatResult {
+    pub success: bool,
+    // The buffer version the edits apply to.
+    pub version: usize,
+    pub edits: Vec<TextEdit>,
+    pub diagnostics: Vec<FormatDiagnostic>,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug)]
+pub struct ExternalFormatter {
+    pub name: String,
+    pub command: String,
+    pub args: Vec<String>,
+    pub timeout_ms: u64,
+}
+
+impl ExternalFormatter {
+    const DEFAULT_TIMEOUT_MS: u64 = 5000;
+
+    #[frb(sync)]
+    pub fn new(name: String, command: String, args: Vec<String>) -> Self {
+        Self {
+            name,
+            command,
+            args,
+            timeout_ms: Self::DEFAULT_TIMEOUT_MS,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn rustfmt() -> Self {
+        Self::new(
+            "rustfmt".to_string(),
+            "rustfmt".to_string(),
+            vec!["--emit".to_string(), "stdout".to_string()],
+        )
+    }
+
+    #[frb(sync)]
+    pub fn dart_format() -> Self {
+        Self::new(
+            "dart format".to_string(),
+            "dart".to_string(),
+            vec!["format".to_string()],
+        )
+    }
+
+    // Prettier picks a parser from the file path, which is not read from disk.
+    #[frb(sync)]
+    pub fn prettier(path: String) -> Self {
+        Self::new(
+            "prettier".to_string(),
+            "prettier".to_string(),
+            vec!["--stdin-filepath".to_string(), path],
+        )
+    }
+
+    pub fn format_buffer(&self, buffer: &mut Buffer) -> anyhow::Result<FormatResult> {
+        let result = self.format_snapshot(&buffer.snapshot())?;
+
+        if result.success {
+            buffer.apply_edits(result.edits.clone());
+        }
+
+        Ok(result)
+    }
+
+    // Formats without touching the buffer, so it can run off the UI thread.
+    // The edits only apply while the buffer is still at `result.version`.
+    pub fn format_snapshot(&self, snapshot: &BufferSnapshot) -> anyhow::Result<FormatResult> {
+        self.format_text(&snapshot.to_string(), 0, false, snapshot.version())
+    }
+
+    // Formats `start_row..=end_row` on its own and splices the result back in.
+    pub fn format_range(
+        &self,
+        buffer: &mut Buffer,
+        start_row: usize,
+        end_row: usize,
+    ) -> anyhow::Result<FormatResult> {
+        let end_row = end_row.min(buffer.last_row());
+        let (start_idx, end_idx) = buffer.row_byte_range(start_row, end_row);
+        let text = buffer.rope().byte_slice(start_idx..end_idx).to_string();
+
+        let mut result = self.format_text(&text, start_row, true, buffer.version)?;
+        for diagnostic in &mut result.diagnostics {
+            diagnostic.row = diagnostic.row.map(|row| row + start_row);
+        }
+
+        if result.success {
+            buffer.apply_edits(result.edits.clone());
+        }
+
+        Ok(result)
+    }
+
+    fn format_text(
+        &self,
+        text: &str,
+        row_offset: usize,
+        is_range: bool,
+        version: usize,
+    ) -> anyhow::Result<FormatResult> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow!("failed to start `{}`: {err}", self.command))?;
+
+        let mut stdin = child.stdin.take().unwrap();
+        let input = text.to_string();
+        let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+        let stdout = read_to_end(child.stdout.take().unwrap());
+        let stderr = read_to_end(child.stderr.take().unwrap());
+
+        let status = self.wait_with_timeout(&mut child)?;
+        // A formatter may exit before consuming its input, so a broken pipe is
+        // reported through the exit status instead.
+        let _ = writer.join();
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+
+        let diagnostics = parse_diagnostics(&stderr);
+        if !status {
+            return Ok(FormatResult {
+                success: false,
+                version,
+                edits: Vec::new(),
+                diagnostics,
+            });
+        }
+
+        let mut formatted = String::from_utf8(stdout)
+            .map_err(|_| anyhow!("`{}` produced invalid UTF-8", self.command))?;
+
+        // A range is formatted as if it were a whole file, so drop the final
+        // newline the formatter adds unless the range already ended in one.
+        if is_range && !text.ends_with('\n') && formatted.ends_with('\n') {
+            formatted.pop();
+        }
+
+        Ok(FormatResult {
+            success: true,
+            version,
+            edits: line_edits(text, &formatted, row_offset),
+            diagnostics,
+        })
+    }
+
+    fn wait_with_timeout(&self, child: &mut Child) -> anyhow::Result<bool> {
+        let deadline = Instant::now() + Duration::from_millis(self.timeout_ms);
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(status.success());
+            }
+
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("`{}` timed out after {}ms", self.command, self.timeout_ms);
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Formatter for ExternalFormatter {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn format(&self, buffer: &mut Buffer) -> anyhow::Result<Vec<TextEdit>> {
+        let result = self.format_buffer(buffer)?;
+
+        if !result.success {
+            let message = result
+                .diagnostics
+                .first()
+                .map(|diagnostic| diagnostic.message.clone())
+                .unwrap_or_else(|| "formatter failed".to_string());
+            bail!("{}: {message}", self.name);
+        }
+
+        Ok(result.edits)
+    }
+}
+
+fn read_to_end(mut reader: impl Read + Send + 'static) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let _ = reader.read_to_end(&mut output);
+        output
+    })
+}
+
+fn parse_diagnostics(stderr: &[u8]) -> Vec<FormatDiagnostic> {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let lowercase = line.to_lowercase();
+            let severity = if lowercase.contains("error") {
+                DiagnosticSeverity::Error
+            } else if lowercase.contains("warn") {
+                DiagnosticSeverity::Warning
+            } else {
+                DiagnosticSeverity::Info
+            };
+            let (row, column) = parse_location(line).unzip();
+
+            FormatDiagnostic {
+                severity,
+                message: line.to_string(),
+                row,
+                column,
+            }
+        })
+        .collect()
+}
+
+// Finds the first `:<line>:<column>` pair, as printed by most formatters.
+// Both are 1-based in the output and 0-based in the result.
+fn parse_location(line: &str) -> Option<(usize, usize)> {
+    let parts: Vec<&str> = line.split(':').collect();
+
+    parts.windows(2).find_map(|pair| {
+        let row: usize = pair[0].trim().parse().ok()?;
+        let column: usize = pair[1]
+            .trim()
+            .split(|ch: char| !ch.is_ascii_digit())
+            .next()?
+            .parse()
+            .ok()?;
+
+        Some((row.saturating_sub(1), column.saturating_sub(1)))
+    })
+}
@@ -0,0 +1,801 @@
+use anyhow::{bail, Context};
+use flutter_rust_bridge::frb;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeymapFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingSource {
+    Default,
+    User,
+}
+
+// A key as reported by Flutter. `key` is the logical key label, e.g. `A`,
+// `Arrow Left` or `Enter`.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct KeyPress {
+    pub key: String,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub meta: bool,
+}
+
+// What a `when` clause can test.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeyContext {
+    pub editor_focused: bool,
+    pub explorer_focused: bool,
+    pub has_selection: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyResolutionKind {
+    // `command` should run.
+    Command,
+    // The key started or continued a chord; wait for the next one.
+    Pending,
+    // A single key with no binding, to be handled by the widget itself,
+    // e.g. as typed text.
+    Unbound,
+    // A chord that matched nothing. The keys are swallowed.
+    Cancelled,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct KeyResolution {
+    pub kind: KeyResolutionKind,
+    pub command: Option<String>,
+    // The keys typed so far, e.g. `ctrl+k` while a chord is pending.
+    pub keys: String,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct KeyBinding {
+    pub keys: String,
+    pub command: String,
+    pub when: Option<String>,
+    pub source: BindingSource,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    // Both bindings use the same keys; the later one wins.
+    Duplicate,
+    // The first binding's keys start the second's chord, so it can never
+    // run while the chord is available.
+    Prefix,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct KeyConflict {
+    pub kind: ConflictKind,
+    pub first: KeyBinding,
+    pub second: KeyBinding,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct KeyStroke {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    meta: bool,
+    key: String,
+}
+
+impl KeyStroke {
+    fn parse(notation: &str) -> anyhow::Result<Self> {
+        let mut stroke = KeyStroke {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            meta: false,
+            key: String::new(),
+        };
+
+        let parts: Vec<&str> = notation.split('+').collect();
+        let (key, modifiers) = match parts.split_last() {
+            // `ctrl++` binds the plus key.
+            Some((&"", rest)) if notation.ends_with("++") => ("+", &rest[..rest.len() - 1]),
+            Some((key, rest)) => (*key, rest),
+            None => bail!("empty key"),
+        };
+
+        for modifier in modifiers {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => stroke.ctrl = true,
+                "alt" | "option" => stroke.alt = true,
+                "shift" => stroke.shift = true,
+                "cmd" | "meta" | "super" | "win" => stroke.meta = true,
+                // The primary modifier: cmd on macOS, ctrl elsewhere.
+                "mod" if cfg!(target_os = "macos") => stroke.meta = true,
+                "mod" => stroke.ctrl = true,
+                other => bail!("unknown modifier `{other}` in `{notation}`"),
+            }
+        }
+
+        stroke.key = normalize_key(key);
+        if stroke.key.is_empty() {
+            bail!("missing key in `{notation}`");
+        }
+
+        Ok(stroke)
+    }
+
+    fn from_press(press: &KeyPress) -> Self {
+        KeyStroke {
+            ctrl: press.ctrl,
+            alt: press.alt,
+            shift: press.shift,
+            meta: press.meta,
+            key: normalize_key(&press.key),
+        }
+    }
+
+    fn notation(&self) -> String {
+        let mut notation = String::new();
+        for (held, name) in [
+            (self.ctrl, "ctrl+"),
+            (self.alt, "alt+"),
+            (self.shift, "shift+"),
+            (self.meta, "cmd+"),
+        ] {
+            if held {
+                notation.push_str(name);
+            }
+        }
+        notation.push_str(&self.key);
+        notation
+    }
+}
+
+fn normalize_key(key: &str) -> String {
+    if key == " " {
+        return "space".to_string();
+    }
+
+    let key: String = key
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+
+    match key.as_str() {
+        "arrowleft" => "left",
+        "arrowright" => "right",
+        "arrowup" => "up",
+        "arrowdown" => "down",
+        "esc" => "escape",
+        "return" => "enter",
+        "del" => "delete",
+        _ => return key,
+    }
+    .to_string()
+}
+
+fn is_modifier(key: &str) -> bool {
+    [
+        "control",
+        "controlleft",
+        "controlright",
+        "alt",
+        "altleft",
+        "altright",
+        "shift",
+        "shiftleft",
+        "shiftright",
+        "meta",
+        "metaleft",
+        "metaright",
+    ]
+    .contains(&key)
+}
+
+fn chord_notation(keys: &[KeyStroke]) -> String {
+    keys.iter()
+        .map(KeyStroke::notation)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// A `when` clause: `||`-separated groups of `&&`-separated context keys,
+// each optionally negated with `!`.
+#[derive(Clone, Debug)]
+struct Condition {
+    any_of: Vec<Vec<(bool, ContextKey)>>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ContextKey {
+    EditorFocus,
+    ExplorerFocus,
+    HasSelection,
+}
+
+impl Condition {
+    fn parse(source: &str) -> anyhow::Result<Self> {
+        let any_of = source
+            .split("||")
+            .map(|group| {
+                group
+                    .split("&&")
+                    .map(|term| {
+                        let term = term.trim();
+                        let (negated, name) = match term.strip_prefix('!') {
+                            Some(name) => (true, name.trim()),
+                            None => (false, term),
+                        };
+                        let key = match name {
+                            "editorFocus" => ContextKey::EditorFocus,
+                            "explorerFocus" => ContextKey::ExplorerFocus,
+                            "hasSelection" => ContextKey::HasSelection,
+                            other => bail!("unknown context `{other}` in `{source}`"),
+                        };
+                        Ok((negated, key))
+                    })
+                    .collect()
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { any_of })
+    }
+
+    fn matches(&self, context: KeyContext) -> bool {
+        self.any_of.iter().any(|group| {
+            group.iter().all(|&(negated, key)| {
+                let value = match key {
+                    ContextKey::EditorFocus => context.editor_focused,
+                    ContextKey::ExplorerFocus => context.explorer_focused,
+                    ContextKey::HasSelection => context.has_selection,
+                };
+                value != negated
+            })
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Binding {
+    keys: Vec<KeyStroke>,
+    command: String,
+    when: Option<(String, Condition)>,
+    source: BindingSource,
+}
+
+impl Binding {
+    fn matches(&self, context: KeyContext) -> bool {
+        self.when
+            .as_ref()
+            .is_none_or(|(_, condition)| condition.matches(context))
+    }
+
+    fn to_key_binding(&self) -> KeyBinding {
+        KeyBinding {
+            keys: chord_notation(&self.keys),
+            command: self.command.clone(),
+            when: self.when.as_ref().map(|(source, _)| source.clone()),
+            source: self.source,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BindingSpec {
+    key: String,
+    command: String,
+    when: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KeymapFile {
+    #[serde(default)]
+    bindings: Vec<BindingSpec>,
+}
+
+// Bindings from the editor's own shortcuts, before any user file is applied.
+const DEFAULT_BINDINGS: &[(&str, &str, Option<&str>)] = &[
+    ("mod+a", "editor.selectAll", Some("editorFocus")),
+    ("mod+x", "editor.cut", Some("editorFocus")),
+    ("mod+c", "editor.copy", Some("editorFocus")),
+    ("mod+v", "editor.paste", Some("editorFocus")),
+    ("mod+s", "file.save", Some("editorFocus")),
+    ("mod+shift+s", "file.saveAs", Some("editorFocus")),
+    ("enter", "editor.newline", Some("editorFocus")),
+    ("backspace", "editor.deleteLeft", Some("editorFocus")),
+    ("escape", "editor.clearSelection", Some("editorFocus")),
+    ("left", "editor.moveLeft", Some("editorFocus")),
+    ("right", "editor.moveRight", Some("editorFocus")),
+    ("up", "editor.moveUp", Some("editorFocus")),
+    ("down", "editor.moveDown", Some("editorFocus")),
+    ("shift+left", "editor.selectLeft", Some("editorFocus")),
+    ("shift+right", "editor.selectRight", Some("editorFocus")),
+    ("shift+up", "editor.selectUp", Some("editorFocus")),
+    ("shift+down", "editor.selectDown", Some("editorFocus")),
+    ("up", "explorer.selectPrevious", Some("explorerFocus")),
+    ("down", "explorer.selectNext", Some("explorerFocus")),
+    ("left", "explorer.collapse", Some("explorerFocus")),
+    ("right", "explorer.expand", Some("explorerFocus")),
+];
+
+// Resolves key presses to command names. User bindings are applied after the
+// defaults and win over them; a user binding whose command starts with `-`
+// removes the matching binding instead.
+#[frb(opaque)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    pending: Vec<KeyStroke>,
+}
+
+impl Keymap {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|&(keys, command, when)| {
+                parse_binding(keys, command, when, BindingSource::Default)
+                    .expect("default bindings are valid")
+            })
+            .collect();
+
+        Self {
+            bindings,
+            pending: Vec::new(),
+        }
+    }
+
+    // The defaults plus the user bindings in `path`, read as JSON if it ends
+    // in `.json` and as TOML otherwise.
+    pub fn load(path: String) -> anyhow::Result<Self> {
+        let format = match Path::new(&path).extension().and_then(|e| e.to_str()) {
+            Some("json") => KeymapFormat::Json,
+            _ => KeymapFormat::Toml,
+        };
+
+        let mut keymap = Self::new();
+        if Path::new(&path).exists() {
+            let contents = fs::read_to_string(&path)?;
+            keymap
+                .add_user_bindings(contents, format)
+                .with_context(|| format!("invalid keymap `{path}`"))?;
+        }
+
+        Ok(keymap)
+    }
+
+    // Applies a list of `{ key, command, when }` bindings under `bindings`.
+    // Nothing is applied if any binding is invalid.
+    #[frb(sync)]
+    pub fn add_user_bindings(
+        &mut self,
+        contents: String,
+        format: KeymapFormat,
+    ) -> anyhow::Result<()> {
+        let file: KeymapFile = match format {
+            KeymapFormat::Toml => toml::from_str(&contents)?,
+            KeymapFormat::Json => serde_json::from_str(&contents)?,
+        };
+
+        let parsed = file
+            .bindings
+            .iter()
+            .enumerate()
+            .map(|(i, spec)| {
+                parse_binding(
+                    &spec.key,
+                    &spec.command,
+                    spec.when.as_deref(),
+                    BindingSource::User,
+                )
+                .with_context(|| format!("binding {}", i + 1))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        for binding in parsed {
+            match binding.command.strip_prefix('-') {
+                Some(command) => self
+                    .bindings
+                    .retain(|b| b.command != command || b.keys != binding.keys),
+                None => self.bindings.push(binding),
+            }
+        }
+
+        self.pending.clear();
+        Ok(())
+    }
+
+    #[frb(sync)]
+    pub fn resolve(&mut self, press: KeyPress, context: KeyContext) -> KeyResolution {
+        let stroke = KeyStroke::from_press(&press);
+        if is_modifier(&stroke.key) {
+            return KeyResolution {
+                kind: if self.pending.is_empty() {
+                    KeyResolutionKind::Unbound
+                } else {
+                    KeyResolutionKind::Pending
+                },
+                command: None,
+                keys: chord_notation(&self.pending),
+            };
+        }
+
+        self.pending.push(stroke);
+        let keys = chord_notation(&self.pending);
+        let active: Vec<&Binding> = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.matches(context))
+            .collect();
+
+        // A longer chord takes precedence over a binding for its prefix.
+        let continues = active.iter().any(|binding| {
+            binding.keys.len() > self.pending.len() && binding.keys.starts_with(&self.pending)
+        });
+        if continues {
+            return KeyResolution {
+                kind: KeyResolutionKind::Pending,
+                command: None,
+                keys,
+            };
+        }
+
+        let command = active
+            .iter()
+            .rev()
+            .find(|binding| binding.keys == self.pending)
+            .map(|binding| binding.command.clone());
+        let chord = self.pending.len() > 1;
+        self.pending.clear();
+
+        let kind = match (&command, chord) {
+            (Some(_), _) => KeyResolutionKind::Command,
+            (None, true) => KeyResolutionKind::Cancelled,
+            (None, false) => KeyResolutionKind::Unbound,
+        };
+
+        KeyResolution {
+            kind,
+            command,
+            keys,
+        }
+    }
+
+    // Drops a partly typed chord, e.g. when focus moves.
+    #[frb(sync)]
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    #[frb(sync)]
+    pub fn pending_keys(&self) -> String {
+        chord_notation(&self.pending)
+    }
+
+    // Every binding in effect, in the order they were added.
+    #[frb(sync)]
+    pub fn bindings(&self) -> Vec<KeyBinding> {
+        self.bindings.iter().map(Binding::to_key_binding).collect()
+    }
+
+    // The key sequences that run `command`, most recently added first, for
+    // showing next to it in the command palette.
+    #[frb(sync)]
+    pub fn keys_for_command(&self, command: String) -> Vec<String> {
+        self.bindings
+            .iter()
+            .rev()
+            .filter(|binding| binding.command == command)
+            .map(|binding| chord_notation(&binding.keys))
+            .collect()
+    }
+
+    // Pairs of bindings that can be active at the same time and where one
+    // hides the other.
+    #[frb(sync)]
+    pub fn conflicts(&self) -> Vec<KeyConflict> {
+        let mut conflicts = Vec::new();
+
+        for (i, first) in self.bindings.iter().enumerate() {
+            for second in &self.bindings[i + 1..] {
+                let kind = if first.keys == second.keys {
+                    if first.command == second.command {
+                        continue;
+                    }
+                    ConflictKind::Duplicate
+                } else if second.keys.starts_with(&first.keys) {
+                    ConflictKind::Prefix
+                } else if first.keys.starts_with(&second.keys) {
+                    conflicts.extend(overlap(second, first, ConflictKind::Prefix));
+                    continue;
+                } else {
+                    continue;
+                };
+
+                conflicts.extend(overlap(first, second, kind));
+            }
+        }
+
+        conflicts
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_binding(
+    keys: &str,
+    command: &str,
+    when: Option<&str>,
+    source: BindingSource,
+) -> anyhow::Result<Binding> {
+    let keys = keys
+        .split_whitespace()
+        .map(KeyStroke::parse)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if keys.is_empty() {
+        bail!("binding for `{command}` has no keys");
+    }
+    if command.trim_start_matches('-').is_empty() {
+        bail!("binding has no command");
+    }
+
+    let when = when
+        .map(|source| Ok::<_, anyhow::Error>((source.to_string(), Condition::parse(source)?)))
+        .transpose()?;
+
+    Ok(Binding {
+        keys,
+        command: command.to_string(),
+        when,
+        source,
+    })
+}
+
+// The conflict between two bindings, if some context makes both active. Only
+// one of the editor and the explorer can have focus.
+fn overlap(first: &Binding, second: &Binding, kind: ConflictKind) -> Option<KeyConflict> {
+    let both_active = [(false, false), (true, false), (false, true)]
+        .into_iter()
+        .flat_map(|(editor_focused, explorer_focused)| {
+            [false, true].map(|has_selection| KeyContext {
+                editor_focused,
+                explorer_focused,
+                has_selection,
+            })
+        })
+        .any(|context| first.matches(context) && second.matches(context));
+
+    both_active.then(|| KeyConflict {
+        kind,
+        first: first.to_key_binding(),
+        second: second.to_key_binding(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(notation: &str) -> KeyPress {
+        let stroke = KeyStroke::parse(notation).unwrap();
+        KeyPress {
+            key: stroke.key,
+            ctrl: stroke.ctrl,
+            alt: stroke.alt,
+            shift: stroke.shift,
+            meta: stroke.meta,
+        }
+    }
+
+    fn editor() -> KeyContext {
+        KeyContext {
+            editor_focused: true,
+            ..KeyContext::default()
+        }
+    }
+
+    fn resolve(
+        keymap: &mut Keymap,
+        notation: &str,
+        context: KeyContext,
+    ) -> (KeyResolutionKind, Option<String>) {
+        let resolution = keymap.resolve(press(notation), context);
+        (resolution.kind, resolution.command)
+    }
+
+    fn keymap(toml: &str) -> Keymap {
+        let mut keymap = Keymap::new();
+        keymap
+            .add_user_bindings(toml.to_string(), KeymapFormat::Toml)
+            .unwrap();
+        keymap
+    }
+
+    #[test]
+    fn chords_wait_for_their_next_key() {
+        let mut keymap = keymap(
+            r#"
+            [[bindings]]
+            key = "ctrl+k ctrl+c"
+            command = "editor.comment"
+            [[bindings]]
+            key = "ctrl+k"
+            command = "editor.deleteToEnd"
+            "#,
+        );
+
+        let resolution = keymap.resolve(press("ctrl+k"), editor());
+        assert_eq!(resolution.kind, KeyResolutionKind::Pending);
+        assert_eq!(resolution.keys, "ctrl+k");
+        // A modifier on its own keeps the chord pending.
+        let control = KeyPress {
+            key: "Control Left".to_string(),
+            ctrl: true,
+            alt: false,
+            shift: false,
+            meta: false,
+        };
+        assert_eq!(
+            keymap.resolve(control, editor()).kind,
+            KeyResolutionKind::Pending
+        );
+        assert_eq!(
+            resolve(&mut keymap, "ctrl+c", editor()),
+            (
+                KeyResolutionKind::Command,
+                Some("editor.comment".to_string())
+            )
+        );
+
+        resolve(&mut keymap, "ctrl+k", editor());
+        assert_eq!(
+            resolve(&mut keymap, "x", editor()),
+            (KeyResolutionKind::Cancelled, None)
+        );
+        assert_eq!(keymap.pending_keys(), "");
+        assert_eq!(
+            resolve(&mut keymap, "x", editor()),
+            (KeyResolutionKind::Unbound, None)
+        );
+    }
+
+    #[test]
+    fn when_clauses_pick_the_binding_for_the_context() {
+        let mut keymap = keymap(
+            r#"
+            [[bindings]]
+            key = "tab"
+            command = "editor.indent"
+            when = "editorFocus && hasSelection"
+            [[bindings]]
+            key = "tab"
+            command = "explorer.open"
+            when = "explorerFocus || !editorFocus"
+            "#,
+        );
+
+        let selecting = KeyContext {
+            has_selection: true,
+            ..editor()
+        };
+        assert_eq!(
+            resolve(&mut keymap, "tab", selecting).1.as_deref(),
+            Some("editor.indent")
+        );
+        assert_eq!(resolve(&mut keymap, "tab", editor()).1, None);
+        assert_eq!(
+            resolve(&mut keymap, "tab", KeyContext::default())
+                .1
+                .as_deref(),
+            Some("explorer.open")
+        );
+        assert_eq!(
+            resolve(&mut keymap, "Arrow Up", editor()).1.as_deref(),
+            Some("editor.moveUp")
+        );
+
+        let mut invalid = Keymap::new();
+        let err = invalid
+            .add_user_bindings(
+                "[[bindings]]\nkey = \"tab\"\ncommand = \"x\"\nwhen = \"focus\"\n".to_string(),
+                KeymapFormat::Toml,
+            )
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("unknown context `focus`"));
+    }
+
+    #[test]
+    fn user_bindings_override_and_remove_defaults() {
+        let mut keymap = Keymap::new();
+        keymap
+            .add_user_bindings(
+                r#"{"bindings": [
+                    {"key": "ctrl+shift+s", "command": "file.saveAll"},
+                    {"key": "enter", "command": "-editor.newline"}
+                ]}"#
+                .to_string(),
+                KeymapFormat::Json,
+            )
+            .unwrap();
+
+        assert_eq!(
+            keymap.keys_for_command("editor.newline".to_string()),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            resolve(&mut keymap, "enter", editor()),
+            (KeyResolutionKind::Unbound, None)
+        );
+        if !cfg!(target_os = "macos") {
+            assert_eq!(
+                resolve(&mut keymap, "ctrl+shift+s", editor()).1.as_deref(),
+                Some("file.saveAll")
+            );
+        }
+    }
+
+    #[test]
+    fn conflicts_need_both_bindings_active_at_once() {
+        let keymap = keymap(
+            r#"
+            [[bindings]]
+            key = "up"
+            command = "editor.scrollUp"
+            when = "editorFocus"
+            [[bindings]]
+            key = "g g"
+            command = "editor.top"
+            [[bindings]]
+            key = "g"
+            command = "explorer.go"
+            when = "explorerFocus"
+            [[bindings]]
+            key = "down"
+            command = "editor.moveDown"
+            when = "editorFocus"
+            "#,
+        );
+
+        let conflicts: Vec<_> = keymap
+            .conflicts()
+            .into_iter()
+            .map(|conflict| {
+                (
+                    conflict.kind,
+                    conflict.first.command,
+                    conflict.second.command,
+                )
+            })
+            .collect();
+        // The explorer's `up` never shares a context with the editor's, and
+        // re-adding the default `down` is not a conflict.
+        assert_eq!(
+            conflicts,
+            [
+                (
+                    ConflictKind::Duplicate,
+                    "editor.moveUp".to_string(),
+                    "editor.scrollUp".to_string()
+                ),
+                (
+                    ConflictKind::Prefix,
+                    "explorer.go".to_string(),
+                    "editor.top".to_string()
+                ),
+            ]
+        );
+    }
+}
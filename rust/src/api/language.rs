@@ -0,0 +1,738 @@
+use anyhow::bail;
+use flutter_rust_bridge::frb;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::buffer::Buffer;
+use super::editorconfig::Indentation;
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenPair {
+    pub open: String,
+    pub close: String,
+}
+
+// Everything the editor knows about one language. `id` is what settings use
+// in `[language.<id>]` tables.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LanguageInfo {
+    pub id: String,
+    pub name: String,
+    // Other names modelines use, such as `sh` for shell.
+    pub aliases: Vec<String>,
+    // Without the leading dot, e.g. `rs` or `d.ts`.
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    // Interpreter names from a `#!` line, without version numbers.
+    pub interpreters: Vec<String>,
+    pub line_comment: Option<String>,
+    pub block_comment: Option<TokenPair>,
+    pub brackets: Vec<TokenPair>,
+    // Characters besides letters, digits and `_` that belong to words.
+    pub word_characters: String,
+    // A line matching this indents the line after it.
+    pub increase_indent_pattern: Option<String>,
+    // A line matching this is indented one level less than the line before.
+    pub decrease_indent_pattern: Option<String>,
+    // TextMate scope name of the grammar to highlight with.
+    pub grammar: Option<String>,
+}
+
+struct Language {
+    info: LanguageInfo,
+    increase_indent: Option<Regex>,
+    decrease_indent: Option<Regex>,
+}
+
+impl Language {
+    fn new(info: LanguageInfo) -> anyhow::Result<Self> {
+        let compile = |pattern: &Option<String>, name: &str| {
+            pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|err| anyhow::anyhow!("invalid {name} for `{}`: {err}", info.id))
+        };
+        let increase_indent = compile(&info.increase_indent_pattern, "increase_indent_pattern")?;
+        let decrease_indent = compile(&info.decrease_indent_pattern, "decrease_indent_pattern")?;
+
+        Ok(Self {
+            info,
+            increase_indent,
+            decrease_indent,
+        })
+    }
+}
+
+struct Spec {
+    id: &'static str,
+    name: &'static str,
+    aliases: &'static [&'static str],
+    extensions: &'static [&'static str],
+    filenames: &'static [&'static str],
+    interpreters: &'static [&'static str],
+    line_comment: Option<&'static str>,
+    block_comment: Option<(&'static str, &'static str)>,
+    brackets: &'static [(&'static str, &'static str)],
+    word_characters: &'static str,
+    indent: Option<(&'static str, &'static str)>,
+    grammar: Option<&'static str>,
+}
+
+impl Spec {
+    fn info(&self) -> LanguageInfo {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        let pair = |&(open, close): &(&str, &str)| TokenPair {
+            open: open.to_string(),
+            close: close.to_string(),
+        };
+
+        LanguageInfo {
+            id: self.id.to_string(),
+            name: self.name.to_string(),
+            aliases: strings(self.aliases),
+            extensions: strings(self.extensions),
+            filenames: strings(self.filenames),
+            interpreters: strings(self.interpreters),
+            line_comment: self.line_comment.map(str::to_string),
+            block_comment: self.block_comment.as_ref().map(pair),
+            brackets: self.brackets.iter().map(pair).collect(),
+            word_characters: self.word_characters.to_string(),
+            increase_indent_pattern: self.indent.map(|(increase, _)| increase.to_string()),
+            decrease_indent_pattern: self.indent.map(|(_, decrease)| decrease.to_string()),
+            grammar: self.grammar.map(str::to_string),
+        }
+    }
+}
+
+const PLAIN_TEXT: &str = "plaintext";
+
+const BRACES: &[(&str, &str)] = &[("{", "}"), ("[", "]"), ("(", ")")];
+const MARKUP: &[(&str, &str)] = &[("<", ">")];
+
+// Indents after an unclosed bracket and outdents a line starting with a
+// closing one.
+const BRACE_INDENT: (&str, &str) = (
+    r#"^.*(\{[^}"'`]*|\([^)"'`]*|\[[^\]"'`]*)$"#,
+    r"^\s*[\}\]\)]",
+);
+
+const C_LIKE: Spec = Spec {
+    id: "",
+    name: "",
+    aliases: &[],
+    extensions: &[],
+    filenames: &[],
+    interpreters: &[],
+    line_comment: Some("//"),
+    block_comment: Some(("/*", "*/")),
+    brackets: BRACES,
+    word_characters: "",
+    indent: Some(BRACE_INDENT),
+    grammar: None,
+};
+
+const LANGUAGES: &[Spec] = &[
+    Spec {
+        id: PLAIN_TEXT,
+        name: "Plain Text",
+        aliases: &["text", "txt"],
+        extensions: &["txt"],
+        line_comment: None,
+        block_comment: None,
+        indent: None,
+        ..C_LIKE
+    },
+    Spec {
+        id: "rust",
+        name: "Rust",
+        aliases: &["rs"],
+        extensions: &["rs"],
+        grammar: Some("source.rust"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "dart",
+        name: "Dart",
+        extensions: &["dart"],
+        grammar: Some("source.dart"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "c",
+        name: "C",
+        extensions: &["c", "h"],
+        grammar: Some("source.c"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "cpp",
+        name: "C++",
+        aliases: &["c++"],
+        extensions: &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        grammar: Some("source.cpp"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "go",
+        name: "Go",
+        aliases: &["golang"],
+        extensions: &["go"],
+        grammar: Some("source.go"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "java",
+        name: "Java",
+        extensions: &["java"],
+        grammar: Some("source.java"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "kotlin",
+        name: "Kotlin",
+        extensions: &["kt", "kts"],
+        grammar: Some("source.kotlin"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "swift",
+        name: "Swift",
+        extensions: &["swift"],
+        grammar: Some("source.swift"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "javascript",
+        name: "JavaScript",
+        aliases: &["js", "node"],
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        interpreters: &["node", "deno"],
+        word_characters: "$",
+        grammar: Some("source.js"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "typescript",
+        name: "TypeScript",
+        aliases: &["ts"],
+        extensions: &["ts", "mts", "cts", "tsx", "d.ts"],
+        word_characters: "$",
+        grammar: Some("source.ts"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "json",
+        name: "JSON",
+        extensions: &["json", "jsonc", "json5"],
+        filenames: &[".babelrc", ".eslintrc", ".prettierrc"],
+        grammar: Some("source.json"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "css",
+        name: "CSS",
+        extensions: &["css"],
+        line_comment: None,
+        word_characters: "-",
+        grammar: Some("source.css"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "scss",
+        name: "SCSS",
+        extensions: &["scss"],
+        word_characters: "-$",
+        grammar: Some("source.css.scss"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "html",
+        name: "HTML",
+        extensions: &["html", "htm", "xhtml"],
+        line_comment: None,
+        block_comment: Some(("<!--", "-->")),
+        brackets: MARKUP,
+        word_characters: "-",
+        indent: Some((r"^\s*<([A-Za-z][\w-]*)(\s[^>]*)?>\s*$", r"^\s*</[A-Za-z]")),
+        grammar: Some("text.html.basic"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "xml",
+        name: "XML",
+        extensions: &["xml", "xsd", "xsl", "svg", "plist", "tmtheme"],
+        line_comment: None,
+        block_comment: Some(("<!--", "-->")),
+        brackets: MARKUP,
+        word_characters: "-",
+        indent: Some((r"^\s*<([A-Za-z][\w:.-]*)(\s[^>]*)?>\s*$", r"^\s*</[A-Za-z]")),
+        grammar: Some("text.xml"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "markdown",
+        name: "Markdown",
+        aliases: &["md"],
+        extensions: &["md", "markdown", "mdx"],
+        line_comment: None,
+        block_comment: Some(("<!--", "-->")),
+        brackets: &[("[", "]"), ("(", ")")],
+        indent: None,
+        grammar: Some("text.html.markdown"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "python",
+        name: "Python",
+        aliases: &["py"],
+        extensions: &["py", "pyi", "pyw"],
+        interpreters: &["python"],
+        line_comment: Some("#"),
+        block_comment: None,
+        indent: Some((
+            r"^.*:\s*(#.*)?$",
+            r"^\s*(elif|else|except|finally)\b.*:\s*(#.*)?$",
+        )),
+        grammar: Some("source.python"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "ruby",
+        name: "Ruby",
+        aliases: &["rb"],
+        extensions: &["rb", "rake", "gemspec"],
+        filenames: &["Gemfile", "Rakefile"],
+        interpreters: &["ruby"],
+        line_comment: Some("#"),
+        block_comment: Some(("=begin", "=end")),
+        word_characters: "?!",
+        indent: Some((
+            r"^\s*(def|class|module|if|unless|while|until|for|begin|case|else|elsif|when|rescue|ensure)\b|\bdo(\s*\|[^|]*\|)?\s*$|\{(\s*\|[^|]*\|)?\s*$",
+            r"^\s*(end|else|elsif|when|rescue|ensure)\b|^\s*\}",
+        )),
+        grammar: Some("source.ruby"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "lua",
+        name: "Lua",
+        extensions: &["lua"],
+        interpreters: &["lua", "luajit"],
+        line_comment: Some("--"),
+        block_comment: Some(("--[[", "]]")),
+        indent: Some((
+            r"\b(then|do)\s*$|\bfunction\b[^)]*\)\s*$|^\s*(else|repeat)\s*$|\{\s*$",
+            r"^\s*(end|else|elseif|until)\b|^\s*\}",
+        )),
+        grammar: Some("source.lua"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "shell",
+        name: "Shell Script",
+        aliases: &["sh", "bash", "zsh"],
+        extensions: &["sh", "bash", "zsh", "ksh"],
+        filenames: &[
+            ".bashrc",
+            ".bash_profile",
+            ".profile",
+            ".zshrc",
+            ".zprofile",
+        ],
+        interpreters: &["sh", "bash", "zsh", "dash", "ksh"],
+        line_comment: Some("#"),
+        block_comment: None,
+        word_characters: "-",
+        indent: Some((
+            r"\b(then|do)\s*$|^\s*else\s*$|\{\s*$|\)\s*$",
+            r"^\s*(fi|done|else|elif|esac)\b|^\s*\}",
+        )),
+        grammar: Some("source.shell"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "makefile",
+        name: "Makefile",
+        aliases: &["make"],
+        extensions: &["mk", "mak"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        interpreters: &["make"],
+        line_comment: Some("#"),
+        block_comment: None,
+        word_characters: "-",
+        indent: Some((r"^[^\s#][^=]*:([^=].*)?$", r"^\s*(else|endif|endef)\b")),
+        grammar: Some("source.makefile"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "dockerfile",
+        name: "Dockerfile",
+        aliases: &["docker"],
+        extensions: &["dockerfile"],
+        filenames: &["Dockerfile", "Containerfile"],
+        line_comment: Some("#"),
+        block_comment: None,
+        indent: None,
+        grammar: Some("source.dockerfile"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "toml",
+        name: "TOML",
+        extensions: &["toml"],
+        filenames: &["Cargo.lock"],
+        line_comment: Some("#"),
+        block_comment: None,
+        word_characters: "-",
+        grammar: Some("source.toml"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "yaml",
+        name: "YAML",
+        aliases: &["yml"],
+        extensions: &["yaml", "yml"],
+        line_comment: Some("#"),
+        block_comment: None,
+        word_characters: "-",
+        indent: Some((r"^.*:\s*(#.*)?$|^\s*-\s*$", r"^\s*[\}\]]")),
+        grammar: Some("source.yaml"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "sql",
+        name: "SQL",
+        extensions: &["sql"],
+        line_comment: Some("--"),
+        grammar: Some("source.sql"),
+        ..C_LIKE
+    },
+    Spec {
+        id: "ignore",
+        name: "Ignore File",
+        extensions: &["gitignore", "dockerignore"],
+        filenames: &[".gitignore", ".dockerignore", ".ignore"],
+        line_comment: Some("#"),
+        block_comment: None,
+        brackets: &[],
+        indent: None,
+        grammar: Some("source.ignore"),
+        ..C_LIKE
+    },
+];
+
+// Known languages and how to tell which one a file is written in.
+#[frb(opaque)]
+pub struct LanguageRegistry {
+    languages: Vec<Language>,
+    index: HashMap<String, usize>,
+}
+
+impl Default for LanguageRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LanguageRegistry {
+    // Lines at each end of a file searched for a modeline.
+    const MODELINE_LINES: usize = 5;
+
+    #[frb(sync)]
+    pub fn new() -> Self {
+        let mut registry = Self {
+            languages: Vec::new(),
+            index: HashMap::new(),
+        };
+        for spec in LANGUAGES {
+            registry
+                .register(spec.info())
+                .expect("built-in languages are valid");
+        }
+
+        registry
+    }
+
+    // Adds a language, replacing any registered with the same id.
+    #[frb(sync)]
+    pub fn register(&mut self, info: LanguageInfo) -> anyhow::Result<()> {
+        if info.id.is_empty() {
+            bail!("language id cannot be empty");
+        }
+
+        let language = Language::new(info)?;
+        match self.index.get(&language.info.id) {
+            Some(&i) => self.languages[i] = language,
+            None => {
+                self.index
+                    .insert(language.info.id.clone(), self.languages.len());
+                self.languages.push(language);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[frb(sync)]
+    pub fn language(&self, id: String) -> Option<LanguageInfo> {
+        self.get(&id).map(|language| language.info.clone())
+    }
+
+    #[frb(sync)]
+    pub fn languages(&self) -> Vec<LanguageInfo> {
+        self.languages
+            .iter()
+            .map(|language| language.info.clone())
+            .collect()
+    }
+
+    // The language of the file at `path` with contents `buffer`, from a
+    // modeline, the file name, its extension or a `#!` line, in that order.
+    // Files matching none are plain text.
+    #[frb(sync)]
+    pub fn detect(&self, path: String, buffer: &Buffer) -> String {
+        self.detect_modeline(buffer)
+            .or_else(|| self.detect_path(&path))
+            .or_else(|| self.detect_shebang(&buffer.line(0)))
+            .unwrap_or_else(|| PLAIN_TEXT.to_string())
+    }
+
+    // The grammar to highlight `buffer` with as `language_id`, or none for a
+    // buffer too large to highlight.
+    #[frb(sync)]
+    pub fn grammar_for(&self, language_id: String, buffer: &Buffer) -> Option<String> {
+        if !buffer.features().highlighting {
+            return None;
+        }
+        self.language(language_id)?.grammar
+    }
+
+    // Like `detect`, for a file whose contents are not loaded.
+    #[frb(sync)]
+    pub fn detect_by_path(&self, path: String) -> String {
+        self.detect_path(&path)
+            .unwrap_or_else(|| PLAIN_TEXT.to_string())
+    }
+
+    // Indentation for a line inserted after `line`: the same as `line`, one
+    // level deeper when the language's increase pattern matches it.
+    #[frb(sync)]
+    pub fn next_line_indent(
+        &self,
+        language_id: String,
+        line: String,
+        indentation: Indentation,
+    ) -> String {
+        let leading = &line[..line.len() - line.trim_start().len()];
+        let increase = self
+            .get(&language_id)
+            .and_then(|language| language.increase_indent.as_ref())
+            .is_some_and(|pattern| pattern.is_match(&line));
+
+        if increase {
+            format!("{leading}{}", indentation.unit())
+        } else {
+            leading.to_string()
+        }
+    }
+
+    // Whether `line`, as typed so far, should lose a level of indentation,
+    // such as a line starting with `}`.
+    #[frb(sync)]
+    pub fn should_outdent(&self, language_id: String, line: String) -> bool {
+        self.get(&language_id)
+            .and_then(|language| language.decrease_indent.as_ref())
+            .is_some_and(|pattern| pattern.is_match(&line))
+    }
+
+    #[frb(sync)]
+    pub fn is_word_char(&self, language_id: String, c: char) -> bool {
+        is_word_char(self.get(&language_id), c)
+    }
+
+    fn get(&self, id: &str) -> Option<&Language> {
+        self.index.get(id).map(|&i| &self.languages[i])
+    }
+
+    fn find(&self, matches: impl Fn(&LanguageInfo) -> bool) -> Option<String> {
+        // Later registrations win, so user languages override built-in ones.
+        self.languages
+            .iter()
+            .rev()
+            .find(|language| matches(&language.info))
+            .map(|language| language.info.id.clone())
+    }
+
+    fn by_name(&self, name: &str) -> Option<String> {
+        let name = name.to_lowercase();
+        self.find(|info| info.id == name || info.aliases.contains(&name))
+    }
+
+    fn detect_path(&self, path: &str) -> Option<String> {
+        let file_name = Path::new(path).file_name()?.to_string_lossy().to_string();
+
+        if let Some(id) = self.find(|info| info.filenames.contains(&file_name)) {
+            return Some(id);
+        }
+
+        // Try the longest extension first, so `a.d.ts` can differ from `.ts`.
+        let lower = file_name.to_lowercase();
+        let stem_start = usize::from(lower.starts_with('.'));
+        let extensions = lower[stem_start..]
+            .match_indices('.')
+            .map(|(i, _)| &lower[stem_start + i + 1..]);
+        for extension in extensions {
+            if let Some(id) = self.find(|info| info.extensions.iter().any(|e| e == extension)) {
+                return Some(id);
+            }
+        }
+
+        // Variants such as `Dockerfile.dev` or `Makefile.am`.
+        let (base, _) = file_name.split_once('.')?;
+        self.find(|info| info.filenames.iter().any(|name| name == base))
+    }
+
+    fn detect_shebang(&self, first_line: &str) -> Option<String> {
+        let command = first_line.strip_prefix("#!")?;
+        let mut words = command.split_whitespace();
+        let mut program = basename(words.next()?);
+
+        // `#!/usr/bin/env -S python3 -u` names the interpreter after `env`
+        // and its options.
+        if program == "env" {
+            program = basename(words.find(|word| !word.starts_with('-') && !word.contains('='))?);
+        }
+
+        let interpreter = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        self.find(|info| info.interpreters.iter().any(|name| name == interpreter))
+    }
+
+    // Reads vim (`vim: set ft=rust:`) and emacs (`-*- mode: rust -*-`)
+    // modelines from the first and last few lines.
+    fn detect_modeline(&self, buffer: &Buffer) -> Option<String> {
+        let line_count = buffer.line_count();
+        let head = 0..line_count.min(Self::MODELINE_LINES);
+        let tail = line_count
+            .saturating_sub(Self::MODELINE_LINES)
+            .max(head.end)..line_count;
+
+        head.chain(tail).find_map(|row| {
+            let line = buffer.line(row);
+            modeline_language(&line).and_then(|name| self.by_name(&name))
+        })
+    }
+}
+
+fn is_word_char(language: Option<&Language>, c: char) -> bool {
+    c.is_alphanumeric()
+        || c == '_'
+        || language.is_some_and(|language| language.info.word_characters.contains(c))
+}
+
+fn basename(program: &str) -> &str {
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+fn modeline_language(line: &str) -> Option<String> {
+    if let Some(start) = line.find("-*-") {
+        let rest = &line[start + 3..];
+        let variables = &rest[..rest.find("-*-")?];
+        if !variables.contains(':') {
+            return Some(variables.trim().to_string());
+        }
+        return variables.split(';').find_map(|variable| {
+            let (name, value) = variable.split_once(':')?;
+            (name.trim().eq_ignore_ascii_case("mode")).then(|| value.trim().to_string())
+        });
+    }
+
+    let start = ["vim:", "vi:", "ex:"]
+        .iter()
+        .filter_map(|marker| {
+            let at = line.find(marker)?;
+            let preceded_by_space = at == 0 || line[..at].ends_with(char::is_whitespace);
+            preceded_by_space.then_some(at + marker.len())
+        })
+        .min()?;
+
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            let (name, value) = option.split_once('=')?;
+            matches!(name, "ft" | "filetype" | "syntax").then(|| value.to_string())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(path: &str, contents: &str) -> String {
+        LanguageRegistry::new().detect(path.to_string(), &Buffer::from(contents.to_string()))
+    }
+
+    #[test]
+    fn detects_by_file_name_and_extension() {
+        assert_eq!(detect("/src/main.rs", ""), "rust");
+        assert_eq!(detect("Makefile", ""), "makefile");
+        assert_eq!(detect("docker/Dockerfile.dev", ""), "dockerfile");
+        assert_eq!(detect(".bashrc", ""), "shell");
+        assert_eq!(detect("Theme.tmTheme", ""), "xml");
+        assert_eq!(detect("types.d.ts", ""), "typescript");
+        assert_eq!(detect("archive.tar.gz", ""), "plaintext");
+        assert_eq!(detect("README", ""), "plaintext");
+    }
+
+    #[test]
+    fn detects_by_shebang() {
+        assert_eq!(detect("run", "#!/bin/bash\necho hi\n"), "shell");
+        assert_eq!(detect("run", "#!/usr/bin/python3.12\n"), "python");
+        assert_eq!(detect("run", "#!/usr/bin/env node\n"), "javascript");
+        assert_eq!(
+            detect("run", "#!/usr/bin/env -S PYTHONPATH=. python3 -u\n"),
+            "python"
+        );
+        // The extension wins over the interpreter.
+        assert_eq!(detect("tool.rb", "#!/bin/sh\n"), "ruby");
+        assert_eq!(detect("run", "# !/bin/sh\n"), "plaintext");
+    }
+
+    #[test]
+    fn modelines_win_over_the_path() {
+        assert_eq!(detect("notes.txt", "// vim: set ft=rust:\n"), "rust");
+        assert_eq!(detect("notes.txt", "# vi: filetype=sh\n"), "shell");
+        assert_eq!(
+            detect("build", "# -*- mode: Ruby; coding: utf-8 -*-\n"),
+            "ruby"
+        );
+        assert_eq!(detect("build", "-*- python -*-\n"), "python");
+
+        // Modelines are read from the last lines too, but not the middle.
+        let tail = format!("{}# vim: ft=yaml\n", "x\n".repeat(8));
+        assert_eq!(detect("conf", &tail), "yaml");
+        let middle = format!("{}# vim: ft=yaml\n{}", "x\n".repeat(6), "x\n".repeat(6));
+        assert_eq!(detect("conf", &middle), "plaintext");
+
+        // `vim:` needs whitespace before it, and unknown names are ignored.
+        assert_eq!(detect("a.rs", "novim: ft=python\n"), "rust");
+        assert_eq!(detect("a.rs", "# vim: ft=klingon\n"), "rust");
+    }
+
+    #[test]
+    fn registered_languages_override_built_in_ones() {
+        let mut registry = LanguageRegistry::new();
+        let mut info = registry.language("rust".to_string()).unwrap();
+        info.id = "ferris".to_string();
+        info.aliases = vec!["crab".to_string()];
+        registry.register(info).unwrap();
+
+        let buffer = Buffer::new();
+        assert_eq!(registry.detect("main.rs".to_string(), &buffer), "ferris");
+        let buffer = Buffer::from("// vim: ft=crab\n".to_string());
+        assert_eq!(registry.detect("a.txt".to_string(), &buffer), "ferris");
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::bail;
+use crop::{Rope, RopeBuilder};
+use flutter_rust_bridge::frb;
+use memmap2::Mmap;
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use super::buffer::Buffer;
+use super::encoding::Encoding;
+
+const CHUNK_SIZE: usize = 1 << 20;
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy, Debug)]
+pub struct LargeFileThresholds {
+    pub highlighting_bytes: u64,
+    pub line_length_tracking_bytes: u64,
+}
+
+impl Default for LargeFileThresholds {
+    #[frb(sync)]
+    fn default() -> Self {
+        Self {
+            highlighting_bytes: 8 << 20,
+            line_length_tracking_bytes: 32 << 20,
+        }
+    }
+}
+
+// Features that get too expensive on very large buffers.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferFeatures {
+    pub highlighting: bool,
+    pub line_length_tracking: bool,
+}
+
+impl Default for BufferFeatures {
+    #[frb(sync)]
+    fn default() -> Self {
+        Self {
+            highlighting: true,
+            line_length_tracking: true,
+        }
+    }
+}
+
+impl BufferFeatures {
+    #[frb(sync, type_64bit_int)]
+    pub fn for_size(byte_len: u64, thresholds: &LargeFileThresholds) -> Self {
+        Self {
+            highlighting: byte_len <= thresholds.highlighting_bytes,
+            line_length_tracking: byte_len <= thresholds.line_length_tracking_bytes,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn is_large_file(&self) -> bool {
+        !self.highlighting || !self.line_length_tracking
+    }
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy, Debug)]
+pub struct LoadProgress {
+    pub loaded_bytes: u64,
+    pub total_bytes: u64,
+    pub done: bool,
+}
+
+struct LoadState {
+    loaded_bytes: AtomicU64,
+    total_bytes: u64,
+    done: AtomicBool,
+    cancelled: AtomicBool,
+}
+
+// Loads a file into a `Buffer` on a background thread, reading it through a
+// memory map so the contents are never held as one large `String`.
+#[frb(opaque)]
+pub struct FileLoader {
+    state: Arc<LoadState>,
+    worker: Option<JoinHandle<anyhow::Result<Buffer>>>,
+}
+
+impl FileLoader {
+    #[frb(sync)]
+    pub fn start(
+        path: String,
+        thresholds: LargeFileThresholds,
+        encoding: Encoding,
+    ) -> anyhow::Result<Self> {
+        let file = File::open(&path)?;
+        let total_bytes = file.metadata()?.len();
+
+        let state = Arc::new(LoadState {
+            loaded_bytes: AtomicU64::new(0),
+            total_bytes,
+            done: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+        });
+
+        let worker_state = state.clone();
+        let worker = thread::spawn(move || {
+            let result = load(&file, &thresholds, encoding, &worker_state);
+            worker_state.done.store(true, Ordering::Release);
+            result
+        });
+
+        Ok(Self {
+            state,
+            worker: Some(worker),
+        })
+    }
+
+    #[frb(sync)]
+    pub fn progress(&self) -> LoadProgress {
+        LoadProgress {
+            loaded_bytes: self.state.loaded_bytes.load(Ordering::Relaxed),
+            total_bytes: self.state.total_bytes,
+            done: self.state.done.load(Ordering::Acquire),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // Waits for the load to complete and takes the resulting buffer.
+    pub fn finish(&mut self) -> anyhow::Result<Buffer> {
+        match self.worker.take() {
+            Some(worker) => match worker.join() {
+                Ok(result) => result,
+                Err(_) => bail!("file loader panicked"),
+            },
+            None => bail!("file loader already finished"),
+        }
+    }
+}
+
+impl Drop for FileLoader {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+impl Buffer {
+    // Loads a file on the calling thread, applying the same large-file
+    // thresholds as `FileLoader`. Saving writes it back in `encoding`.
+    pub fn open(
+        path: String,
+        thresholds: LargeFileThresholds,
+        encoding: Encoding,
+    ) -> anyhow::Result<Buffer> {
+        let file = File::open(&path)?;
+        let state = LoadState {
+            loaded_bytes: AtomicU64::new(0),
+            total_bytes: file.metadata()?.len(),
+            done: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+        };
+
+        load(&file, &thresholds, encoding, &state)
+    }
+}
+
+fn load(
+    file: &File,
+    thresholds: &LargeFileThresholds,
+    encoding: Encoding,
+    state: &LoadState,
+) -> anyhow::Result<Buffer> {
+    let features = BufferFeatures::for_size(state.total_bytes, thresholds);
+    if state.total_bytes == 0 {
+        let mut buffer = Buffer::from_rope(Rope::new(), features, 0);
+        buffer.set_encoding(encoding);
+        return Ok(buffer);
+    }
+
+    // Safety: the map is only read while loading, and a file truncated by
+    // another process mid-load is an accepted risk, as in most editors.
+    let mmap = unsafe { Mmap::map(file)? };
+
+    let mut builder = RopeBuilder::new();
+    let mut longest_line = 0;
+    let mut line_len = 0;
+    let mut offset = 0;
+    let mut lossy = false;
+
+    while offset < mmap.len() {
+        if state.cancelled.load(Ordering::Relaxed) {
+            bail!("load cancelled");
+        }
+
+        let end = (offset + CHUNK_SIZE).min(mmap.len());
+        let (text, consumed, replaced) =
+            encoding.decode_chunk(&mmap[offset..end], end == mmap.len());
+        lossy |= replaced;
+
+        for (idx, segment) in text.split('\n').enumerate() {
+            if idx > 0 {
+                longest_line = longest_line.max(line_len);
+                line_len = 0;
+            }
+            line_len += segment.len();
+        }
+
+        builder.append(text.as_ref());
+        offset += consumed;
+        state.loaded_bytes.store(offset as u64, Ordering::Relaxed);
+    }
+
+    longest_line = longest_line.max(line_len);
+    let mut buffer = Buffer::from_rope(builder.build(), features, longest_line);
+    buffer.set_lossy(lossy);
+    buffer.set_encoding(encoding);
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::language::LanguageRegistry;
+    use crate::api::save::{SaveActions, SavePipeline};
+    use std::fs;
+
+    #[test]
+    fn invalid_utf8_marks_the_buffer_lossy_and_blocks_saving() {
+        let dir = std::env::temp_dir().join(format!("rei-large-file-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("latin1.rs");
+        fs::write(&path, b"caf\xe9\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let mut buffer =
+            Buffer::open(path.clone(), LargeFileThresholds::default(), Encoding::Utf8).unwrap();
+        assert!(buffer.is_lossy());
+        assert_eq!(buffer.to_string(), "caf\u{FFFD}\n");
+
+        let pipeline = SavePipeline::new(SaveActions::default());
+        assert!(buffer.save(path.clone(), &pipeline).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9\n");
+
+        buffer.clear_lossy();
+        buffer.save(path.clone(), &pipeline).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "caf\u{FFFD}\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn large_buffers_are_not_highlighted() {
+        let registry = LanguageRegistry::new();
+        let small = Buffer::from("fn main() {}\n".to_string());
+        assert_eq!(
+            registry.grammar_for("rust".to_string(), &small),
+            Some("source.rust".to_string())
+        );
+
+        let thresholds = LargeFileThresholds {
+            highlighting_bytes: 4,
+            ..LargeFileThresholds::default()
+        };
+        let large = Buffer::from_rope(
+            Rope::from("fn main() {}\n"),
+            BufferFeatures::for_size(13, &thresholds),
+            0,
+        );
+        assert_eq!(registry.grammar_for("rust".to_string(), &large), None);
+    }
+}
@@ -0,0 +1,310 @@
+use std::ops::Range;
+
+const NIL: u32 = u32::MAX;
+
+#[derive(Clone, Copy)]
+struct Node {
+    left: u32,
+    right: u32,
+    priority: u32,
+    length: usize,
+    // Summaries of the subtree rooted at this node.
+    count: usize,
+    max: usize,
+}
+
+// Byte lengths of the buffer's rows, kept in an implicit treap ordered by row
+// so splicing the rows touched by an edit costs O(log n) regardless of how many
+// rows follow it, and the longest row is always available from the root.
+#[derive(Clone)]
+pub(crate) struct LineMetrics {
+    nodes: Vec<Node>,
+    free: Vec<u32>,
+    root: u32,
+}
+
+impl LineMetrics {
+    pub fn from_lengths(lengths: impl IntoIterator<Item = usize>) -> Self {
+        let mut metrics = Self {
+            nodes: Vec::new(),
+            free: Vec::new(),
+            root: NIL,
+        };
+        metrics.root = metrics.build(lengths);
+        metrics
+    }
+
+    pub fn get(&self, row: usize) -> Option<usize> {
+        let mut idx = self.root;
+        let mut row = row;
+
+        while idx != NIL {
+            let node = &self.nodes[idx as usize];
+            let left_count = self.count(node.left);
+
+            if row < left_count {
+                idx = node.left;
+            } else if row == left_count {
+                return Some(node.length);
+            } else {
+                row -= left_count + 1;
+                idx = node.right;
+            }
+        }
+
+        None
+    }
+
+    pub fn max(&self) -> usize {
+        self.max_of(self.root)
+    }
+
+    // Replaces the lengths of the rows in `rows` with `lengths`, shifting every
+    // later row by the difference in count.
+    pub fn splice(&mut self, rows: Range<usize>, lengths: impl IntoIterator<Item = usize>) {
+        let (before, rest) = self.split(self.root, rows.start);
+        let (removed, after) = self.split(rest, rows.end.saturating_sub(rows.start));
+        self.release(removed);
+
+        let inserted = self.build(lengths);
+        let merged = self.merge(before, inserted);
+        self.root = self.merge(merged, after);
+    }
+
+    fn count(&self, idx: u32) -> usize {
+        if idx == NIL {
+            0
+        } else {
+            self.nodes[idx as usize].count
+        }
+    }
+
+    fn max_of(&self, idx: u32) -> usize {
+        if idx == NIL {
+            0
+        } else {
+            self.nodes[idx as usize].max
+        }
+    }
+
+    fn update(&mut self, idx: u32) {
+        let Node {
+            left,
+            right,
+            length,
+            ..
+        } = self.nodes[idx as usize];
+
+        let count = self.count(left) + self.count(right) + 1;
+        let max = length.max(self.max_of(left)).max(self.max_of(right));
+
+        let node = &mut self.nodes[idx as usize];
+        node.count = count;
+        node.max = max;
+    }
+
+    fn alloc(&mut self, length: usize) -> u32 {
+        let node = Node {
+            left: NIL,
+            right: NIL,
+            priority: rand::random(),
+            length,
+            count: 1,
+            max: length,
+        };
+
+        match self.free.pop() {
+            Some(idx) => {
+                self.nodes[idx as usize] = node;
+                idx
+            }
+            None => {
+                self.nodes.push(node);
+                (self.nodes.len() - 1) as u32
+            }
+        }
+    }
+
+    fn release(&mut self, idx: u32) {
+        let mut stack = vec![idx];
+
+        while let Some(idx) = stack.pop() {
+            if idx == NIL {
+                continue;
+            }
+
+            let node = self.nodes[idx as usize];
+            stack.push(node.left);
+            stack.push(node.right);
+            self.free.push(idx);
+        }
+    }
+
+    // Builds a subtree from lengths in row order in linear time, keeping the
+    // rightmost path on a stack as in a Cartesian tree construction. A node's
+    // summaries are final once it leaves the stack.
+    fn build(&mut self, lengths: impl IntoIterator<Item = usize>) -> u32 {
+        let mut stack: Vec<u32> = Vec::new();
+
+        for length in lengths {
+            let idx = self.alloc(length);
+            let priority = self.nodes[idx as usize].priority;
+            let mut last = NIL;
+
+            while let Some(&top) = stack.last() {
+                if self.nodes[top as usize].priority >= priority {
+                    break;
+                }
+
+                stack.pop();
+                self.update(top);
+                last = top;
+            }
+
+            self.nodes[idx as usize].left = last;
+            if let Some(&top) = stack.last() {
+                self.nodes[top as usize].right = idx;
+            }
+            stack.push(idx);
+        }
+
+        let root = stack.first().copied().unwrap_or(NIL);
+        while let Some(top) = stack.pop() {
+            self.update(top);
+        }
+
+        root
+    }
+
+    // Splits off the first `count` rows of the subtree into the left result.
+    fn split(&mut self, idx: u32, count: usize) -> (u32, u32) {
+        if idx == NIL {
+            return (NIL, NIL);
+        }
+
+        let node = self.nodes[idx as usize];
+        let left_count = self.count(node.left);
+
+        if count <= left_count {
+            let (left, right) = self.split(node.left, count);
+            self.nodes[idx as usize].left = right;
+            self.update(idx);
+            (left, idx)
+        } else {
+            let (left, right) = self.split(node.right, count - left_count - 1);
+            self.nodes[idx as usize].right = left;
+            self.update(idx);
+            (idx, right)
+        }
+    }
+
+    fn merge(&mut self, left: u32, right: u32) -> u32 {
+        if left == NIL {
+            return right;
+        }
+        if right == NIL {
+            return left;
+        }
+
+        if self.nodes[left as usize].priority > self.nodes[right as usize].priority {
+            let merged = self.merge(self.nodes[left as usize].right, right);
+            self.nodes[left as usize].right = merged;
+            self.update(left);
+            left
+        } else {
+            let merged = self.merge(left, self.nodes[right as usize].left);
+            self.nodes[right as usize].left = merged;
+            self.update(right);
+            right
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::buffer::Buffer;
+    use rand::{Rng, SeedableRng};
+
+    fn assert_matches(metrics: &LineMetrics, model: &[usize]) {
+        for (row, &length) in model.iter().enumerate() {
+            assert_eq!(metrics.get(row), Some(length), "row {row}");
+        }
+        assert_eq!(metrics.get(model.len()), None);
+        assert_eq!(metrics.max(), model.iter().copied().max().unwrap_or(0));
+    }
+
+    #[test]
+    fn splices_match_a_vec_model() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(34);
+        let mut model: Vec<usize> = (0..50).map(|_| rng.random_range(0..100)).collect();
+        let mut metrics = LineMetrics::from_lengths(model.iter().copied());
+        assert_matches(&metrics, &model);
+
+        for _ in 0..2000 {
+            let start = rng.random_range(0..=model.len());
+            let end = rng.random_range(start..=model.len().min(start + 8));
+            let lengths: Vec<usize> = (0..rng.random_range(0..8))
+                .map(|_| rng.random_range(0..1000))
+                .collect();
+
+            metrics.splice(start..end, lengths.iter().copied());
+            model.splice(start..end, lengths);
+            assert_matches(&metrics, &model);
+        }
+    }
+
+    fn assert_buffer_lengths(buffer: &Buffer) {
+        let text = buffer.to_string();
+        let lines: Vec<usize> = text.split('\n').map(str::len).collect();
+        for (row, &length) in lines.iter().enumerate() {
+            assert_eq!(buffer.line_len(row), length, "row {row} of {text:?}");
+        }
+        assert_eq!(
+            buffer.max_line_length(),
+            lines.iter().copied().max().unwrap_or(0),
+            "{text:?}"
+        );
+    }
+
+    #[test]
+    fn buffer_lengths_follow_edits_around_trailing_newlines() {
+        let mut buffer = Buffer::from("abc\n".to_string());
+        assert_buffer_lengths(&buffer);
+
+        // Typing on the empty row after the trailing newline.
+        buffer.insert(1, 0, "defgh".to_string());
+        assert_buffer_lengths(&buffer);
+        buffer.insert(1, 5, "\n".to_string());
+        assert_buffer_lengths(&buffer);
+
+        // Removing the trailing newline, then the longest row.
+        let last = buffer.last_row();
+        buffer.replace_range(last - 1, 5, last, 0, String::new());
+        assert_buffer_lengths(&buffer);
+        buffer.replace_range(0, 3, 1, 5, String::new());
+        assert_eq!(buffer.to_string(), "abc");
+        assert_buffer_lengths(&buffer);
+
+        buffer.replace_range(0, 0, 0, 3, String::new());
+        assert_buffer_lengths(&buffer);
+        buffer.insert(0, 0, "\n\n".to_string());
+        assert_buffer_lengths(&buffer);
+    }
+
+    #[test]
+    fn buffer_lengths_follow_random_edits() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let mut buffer = Buffer::from("one\ntwo\n\nthree\n".to_string());
+        let pieces = ["", "\n", "x", "long line\n", "\n\n", "ab\ncd"];
+
+        for _ in 0..500 {
+            let len = buffer.to_string().len();
+            let start = rng.random_range(0..=len);
+            let end = rng.random_range(start..=len.min(start + 12));
+            let text = pieces[rng.random_range(0..pieces.len())];
+            buffer.replace_bytes(start..end, text);
+            assert_buffer_lengths(&buffer);
+        }
+    }
+}
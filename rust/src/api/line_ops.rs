@@ -0,0 +1,502 @@
+use flutter_rust_bridge::frb;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use super::buffer::{Buffer, TextEdit};
+use super::cursor::Cursor;
+use super::editorconfig::Indentation;
+use super::selection::Selection;
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Copy)]
+pub struct EditResult {
+    pub cursor: Cursor,
+    pub selection: Selection,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortKind {
+    Lexical,
+    Numeric,
+    CaseInsensitive,
+}
+
+impl Buffer {
+    #[frb(sync)]
+    pub fn duplicate_lines(
+        &mut self,
+        cursor: Cursor,
+        selection: Selection,
+        up: bool,
+    ) -> EditResult {
+        let (start_row, end_row) = self.selected_rows(cursor, selection);
+        let lines = self.lines_in_rows(start_row, end_row);
+        let text = lines.join("\n");
+
+        self.replace_rows(start_row, end_row, &format!("{text}\n{text}"));
+
+        if up {
+            EditResult { cursor, selection }
+        } else {
+            let offset = (end_row - start_row + 1) as isize;
+            shift_rows(cursor, selection, offset)
+        }
+    }
+
+    #[frb(sync)]
+    pub fn move_lines_up(&mut self, cursor: Cursor, selection: Selection) -> EditResult {
+        let (start_row, end_row) = self.selected_rows(cursor, selection);
+        if start_row == 0 {
+            return EditResult { cursor, selection };
+        }
+
+        let mut lines = self.lines_in_rows(start_row - 1, end_row);
+        lines.rotate_left(1);
+        self.replace_rows(start_row - 1, end_row, &lines.join("\n"));
+
+        shift_rows(cursor, selection, -1)
+    }
+
+    #[frb(sync)]
+    pub fn move_lines_down(&mut self, cursor: Cursor, selection: Selection) -> EditResult {
+        let (start_row, end_row) = self.selected_rows(cursor, selection);
+        if end_row >= self.last_row() {
+            return EditResult { cursor, selection };
+        }
+
+        let mut lines = self.lines_in_rows(start_row, end_row + 1);
+        lines.rotate_right(1);
+        self.replace_rows(start_row, end_row + 1, &lines.join("\n"));
+
+        shift_rows(cursor, selection, 1)
+    }
+
+    #[frb(sync)]
+    pub fn join_lines(&mut self, cursor: Cursor, selection: Selection) -> EditResult {
+        let (start_row, mut end_row) = self.selected_rows(cursor, selection);
+        if start_row == end_row {
+            end_row += 1;
+        }
+        if end_row > self.last_row() {
+            return EditResult { cursor, selection };
+        }
+
+        let lines = self.lines_in_rows(start_row, end_row);
+        let mut joined = lines[0].clone();
+        let mut join_column = joined.len();
+
+        for line in &lines[1..] {
+            let line = line.trim_start();
+
+            joined.truncate(joined.trim_end().len());
+            join_column = joined.len();
+            if !joined.is_empty() && !line.is_empty() {
+                joined.push(' ');
+            }
+            joined.push_str(line);
+        }
+
+        self.replace_rows(start_row, end_row, &joined);
+
+        let new_cursor = Cursor::new(start_row, join_column, join_column);
+        if selection.is_empty() {
+            EditResult {
+                cursor: new_cursor,
+                selection: Selection::default(),
+            }
+        } else {
+            self.select_rows(start_row, start_row)
+        }
+    }
+
+    #[frb(sync)]
+    pub fn delete_lines(&mut self, cursor: Cursor, selection: Selection) -> EditResult {
+        let (start_row, end_row) = self.selected_rows(cursor, selection);
+        let last_row = self.last_row();
+
+        if end_row < last_row {
+            let start_idx = self.byte_of_line(start_row);
+            let end_idx = self.byte_of_line(end_row + 1);
+            self.replace_bytes(start_idx..end_idx, "");
+        } else if start_row > 0 {
+            let start_idx = self.byte_of_line(start_row - 1) + self.line(start_row - 1).len();
+            let (_, end_idx) = self.row_byte_range(start_row, end_row);
+            self.replace_bytes(start_idx..end_idx, "");
+        } else {
+            let (start_idx, end_idx) = self.row_byte_range(start_row, end_row);
+            self.replace_bytes(start_idx..end_idx, "");
+        }
+
+        let row = start_row.min(self.last_row());
+        let column = cursor.sticky_column.min(self.line(row).len());
+
+        EditResult {
+            cursor: Cursor::new(row, column, cursor.sticky_column),
+            selection: Selection::default(),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn sort_lines(
+        &mut self,
+        cursor: Cursor,
+        selection: Selection,
+        kind: SortKind,
+        unique: bool,
+    ) -> EditResult {
+        let (start_row, end_row) = self.rows_or_document(cursor, selection);
+        let mut lines = self.lines_in_rows(start_row, end_row);
+
+        match kind {
+            SortKind::Lexical => lines.sort(),
+            SortKind::CaseInsensitive => lines.sort_by_cached_key(|line| line.to_lowercase()),
+            SortKind::Numeric => lines.sort_by(|a, b| compare_numeric(a, b)),
+        }
+
+        if unique {
+            let mut seen = HashSet::new();
+            lines.retain(|line| match kind {
+                SortKind::CaseInsensitive => seen.insert(line.to_lowercase()),
+                _ => seen.insert(line.clone()),
+            });
+        }
+
+        self.rewrite_rows(cursor, selection, start_row, end_row, lines)
+    }
+
+    #[frb(sync)]
+    pub fn reverse_lines(&mut self, cursor: Cursor, selection: Selection) -> EditResult {
+        let (start_row, end_row) = self.rows_or_document(cursor, selection);
+        let mut lines = self.lines_in_rows(start_row, end_row);
+        lines.reverse();
+
+        self.rewrite_rows(cursor, selection, start_row, end_row, lines)
+    }
+
+    #[frb(sync)]
+    pub fn indent_lines(
+        &mut self,
+        cursor: Cursor,
+        selection: Selection,
+        indentation: Indentation,
+    ) -> EditResult {
+        self.shift_lines(cursor, selection, true, indentation)
+    }
+
+    #[frb(sync)]
+    pub fn outdent_lines(
+        &mut self,
+        cursor: Cursor,
+        selection: Selection,
+        indentation: Indentation,
+    ) -> EditResult {
+        self.shift_lines(cursor, selection, false, indentation)
+    }
+
+    fn shift_lines(
+        &mut self,
+        cursor: Cursor,
+        selection: Selection,
+        indent: bool,
+        indentation: Indentation,
+    ) -> EditResult {
+        let (start_row, end_row) = self.selected_rows(cursor, selection);
+        let deltas = self.shift_indentation(start_row, end_row, indent, indentation);
+        let shift = |position: Cursor| {
+            if position.row < start_row || position.row > end_row {
+                return position;
+            }
+
+            let column = position
+                .column
+                .saturating_add_signed(deltas[position.row - start_row]);
+            Cursor::new(position.row, column, column)
+        };
+
+        EditResult {
+            cursor: shift(cursor),
+            selection: if selection.is_empty() {
+                selection
+            } else {
+                Selection::new(shift(selection.start), shift(selection.end))
+            },
+        }
+    }
+
+    // Adds or removes one level of indentation on each row, leaving empty rows
+    // alone, and returns how many bytes each row grew by. Outdenting removes a
+    // leading tab or up to `indentation.size` leading spaces.
+    pub(crate) fn shift_indentation(
+        &mut self,
+        start_row: usize,
+        end_row: usize,
+        indent: bool,
+        indentation: Indentation,
+    ) -> Vec<isize> {
+        let unit = indentation.unit();
+        let mut deltas = Vec::new();
+        let edits = (start_row..=end_row)
+            .filter_map(|row| {
+                let line = self.line(row);
+                let (edit, delta) = if indent {
+                    let edit = (!line.is_empty()).then(|| TextEdit {
+                        start_row: row,
+                        start_column: 0,
+                        end_row: row,
+                        end_column: 0,
+                        text: unit.clone(),
+                    });
+                    let delta = if edit.is_some() { unit.len() } else { 0 };
+                    (edit, delta as isize)
+                } else {
+                    let width = if line.starts_with('\t') {
+                        1
+                    } else {
+                        let spaces = line.len() - line.trim_start_matches(' ').len();
+                        spaces.min(indentation.size.max(1))
+                    };
+                    let edit = (width > 0).then(|| TextEdit {
+                        start_row: row,
+                        start_column: 0,
+                        end_row: row,
+                        end_column: width,
+                        text: String::new(),
+                    });
+                    (edit, -(width as isize))
+                };
+                deltas.push(delta);
+                edit
+            })
+            .collect();
+
+        self.apply_edits(edits);
+        deltas
+    }
+
+    // Rows touched by a line command. A selection ending at column 0 does not
+    // include its last row.
+    pub(crate) fn selected_rows(&self, cursor: Cursor, selection: Selection) -> (usize, usize) {
+        if selection.is_empty() {
+            return (cursor.row, cursor.row);
+        }
+
+        let normalized = selection.normalized();
+        let end_row = if normalized.end.column == 0 && normalized.end.row > normalized.start.row {
+            normalized.end.row - 1
+        } else {
+            normalized.end.row
+        };
+
+        (normalized.start.row, end_row)
+    }
+
+    pub(crate) fn last_row(&self) -> usize {
+        self.line_count_with_trailing_newline().max(1) - 1
+    }
+
+    pub(crate) fn lines_in_rows(&self, start_row: usize, end_row: usize) -> Vec<String> {
+        (start_row..=end_row).map(|row| self.line(row)).collect()
+    }
+
+    // Byte range covering the contents of `start_row..=end_row`, excluding the
+    // final line break.
+    pub(crate) fn row_byte_range(&self, start_row: usize, end_row: usize) -> (usize, usize) {
+        let start_idx = self.byte_of_line(start_row);
+        let end_idx = self.byte_of_line(end_row) + self.line(end_row).len();
+
+        (start_idx, end_idx)
+    }
+
+    pub(crate) fn replace_rows(&mut self, start_row: usize, end_row: usize, text: &str) {
+        let (start_idx, end_idx) = self.row_byte_range(start_row, end_row);
+        self.replace_bytes(start_idx..end_idx, text);
+    }
+
+    fn rows_or_document(&self, cursor: Cursor, selection: Selection) -> (usize, usize) {
+        let (start_row, end_row) = self.selected_rows(cursor, selection);
+        if start_row == end_row {
+            (0, self.line_count().max(1) - 1)
+        } else {
+            (start_row, end_row)
+        }
+    }
+
+    fn rewrite_rows(
+        &mut self,
+        cursor: Cursor,
+        selection: Selection,
+        start_row: usize,
+        end_row: usize,
+        lines: Vec<String>,
+    ) -> EditResult {
+        let new_end_row = start_row + lines.len().max(1) - 1;
+        self.replace_rows(start_row, end_row, &lines.join("\n"));
+
+        if selection.is_empty() {
+            let row = cursor.row.min(self.last_row());
+            let column = cursor.column.min(self.line(row).len());
+
+            EditResult {
+                cursor: Cursor::new(row, column, column),
+                selection,
+            }
+        } else {
+            self.select_rows(start_row, new_end_row)
+        }
+    }
+
+    fn select_rows(&self, start_row: usize, end_row: usize) -> EditResult {
+        let end_column = self.line(end_row).len();
+        let start = Cursor::new(start_row, 0, 0);
+        let end = Cursor::new(end_row, end_column, end_column);
+
+        EditResult {
+            cursor: end,
+            selection: Selection::new(start, end),
+        }
+    }
+}
+
+fn shift_rows(cursor: Cursor, selection: Selection, offset: isize) -> EditResult {
+    let shift = |cursor: Cursor| Cursor {
+        row: cursor.row.saturating_add_signed(offset),
+        ..cursor
+    };
+
+    EditResult {
+        cursor: shift(cursor),
+        selection: if selection.is_empty() {
+            selection
+        } else {
+            Selection::new(shift(selection.start), shift(selection.end))
+        },
+    }
+}
+
+// Orders lines by their leading number, placing lines without one first and
+// falling back to lexical order.
+fn compare_numeric(a: &str, b: &str) -> Ordering {
+    match (leading_number(a), leading_number(b)) {
+        (Some(x), Some(y)) => x
+            .partial_cmp(&y)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.cmp(b)),
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let mut end = 0;
+    let mut seen_digit = false;
+    let mut seen_dot = false;
+
+    for (idx, ch) in line.char_indices() {
+        match ch {
+            '-' | '+' if idx == 0 => {}
+            '.' if !seen_dot => seen_dot = true,
+            '0'..='9' => seen_digit = true,
+            _ => break,
+        }
+        end = idx + ch.len_utf8();
+    }
+
+    if !seen_digit {
+        return None;
+    }
+
+    line[..end].trim_end_matches('.').parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(row: usize, column: usize) -> Cursor {
+        Cursor::new(row, column, column)
+    }
+
+    fn position(cursor: Cursor) -> (usize, usize) {
+        (cursor.row, cursor.column)
+    }
+
+    #[test]
+    fn moving_stops_at_the_first_and_last_rows() {
+        let mut buffer = Buffer::from("a\nb\nc".to_string());
+        let result = buffer.move_lines_up(at(0, 1), Selection::default());
+        assert_eq!(position(result.cursor), (0, 1));
+        let result = buffer.move_lines_down(at(2, 0), Selection::default());
+        assert_eq!(position(result.cursor), (2, 0));
+        assert_eq!(buffer.to_string(), "a\nb\nc");
+
+        let result = buffer.move_lines_up(at(2, 1), Selection::default());
+        assert_eq!(buffer.to_string(), "a\nc\nb");
+        assert_eq!(position(result.cursor), (1, 1));
+
+        // A selection ending at column 0 leaves its last row in place.
+        let selection = Selection::new(at(0, 0), at(2, 0));
+        let result = buffer.move_lines_down(at(2, 0), selection);
+        assert_eq!(buffer.to_string(), "b\na\nc");
+        assert_eq!(position(result.selection.start), (1, 0));
+        assert_eq!(position(result.selection.end), (3, 0));
+    }
+
+    #[test]
+    fn joining_trims_the_seam_and_stops_at_the_last_row() {
+        let mut buffer = Buffer::from("a  \n   b\n\nc".to_string());
+        let result = buffer.join_lines(at(0, 0), Selection::default());
+        assert_eq!(buffer.to_string(), "a b\n\nc");
+        assert_eq!(position(result.cursor), (0, 1));
+
+        // Joining an empty row adds no space.
+        let result = buffer.join_lines(at(1, 0), Selection::default());
+        assert_eq!(buffer.to_string(), "a b\nc");
+        assert_eq!(position(result.cursor), (1, 0));
+
+        let result = buffer.join_lines(at(1, 1), Selection::default());
+        assert_eq!(buffer.to_string(), "a b\nc");
+        assert_eq!(position(result.cursor), (1, 1));
+    }
+
+    #[test]
+    fn deleting_the_first_last_and_only_rows() {
+        let mut buffer = Buffer::from("a\nb\nc".to_string());
+        buffer.delete_lines(at(0, 0), Selection::default());
+        assert_eq!(buffer.to_string(), "b\nc");
+
+        let result = buffer.delete_lines(at(1, 1), Selection::default());
+        assert_eq!(buffer.to_string(), "b");
+        assert_eq!(position(result.cursor), (0, 1));
+
+        let result = buffer.delete_lines(at(0, 0), Selection::default());
+        assert_eq!(buffer.to_string(), "");
+        assert_eq!(position(result.cursor), (0, 0));
+    }
+
+    #[test]
+    fn sorting_keeps_the_trailing_newline() {
+        let mut buffer = Buffer::from("b\n10\n9\na\n".to_string());
+        buffer.sort_lines(at(0, 0), Selection::default(), SortKind::Numeric, false);
+        assert_eq!(buffer.to_string(), "a\nb\n9\n10\n");
+
+        let mut buffer = Buffer::from("b\nB\na\nb".to_string());
+        let result = buffer.sort_lines(
+            at(3, 1),
+            Selection::default(),
+            SortKind::CaseInsensitive,
+            true,
+        );
+        assert_eq!(buffer.to_string(), "a\nb");
+        // The cursor was on a row that no longer exists.
+        assert_eq!(position(result.cursor), (1, 1));
+
+        let mut buffer = Buffer::from("z\nc\nb\na".to_string());
+        let selection = Selection::new(at(1, 0), at(3, 1));
+        let result = buffer.reverse_lines(at(3, 1), selection);
+        assert_eq!(buffer.to_string(), "z\na\nb\nc");
+        assert_eq!(position(result.selection.start), (1, 0));
+        assert_eq!(position(result.selection.end), (3, 1));
+    }
+}
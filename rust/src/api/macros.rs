@@ -0,0 +1,423 @@
+use anyhow::bail;
+use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use super::anchor::Bias;
+use super::buffer::Buffer;
+use super::command::CommandRegistry;
+use super::cursor::Cursor;
+use super::selection::Selection;
+use super::session::write_atomically;
+
+// One recorded command, with its arguments as given to `invoke`.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacroStep {
+    pub command: String,
+    pub args: String,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone)]
+pub struct MacroPlayback {
+    pub cursor: Cursor,
+    pub selection: Selection,
+    // Where each cursor ended up, in the order they were given.
+    pub cursors: Vec<Cursor>,
+    // How many runs finished before playback stopped.
+    pub completed_runs: usize,
+    // Why playback stopped early, naming the failed step.
+    pub error: Option<String>,
+}
+
+impl CommandRegistry {
+    // Starts recording the commands run through this registry, discarding any
+    // unfinished recording. Host commands are not recorded, since they cannot
+    // be replayed here.
+    #[frb(sync)]
+    pub fn start_recording(&mut self) {
+        *self.recording_mut() = Some(Vec::new());
+    }
+
+    #[frb(sync)]
+    pub fn stop_recording(&mut self) -> Vec<MacroStep> {
+        self.recording_mut().take().unwrap_or_default()
+    }
+
+    #[frb(sync)]
+    pub fn is_recording(&self) -> bool {
+        self.recording().is_some()
+    }
+
+    // Runs `steps` `times` times, stopping at the first step that fails.
+    #[frb(sync, type_64bit_int)]
+    pub fn play(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        selection: Selection,
+        steps: Vec<MacroStep>,
+        times: usize,
+    ) -> MacroPlayback {
+        let mut playback = MacroPlayback {
+            cursor,
+            selection,
+            cursors: Vec::new(),
+            completed_runs: 0,
+            error: None,
+        };
+
+        for _ in 0..times {
+            let result = self.play_once(
+                buffer,
+                &mut playback.cursor,
+                &mut playback.selection,
+                &steps,
+            );
+            match result {
+                Ok(()) => playback.completed_runs += 1,
+                Err(err) => {
+                    playback.error = Some(format!("{err:#}"));
+                    break;
+                }
+            }
+        }
+
+        playback.cursors = vec![playback.cursor];
+        playback
+    }
+
+    // Runs `steps` once at each cursor. Positions are tracked with anchors, so
+    // edits made for one cursor move the others along with the text.
+    #[frb(sync)]
+    pub fn play_on_cursors(
+        &mut self,
+        buffer: &mut Buffer,
+        cursors: Vec<Cursor>,
+        steps: Vec<MacroStep>,
+    ) -> MacroPlayback {
+        let anchors: Vec<_> = cursors
+            .iter()
+            .map(|cursor| buffer.create_anchor(cursor.row, cursor.column, Bias::Right))
+            .collect();
+        let mut finished = anchors.clone();
+        let mut playback = MacroPlayback {
+            cursor: cursors.first().copied().unwrap_or_default(),
+            selection: Selection::default(),
+            cursors: Vec::new(),
+            completed_runs: 0,
+            error: None,
+        };
+
+        for (i, &anchor) in anchors.iter().enumerate() {
+            let Some((row, column)) = buffer.resolve_anchor(anchor) else {
+                continue;
+            };
+
+            let mut cursor = Cursor::new(row, column, column);
+            let result = self.play_once(buffer, &mut cursor, &mut Selection::default(), &steps);
+            finished[i] = buffer.create_anchor(cursor.row, cursor.column, Bias::Right);
+            match result {
+                Ok(()) => playback.completed_runs += 1,
+                Err(err) => {
+                    playback.error = Some(format!("cursor {}: {err:#}", i + 1));
+                    break;
+                }
+            }
+        }
+
+        playback.cursors = finished
+            .iter()
+            .zip(&cursors)
+            .map(|(&anchor, &original)| {
+                buffer
+                    .resolve_anchor(anchor)
+                    .map_or(original, |(row, column)| Cursor::new(row, column, column))
+            })
+            .collect();
+        if let Some(&first) = playback.cursors.first() {
+            playback.cursor = first;
+        }
+
+        for anchor in anchors.into_iter().chain(finished) {
+            buffer.drop_anchor(anchor);
+        }
+
+        playback
+    }
+
+    // Runs `steps` once, leaving `cursor` and `selection` where the last
+    // step that ran left them.
+    fn play_once(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: &mut Cursor,
+        selection: &mut Selection,
+        steps: &[MacroStep],
+    ) -> anyhow::Result<()> {
+        for (i, step) in steps.iter().enumerate() {
+            if self.is_host_command(&step.command) {
+                bail!(
+                    "step {} (`{}`): host commands cannot be replayed",
+                    i + 1,
+                    step.command
+                );
+            }
+
+            let (result, blocked) = self
+                .run(buffer, *cursor, *selection, &step.command, &step.args)
+                .map_err(|err| err.context(format!("step {} (`{}`)", i + 1, step.command)))?;
+            *cursor = result.cursor;
+            *selection = result.selection;
+            if blocked {
+                bail!("step {} (`{}`): cannot move further", i + 1, step.command);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredStep {
+    command: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    args: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MacroFile {
+    version: u32,
+    macros: BTreeMap<String, Vec<StoredStep>>,
+}
+
+// Named macros saved in the data directory.
+#[frb(opaque)]
+pub struct MacroStore {
+    dir: PathBuf,
+    macros: BTreeMap<String, Vec<MacroStep>>,
+}
+
+impl MacroStore {
+    const FILE_NAME: &'static str = "macros.json";
+    const FORMAT_VERSION: u32 = 1;
+
+    pub fn load(data_dir: String) -> anyhow::Result<Self> {
+        let dir = PathBuf::from(data_dir);
+        let path = dir.join(Self::FILE_NAME);
+
+        let macros = if path.exists() {
+            let file: MacroFile = serde_json::from_slice(&fs::read(path)?)?;
+            file.macros
+                .into_iter()
+                .map(|(name, steps)| {
+                    let steps = steps
+                        .into_iter()
+                        .map(|step| MacroStep {
+                            command: step.command,
+                            args: if step.args.is_null() {
+                                String::new()
+                            } else {
+                                step.args.to_string()
+                            },
+                        })
+                        .collect();
+                    (name, steps)
+                })
+                .collect()
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self { dir, macros })
+    }
+
+    #[frb(sync)]
+    pub fn names(&self) -> Vec<String> {
+        self.macros.keys().cloned().collect()
+    }
+
+    #[frb(sync)]
+    pub fn get(&self, name: String) -> Option<Vec<MacroStep>> {
+        self.macros.get(&name).cloned()
+    }
+
+    #[frb(sync)]
+    pub fn set(&mut self, name: String, steps: Vec<MacroStep>) {
+        self.macros.insert(name, steps);
+    }
+
+    #[frb(sync)]
+    pub fn remove(&mut self, name: String) -> bool {
+        self.macros.remove(&name).is_some()
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let macros = self
+            .macros
+            .iter()
+            .map(|(name, steps)| {
+                let steps = steps
+                    .iter()
+                    .map(|step| {
+                        let args = if step.args.trim().is_empty() {
+                            Value::Null
+                        } else {
+                            serde_json::from_str(&step.args)?
+                        };
+                        Ok(StoredStep {
+                            command: step.command.clone(),
+                            args,
+                        })
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+                Ok((name.clone(), steps))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let file = MacroFile {
+            version: Self::FORMAT_VERSION,
+            macros,
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        write_atomically(
+            &self.dir.join(Self::FILE_NAME),
+            &serde_json::to_vec_pretty(&file)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(row: usize, column: usize) -> Cursor {
+        Cursor::new(row, column, column)
+    }
+
+    fn step(command: &str, args: &str) -> MacroStep {
+        MacroStep {
+            command: command.to_string(),
+            args: args.to_string(),
+        }
+    }
+
+    #[test]
+    fn records_invoked_commands() {
+        let mut registry = CommandRegistry::new();
+        let mut buffer = Buffer::from("a\nb".to_string());
+        registry.start_recording();
+        assert!(registry.is_recording());
+
+        let args = r#"{"text": "- "}"#;
+        let result = registry
+            .invoke(
+                &mut buffer,
+                at(0, 0),
+                Selection::default(),
+                "editor.insert".to_string(),
+                args.to_string(),
+            )
+            .unwrap();
+        registry
+            .invoke(
+                &mut buffer,
+                result.cursor,
+                Selection::default(),
+                "editor.moveDown".to_string(),
+                String::new(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            registry.stop_recording(),
+            [step("editor.insert", args), step("editor.moveDown", "")]
+        );
+        assert!(!registry.is_recording());
+    }
+
+    #[test]
+    fn playback_stops_when_a_motion_is_blocked() {
+        let mut registry = CommandRegistry::new();
+        let mut buffer = Buffer::from("a\nb\nc".to_string());
+        let steps = vec![
+            step("editor.insert", r#"{"text": "- "}"#),
+            step("editor.moveToLineStart", ""),
+            step("editor.moveDown", ""),
+        ];
+
+        let playback = registry.play(&mut buffer, at(0, 0), Selection::default(), steps, 10);
+        assert_eq!(buffer.to_string(), "- a\n- b\n- c");
+        assert_eq!(playback.completed_runs, 2);
+        assert_eq!(
+            playback.error.as_deref(),
+            Some("step 3 (`editor.moveDown`): cannot move further")
+        );
+        // The blocked move still went to the end of the last row.
+        assert_eq!((playback.cursor.row, playback.cursor.column), (2, 3));
+    }
+
+    #[test]
+    fn cursors_follow_edits_made_at_earlier_cursors() {
+        let mut registry = CommandRegistry::new();
+        let mut buffer = Buffer::from("ab\ncd\nef".to_string());
+        let steps = vec![
+            step("editor.insert", r#"{"text": "x\ny"}"#),
+            step("editor.moveRight", ""),
+        ];
+
+        let playback =
+            registry.play_on_cursors(&mut buffer, vec![at(0, 1), at(1, 0), at(1, 2)], steps);
+        assert_eq!(buffer.to_string(), "ax\nyb\nx\nycdx\ny\nef");
+        assert_eq!(playback.completed_runs, 3);
+        assert!(playback.error.is_none());
+        let cursors: Vec<_> = playback
+            .cursors
+            .iter()
+            .map(|cursor| (cursor.row, cursor.column))
+            .collect();
+        assert_eq!(cursors, [(1, 2), (3, 2), (5, 0)]);
+
+        // A blocked step names the cursor it stopped at.
+        let playback = registry.play_on_cursors(
+            &mut buffer,
+            vec![at(0, 0), at(5, 2)],
+            vec![step("editor.moveDown", "")],
+        );
+        assert_eq!(playback.completed_runs, 1);
+        assert_eq!(
+            playback.error.as_deref(),
+            Some("cursor 2: step 1 (`editor.moveDown`): cannot move further")
+        );
+    }
+
+    #[test]
+    fn stores_macros_by_name() {
+        let dir = std::env::temp_dir().join(format!("rei-macros-test-{}", std::process::id()));
+        let data_dir = dir.to_string_lossy().into_owned();
+        let mut store = MacroStore::load(data_dir.clone()).unwrap();
+        store.set(
+            "bullet".to_string(),
+            vec![
+                step("editor.insert", r#"{"text":"- "}"#),
+                step("editor.moveDown", ""),
+            ],
+        );
+        store.save().unwrap();
+
+        let mut store = MacroStore::load(data_dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(store.names(), ["bullet"]);
+        assert_eq!(
+            store.get("bullet".to_string()).unwrap()[0].args,
+            r#"{"text":"- "}"#
+        );
+        assert!(store.remove("bullet".to_string()));
+        assert!(store.get("bullet".to_string()).is_none());
+    }
+}
@@ -1,3 +1,32 @@
+pub mod anchor;
+pub mod block_selection;
+pub mod bookmark;
 pub mod buffer;
+pub mod changes;
+pub mod clipboard;
+pub mod command;
+pub mod completion;
 pub mod cursor;
+pub mod diff;
+pub mod editorconfig;
+pub mod emacs;
+pub mod encoding;
+pub mod formatter;
+pub mod keymap;
+pub mod language;
+pub mod large_file;
+pub mod line_metrics;
+pub mod line_ops;
+pub mod macros;
+pub mod save;
 pub mod selection;
+pub mod session;
+pub mod settings;
+pub mod snapshot;
+pub mod snippet;
+pub mod swap;
+pub mod text;
+pub mod theme;
+pub mod vim;
+pub mod whitespace;
+pub mod wrap_map;
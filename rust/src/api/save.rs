@@ -0,0 +1,183 @@
+use anyhow::bail;
+use flutter_rust_bridge::frb;
+use std::path::Path;
+
+use super::buffer::{Buffer, TextEdit};
+use super::editorconfig::LineEnding;
+use super::session::write_atomically_with;
+
+pub trait Formatter: Send + Sync {
+    fn name(&self) -> String;
+
+    fn format(&self, buffer: &mut Buffer) -> anyhow::Result<Vec<TextEdit>>;
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy)]
+pub struct SaveActions {
+    pub format: bool,
+    pub trim_trailing_whitespace: bool,
+    pub ensure_final_newline: bool,
+    pub collapse_blank_lines: bool,
+    pub max_blank_lines: usize,
+    // Line breaks are rewritten to this ending when set.
+    pub line_ending: Option<LineEnding>,
+    // Whether the file starts with a UTF-8 byte order mark; left as loaded
+    // when unset.
+    pub byte_order_mark: Option<bool>,
+}
+
+impl Default for SaveActions {
+    #[frb(sync)]
+    fn default() -> Self {
+        Self {
+            format: true,
+            trim_trailing_whitespace: false,
+            ensure_final_newline: false,
+            collapse_blank_lines: false,
+            max_blank_lines: 1,
+            line_ending: None,
+            byte_order_mark: None,
+        }
+    }
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct SaveStepReport {
+    pub step: String,
+    pub edits: Vec<TextEdit>,
+}
+
+#[frb(opaque)]
+pub struct SavePipeline {
+    pub actions: SaveActions,
+    formatters: Vec<Box<dyn Formatter>>,
+}
+
+impl SavePipeline {
+    #[frb(sync)]
+    pub fn new(actions: SaveActions) -> Self {
+        Self {
+            actions,
+            formatters: Vec::new(),
+        }
+    }
+
+    #[frb(ignore)]
+    pub fn register_formatter(&mut self, formatter: Box<dyn Formatter>) {
+        self.formatters.push(formatter);
+    }
+
+    #[frb(sync)]
+    pub fn formatter_names(&self) -> Vec<String> {
+        self.formatters
+            .iter()
+            .map(|formatter| formatter.name())
+            .collect()
+    }
+
+    // Runs the enabled steps in order, returning the edits made by each one.
+    pub fn run(&self, buffer: &mut Buffer) -> anyhow::Result<Vec<SaveStepReport>> {
+        let mut reports = Vec::new();
+
+        if self.actions.format {
+            for formatter in &self.formatters {
+                let edits = formatter.format(buffer)?;
+                reports.push(SaveStepReport {
+                    step: formatter.name(),
+                    edits,
+                });
+            }
+        }
+
+        if self.actions.trim_trailing_whitespace {
+            reports.push(SaveStepReport {
+                step: "trim_trailing_whitespace".to_string(),
+                edits: buffer.trim_trailing_whitespace(),
+            });
+        }
+
+        if self.actions.collapse_blank_lines {
+            reports.push(SaveStepReport {
+                step: "collapse_blank_lines".to_string(),
+                edits: buffer.collapse_blank_lines(self.actions.max_blank_lines),
+            });
+        }
+
+        if self.actions.ensure_final_newline {
+            reports.push(SaveStepReport {
+                step: "ensure_final_newline".to_string(),
+                edits: buffer.ensure_final_newline(),
+            });
+        }
+
+        if let Some(line_ending) = self.actions.line_ending {
+            reports.push(SaveStepReport {
+                step: "normalize_line_endings".to_string(),
+                edits: buffer.normalize_line_endings(line_ending),
+            });
+        }
+
+        if let Some(present) = self.actions.byte_order_mark {
+            reports.push(SaveStepReport {
+                step: "byte_order_mark".to_string(),
+                edits: buffer.set_byte_order_mark(present),
+            });
+        }
+
+        Ok(reports)
+    }
+}
+
+impl Buffer {
+    pub fn save(
+        &mut self,
+        path: String,
+        pipeline: &SavePipeline,
+    ) -> anyhow::Result<Vec<SaveStepReport>> {
+        if self.is_lossy() {
+            bail!(
+                "{path} had bytes that could not be decoded when opened; saving would replace them"
+            );
+        }
+
+        let reports = pipeline.run(self)?;
+        write_atomically_with(Path::new(&path), |writer| self.write_to(writer))?;
+
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io;
+
+    #[test]
+    fn save_replaces_the_file_without_leaving_a_temporary() {
+        let dir = std::env::temp_dir().join(format!("rei-save-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+        fs::write(&path, "old\n").unwrap();
+
+        let mut buffer = Buffer::from("new\n".to_string());
+        let pipeline = SavePipeline::new(SaveActions::default());
+        buffer
+            .save(path.to_string_lossy().into_owned(), &pipeline)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+
+        // A write that fails halfway leaves the old contents in place.
+        let failed = write_atomically_with(&path, |writer| {
+            io::Write::write_all(writer, b"partial")?;
+            Err(io::Error::other("disk full"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new\n");
+
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,10 @@
 use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
 
 use super::cursor::Cursor;
 
 #[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Selection {
     pub start: Cursor,
     pub end: Cursor,
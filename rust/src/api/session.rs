@@ -0,0 +1,380 @@
+use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::buffer::{Buffer, TextEdit};
+use super::cursor::Cursor;
+use super::diff::line_edits;
+use super::selection::Selection;
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FoldRange {
+    pub start_row: usize,
+    pub end_row: usize,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BufferViewState {
+    pub path: String,
+    pub cursor: Cursor,
+    pub selection: Selection,
+    pub scroll_x: f64,
+    pub scroll_y: f64,
+    pub folds: Vec<FoldRange>,
+    pub is_active: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreStatus {
+    // The buffer matches the file on disk.
+    Clean,
+    // The unsaved contents were restored, and the file on disk is as it was
+    // when they were recorded.
+    Unsaved,
+    // The unsaved contents were restored, but the file changed on disk since
+    // they were recorded. `unsaved_edits` turn its current contents into them.
+    Diverged,
+    // The file no longer exists. Unsaved contents are still restored.
+    Missing,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone)]
+pub struct RestoredBuffer {
+    // The id to give the restored buffer with `Buffer::set_id`, so it keeps
+    // its session entry and swap file.
+    pub buffer_id: u64,
+    pub state: BufferViewState,
+    pub contents: String,
+    pub status: RestoreStatus,
+    pub unsaved_edits: Vec<TextEdit>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+enum StoredContents {
+    Disk,
+    // The full text, since edits against the file on disk could not be
+    // replayed once it changes. The hash is of the file as it was then, if it
+    // existed.
+    Unsaved {
+        text: String,
+        disk_hash: Option<u64>,
+    },
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredBuffer {
+    buffer_id: u64,
+    state: BufferViewState,
+    contents: StoredContents,
+}
+
+#[derive(Deserialize)]
+struct SessionVersion {
+    version: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SessionFile {
+    version: u32,
+    buffers: Vec<StoredBuffer>,
+}
+
+#[frb(opaque)]
+pub struct SessionStore {
+    dir: PathBuf,
+    buffers: Vec<StoredBuffer>,
+}
+
+impl SessionStore {
+    const FILE_NAME: &'static str = "session.json";
+    const FORMAT_VERSION: u32 = 2;
+
+    #[frb(sync)]
+    pub fn new(data_dir: String) -> Self {
+        Self {
+            dir: PathBuf::from(data_dir),
+            buffers: Vec::new(),
+        }
+    }
+
+    // Records the current state of an open buffer, replacing any previous
+    // entry for the same buffer. Dirty buffers are stored in full, with a hash
+    // of the file on disk to tell whether it changed by the time they are
+    // restored.
+    #[frb(sync)]
+    pub fn record(&mut self, buffer: &Buffer, state: BufferViewState, is_dirty: bool) {
+        let contents = if !is_dirty {
+            StoredContents::Disk
+        } else {
+            StoredContents::Unsaved {
+                text: buffer.to_string(),
+                disk_hash: fs::read(&state.path).ok().map(|disk| content_hash(&disk)),
+            }
+        };
+
+        let stored = StoredBuffer {
+            buffer_id: buffer.id(),
+            state,
+            contents,
+        };
+        match self
+            .buffers
+            .iter_mut()
+            .find(|entry| entry.buffer_id == stored.buffer_id)
+        {
+            Some(entry) => *entry = stored,
+            None => self.buffers.push(stored),
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn remove(&mut self, buffer_id: u64) {
+        self.buffers.retain(|entry| entry.buffer_id != buffer_id);
+    }
+
+    #[frb(sync)]
+    pub fn clear(&mut self) {
+        self.buffers.clear();
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let file = SessionFile {
+            version: Self::FORMAT_VERSION,
+            buffers: self.buffers.clone(),
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        write_atomically(
+            &self.dir.join(Self::FILE_NAME),
+            &serde_json::to_vec_pretty(&file)?,
+        )
+    }
+
+    // Loads the saved session and rebuilds each buffer against the current
+    // contents of its file. A session saved in another format is ignored.
+    pub fn restore(&mut self) -> anyhow::Result<Vec<RestoredBuffer>> {
+        let path = self.dir.join(Self::FILE_NAME);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let json = fs::read(path)?;
+        let SessionVersion { version } = serde_json::from_slice(&json)?;
+        if version != Self::FORMAT_VERSION {
+            return Ok(Vec::new());
+        }
+        let file: SessionFile = serde_json::from_slice(&json)?;
+        self.buffers = file.buffers;
+
+        Ok(self.buffers.iter().map(restore_buffer).collect())
+    }
+}
+
+fn restore_buffer(stored: &StoredBuffer) -> RestoredBuffer {
+    let disk = fs::read_to_string(&stored.state.path).ok();
+
+    let (contents, status, unsaved_edits) = match (&stored.contents, disk) {
+        (StoredContents::Disk, Some(disk)) => (disk, RestoreStatus::Clean, Vec::new()),
+        (StoredContents::Disk, None) => (String::new(), RestoreStatus::Missing, Vec::new()),
+        (StoredContents::Unsaved { text, disk_hash }, Some(disk))
+            if *disk_hash != Some(content_hash(disk.as_bytes())) =>
+        {
+            let edits = line_edits(&disk, text, 0);
+            (text.clone(), RestoreStatus::Diverged, edits)
+        }
+        (StoredContents::Unsaved { text, .. }, Some(_)) => {
+            (text.clone(), RestoreStatus::Unsaved, Vec::new())
+        }
+        // Never saved, e.g. an untitled buffer.
+        (
+            StoredContents::Unsaved {
+                text,
+                disk_hash: None,
+            },
+            None,
+        ) => (text.clone(), RestoreStatus::Unsaved, Vec::new()),
+        (StoredContents::Unsaved { text, .. }, None) => {
+            (text.clone(), RestoreStatus::Missing, Vec::new())
+        }
+    };
+
+    let buffer = Buffer::from(contents.clone());
+    let mut state = stored.state.clone();
+    state.cursor = clamp_cursor(&buffer, state.cursor);
+    state.selection = Selection::new(
+        clamp_cursor(&buffer, state.selection.start),
+        clamp_cursor(&buffer, state.selection.end),
+    );
+
+    RestoredBuffer {
+        buffer_id: stored.buffer_id,
+        state,
+        contents,
+        status,
+        unsaved_edits,
+    }
+}
+
+fn clamp_cursor(buffer: &Buffer, cursor: Cursor) -> Cursor {
+    let row = cursor.row.min(buffer.last_row());
+    let column = cursor.column.min(buffer.line(row).len());
+
+    Cursor::new(row, column, cursor.sticky_column)
+}
+
+pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    write_atomically_with(path, |writer| writer.write_all(contents))
+}
+
+// Writes `path` through a temporary file beside it that is synced to disk and
+// then renamed over it, so a crash or a failed write leaves the old contents
+// intact. A symlinked path is written through to its target, which keeps its
+// permissions.
+pub(crate) fn write_atomically_with(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> anyhow::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = (|| -> io::Result<()> {
+        let file = File::create(&tmp_path)?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    Ok(result?)
+}
+
+// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+pub(crate) fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(path: &Path, row: usize, column: usize) -> BufferViewState {
+        let cursor = Cursor::new(row, column, column);
+        BufferViewState {
+            path: path.to_string_lossy().into_owned(),
+            cursor,
+            selection: Selection::new(cursor, cursor),
+            scroll_x: 0.0,
+            scroll_y: 12.5,
+            folds: vec![FoldRange {
+                start_row: 0,
+                end_row: 1,
+            }],
+            is_active: true,
+        }
+    }
+
+    fn round_trip(dir: &Path, record: impl FnOnce(&mut SessionStore)) -> Vec<RestoredBuffer> {
+        let mut store = SessionStore::new(dir.to_string_lossy().into_owned());
+        record(&mut store);
+        store.save().unwrap();
+        SessionStore::new(dir.to_string_lossy().into_owned())
+            .restore()
+            .unwrap()
+    }
+
+    #[test]
+    fn restores_clean_unsaved_diverged_and_missing_buffers() {
+        let dir = std::env::temp_dir().join(format!("rei-session-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = ["clean", "unsaved", "diverged", "missing"]
+            .iter()
+            .map(|name| dir.join(format!("{name}.txt")))
+            .collect();
+        for path in &paths {
+            fs::write(path, "one\ntwo\n").unwrap();
+        }
+
+        let mut buffers: Vec<Buffer> = (0..4)
+            .map(|_| Buffer::from("one\ntwo\n".to_string()))
+            .collect();
+        for buffer in &mut buffers[1..] {
+            buffer.replace_range(1, 3, 1, 3, " and three".to_string());
+        }
+        round_trip(&dir.join("data"), |store| {
+            for (idx, (buffer, path)) in buffers.iter().zip(&paths).enumerate() {
+                store.record(buffer, state(path, 1, 2), idx > 0);
+            }
+        });
+        fs::write(&paths[2], "one\ntwo\nfour\n").unwrap();
+        fs::remove_file(&paths[3]).unwrap();
+
+        let restored = SessionStore::new(dir.join("data").to_string_lossy().into_owned())
+            .restore()
+            .unwrap();
+        let edited = "one\ntwo and three\n";
+        let expected = [
+            ("one\ntwo\n", RestoreStatus::Clean),
+            (edited, RestoreStatus::Unsaved),
+            (edited, RestoreStatus::Diverged),
+            (edited, RestoreStatus::Missing),
+        ];
+        for ((entry, buffer), (contents, status)) in restored.iter().zip(&buffers).zip(expected) {
+            assert_eq!(entry.buffer_id, buffer.id());
+            assert_eq!(entry.contents, contents);
+            assert_eq!(entry.status, status);
+            assert_eq!((entry.state.cursor.row, entry.state.cursor.column), (1, 2));
+            assert_eq!(entry.state.scroll_y, 12.5);
+        }
+
+        // The diverged buffer's edits turn the changed file into its contents.
+        let mut disk = Buffer::from("one\ntwo\nfour\n".to_string());
+        disk.apply_edits(restored[2].unsaved_edits.clone());
+        assert_eq!(disk.to_string(), edited);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn untitled_buffers_keep_separate_entries() {
+        let dir = std::env::temp_dir().join(format!("rei-session-untitled-{}", std::process::id()));
+        let first = Buffer::from("first".to_string());
+        let second = Buffer::from("second".to_string());
+
+        let restored = round_trip(&dir, |store| {
+            store.record(&first, state(Path::new(""), 0, 0), true);
+            store.record(&second, state(Path::new(""), 0, 0), true);
+            store.record(&first, state(Path::new(""), 0, 3), true);
+        });
+
+        let contents: Vec<(u64, &str, RestoreStatus)> = restored
+            .iter()
+            .map(|entry| (entry.buffer_id, entry.contents.as_str(), entry.status))
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                (first.id(), "first", RestoreStatus::Unsaved),
+                (second.id(), "second", RestoreStatus::Unsaved),
+            ]
+        );
+        assert_eq!(restored[0].state.cursor.column, 3);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1003 @@
+use anyhow::bail;
+use flutter_rust_bridge::{frb, DartFnFuture};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::Duration;
+
+use super::editorconfig::{IndentStyle, Indentation};
+use super::large_file::LargeFileThresholds;
+use super::save::SaveActions;
+use super::session::content_hash;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingKind {
+    Bool,
+    Integer,
+    Float,
+    String,
+    Choice,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug)]
+pub struct SettingInfo {
+    pub key: String,
+    pub kind: SettingKind,
+    pub description: String,
+    // The default value as JSON.
+    pub default_value: String,
+    // Allowed values for `Choice` settings.
+    pub choices: Vec<String>,
+    // Whether `[language.<id>]` tables can override it.
+    pub per_language: bool,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettingsError {
+    pub path: String,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettingChange {
+    pub key: String,
+    // Set when only the value for this language changed.
+    pub language: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl Value {
+    fn to_json(&self) -> String {
+        match self {
+            Value::Bool(value) => value.to_string(),
+            Value::Integer(value) => value.to_string(),
+            Value::Float(value) => serde_json::json!(value).to_string(),
+            Value::String(value) => serde_json::json!(value).to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Bool,
+    Integer { min: i64, max: i64 },
+    Float { min: f64, max: f64 },
+    String,
+    Choice(&'static [&'static str]),
+}
+
+#[derive(Clone, Copy)]
+enum DefaultValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    Str(&'static str),
+}
+
+struct Spec {
+    key: &'static str,
+    kind: Kind,
+    default: DefaultValue,
+    per_language: bool,
+    description: &'static str,
+}
+
+impl Spec {
+    fn default_value(&self) -> Value {
+        match self.default {
+            DefaultValue::Bool(value) => Value::Bool(value),
+            DefaultValue::Integer(value) => Value::Integer(value),
+            DefaultValue::Float(value) => Value::Float(value),
+            DefaultValue::Str(value) => Value::String(value.to_string()),
+        }
+    }
+
+    fn validate(&self, value: &toml::Value) -> Result<Value, String> {
+        match (self.kind, value) {
+            (Kind::Bool, toml::Value::Boolean(value)) => Ok(Value::Bool(*value)),
+            (Kind::Integer { min, max }, toml::Value::Integer(value)) => {
+                if (min..=max).contains(value) {
+                    Ok(Value::Integer(*value))
+                } else {
+                    Err(format!("must be between {min} and {max}, got {value}"))
+                }
+            }
+            (Kind::Float { min, max }, toml::Value::Integer(_) | toml::Value::Float(_)) => {
+                let value = value
+                    .as_float()
+                    .or(value.as_integer().map(|value| value as f64))
+                    .unwrap_or_default();
+                if (min..=max).contains(&value) {
+                    Ok(Value::Float(value))
+                } else {
+                    Err(format!("must be between {min} and {max}, got {value}"))
+                }
+            }
+            (Kind::String, toml::Value::String(value)) => Ok(Value::String(value.clone())),
+            (Kind::Choice(choices), toml::Value::String(value)) => {
+                if choices.contains(&value.as_str()) {
+                    Ok(Value::String(value.clone()))
+                } else {
+                    Err(format!(
+                        "must be one of {}, got \"{value}\"",
+                        quoted_list(choices)
+                    ))
+                }
+            }
+            (kind, value) => Err(format!(
+                "must be {}, got {} `{value}`",
+                kind_name(kind),
+                value.type_str()
+            )),
+        }
+    }
+}
+
+fn kind_name(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Bool => "a boolean",
+        Kind::Integer { .. } => "an integer",
+        Kind::Float { .. } => "a number",
+        Kind::String | Kind::Choice(_) => "a string",
+    }
+}
+
+fn quoted_list(items: &[&str]) -> String {
+    items
+        .iter()
+        .map(|item| format!("\"{item}\""))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+const SCHEMA: &[Spec] = &[
+    Spec {
+        key: "editor.tab_width",
+        kind: Kind::Integer { min: 1, max: 16 },
+        default: DefaultValue::Integer(4),
+        per_language: true,
+        description: "Columns per indentation level",
+    },
+    Spec {
+        key: "editor.insert_spaces",
+        kind: Kind::Bool,
+        default: DefaultValue::Bool(true),
+        per_language: true,
+        description: "Indent with spaces rather than tabs",
+    },
+    Spec {
+        key: "editor.font_family",
+        kind: Kind::String,
+        default: DefaultValue::Str("IBM Plex Mono"),
+        per_language: false,
+        description: "Font for the text area",
+    },
+    Spec {
+        key: "editor.font_size",
+        kind: Kind::Float {
+            min: 6.0,
+            max: 72.0,
+        },
+        default: DefaultValue::Float(15.0),
+        per_language: false,
+        description: "Font size for the text area, in logical pixels",
+    },
+    Spec {
+        key: "editor.word_wrap",
+        kind: Kind::Bool,
+        default: DefaultValue::Bool(false),
+        per_language: true,
+        description: "Wrap long lines at the viewport edge",
+    },
+    Spec {
+        key: "editor.keymap",
+        kind: Kind::Choice(&["default", "vim", "emacs"]),
+        default: DefaultValue::Str("default"),
+        per_language: false,
+        description: "Key bindings to edit with",
+    },
+    Spec {
+        key: "ui.theme",
+        kind: Kind::String,
+        default: DefaultValue::Str("dark"),
+        per_language: false,
+        description: "Color theme name",
+    },
+    Spec {
+        key: "ui.font_family",
+        kind: Kind::String,
+        default: DefaultValue::Str("IBM Plex Sans"),
+        per_language: false,
+        description: "Font for tabs, menus and the file explorer",
+    },
+    Spec {
+        key: "files.format_on_save",
+        kind: Kind::Bool,
+        default: DefaultValue::Bool(true),
+        per_language: true,
+        description: "Run the formatter when saving",
+    },
+    Spec {
+        key: "files.trim_trailing_whitespace",
+        kind: Kind::Bool,
+        default: DefaultValue::Bool(false),
+        per_language: true,
+        description: "Remove whitespace at the end of lines when saving",
+    },
+    Spec {
+        key: "files.insert_final_newline",
+        kind: Kind::Bool,
+        default: DefaultValue::Bool(false),
+        per_language: true,
+        description: "End the file with a line break when saving",
+    },
+    Spec {
+        key: "files.max_blank_lines",
+        kind: Kind::Integer { min: 0, max: 100 },
+        default: DefaultValue::Integer(0),
+        per_language: true,
+        description: "Collapse longer runs of blank lines when saving; 0 keeps them",
+    },
+    Spec {
+        key: "files.large_file_highlighting_bytes",
+        kind: Kind::Integer {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: DefaultValue::Integer(8 << 20),
+        per_language: false,
+        description: "Files larger than this open without syntax highlighting",
+    },
+    Spec {
+        key: "files.large_file_line_length_bytes",
+        kind: Kind::Integer {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: DefaultValue::Integer(32 << 20),
+        per_language: false,
+        description: "Files larger than this skip longest-line tracking",
+    },
+];
+
+fn spec(key: &str) -> Option<&'static Spec> {
+    SCHEMA.iter().find(|spec| spec.key == key)
+}
+
+// Values from one settings file. Invalid entries are left out and reported
+// in `errors`, so one typo does not discard the rest of the file.
+#[derive(Clone, Default)]
+struct Layer {
+    path: Option<PathBuf>,
+    // Hash of the contents last read, or `None` if the file was missing.
+    stamp: Option<u64>,
+    global: HashMap<&'static str, Value>,
+    languages: HashMap<String, HashMap<&'static str, Value>>,
+    errors: Vec<SettingsError>,
+}
+
+impl Layer {
+    fn load(path: Option<PathBuf>) -> Self {
+        let mut layer = Layer {
+            path,
+            ..Layer::default()
+        };
+
+        let Some(path) = layer.path.clone() else {
+            return layer;
+        };
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return layer,
+            Err(err) => {
+                layer.error(None, err.to_string());
+                return layer;
+            }
+        };
+        layer.stamp = Some(content_hash(&bytes));
+        let contents = match String::from_utf8(bytes) {
+            Ok(contents) => contents,
+            Err(err) => {
+                layer.error(None, err.to_string());
+                return layer;
+            }
+        };
+
+        match contents.parse::<toml::Table>() {
+            Ok(table) => layer.read_table(&table),
+            Err(err) => layer.error(None, err.to_string().trim_end().to_string()),
+        }
+
+        layer
+    }
+
+    fn read_table(&mut self, table: &toml::Table) {
+        for (name, value) in table {
+            if name == "language" {
+                match value {
+                    toml::Value::Table(languages) => self.read_languages(languages),
+                    _ => self.error(Some(name), "must be a table of languages".to_string()),
+                }
+                continue;
+            }
+
+            for (key, value) in flatten(name, value) {
+                if let Some((spec, value)) = self.validate(&key, value) {
+                    self.global.insert(spec.key, value);
+                }
+            }
+        }
+    }
+
+    fn read_languages(&mut self, languages: &toml::Table) {
+        for (language, table) in languages {
+            let prefix = format!("language.{language}");
+            let toml::Value::Table(table) = table else {
+                self.error(Some(&prefix), "must be a table of settings".to_string());
+                continue;
+            };
+
+            for (name, value) in table {
+                for (key, value) in flatten(name, value) {
+                    let Some((spec, value)) = self.validate(&key, value) else {
+                        continue;
+                    };
+                    if !spec.per_language {
+                        self.error(
+                            Some(&format!("{prefix}.{key}")),
+                            "cannot be set per language".to_string(),
+                        );
+                        continue;
+                    }
+
+                    self.languages
+                        .entry(language.clone())
+                        .or_default()
+                        .insert(spec.key, value);
+                }
+            }
+        }
+    }
+
+    fn validate(&mut self, key: &str, value: &toml::Value) -> Option<(&'static Spec, Value)> {
+        let Some(spec) = spec(key) else {
+            let message = match suggest(key) {
+                Some(suggestion) => format!("unknown setting; did you mean `{suggestion}`?"),
+                None => "unknown setting".to_string(),
+            };
+            self.error(Some(key), message);
+            return None;
+        };
+
+        match spec.validate(value) {
+            Ok(value) => Some((spec, value)),
+            Err(message) => {
+                self.error(Some(key), message);
+                None
+            }
+        }
+    }
+
+    fn error(&mut self, key: Option<&str>, message: String) {
+        self.errors.push(SettingsError {
+            path: self
+                .path
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            key: key.map(str::to_string),
+            message,
+        });
+    }
+}
+
+// Dotted keys for the leaves under a top-level table, e.g. `editor.tab_width`.
+fn flatten<'a>(name: &str, value: &'a toml::Value) -> Vec<(String, &'a toml::Value)> {
+    match value {
+        toml::Value::Table(table) => table
+            .iter()
+            .flat_map(|(key, value)| flatten(&format!("{name}.{key}"), value))
+            .collect(),
+        value => vec![(name.to_string(), value)],
+    }
+}
+
+// The known key closest to `key`, if it looks like a typo of it.
+fn suggest(key: &str) -> Option<&'static str> {
+    SCHEMA
+        .iter()
+        .map(|spec| (edit_distance(key, spec.key), spec.key))
+        .filter(|&(distance, _)| distance <= 3)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, key)| key)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &b) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// Hashing the contents catches edits that keep the size and land within the
+// modification time's resolution.
+fn file_stamp(path: &Path) -> Option<u64> {
+    fs::read(path).ok().map(|bytes| content_hash(&bytes))
+}
+
+// Settings merged from the defaults, the user's settings file and the open
+// workspace's `.rei/settings.toml`, later layers winning. `[language.<id>]`
+// tables in either file override both for that language, the workspace's
+// again winning over the user's.
+#[frb(opaque)]
+#[derive(Clone)]
+pub struct Settings {
+    user: Layer,
+    workspace: Layer,
+}
+
+impl Settings {
+    const WORKSPACE_FILE: &'static str = ".rei/settings.toml";
+
+    // Missing files count as empty. Errors in either file are available from
+    // `errors` rather than failing the load.
+    #[frb(sync)]
+    pub fn load(user_settings_path: String, workspace_root: Option<String>) -> Self {
+        Self {
+            user: Layer::load(Some(PathBuf::from(user_settings_path))),
+            workspace: Layer::load(workspace_root.map(Self::workspace_path)),
+        }
+    }
+
+    // Switches to another workspace's settings, returning what changed.
+    #[frb(sync)]
+    pub fn set_workspace(&mut self, workspace_root: Option<String>) -> Vec<SettingChange> {
+        let old = std::mem::take(&mut self.workspace);
+        self.workspace = Layer::load(workspace_root.map(Self::workspace_path));
+        self.changes_from(&self.user, &old)
+    }
+
+    // Reloads whichever files changed on disk since they were last read and
+    // returns the settings whose values changed as a result. Call this when
+    // the app regains focus, or when `watch` reports a change, to pick up
+    // edits.
+    #[frb(sync)]
+    pub fn poll(&mut self) -> Vec<SettingChange> {
+        let old_user = self.user.clone();
+        let old_workspace = self.workspace.clone();
+
+        for layer in [&mut self.user, &mut self.workspace] {
+            let stamp = layer.path.as_deref().and_then(file_stamp);
+            if stamp != layer.stamp {
+                *layer = Layer::load(layer.path.take());
+            }
+        }
+
+        self.changes_from(&old_user, &old_workspace)
+    }
+
+    // Polls the same files every `interval_ms` on a background thread and
+    // calls `on_change` with what changed after each edit, so changes made
+    // outside the app apply without waiting for focus. The values here are
+    // only updated by `poll`, so the callback should call it.
+    #[frb(type_64bit_int)]
+    pub fn watch(
+        &self,
+        interval_ms: u64,
+        on_change: impl Fn(Vec<SettingChange>) -> DartFnFuture<()> + Send + Sync + 'static,
+    ) -> SettingsWatcher {
+        SettingsWatcher::spawn(
+            self.clone(),
+            Duration::from_millis(interval_ms),
+            move |changes| block_on(on_change(changes)),
+        )
+    }
+
+    #[frb(sync)]
+    pub fn errors(&self) -> Vec<SettingsError> {
+        self.user
+            .errors
+            .iter()
+            .chain(&self.workspace.errors)
+            .cloned()
+            .collect()
+    }
+
+    #[frb(sync)]
+    pub fn schema(&self) -> Vec<SettingInfo> {
+        SCHEMA
+            .iter()
+            .map(|spec| SettingInfo {
+                key: spec.key.to_string(),
+                kind: match spec.kind {
+                    Kind::Bool => SettingKind::Bool,
+                    Kind::Integer { .. } => SettingKind::Integer,
+                    Kind::Float { .. } => SettingKind::Float,
+                    Kind::String => SettingKind::String,
+                    Kind::Choice(_) => SettingKind::Choice,
+                },
+                description: spec.description.to_string(),
+                default_value: spec.default_value().to_json(),
+                choices: match spec.kind {
+                    Kind::Choice(choices) => choices.iter().map(|c| c.to_string()).collect(),
+                    _ => Vec::new(),
+                },
+                per_language: spec.per_language,
+            })
+            .collect()
+    }
+
+    // The effective value of `key` as JSON, for settings without a typed
+    // getter.
+    #[frb(sync)]
+    pub fn get_json(&self, key: String, language: Option<String>) -> anyhow::Result<String> {
+        Ok(self.value(&key, language.as_deref())?.to_json())
+    }
+
+    #[frb(sync)]
+    pub fn get_bool(&self, key: String, language: Option<String>) -> anyhow::Result<bool> {
+        match self.value(&key, language.as_deref())? {
+            Value::Bool(value) => Ok(value),
+            _ => bail!("`{key}` is not a boolean setting"),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn get_integer(&self, key: String, language: Option<String>) -> anyhow::Result<i64> {
+        match self.value(&key, language.as_deref())? {
+            Value::Integer(value) => Ok(value),
+            _ => bail!("`{key}` is not an integer setting"),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn get_float(&self, key: String, language: Option<String>) -> anyhow::Result<f64> {
+        match self.value(&key, language.as_deref())? {
+            Value::Float(value) => Ok(value),
+            _ => bail!("`{key}` is not a number setting"),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn get_string(&self, key: String, language: Option<String>) -> anyhow::Result<String> {
+        match self.value(&key, language.as_deref())? {
+            Value::String(value) => Ok(value),
+            _ => bail!("`{key}` is not a string setting"),
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn tab_width(&self, language: Option<String>) -> usize {
+        self.integer("editor.tab_width", language.as_deref()) as usize
+    }
+
+    #[frb(sync)]
+    pub fn insert_spaces(&self, language: Option<String>) -> bool {
+        self.bool("editor.insert_spaces", language.as_deref())
+    }
+
+    // Indentation from `editor.tab_width` and `editor.insert_spaces`, to pass
+    // as the fallback when resolving EditorConfig.
+    #[frb(sync)]
+    pub fn indentation(&self, language: Option<String>) -> Indentation {
+        let tab_width = self.tab_width(language.clone());
+
+        Indentation {
+            style: if self.insert_spaces(language) {
+                IndentStyle::Space
+            } else {
+                IndentStyle::Tab
+            },
+            size: tab_width,
+            tab_width,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn word_wrap(&self, language: Option<String>) -> bool {
+        self.bool("editor.word_wrap", language.as_deref())
+    }
+
+    #[frb(sync)]
+    pub fn font_family(&self) -> String {
+        self.string("editor.font_family")
+    }
+
+    #[frb(sync)]
+    pub fn font_size(&self) -> f64 {
+        match self.effective("editor.font_size", None) {
+            Value::Float(value) => value,
+            _ => unreachable!("`editor.font_size` is a number"),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn keymap(&self) -> String {
+        self.string("editor.keymap")
+    }
+
+    #[frb(sync)]
+    pub fn theme(&self) -> String {
+        self.string("ui.theme")
+    }
+
+    #[frb(sync)]
+    pub fn ui_font_family(&self) -> String {
+        self.string("ui.font_family")
+    }
+
+    #[frb(sync)]
+    pub fn save_actions(&self, language: Option<String>) -> SaveActions {
+        let language = language.as_deref();
+        let max_blank_lines = self.integer("files.max_blank_lines", language) as usize;
+
+        SaveActions {
+            format: self.bool("files.format_on_save", language),
+            trim_trailing_whitespace: self.bool("files.trim_trailing_whitespace", language),
+            ensure_final_newline: self.bool("files.insert_final_newline", language),
+            collapse_blank_lines: max_blank_lines > 0,
+            max_blank_lines: max_blank_lines.max(1),
+            ..SaveActions::default()
+        }
+    }
+
+    #[frb(sync)]
+    pub fn large_file_thresholds(&self) -> LargeFileThresholds {
+        LargeFileThresholds {
+            highlighting_bytes: self.integer("files.large_file_highlighting_bytes", None) as u64,
+            line_length_tracking_bytes: self.integer("files.large_file_line_length_bytes", None)
+                as u64,
+        }
+    }
+
+    fn workspace_path(root: String) -> PathBuf {
+        Path::new(&root).join(Self::WORKSPACE_FILE)
+    }
+
+    fn value(&self, key: &str, language: Option<&str>) -> anyhow::Result<Value> {
+        if spec(key).is_none() {
+            bail!("unknown setting `{key}`");
+        }
+        Ok(self.effective(key, language))
+    }
+
+    fn effective(&self, key: &str, language: Option<&str>) -> Value {
+        resolve(&self.user, &self.workspace, key, language)
+    }
+
+    fn bool(&self, key: &str, language: Option<&str>) -> bool {
+        match self.effective(key, language) {
+            Value::Bool(value) => value,
+            _ => unreachable!("`{key}` is a boolean"),
+        }
+    }
+
+    fn integer(&self, key: &str, language: Option<&str>) -> i64 {
+        match self.effective(key, language) {
+            Value::Integer(value) => value,
+            _ => unreachable!("`{key}` is an integer"),
+        }
+    }
+
+    fn string(&self, key: &str) -> String {
+        match self.effective(key, None) {
+            Value::String(value) => value,
+            _ => unreachable!("`{key}` is a string"),
+        }
+    }
+
+    // Settings whose value differs between the given old layers and the
+    // current ones, globally or for a language either of them overrides.
+    fn changes_from(&self, old_user: &Layer, old_workspace: &Layer) -> Vec<SettingChange> {
+        let languages: BTreeSet<&String> = [old_user, old_workspace, &self.user, &self.workspace]
+            .iter()
+            .flat_map(|layer| layer.languages.keys())
+            .collect();
+
+        let mut changes = Vec::new();
+        for spec in SCHEMA {
+            let changed = |language: Option<&str>| {
+                resolve(old_user, old_workspace, spec.key, language)
+                    != resolve(&self.user, &self.workspace, spec.key, language)
+            };
+
+            if changed(None) {
+                changes.push(SettingChange {
+                    key: spec.key.to_string(),
+                    language: None,
+                });
+                continue;
+            }
+
+            if spec.per_language {
+                changes.extend(
+                    languages
+                        .iter()
+                        .filter(|language| changed(Some(language)))
+                        .map(|language| SettingChange {
+                            key: spec.key.to_string(),
+                            language: Some(language.to_string()),
+                        }),
+                );
+            }
+        }
+
+        changes
+    }
+}
+
+// The background thread behind `Settings::watch`. It stops when stopped or
+// dropped.
+#[frb(opaque)]
+pub struct SettingsWatcher {
+    stop: Option<mpsc::Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl SettingsWatcher {
+    #[frb(ignore)]
+    pub fn spawn(
+        mut settings: Settings,
+        interval: Duration,
+        mut notify: impl FnMut(Vec<SettingChange>) + Send + 'static,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let changes = settings.poll();
+                if !changes.is_empty() {
+                    notify(changes);
+                }
+            }
+        });
+
+        Self {
+            stop: Some(stop),
+            worker: Some(worker),
+        }
+    }
+
+    #[frb(sync)]
+    pub fn stop(&mut self) {
+        // Dropping the sender wakes the thread, which then exits.
+        self.stop.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for SettingsWatcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// Runs a callback's future to completion on the watcher thread, so each
+// batch of changes is delivered before the next poll.
+fn block_on<F: Future>(future: F) -> F::Output {
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+fn resolve(user: &Layer, workspace: &Layer, key: &str, language: Option<&str>) -> Value {
+    let overrides = language
+        .into_iter()
+        .flat_map(|language| {
+            [workspace, user]
+                .into_iter()
+                .filter_map(move |layer| layer.languages.get(language))
+        })
+        .chain([&workspace.global, &user.global]);
+
+    for values in overrides {
+        if let Some(value) = values.get(key) {
+            return value.clone();
+        }
+    }
+
+    spec(key)
+        .map(Spec::default_value)
+        .expect("resolved keys are in the schema")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watcher_reports_edits_to_the_settings_file() {
+        let dir = std::env::temp_dir().join(format!("rei-settings-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("settings.toml");
+        fs::write(&path, "[editor]\ntab_width = 4\n").unwrap();
+
+        let settings = Settings::load(path.to_string_lossy().into_owned(), None);
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher =
+            SettingsWatcher::spawn(settings.clone(), Duration::from_millis(5), move |changes| {
+                let _ = sender.send(changes);
+            });
+
+        fs::write(&path, "[editor]\ntab_width = 12\n").unwrap();
+        let changes = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            changes,
+            vec![SettingChange {
+                key: "editor.tab_width".to_string(),
+                language: None,
+            }]
+        );
+
+        watcher.stop();
+        assert!(receiver.recv().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rei-settings-{name}-{}", std::process::id()));
+        fs::create_dir_all(dir.join(".rei")).unwrap();
+        dir
+    }
+
+    fn change(key: &str, language: Option<&str>) -> SettingChange {
+        SettingChange {
+            key: key.to_string(),
+            language: language.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn poll_picks_up_same_length_edits() {
+        let dir = temp_dir("poll");
+        let path = dir.join("settings.toml");
+        fs::write(&path, "[editor]\ntab_width = 4\n").unwrap();
+        let mut settings = Settings::load(path.to_string_lossy().into_owned(), None);
+        assert!(settings.poll().is_empty());
+
+        // Same size, and likely within the same mtime tick.
+        fs::write(&path, "[editor]\ntab_width = 8\n").unwrap();
+        assert_eq!(settings.poll(), [change("editor.tab_width", None)]);
+        assert_eq!(settings.tab_width(None), 8);
+        assert!(settings.poll().is_empty());
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(settings.poll(), [change("editor.tab_width", None)]);
+        assert_eq!(settings.tab_width(None), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn later_layers_and_language_tables_take_precedence() {
+        let dir = temp_dir("layers");
+        let user = dir.join("user.toml");
+        fs::write(
+            &user,
+            "editor.tab_width = 3\neditor.word_wrap = true\n\
+             [language.rust]\neditor.tab_width = 5\n\
+             [language.go]\neditor.insert_spaces = false\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join(Settings::WORKSPACE_FILE),
+            "[editor]\ntab_width = 2\n[language.go]\neditor.insert_spaces = true\n",
+        )
+        .unwrap();
+
+        let root = dir.to_string_lossy().into_owned();
+        let mut settings = Settings::load(user.to_string_lossy().into_owned(), None);
+        assert_eq!(settings.tab_width(None), 3);
+
+        let changes = settings.set_workspace(Some(root));
+        assert_eq!(
+            changes,
+            [
+                change("editor.tab_width", None),
+                change("editor.insert_spaces", Some("go"))
+            ]
+        );
+        // The workspace wins globally, and either file's language table wins
+        // over both globals.
+        assert_eq!(settings.tab_width(None), 2);
+        assert_eq!(settings.tab_width(Some("rust".to_string())), 5);
+        assert!(settings.insert_spaces(Some("go".to_string())));
+        assert!(settings.word_wrap(None));
+        assert_eq!(settings.keymap(), "default");
+        assert!(settings.errors().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_entries_are_reported_and_skipped() {
+        let dir = temp_dir("errors");
+        let path = dir.join("settings.toml");
+        fs::write(
+            &path,
+            "[editor]\ntab_widht = 2\ntab_width = 40\nword_wrap = \"yes\"\n\
+             keymap = \"nano\"\nfont_size = 20\n\
+             [language.rust]\neditor.font_family = \"Mono\"\n",
+        )
+        .unwrap();
+        let settings = Settings::load(path.to_string_lossy().into_owned(), None);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let messages: Vec<_> = settings
+            .errors()
+            .into_iter()
+            .map(|error| (error.key.unwrap_or_default(), error.message))
+            .collect();
+        let expected = [
+            (
+                "editor.tab_widht",
+                "unknown setting; did you mean `editor.tab_width`?",
+            ),
+            ("editor.tab_width", "must be between 1 and 16, got 40"),
+            (
+                "editor.word_wrap",
+                "must be a boolean, got string `\"yes\"`",
+            ),
+            (
+                "editor.keymap",
+                "must be one of \"default\", \"vim\", \"emacs\", got \"nano\"",
+            ),
+            (
+                "language.rust.editor.font_family",
+                "cannot be set per language",
+            ),
+        ];
+        for (key, message) in expected {
+            assert!(
+                messages.contains(&(key.to_string(), message.to_string())),
+                "{key}: {messages:?}"
+            );
+        }
+        assert_eq!(messages.len(), expected.len());
+
+        // Valid entries beside them still apply.
+        assert_eq!(settings.font_size(), 20.0);
+        assert_eq!(settings.tab_width(None), 4);
+    }
+}
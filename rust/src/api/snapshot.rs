@@ -0,0 +1,169 @@
+use crop::Rope;
+use flutter_rust_bridge::frb;
+
+use super::buffer::{Buffer, TextEdit};
+use super::diff::line_edits;
+
+// An immutable view of a buffer at one version. Cloning the rope only bumps
+// reference counts, so snapshots are cheap to take on every edit and can be
+// handed to background threads while the buffer keeps changing.
+#[frb(opaque)]
+#[derive(Clone)]
+pub struct BufferSnapshot {
+    text: Rope,
+    version: usize,
+    buffer_id: u64,
+}
+
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<BufferSnapshot>();
+};
+
+impl Buffer {
+    #[frb(sync)]
+    pub fn snapshot(&self) -> BufferSnapshot {
+        BufferSnapshot {
+            text: self.rope().clone(),
+            version: self.version,
+            buffer_id: self.id(),
+        }
+    }
+}
+
+impl BufferSnapshot {
+    #[frb(sync, type_64bit_int)]
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn buffer_id(&self) -> u64 {
+        self.buffer_id
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn byte_len(&self) -> usize {
+        self.text.byte_len()
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn line_count(&self) -> usize {
+        self.text.line_len()
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn line(&self, row: usize) -> String {
+        if row >= self.line_count() {
+            String::new()
+        } else {
+            self.text.line(row).to_string()
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn line_len(&self, row: usize) -> usize {
+        if row >= self.line_count() {
+            0
+        } else {
+            self.text.line(row).byte_len()
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn row_column_to_idx(&self, row: usize, column: usize) -> usize {
+        if self.line_count() == 0 {
+            return 0;
+        }
+
+        self.text.byte_of_line(row) + column
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn idx_to_row_column(&self, idx: usize) -> (usize, usize) {
+        if self.line_count() == 0 {
+            return (0, 0);
+        }
+
+        let row = self.text.line_of_byte(idx);
+        (row, idx - self.text.byte_of_line(row))
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn text_in_range(
+        &self,
+        start_row: usize,
+        start_column: usize,
+        end_row: usize,
+        end_column: usize,
+    ) -> String {
+        let start_idx = self.row_column_to_idx(start_row, start_column);
+        let end_idx = self.row_column_to_idx(end_row, end_column);
+
+        self.text.byte_slice(start_idx..end_idx).to_string()
+    }
+
+    #[frb(sync)]
+    pub fn to_string(&self) -> String {
+        self.text.to_string()
+    }
+
+    // Line-level edits that turn this snapshot into `newer`, e.g. to send an
+    // incremental sync to a language server.
+    pub fn edits_to(&self, newer: &BufferSnapshot) -> Vec<TextEdit> {
+        line_edits(&self.to_string(), &newer.to_string(), 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_keep_their_version_while_the_buffer_changes() {
+        let mut buffer = Buffer::from("one\ntwo\n".to_string());
+        let before = buffer.snapshot();
+        buffer.insert(1, 0, "zero ".to_string());
+        let after = buffer.snapshot();
+
+        assert_eq!(before.to_string(), "one\ntwo\n");
+        assert_eq!(before.version() + 1, after.version());
+        assert_eq!(before.buffer_id(), buffer.id());
+
+        // Read on another thread while the buffer moves on.
+        let reader = std::thread::spawn(move || (before.line(1), before.line_len(1)));
+        buffer.insert(0, 0, "x".to_string());
+        assert_eq!(reader.join().unwrap(), ("two".to_string(), 3));
+        assert_eq!(after.line(1), "zero two");
+    }
+
+    #[test]
+    fn converts_positions_and_reads_ranges() {
+        let snapshot = Buffer::from("ab\ncde\n".to_string()).snapshot();
+
+        assert_eq!(snapshot.byte_len(), 7);
+        assert_eq!(snapshot.row_column_to_idx(1, 2), 5);
+        assert_eq!(snapshot.idx_to_row_column(5), (1, 2));
+        assert_eq!(snapshot.text_in_range(0, 1, 1, 2), "b\ncd");
+        assert_eq!(snapshot.line(5), "");
+        assert_eq!(snapshot.line_len(5), 0);
+
+        let empty = Buffer::new().snapshot();
+        assert_eq!(empty.row_column_to_idx(3, 3), 0);
+        assert_eq!(empty.idx_to_row_column(3), (0, 0));
+    }
+
+    #[test]
+    fn edits_to_a_newer_snapshot_reproduce_it() {
+        let mut buffer = Buffer::from("fn a() {}\nfn b() {}\nfn c() {}\n".to_string());
+        let old = buffer.snapshot();
+        buffer.replace_range(1, 3, 1, 4, "beta".to_string());
+        buffer.insert(3, 0, "fn d() {}\n".to_string());
+        let new = buffer.snapshot();
+
+        let mut replay = Buffer::from(old.to_string());
+        replay.apply_edits(old.edits_to(&new));
+        assert_eq!(replay.to_string(), new.to_string());
+        assert!(new.edits_to(&new).is_empty());
+    }
+}
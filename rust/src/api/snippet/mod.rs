@@ -0,0 +1,997 @@
+mod syntax;
+
+use flutter_rust_bridge::frb;
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::anchor::{Anchor, Bias};
+use super::buffer::{Buffer, TextEdit};
+use super::cursor::Cursor;
+use super::editorconfig::Indentation;
+use super::language::LanguageInfo;
+use super::selection::Selection;
+use super::theme::strip_json_comments;
+use syntax::{parse, Piece, Transform};
+
+// What snippet variables are resolved from, besides the buffer itself.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, Default)]
+pub struct SnippetContext {
+    pub file_path: Option<String>,
+    pub workspace_root: Option<String>,
+    pub clipboard: Option<String>,
+    // For the comment variables and what counts as the current word.
+    pub language: Option<LanguageInfo>,
+    // Tabs in the snippet are written as one level of this.
+    pub indentation: Indentation,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone)]
+pub struct SnippetStop {
+    pub index: usize,
+    // The placeholder to select, the first of the stop's copies.
+    pub selection: Selection,
+    // The other copies of the placeholder, which follow edits to `selection`.
+    pub mirrors: Vec<Selection>,
+    pub choices: Vec<String>,
+}
+
+struct Occurrence {
+    start: Anchor,
+    end: Anchor,
+    // Mirrors with a transform show the edited placeholder transformed.
+    transform: Option<Transform>,
+}
+
+struct Stop {
+    index: usize,
+    occurrences: Vec<Occurrence>,
+    choices: Vec<String>,
+}
+
+// An inserted snippet whose tab stops are still being filled in. Placeholders
+// are tracked with anchors, so edits made anywhere in the buffer keep them in
+// place.
+#[frb(opaque)]
+pub struct SnippetSession {
+    // In visiting order, ending with `$0`.
+    stops: Vec<Stop>,
+    current: usize,
+    active: bool,
+    last_stop: Option<SnippetStop>,
+}
+
+impl SnippetSession {
+    // Inserts `body` in place of `selection`, or at `cursor` when nothing is
+    // selected, and selects the first tab stop. A snippet without tab stops
+    // leaves the cursor at its end and the session finished.
+    #[frb(sync)]
+    pub fn insert(
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        selection: Selection,
+        body: String,
+        context: SnippetContext,
+    ) -> Self {
+        let (start, end) = if selection.is_empty() {
+            (cursor, cursor)
+        } else {
+            let normalized = selection.normalized();
+            (normalized.start, normalized.end)
+        };
+
+        let line = buffer.line(start.row);
+        let variables = Variables {
+            buffer,
+            start,
+            end,
+            line: &line,
+            context: &context,
+        };
+        let indent = line[..line.len() - line.trim_start().len()].to_string();
+        let pieces = parse(&body);
+        let rendered = Renderer::render(&pieces, &variables, &indent, &context.indentation);
+
+        let start_idx = buffer.row_column_to_idx(start.row, start.column);
+        let end_idx = buffer.row_column_to_idx(end.row, end.column);
+        buffer.replace_bytes(start_idx..end_idx, &rendered.text);
+
+        let stops = rendered
+            .stops
+            .into_iter()
+            .map(|stop| Stop {
+                index: stop.index,
+                occurrences: stop
+                    .occurrences
+                    .into_iter()
+                    .map(|(range, transform)| Occurrence {
+                        start: buffer
+                            .anchors_mut()
+                            .insert(start_idx + range.0, Bias::Right),
+                        end: buffer
+                            .anchors_mut()
+                            .insert(start_idx + range.1, Bias::Right),
+                        transform,
+                    })
+                    .collect(),
+                choices: stop.choices,
+            })
+            .collect();
+
+        let mut session = Self {
+            stops,
+            current: 0,
+            active: true,
+            last_stop: None,
+        };
+        session.select(buffer, 0);
+        session
+    }
+
+    #[frb(sync)]
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // The stop selected last, with positions as of when it was selected.
+    #[frb(sync)]
+    pub fn stop(&self) -> Option<SnippetStop> {
+        self.last_stop.clone()
+    }
+
+    // Moves to the next tab stop. Reaching `$0` finishes the session.
+    #[frb(sync)]
+    pub fn next(&mut self, buffer: &mut Buffer) -> Option<SnippetStop> {
+        if !self.active {
+            return None;
+        }
+        self.sync_mirrors(buffer);
+        self.select(buffer, self.current + 1)
+    }
+
+    #[frb(sync)]
+    pub fn previous(&mut self, buffer: &mut Buffer) -> Option<SnippetStop> {
+        if !self.active || self.current == 0 {
+            return None;
+        }
+        self.sync_mirrors(buffer);
+        self.select(buffer, self.current - 1)
+    }
+
+    // Whether `cursor` is inside the current stop's placeholders, so the
+    // caller can finish the session once editing moves elsewhere.
+    #[frb(sync)]
+    pub fn contains(&self, buffer: &Buffer, cursor: Cursor) -> bool {
+        let Some(stop) = self.stops.get(self.current).filter(|_| self.active) else {
+            return false;
+        };
+        let offset = buffer.row_column_to_idx(cursor.row, cursor.column);
+
+        stop.occurrences.iter().any(|occurrence| {
+            let (start, end) = range(buffer, occurrence);
+            (start..=end).contains(&offset)
+        })
+    }
+
+    // Rewrites the current stop's mirrors to match its placeholder, returning
+    // the edits made. Call after each edit while the session is active.
+    #[frb(sync)]
+    pub fn sync_mirrors(&mut self, buffer: &mut Buffer) -> Vec<TextEdit> {
+        let Some(stop) = self.stops.get(self.current).filter(|_| self.active) else {
+            return Vec::new();
+        };
+        let Some((primary, mirrors)) = stop.occurrences.split_first() else {
+            return Vec::new();
+        };
+
+        let (start, end) = range(buffer, primary);
+        let text = buffer.rope().byte_slice(start..end).to_string();
+        let mut edits = Vec::new();
+
+        for mirror in mirrors {
+            let replacement = match &mirror.transform {
+                Some(transform) => transform.apply(&text),
+                None => text.clone(),
+            };
+            let (start, end) = range(buffer, mirror);
+            if buffer.rope().byte_slice(start..end) == replacement.as_str() {
+                continue;
+            }
+
+            let (start_row, start_column) = buffer.idx_to_row_column(start);
+            let (end_row, end_column) = buffer.idx_to_row_column(end);
+            buffer.replace_bytes(start..end, &replacement);
+            edits.push(TextEdit {
+                start_row,
+                start_column,
+                end_row,
+                end_column,
+                text: replacement,
+            });
+        }
+
+        edits
+    }
+
+    // Ends the session, releasing its anchors.
+    #[frb(sync)]
+    pub fn finish(&mut self, buffer: &mut Buffer) {
+        for stop in &self.stops {
+            for occurrence in &stop.occurrences {
+                buffer.drop_anchor(occurrence.start);
+                buffer.drop_anchor(occurrence.end);
+            }
+        }
+        self.stops.clear();
+        self.active = false;
+    }
+
+    fn select(&mut self, buffer: &mut Buffer, index: usize) -> Option<SnippetStop> {
+        let index = index.min(self.stops.len().saturating_sub(1));
+        self.current = index;
+        self.reanchor(buffer);
+
+        let stop = &self.stops.get(index)?;
+        let mut selections = stop.occurrences.iter().map(|occurrence| {
+            let (start, end) = range(buffer, occurrence);
+            Selection::new(cursor_at(buffer, start), cursor_at(buffer, end))
+        });
+        let selected = SnippetStop {
+            index: stop.index,
+            selection: selections.next()?,
+            mirrors: selections.collect(),
+            choices: stop.choices.clone(),
+        };
+
+        self.last_stop = Some(selected.clone());
+        if index + 1 == self.stops.len() {
+            self.finish(buffer);
+        }
+        Some(selected)
+    }
+
+    // Makes only the current stop's placeholders grow with text typed at their
+    // edges, so typing at the end of one stop does not extend the next.
+    fn reanchor(&mut self, buffer: &mut Buffer) {
+        for (i, stop) in self.stops.iter_mut().enumerate() {
+            for occurrence in &mut stop.occurrences {
+                let (start, end) = range(buffer, occurrence);
+                let (start_bias, end_bias) = if i == self.current {
+                    (Bias::Left, Bias::Right)
+                } else if start == end {
+                    (Bias::Right, Bias::Right)
+                } else {
+                    (Bias::Right, Bias::Left)
+                };
+
+                buffer.drop_anchor(occurrence.start);
+                buffer.drop_anchor(occurrence.end);
+                occurrence.start = buffer.anchors_mut().insert(start, start_bias);
+                occurrence.end = buffer.anchors_mut().insert(end, end_bias);
+            }
+        }
+    }
+}
+
+fn range(buffer: &Buffer, occurrence: &Occurrence) -> (usize, usize) {
+    let start = buffer.anchors().offset(occurrence.start).unwrap_or(0);
+    let end = buffer.anchors().offset(occurrence.end).unwrap_or(start);
+    (start.min(end), end.max(start))
+}
+
+fn cursor_at(buffer: &Buffer, offset: usize) -> Cursor {
+    let (row, column) = buffer.idx_to_row_column(offset);
+    Cursor::new(row, column, column)
+}
+
+struct RenderedStop {
+    index: usize,
+    // Byte ranges into the rendered text.
+    occurrences: Vec<((usize, usize), Option<Transform>)>,
+    choices: Vec<String>,
+}
+
+struct Rendered {
+    text: String,
+    stops: Vec<RenderedStop>,
+}
+
+struct Renderer<'a> {
+    variables: &'a Variables<'a>,
+    indent: &'a str,
+    tab: String,
+    text: String,
+    // The occurrence that defines each stop's placeholder.
+    definitions: HashMap<usize, &'a Piece>,
+    stops: Vec<RenderedStop>,
+    // Unknown variables become placeholders holding their name.
+    unknown: HashMap<String, usize>,
+    next_index: usize,
+    expanding: HashSet<usize>,
+}
+
+impl<'a> Renderer<'a> {
+    fn render(
+        pieces: &'a [Piece],
+        variables: &'a Variables<'a>,
+        indent: &'a str,
+        indentation: &Indentation,
+    ) -> Rendered {
+        let mut definitions = HashMap::new();
+        let mut max_index = 0;
+        collect_definitions(pieces, &mut definitions, &mut max_index);
+
+        let mut renderer = Renderer {
+            variables,
+            indent,
+            tab: indentation.unit(),
+            text: String::new(),
+            definitions,
+            stops: Vec::new(),
+            unknown: HashMap::new(),
+            next_index: max_index + 1,
+            expanding: HashSet::new(),
+        };
+        renderer.pieces(pieces, true);
+
+        // Stops are visited in order with `$0` last, added at the end of the
+        // snippet when it has none.
+        let mut stops = renderer.stops;
+        if !stops.iter().any(|stop| stop.index == 0) {
+            let end = renderer.text.len();
+            stops.push(RenderedStop {
+                index: 0,
+                occurrences: vec![((end, end), None)],
+                choices: Vec::new(),
+            });
+        }
+        stops.sort_by_key(|stop| {
+            if stop.index == 0 {
+                usize::MAX
+            } else {
+                stop.index
+            }
+        });
+
+        Rendered {
+            text: renderer.text,
+            stops,
+        }
+    }
+
+    fn pieces(&mut self, pieces: &'a [Piece], track: bool) {
+        for piece in pieces {
+            self.piece(piece, track);
+        }
+    }
+
+    fn piece(&mut self, piece: &'a Piece, track: bool) {
+        match piece {
+            Piece::Text(text) => self.push_text(text),
+            Piece::TabStop {
+                index,
+                choices,
+                transform,
+                ..
+            } => {
+                let start = self.text.len();
+                let defines = self
+                    .definitions
+                    .get(index)
+                    .is_some_and(|definition| std::ptr::eq(*definition, piece));
+
+                if defines {
+                    self.placeholder(piece, track);
+                } else if let Some(&definition) = self.definitions.get(index) {
+                    // Mirrors show the placeholder's text without its nested
+                    // stops.
+                    if self.expanding.insert(*index) {
+                        self.placeholder(definition, false);
+                        self.expanding.remove(index);
+                    }
+                    if let Some(transform) = transform {
+                        let mirrored = self.text.split_off(start);
+                        self.text.push_str(&transform.apply(&mirrored));
+                    }
+                }
+
+                if track {
+                    let end = self.text.len();
+                    self.track(*index, start, end, transform.clone(), choices, defines);
+                }
+            }
+            Piece::Variable {
+                name,
+                default,
+                transform,
+            } => match self.variables.resolve(name) {
+                Some(value) => {
+                    let value = match transform {
+                        Some(transform) => transform.apply(&value),
+                        None => value,
+                    };
+                    self.push_text(&value);
+                }
+                None => match default {
+                    Some(default) => self.pieces(default, track),
+                    None => {
+                        let index = match self.unknown.get(name) {
+                            Some(&index) => index,
+                            None => {
+                                let index = self.next_index;
+                                self.next_index += 1;
+                                self.unknown.insert(name.clone(), index);
+                                index
+                            }
+                        };
+                        let start = self.text.len();
+                        self.push_text(name);
+                        if track {
+                            let end = self.text.len();
+                            self.track(index, start, end, None, &None, false);
+                        }
+                    }
+                },
+            },
+        }
+    }
+
+    fn placeholder(&mut self, piece: &'a Piece, track: bool) {
+        if let Piece::TabStop {
+            placeholder,
+            choices,
+            ..
+        } = piece
+        {
+            if let Some(placeholder) = placeholder {
+                self.pieces(placeholder, track);
+            } else if let Some(first) = choices.as_ref().and_then(|choices| choices.first()) {
+                self.push_text(first);
+            }
+        }
+    }
+
+    fn track(
+        &mut self,
+        index: usize,
+        start: usize,
+        end: usize,
+        transform: Option<Transform>,
+        choices: &Option<Vec<String>>,
+        primary: bool,
+    ) {
+        let position = self.stops.iter().position(|stop| stop.index == index);
+        let stop = match position {
+            Some(i) => &mut self.stops[i],
+            None => {
+                self.stops.push(RenderedStop {
+                    index,
+                    occurrences: Vec::new(),
+                    choices: Vec::new(),
+                });
+                self.stops.last_mut().unwrap()
+            }
+        };
+
+        if let Some(choices) = choices {
+            stop.choices = choices.clone();
+        }
+        // The defining occurrence goes first, so it is the one selected and
+        // the others mirror it.
+        let occurrence = ((start, end), transform);
+        if primary {
+            stop.occurrences.insert(0, occurrence);
+        } else {
+            stop.occurrences.push(occurrence);
+        }
+    }
+
+    // Continues each new line at the insertion line's indentation and writes
+    // tabs as the configured indentation.
+    fn push_text(&mut self, text: &str) {
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.text.push('\n');
+                self.text.push_str(self.indent);
+            }
+            self.text.push_str(&line.replace('\t', &self.tab));
+        }
+    }
+}
+
+// Finds the occurrence of each tab stop that defines its placeholder: the
+// first with placeholder text or choices, or else the first occurrence.
+fn collect_definitions<'a>(
+    pieces: &'a [Piece],
+    definitions: &mut HashMap<usize, &'a Piece>,
+    max_index: &mut usize,
+) {
+    for piece in pieces {
+        match piece {
+            Piece::TabStop {
+                index,
+                placeholder,
+                choices,
+                transform,
+            } => {
+                *max_index = (*max_index).max(*index);
+                let has_content = placeholder.is_some() || choices.is_some();
+                let replaces = match definitions.get(index) {
+                    None => transform.is_none(),
+                    Some(Piece::TabStop {
+                        placeholder: None,
+                        choices: None,
+                        ..
+                    }) => has_content,
+                    Some(_) => false,
+                };
+                if replaces {
+                    definitions.insert(*index, piece);
+                }
+                if let Some(placeholder) = placeholder {
+                    collect_definitions(placeholder, definitions, max_index);
+                }
+            }
+            Piece::Variable {
+                default: Some(default),
+                ..
+            } => collect_definitions(default, definitions, max_index),
+            _ => {}
+        }
+    }
+}
+
+struct Variables<'a> {
+    buffer: &'a Buffer,
+    start: Cursor,
+    end: Cursor,
+    line: &'a str,
+    context: &'a SnippetContext,
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const DAY_NAMES: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+impl Variables<'_> {
+    // The value of a snippet variable, or `None` when it is unknown or has no
+    // value here, such as `TM_FILENAME` for an unsaved buffer.
+    fn resolve(&self, name: &str) -> Option<String> {
+        let path = self.context.file_path.as_deref().map(Path::new);
+        let language = self.context.language.as_ref();
+        let file_name = || {
+            path.and_then(Path::file_name)
+                .map(|name| name.to_string_lossy().to_string())
+        };
+
+        let value = match name {
+            "TM_SELECTED_TEXT" => {
+                let (start, end) = (self.start, self.end);
+                self.buffer
+                    .text_in_range(start.row, start.column, end.row, end.column)
+            }
+            "TM_CURRENT_LINE" => self.line.to_string(),
+            "TM_CURRENT_WORD" => self.current_word()?,
+            "TM_LINE_INDEX" => self.start.row.to_string(),
+            "TM_LINE_NUMBER" => (self.start.row + 1).to_string(),
+            "TM_FILENAME" => file_name()?,
+            "TM_FILENAME_BASE" => {
+                let name = file_name()?;
+                match name.find('.').filter(|&i| i > 0) {
+                    Some(i) => name[..i].to_string(),
+                    None => name,
+                }
+            }
+            "TM_DIRECTORY" => path?.parent()?.to_string_lossy().to_string(),
+            "TM_FILEPATH" => path?.to_string_lossy().to_string(),
+            "RELATIVE_FILEPATH" => {
+                let root = self.context.workspace_root.as_deref()?;
+                path?.strip_prefix(root).ok()?.to_string_lossy().to_string()
+            }
+            "WORKSPACE_NAME" => {
+                let root = Path::new(self.context.workspace_root.as_deref()?);
+                root.file_name()?.to_string_lossy().to_string()
+            }
+            "WORKSPACE_FOLDER" => self.context.workspace_root.clone()?,
+            "CLIPBOARD" => self.context.clipboard.clone()?,
+            "LINE_COMMENT" => language?.line_comment.clone()?,
+            "BLOCK_COMMENT_START" => language?.block_comment.as_ref()?.open.clone(),
+            "BLOCK_COMMENT_END" => language?.block_comment.as_ref()?.close.clone(),
+            "RANDOM" => format!("{:06}", rand::rng().random_range(0..1_000_000)),
+            "RANDOM_HEX" => format!("{:06x}", rand::rng().random_range(0..0x1000000)),
+            "UUID" => uuid(),
+            _ => date_variable(name)?,
+        };
+
+        Some(value)
+    }
+
+    fn current_word(&self) -> Option<String> {
+        let extra = self
+            .context
+            .language
+            .as_ref()
+            .map_or("", |language| language.word_characters.as_str());
+        let is_word = |c: char| c.is_alphanumeric() || c == '_' || extra.contains(c);
+
+        let column = self.start.column.min(self.line.len());
+        let before = self.line[..column]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_word(c))
+            .last()
+            .map_or(column, |(i, _)| i);
+        let after = self.line[column..]
+            .char_indices()
+            .find(|&(_, c)| !is_word(c))
+            .map_or(self.line.len(), |(i, _)| column + i);
+
+        (before < after).then(|| self.line[before..after].to_string())
+    }
+}
+
+fn uuid() -> String {
+    let mut bytes: [u8; 16] = rand::rng().random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// Date and time variables, in UTC.
+fn date_variable(name: &str) -> Option<String> {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    let weekday = (days + 4).rem_euclid(7) as usize;
+
+    let value = match name {
+        "CURRENT_YEAR" => year.to_string(),
+        "CURRENT_YEAR_SHORT" => format!("{:02}", year % 100),
+        "CURRENT_MONTH" => format!("{month:02}"),
+        "CURRENT_MONTH_NAME" => MONTH_NAMES[month as usize - 1].to_string(),
+        "CURRENT_MONTH_NAME_SHORT" => MONTH_NAMES[month as usize - 1][..3].to_string(),
+        "CURRENT_DATE" => format!("{day:02}"),
+        "CURRENT_DAY_NAME" => DAY_NAMES[weekday].to_string(),
+        "CURRENT_DAY_NAME_SHORT" => DAY_NAMES[weekday][..3].to_string(),
+        "CURRENT_HOUR" => format!("{:02}", time / 3600),
+        "CURRENT_MINUTE" => format!("{:02}", time / 60 % 60),
+        "CURRENT_SECOND" => format!("{:02}", time % 60),
+        "CURRENT_SECONDS_UNIX" => seconds.to_string(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// Converts days since 1970-01-01 to a (year, month, day) date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnippetInfo {
+    pub name: String,
+    pub prefixes: Vec<String>,
+    pub body: String,
+    pub description: Option<String>,
+    // Language ids the snippet applies to, or empty for every language.
+    pub languages: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct SnippetFileEntry {
+    prefix: Option<Value>,
+    body: Value,
+    description: Option<String>,
+    scope: Option<String>,
+}
+
+// Snippets from VS Code snippet files: `<language id>.json` for one language,
+// or `*.code-snippets` with an optional `scope` list per snippet.
+#[frb(opaque)]
+#[derive(Default)]
+pub struct SnippetLibrary {
+    snippets: Vec<SnippetInfo>,
+}
+
+impl SnippetLibrary {
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Loads every snippet file in `dir`, returning how many snippets were
+    // added.
+    pub fn load_dir(&mut self, dir: String) -> anyhow::Result<usize> {
+        let mut paths: Vec<_> = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|ext| ext == "json" || ext == "code-snippets")
+            })
+            .collect();
+        paths.sort();
+
+        let mut added = 0;
+        for path in paths {
+            added += self.load_file(path.to_string_lossy().to_string(), None)?;
+        }
+        Ok(added)
+    }
+
+    // Loads one snippet file. Snippets in a `.json` file apply to `language`,
+    // or to the language named by the file when it is `None`.
+    pub fn load_file(&mut self, path: String, language: Option<String>) -> anyhow::Result<usize> {
+        let path = Path::new(&path);
+        let contents = fs::read_to_string(path)?;
+        let entries: HashMap<String, Value> = serde_json::from_str(&strip_json_comments(&contents))
+            .map_err(|err| anyhow::anyhow!("invalid snippet file {}: {err}", path.display()))?;
+
+        let is_json = path.extension().is_some_and(|ext| ext == "json");
+        let file_language = match language {
+            Some(language) => Some(language),
+            None if is_json => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string()),
+            None => None,
+        };
+
+        let mut snippets: Vec<SnippetInfo> = entries
+            .into_iter()
+            .filter_map(|(name, value)| {
+                // Entries that are not snippets, such as comments some files
+                // keep as strings, are skipped.
+                let entry: SnippetFileEntry = serde_json::from_value(value).ok()?;
+                let languages = match (&file_language, &entry.scope) {
+                    (Some(language), _) => vec![language.clone()],
+                    (None, Some(scope)) => scope
+                        .split(',')
+                        .map(|language| language.trim().to_string())
+                        .filter(|language| !language.is_empty())
+                        .collect(),
+                    (None, None) => Vec::new(),
+                };
+
+                Some(SnippetInfo {
+                    name,
+                    prefixes: strings(entry.prefix.as_ref()),
+                    body: strings(Some(&entry.body)).join("\n"),
+                    description: entry.description,
+                    languages,
+                })
+            })
+            .collect();
+        snippets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let added = snippets.len();
+        self.snippets.extend(snippets);
+        Ok(added)
+    }
+
+    #[frb(sync)]
+    pub fn add(&mut self, snippet: SnippetInfo) {
+        self.snippets.push(snippet);
+    }
+
+    #[frb(sync)]
+    pub fn snippets_for(&self, language: String) -> Vec<SnippetInfo> {
+        self.snippets
+            .iter()
+            .filter(|snippet| applies_to(snippet, &language))
+            .cloned()
+            .collect()
+    }
+
+    // Snippets for `language` with a prefix starting with `typed`, shortest
+    // prefix first.
+    #[frb(sync)]
+    pub fn with_prefix(&self, language: String, typed: String) -> Vec<SnippetInfo> {
+        let mut matches: Vec<(usize, &SnippetInfo)> = self
+            .snippets
+            .iter()
+            .filter(|snippet| applies_to(snippet, &language))
+            .filter_map(|snippet| {
+                let shortest = snippet
+                    .prefixes
+                    .iter()
+                    .filter(|prefix| prefix.starts_with(&typed))
+                    .map(String::len)
+                    .min()?;
+                Some((shortest, snippet))
+            })
+            .collect();
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.name.cmp(&b.1.name)));
+
+        matches
+            .into_iter()
+            .map(|(_, snippet)| snippet.clone())
+            .collect()
+    }
+}
+
+fn applies_to(snippet: &SnippetInfo, language: &str) -> bool {
+    snippet.languages.is_empty() || snippet.languages.iter().any(|id| id == language)
+}
+
+// A string or array of strings, as `prefix` and `body` may be.
+fn strings(value: Option<&Value>) -> Vec<String> {
+    match value {
+        Some(Value::String(value)) => vec![value.clone()],
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(buffer: &mut Buffer, row: usize, column: usize, body: &str) -> SnippetSession {
+        let cursor = Cursor::new(row, column, column);
+        SnippetSession::insert(
+            buffer,
+            cursor,
+            Selection::new(cursor, cursor),
+            body.to_string(),
+            SnippetContext::default(),
+        )
+    }
+
+    fn selected(buffer: &Buffer, stop: &SnippetStop) -> String {
+        let (start, end) = (stop.selection.start, stop.selection.end);
+        buffer.text_in_range(start.row, start.column, end.row, end.column)
+    }
+
+    #[test]
+    fn visits_stops_in_order_and_back() {
+        let mut buffer = Buffer::from("    x\n".to_string());
+        let mut session = insert(&mut buffer, 0, 4, "fn ${1:name}(${2:arg}) {\n\t$0\n}");
+        assert_eq!(buffer.to_string(), "    fn name(arg) {\n        \n    }x\n");
+
+        let stop = session.stop().unwrap();
+        assert_eq!((stop.index, selected(&buffer, &stop).as_str()), (1, "name"));
+        let stop = session.next(&mut buffer).unwrap();
+        assert_eq!((stop.index, selected(&buffer, &stop).as_str()), (2, "arg"));
+        let stop = session.previous(&mut buffer).unwrap();
+        assert_eq!(stop.index, 1);
+        assert!(session.previous(&mut buffer).is_none());
+
+        session.next(&mut buffer);
+        let stop = session.next(&mut buffer).unwrap();
+        assert_eq!(stop.index, 0);
+        assert_eq!(
+            (stop.selection.start.row, stop.selection.start.column),
+            (1, 8)
+        );
+        assert!(!session.is_active());
+        assert!(session.next(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn selects_choices_and_nested_placeholders() {
+        let mut buffer = Buffer::new();
+        let mut session = insert(&mut buffer, 0, 0, "${1|let,const|} ${2:a ${3:b}};");
+        assert_eq!(buffer.to_string(), "let a b;");
+        assert_eq!(session.stop().unwrap().choices, ["let", "const"]);
+
+        let stop = session.next(&mut buffer).unwrap();
+        assert_eq!(selected(&buffer, &stop), "a b");
+        let stop = session.next(&mut buffer).unwrap();
+        assert_eq!(selected(&buffer, &stop), "b");
+    }
+
+    #[test]
+    fn mirrors_follow_the_primary_occurrence() {
+        let mut buffer = Buffer::new();
+        let mut session = insert(
+            &mut buffer,
+            0,
+            0,
+            "<${1:div}>$0</$1> ${1/(.*)/${1:/upcase}/}",
+        );
+        assert_eq!(buffer.to_string(), "<div></div> DIV");
+        let stop = session.stop().unwrap();
+        assert_eq!(stop.mirrors.len(), 2);
+
+        // Retype the placeholder, growing it at both edges.
+        let start = stop.selection.start;
+        buffer.replace_range(start.row, start.column, 0, 4, "span".to_string());
+        buffer.insert(0, 5, "x".to_string());
+        let edits = session.sync_mirrors(&mut buffer);
+        assert_eq!(edits.len(), 2);
+        assert_eq!(buffer.to_string(), "<spanx></spanx> SPANX");
+        assert!(session.sync_mirrors(&mut buffer).is_empty());
+
+        // Moving on leaves the synced mirrors as they are.
+        session.next(&mut buffer);
+        assert_eq!(buffer.to_string(), "<spanx></spanx> SPANX");
+    }
+
+    #[test]
+    fn loads_code_snippets_files() {
+        let dir = std::env::temp_dir().join(format!("rei-snippet-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mine.code-snippets");
+        fs::write(
+            &path,
+            r#"{
+                // Comments are allowed.
+                "Log": {
+                    "prefix": ["log", "lg"],
+                    "body": ["console.log($1);", "$0"],
+                    "scope": "javascript, typescript"
+                },
+                "Everywhere": { "prefix": "todo", "body": "TODO: $0" },
+                "note": "not a snippet"
+            }"#,
+        )
+        .unwrap();
+
+        let mut library = SnippetLibrary::new();
+        let added = library
+            .load_file(path.to_string_lossy().to_string(), None)
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(added, 2);
+        let log = &library.with_prefix("typescript".to_string(), "l".to_string())[0];
+        assert_eq!(log.name, "Log");
+        assert_eq!(log.body, "console.log($1);\n$0");
+        assert_eq!(log.languages, ["javascript", "typescript"]);
+        assert!(library
+            .with_prefix("rust".to_string(), "l".to_string())
+            .is_empty());
+        assert_eq!(library.snippets_for("rust".to_string()).len(), 1);
+    }
+}
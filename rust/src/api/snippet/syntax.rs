@@ -0,0 +1,583 @@
+use regex::{Captures, Regex, RegexBuilder};
+
+// A snippet body in VS Code/TextMate syntax, parsed. Malformed constructs are
+// kept as literal text, as VS Code does.
+pub(super) enum Piece {
+    Text(String),
+    TabStop {
+        index: usize,
+        placeholder: Option<Vec<Piece>>,
+        choices: Option<Vec<String>>,
+        transform: Option<Transform>,
+    },
+    Variable {
+        name: String,
+        default: Option<Vec<Piece>>,
+        transform: Option<Transform>,
+    },
+}
+
+#[derive(Clone)]
+pub(super) struct Transform {
+    regex: Regex,
+    format: Vec<FormatPiece>,
+    global: bool,
+}
+
+#[derive(Clone)]
+enum FormatPiece {
+    Text(String),
+    Group(usize),
+    Case(usize, CaseChange),
+    // `${1:+if}`, `${1:-else}` and `${1:?if:else}`.
+    Conditional {
+        group: usize,
+        matched: String,
+        otherwise: String,
+    },
+}
+
+#[derive(Clone, Copy)]
+enum CaseChange {
+    Upcase,
+    Downcase,
+    Capitalize,
+    CamelCase,
+    PascalCase,
+}
+
+impl Transform {
+    pub(super) fn apply(&self, text: &str) -> String {
+        let replace = |captures: &Captures| -> String {
+            let group = |n: usize| captures.get(n).map_or("", |m| m.as_str());
+            self.format
+                .iter()
+                .map(|piece| match piece {
+                    FormatPiece::Text(text) => text.clone(),
+                    FormatPiece::Group(n) => group(*n).to_string(),
+                    FormatPiece::Case(n, case) => apply_case(group(*n), *case),
+                    FormatPiece::Conditional {
+                        group: n,
+                        matched,
+                        otherwise,
+                    } => {
+                        if group(*n).is_empty() {
+                            otherwise.clone()
+                        } else {
+                            matched.clone()
+                        }
+                    }
+                })
+                .collect()
+        };
+
+        if self.global {
+            self.regex.replace_all(text, replace).into_owned()
+        } else {
+            self.regex.replace(text, replace).into_owned()
+        }
+    }
+}
+
+fn apply_case(text: &str, case: CaseChange) -> String {
+    let words = || {
+        text.split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+    };
+    let capitalize = |word: &str| {
+        let mut chars = word.chars();
+        chars.next().map_or(String::new(), |first| {
+            first.to_uppercase().chain(chars).collect()
+        })
+    };
+
+    match case {
+        CaseChange::Upcase => text.to_uppercase(),
+        CaseChange::Downcase => text.to_lowercase(),
+        CaseChange::Capitalize => capitalize(text),
+        CaseChange::PascalCase => words().map(capitalize).collect(),
+        CaseChange::CamelCase => words()
+            .enumerate()
+            .map(|(i, word)| {
+                if i == 0 {
+                    word.to_lowercase()
+                } else {
+                    capitalize(word)
+                }
+            })
+            .collect(),
+    }
+}
+
+pub(super) fn parse(body: &str) -> Vec<Piece> {
+    let mut parser = Parser {
+        chars: body.chars().collect(),
+        pos: 0,
+    };
+    parser.pieces(false)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    fn pieces(&mut self, in_placeholder: bool) -> Vec<Piece> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '}' if in_placeholder => break,
+                '\\' => {
+                    self.pos += 1;
+                    match self.peek() {
+                        Some(escaped @ ('$' | '}' | '\\')) => {
+                            text.push(escaped);
+                            self.pos += 1;
+                        }
+                        _ => text.push('\\'),
+                    }
+                }
+                '$' => {
+                    let start = self.pos;
+                    match self.dollar() {
+                        Some(piece) => {
+                            if !text.is_empty() {
+                                pieces.push(Piece::Text(std::mem::take(&mut text)));
+                            }
+                            pieces.push(piece);
+                        }
+                        None => {
+                            self.pos = start + 1;
+                            text.push('$');
+                        }
+                    }
+                }
+                c => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(text));
+        }
+        pieces
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            return None;
+        }
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.pos += 1;
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn dollar(&mut self) -> Option<Piece> {
+        self.pos += 1;
+
+        if !self.eat('{') {
+            if let Some(index) = self.number() {
+                return Some(tab_stop(index));
+            }
+            return self.name().map(|name| Piece::Variable {
+                name,
+                default: None,
+                transform: None,
+            });
+        }
+
+        if let Some(index) = self.number() {
+            if self.eat('}') {
+                return Some(tab_stop(index));
+            }
+            if self.eat(':') {
+                let placeholder = self.pieces(true);
+                return self.eat('}').then_some(Piece::TabStop {
+                    index,
+                    placeholder: Some(placeholder),
+                    choices: None,
+                    transform: None,
+                });
+            }
+            if self.eat('|') {
+                let choices = self.choices()?;
+                return Some(Piece::TabStop {
+                    index,
+                    placeholder: None,
+                    choices: Some(choices),
+                    transform: None,
+                });
+            }
+            if self.eat('/') {
+                let transform = self.transform()?;
+                return Some(Piece::TabStop {
+                    index,
+                    placeholder: None,
+                    choices: None,
+                    transform: Some(transform),
+                });
+            }
+            return None;
+        }
+
+        let name = self.name()?;
+        if self.eat('}') {
+            return Some(Piece::Variable {
+                name,
+                default: None,
+                transform: None,
+            });
+        }
+        if self.eat(':') {
+            let default = self.pieces(true);
+            return self.eat('}').then_some(Piece::Variable {
+                name,
+                default: Some(default),
+                transform: None,
+            });
+        }
+        if self.eat('/') {
+            let transform = self.transform()?;
+            return Some(Piece::Variable {
+                name,
+                default: None,
+                transform: Some(transform),
+            });
+        }
+        None
+    }
+
+    // `a,b,c|}`, after the opening `${1|`.
+    fn choices(&mut self) -> Option<Vec<String>> {
+        let mut choices = vec![String::new()];
+        loop {
+            match self.peek()? {
+                '\\' => {
+                    self.pos += 1;
+                    let escaped = self.peek()?;
+                    if !matches!(escaped, ',' | '|' | '\\' | '$' | '}') {
+                        choices.last_mut()?.push('\\');
+                    }
+                    choices.last_mut()?.push(escaped);
+                }
+                ',' => choices.push(String::new()),
+                '|' => {
+                    self.pos += 1;
+                    return self.eat('}').then_some(choices);
+                }
+                c => choices.last_mut()?.push(c),
+            }
+            self.pos += 1;
+        }
+    }
+
+    // `regex/format/flags}`, after the opening `${1/`.
+    fn transform(&mut self) -> Option<Transform> {
+        let regex = self.until_slash()?;
+        let format = self.until_slash()?;
+        let mut flags = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == '}' {
+                let regex = RegexBuilder::new(&regex)
+                    .case_insensitive(flags.contains('i'))
+                    .multi_line(flags.contains('m'))
+                    .dot_matches_new_line(flags.contains('s'))
+                    .build()
+                    .ok()?;
+                return Some(Transform {
+                    regex,
+                    format: parse_format(&format),
+                    global: flags.contains('g'),
+                });
+            }
+            flags.push(c);
+        }
+        None
+    }
+
+    // Text up to an unescaped `/` outside `${...}`, which is consumed. Only
+    // `\/` is unescaped; other escapes are left for the regex or format parser.
+    fn until_slash(&mut self) -> Option<String> {
+        let mut text = String::new();
+        let mut depth = 0;
+        loop {
+            let c = self.peek()?;
+            self.pos += 1;
+            match c {
+                '/' if depth == 0 => return Some(text),
+                '$' if self.peek() == Some('{') => {
+                    depth += 1;
+                    text.push_str("${");
+                    self.pos += 1;
+                }
+                '}' if depth > 0 => {
+                    depth -= 1;
+                    text.push('}');
+                }
+                '\\' if self.peek() == Some('/') => {
+                    text.push('/');
+                    self.pos += 1;
+                }
+                '\\' => {
+                    text.push('\\');
+                    text.push(self.peek()?);
+                    self.pos += 1;
+                }
+                c => text.push(c),
+            }
+        }
+    }
+}
+
+fn tab_stop(index: usize) -> Piece {
+    Piece::TabStop {
+        index,
+        placeholder: None,
+        choices: None,
+        transform: None,
+    }
+}
+
+fn parse_format(format: &str) -> Vec<FormatPiece> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+
+    let digits = |i: &mut usize| {
+        let start = *i;
+        while chars.get(*i).is_some_and(|c| c.is_ascii_digit()) {
+            *i += 1;
+        }
+        chars[start..*i]
+            .iter()
+            .collect::<String>()
+            .parse::<usize>()
+            .ok()
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                text.push(match chars[i + 1] {
+                    'n' => '\n',
+                    't' => '\t',
+                    c => c,
+                });
+                i += 2;
+                continue;
+            }
+            '$' => {
+                let start = i;
+                i += 1;
+                let piece = if chars.get(i) == Some(&'{') {
+                    i += 1;
+                    digits(&mut i).and_then(|group| format_group(&chars, &mut i, group))
+                } else {
+                    digits(&mut i).map(FormatPiece::Group)
+                };
+                match piece {
+                    Some(piece) => {
+                        if !text.is_empty() {
+                            pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+                        }
+                        pieces.push(piece);
+                    }
+                    None => {
+                        text.push('$');
+                        i = start + 1;
+                    }
+                }
+                continue;
+            }
+            c => text.push(c),
+        }
+        i += 1;
+    }
+
+    if !text.is_empty() {
+        pieces.push(FormatPiece::Text(text));
+    }
+    pieces
+}
+
+// The rest of `${1...}` in a format string, after the group number.
+fn format_group(chars: &[char], i: &mut usize, group: usize) -> Option<FormatPiece> {
+    let end = (*i..chars.len()).find(|&j| chars[j] == '}')?;
+    let inner: String = chars[*i..end].iter().collect();
+    *i = end + 1;
+
+    if inner.is_empty() {
+        return Some(FormatPiece::Group(group));
+    }
+    let option = inner.strip_prefix(':')?;
+
+    let conditional = |matched: &str, otherwise: &str| FormatPiece::Conditional {
+        group,
+        matched: matched.to_string(),
+        otherwise: otherwise.to_string(),
+    };
+    let piece = match option.chars().next()? {
+        '/' => FormatPiece::Case(
+            group,
+            match &option[1..] {
+                "upcase" => CaseChange::Upcase,
+                "downcase" => CaseChange::Downcase,
+                "capitalize" => CaseChange::Capitalize,
+                "camelcase" => CaseChange::CamelCase,
+                "pascalcase" => CaseChange::PascalCase,
+                _ => return None,
+            },
+        ),
+        '+' => conditional(&option[1..], ""),
+        '-' => conditional("", &option[1..]),
+        '?' => {
+            let (matched, otherwise) = option[1..].split_once(':').unwrap_or((&option[1..], ""));
+            conditional(matched, otherwise)
+        }
+        _ => conditional("", option),
+    };
+    Some(piece)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A compact rendering of parsed pieces, to compare against.
+    fn describe(pieces: &[Piece]) -> String {
+        pieces
+            .iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => format!("{text:?}"),
+                Piece::TabStop {
+                    index,
+                    placeholder,
+                    choices,
+                    transform,
+                } => {
+                    let mut out = format!("${index}");
+                    if let Some(placeholder) = placeholder {
+                        out += &format!("[{}]", describe(placeholder));
+                    }
+                    if let Some(choices) = choices {
+                        out += &format!("{choices:?}");
+                    }
+                    if transform.is_some() {
+                        out += "/t";
+                    }
+                    out
+                }
+                Piece::Variable {
+                    name,
+                    default,
+                    transform,
+                } => {
+                    let mut out = format!("${name}");
+                    if let Some(default) = default {
+                        out += &format!("[{}]", describe(default));
+                    }
+                    if transform.is_some() {
+                        out += "/t";
+                    }
+                    out
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn transform(body: &str) -> Transform {
+        match parse(body).pop() {
+            Some(Piece::TabStop {
+                transform: Some(transform),
+                ..
+            }) => transform,
+            _ => panic!("{body} has no transform"),
+        }
+    }
+
+    #[test]
+    fn parses_placeholders_choices_and_variables() {
+        assert_eq!(
+            describe(&parse("fn ${1:name}($2) {\n\t$0\n}")),
+            r#""fn " $1["name"] "(" $2 ") {\n\t" $0 "\n}""#
+        );
+        assert_eq!(
+            describe(&parse(r"${1|one,t\,wo,three|}")),
+            r#"$1["one", "t,wo", "three"]"#
+        );
+        assert_eq!(
+            describe(&parse("${1:outer ${2:inner $3} end}")),
+            r#"$1["outer " $2["inner " $3] " end"]"#
+        );
+        assert_eq!(
+            describe(&parse("$TM_FILENAME ${CLIPBOARD:${1:none}}")),
+            r#"$TM_FILENAME " " $CLIPBOARD[$1["none"]]"#
+        );
+    }
+
+    #[test]
+    fn keeps_escapes_and_malformed_constructs_as_text() {
+        assert_eq!(describe(&parse(r"\$1 \} \\ \n")), r#""$1 } \\ \\n""#);
+        assert_eq!(describe(&parse("${1:open")), r#""${1:open""#);
+        assert_eq!(describe(&parse("$ and ${}")), r#""$ and ${}""#);
+    }
+
+    #[test]
+    fn applies_transforms() {
+        assert_eq!(describe(&parse("${1/(a)/b/g}")), "$1/t");
+        assert_eq!(transform("${1/a/b/g}").apply("banana"), "bbnbnb");
+        assert_eq!(transform("${1/a/b/}").apply("banana"), "bbnana");
+        assert_eq!(transform("${1/A/b/gi}").apply("aA"), "bb");
+        assert_eq!(
+            transform("${1/(\\w+) (\\w+)/${2:/upcase} ${1:/capitalize}/}").apply("hello world"),
+            "WORLD Hello"
+        );
+        assert_eq!(
+            transform("${1/(.*)/${1:/pascalcase}/}").apply("snake_case name"),
+            "SnakeCaseName"
+        );
+        assert_eq!(transform("${1/(x)?.*/${1:?yes:no}/}").apply("abc"), "no");
+        assert_eq!(transform("${1/(x)?.*/${1:+yes}/}").apply("xyz"), "yes");
+        assert_eq!(transform(r"${1/\//\\/g}").apply("a/b"), "a\\b");
+    }
+}
@@ -0,0 +1,448 @@
+use flutter_rust_bridge::frb;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::buffer::{Buffer, TextEdit};
+use super::diff::line_edits;
+use super::session::write_atomically;
+use super::snapshot::BufferSnapshot;
+
+const SWAP_EXTENSION: &str = "swap";
+// Rewrite the journal as a single snapshot once it grows past this many deltas.
+const MAX_DELTAS: usize = 200;
+
+#[derive(Serialize, Deserialize)]
+enum JournalEntry {
+    Header {
+        path: String,
+        buffer_id: u64,
+    },
+    Snapshot {
+        version: usize,
+        timestamp_ms: u64,
+        text: String,
+    },
+    Delta {
+        version: usize,
+        timestamp_ms: u64,
+        edits: Vec<TextEdit>,
+    },
+}
+
+struct Journal {
+    path: String,
+    journaled: Option<BufferSnapshot>,
+    pending: Option<BufferSnapshot>,
+    delta_count: usize,
+}
+
+struct PendingWrite {
+    buffer_id: u64,
+    path: String,
+    snapshot: BufferSnapshot,
+    journaled: Option<BufferSnapshot>,
+    delta_count: usize,
+}
+
+#[derive(Default)]
+struct SwapState {
+    // Keyed by buffer id, so untitled buffers and a file open twice each get
+    // their own swap file.
+    journals: HashMap<u64, Journal>,
+    stopped: bool,
+}
+
+struct Shared {
+    dir: PathBuf,
+    state: Mutex<SwapState>,
+    wake: Condvar,
+    // Held for a whole flush. Each delta is computed against the snapshot
+    // the previous flush journaled, so two flushes running at once, from the
+    // worker and from `SwapWriter::flush`, would append deltas against the
+    // same base and corrupt the replay.
+    flushing: Mutex<()>,
+}
+
+// Periodically journals the edits made to dirty buffers so they can be
+// recovered after a crash. Recording only takes a snapshot; diffing and
+// writing happen on a background thread.
+#[frb(opaque)]
+pub struct SwapWriter {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl SwapWriter {
+    #[frb(sync, type_64bit_int)]
+    pub fn new(recovery_dir: String, interval_ms: u64) -> Self {
+        let shared = Arc::new(Shared {
+            dir: PathBuf::from(recovery_dir),
+            state: Mutex::new(SwapState::default()),
+            wake: Condvar::new(),
+            flushing: Mutex::new(()),
+        });
+
+        let worker_shared = shared.clone();
+        let interval = Duration::from_millis(interval_ms);
+        let worker = thread::spawn(move || loop {
+            let state = worker_shared.state.lock().unwrap();
+            let (state, _) = worker_shared.wake.wait_timeout(state, interval).unwrap();
+            if state.stopped {
+                return;
+            }
+            drop(state);
+
+            // Errors are retried on the next tick.
+            let _ = worker_shared.flush();
+        });
+
+        Self {
+            shared,
+            worker: Some(worker),
+        }
+    }
+
+    // Queues the buffer's current contents to be journaled on the next tick.
+    #[frb(sync)]
+    pub fn record(&self, path: String, buffer: &Buffer) {
+        self.record_snapshot(path, buffer.snapshot());
+    }
+
+    #[frb(sync)]
+    pub fn record_snapshot(&self, path: String, snapshot: BufferSnapshot) {
+        let mut state = self.shared.state.lock().unwrap();
+        let journal = state
+            .journals
+            .entry(snapshot.buffer_id())
+            .or_insert_with(|| Journal {
+                path: path.clone(),
+                journaled: None,
+                pending: None,
+                delta_count: 0,
+            });
+
+        // A renamed buffer rewrites its journal so the header has the new path.
+        if journal.path != path {
+            journal.path = path;
+            journal.journaled = None;
+        }
+
+        let is_journaled = matches!(
+            &journal.journaled,
+            Some(journaled) if journaled.version() == snapshot.version()
+        );
+        if !is_journaled {
+            journal.pending = Some(snapshot);
+        }
+    }
+
+    // Stops journaling a buffer and deletes its swap file, e.g. once it has
+    // been saved or closed without changes.
+    #[frb(sync, type_64bit_int)]
+    pub fn discard(&self, buffer_id: u64) -> anyhow::Result<()> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .journals
+            .remove(&buffer_id);
+
+        let swap_path = self.shared.swap_path(buffer_id);
+        if swap_path.exists() {
+            fs::remove_file(swap_path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        self.shared.flush()
+    }
+}
+
+impl Drop for SwapWriter {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.wake.notify_all();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        let _ = self.shared.flush();
+    }
+}
+
+impl Shared {
+    fn swap_path(&self, buffer_id: u64) -> PathBuf {
+        self.dir
+            .join(format!("{buffer_id:016x}"))
+            .with_extension(SWAP_EXTENSION)
+    }
+
+    fn flush(&self) -> anyhow::Result<()> {
+        let _flushing = self.flushing.lock().unwrap();
+        let pending: Vec<PendingWrite> = {
+            let mut state = self.state.lock().unwrap();
+            state
+                .journals
+                .iter_mut()
+                .filter_map(|(&buffer_id, journal)| {
+                    Some(PendingWrite {
+                        buffer_id,
+                        path: journal.path.clone(),
+                        snapshot: journal.pending.take()?,
+                        journaled: journal.journaled.clone(),
+                        delta_count: journal.delta_count,
+                    })
+                })
+                .collect()
+        };
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+
+        for PendingWrite {
+            buffer_id,
+            path,
+            snapshot,
+            journaled,
+            delta_count,
+        } in pending
+        {
+            let swap_path = self.swap_path(buffer_id);
+            let version = snapshot.version();
+            let timestamp_ms = now_ms();
+
+            let delta_count = match journaled {
+                Some(journaled) if delta_count < MAX_DELTAS && swap_path.exists() => {
+                    let entry = JournalEntry::Delta {
+                        version,
+                        timestamp_ms,
+                        edits: journaled.edits_to(&snapshot),
+                    };
+
+                    let mut file = OpenOptions::new().append(true).open(&swap_path)?;
+                    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+                    delta_count + 1
+                }
+                _ => {
+                    let header = JournalEntry::Header { path, buffer_id };
+                    let snapshot = JournalEntry::Snapshot {
+                        version,
+                        timestamp_ms,
+                        text: snapshot.to_string(),
+                    };
+                    let contents = format!(
+                        "{}\n{}\n",
+                        serde_json::to_string(&header)?,
+                        serde_json::to_string(&snapshot)?
+                    );
+
+                    write_atomically(&swap_path, contents.as_bytes())?;
+                    0
+                }
+            };
+
+            let mut state = self.state.lock().unwrap();
+            match state.journals.get_mut(&buffer_id) {
+                Some(journal) => {
+                    journal.journaled = Some(snapshot);
+                    journal.delta_count = delta_count;
+                }
+                // Discarded while this entry was being written.
+                None => fs::remove_file(&swap_path)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug)]
+pub struct RecoverableBuffer {
+    // The id to give the recovered buffer with `Buffer::set_id`.
+    pub buffer_id: u64,
+    pub path: String,
+    pub swap_path: String,
+    pub version: usize,
+    pub timestamp_ms: u64,
+    pub contents: String,
+    // Edits that turn the file currently on disk into the recovered contents.
+    pub disk_diff: Vec<TextEdit>,
+}
+
+pub fn list_recoverable_buffers(recovery_dir: String) -> anyhow::Result<Vec<RecoverableBuffer>> {
+    let dir = Path::new(&recovery_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut buffers = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let swap_path = entry?.path();
+        if swap_path.extension().and_then(|ext| ext.to_str()) != Some(SWAP_EXTENSION) {
+            continue;
+        }
+
+        // A journal cut short by a crash still recovers up to its last
+        // complete entry.
+        if let Some(buffer) = replay_journal(&swap_path) {
+            buffers.push(buffer);
+        }
+    }
+
+    buffers.sort_by_key(|buffer| Reverse(buffer.timestamp_ms));
+    Ok(buffers)
+}
+
+pub fn discard_recoverable_buffer(swap_path: String) -> anyhow::Result<()> {
+    fs::remove_file(swap_path)?;
+    Ok(())
+}
+
+fn replay_journal(swap_path: &Path) -> Option<RecoverableBuffer> {
+    let journal = fs::read_to_string(swap_path).ok()?;
+    let mut entries = journal
+        .lines()
+        .map_while(|line| serde_json::from_str::<JournalEntry>(line).ok());
+
+    let Some(JournalEntry::Header { path, buffer_id }) = entries.next() else {
+        return None;
+    };
+
+    let mut buffer: Option<Buffer> = None;
+    let (mut version, mut timestamp_ms) = (0, 0);
+
+    for entry in entries {
+        match entry {
+            JournalEntry::Snapshot {
+                version: entry_version,
+                timestamp_ms: entry_timestamp,
+                text,
+            } => {
+                buffer = Some(Buffer::from(text));
+                (version, timestamp_ms) = (entry_version, entry_timestamp);
+            }
+            JournalEntry::Delta {
+                version: entry_version,
+                timestamp_ms: entry_timestamp,
+                edits,
+            } => {
+                buffer.as_mut()?.apply_edits(edits);
+                (version, timestamp_ms) = (entry_version, entry_timestamp);
+            }
+            JournalEntry::Header { .. } => return None,
+        }
+    }
+
+    let contents = buffer?.to_string();
+    let disk = fs::read_to_string(&path).unwrap_or_default();
+
+    Some(RecoverableBuffer {
+        disk_diff: line_edits(&disk, &contents, 0),
+        buffer_id,
+        path,
+        swap_path: swap_path.to_string_lossy().into_owned(),
+        version,
+        timestamp_ms,
+        contents,
+    })
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn concurrent_flushes_replay_to_the_latest_text() {
+        let dir = std::env::temp_dir().join(format!("rei-swap-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let recovery_dir = dir.to_string_lossy().into_owned();
+        let path = dir.join("file.txt").to_string_lossy().into_owned();
+
+        let writer = SwapWriter::new(recovery_dir.clone(), 1);
+        let mut buffer = Buffer::from("start\n".to_string());
+        // Flushing from several threads while edits are recorded makes
+        // flushes overlap.
+        let done = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        writer.flush().unwrap();
+                    }
+                });
+            }
+            for i in 0..300 {
+                let row = buffer.last_row();
+                buffer.insert(row, 0, format!("line {i}\n"));
+                writer.record(path.clone(), &buffer);
+                thread::sleep(Duration::from_micros(50));
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        writer.flush().unwrap();
+
+        let recovered = list_recoverable_buffers(recovery_dir).unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].contents, buffer.to_string());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn untitled_buffers_recover_separately() {
+        let dir = std::env::temp_dir().join(format!("rei-swap-untitled-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let recovery_dir = dir.to_string_lossy().into_owned();
+
+        let writer = SwapWriter::new(recovery_dir.clone(), 60_000);
+        let mut first = Buffer::from("first\n".to_string());
+        let mut second = Buffer::from("second\n".to_string());
+        writer.record(String::new(), &first);
+        writer.record(String::new(), &second);
+        writer.flush().unwrap();
+        first.insert(1, 0, "more\n".to_string());
+        second.insert(0, 0, "before ".to_string());
+        writer.record(String::new(), &first);
+        writer.record(String::new(), &second);
+        writer.flush().unwrap();
+
+        let mut recovered: Vec<(u64, String)> = list_recoverable_buffers(recovery_dir.clone())
+            .unwrap()
+            .into_iter()
+            .map(|buffer| (buffer.buffer_id, buffer.contents))
+            .collect();
+        recovered.sort();
+        let mut expected = vec![
+            (first.id(), "first\nmore\n".to_string()),
+            (second.id(), "before second\n".to_string()),
+        ];
+        expected.sort();
+        assert_eq!(recovered, expected);
+
+        writer.discard(first.id()).unwrap();
+        let recovered = list_recoverable_buffers(recovery_dir).unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].buffer_id, second.id());
+        drop(writer);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
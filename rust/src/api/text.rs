@@ -0,0 +1,73 @@
+// Text helpers shared by the editing modules.
+
+// Applies `step` up to `times` times, stopping early once it no longer moves
+// so that a huge count ends at the buffer's edge instead of spinning there.
+pub(crate) fn repeat_motion(start: usize, times: usize, step: impl Fn(usize) -> usize) -> usize {
+    let mut position = start;
+    for _ in 0..times {
+        let next = step(position);
+        if next == position {
+            break;
+        }
+        position = next;
+    }
+    position
+}
+
+// The number of columns `text` takes on screen when it starts at a tab stop.
+pub(crate) fn visual_width(text: &str, tab_width: usize) -> usize {
+    text.chars()
+        .fold(0, |column, c| column + char_width(c, column, tab_width))
+}
+
+// Columns taken by `c` at visual `column`: a tab reaches the next multiple of
+// the tab width, East Asian wide characters and emoji take two and combining
+// marks none.
+pub(crate) fn char_width(c: char, column: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    match c as u32 {
+        0x09 => tab_width - column % tab_width,
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+// The byte column where `visual` falls in `line`. A character straddling it
+// is excluded, or included when `round_up`.
+pub(crate) fn byte_column(line: &str, visual: usize, tab_width: usize, round_up: bool) -> usize {
+    let mut column = 0;
+    for (idx, c) in line.char_indices() {
+        if column >= visual {
+            return idx;
+        }
+        column += char_width(c, column, tab_width);
+        if column > visual {
+            return if round_up { idx + c.len_utf8() } else { idx };
+        }
+    }
+    line.len()
+}
+
+// `column`, clamped to `line` and moved back to the start of the character it
+// falls inside.
+pub(crate) fn floor_char_boundary(line: &str, column: usize) -> usize {
+    let mut column = column.min(line.len());
+    while !line.is_char_boundary(column) {
+        column -= 1;
+    }
+    column
+}
@@ -0,0 +1,813 @@
+use anyhow::{anyhow, bail};
+use flutter_rust_bridge::frb;
+use serde::Deserialize;
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThemeFormat {
+    // VS Code color theme JSON, comments and trailing commas allowed.
+    VsCode,
+    // TextMate `.tmTheme` property list.
+    TextMate,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThemeKind {
+    Dark,
+    Light,
+}
+
+// Colors for the parts of the editor outside the text. Every color is ARGB,
+// as Flutter's `Color` takes it.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EditorColors {
+    pub background: u32,
+    pub foreground: u32,
+    pub cursor: u32,
+    pub selection: u32,
+    pub line_highlight: u32,
+    pub gutter_background: u32,
+    pub gutter_foreground: u32,
+    pub gutter_active_foreground: u32,
+}
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TokenStyle {
+    pub foreground: u32,
+    pub background: Option<u32>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+}
+
+const BUNDLED_THEMES: &[(&str, &str)] = &[
+    ("dark", include_str!("dark.json")),
+    ("light", include_str!("light.json")),
+];
+
+// Highlight capture names and the TextMate scope each is styled as. Captures
+// not listed fall back to their closest listed parent, e.g.
+// `function.method.call` to `function.method`.
+const CAPTURE_SCOPES: &[(&str, &str)] = &[
+    ("attribute", "entity.other.attribute-name"),
+    ("boolean", "constant.language.boolean"),
+    ("character", "constant.character"),
+    ("comment", "comment"),
+    ("comment.documentation", "comment.block.documentation"),
+    ("constant", "constant.other"),
+    ("constant.builtin", "constant.language"),
+    ("constructor", "entity.name.type"),
+    ("escape", "constant.character.escape"),
+    ("function", "entity.name.function"),
+    ("function.builtin", "support.function"),
+    ("function.macro", "entity.name.function.macro"),
+    ("keyword", "keyword"),
+    ("keyword.operator", "keyword.operator"),
+    ("label", "entity.name.label"),
+    ("module", "entity.name.namespace"),
+    ("namespace", "entity.name.namespace"),
+    ("number", "constant.numeric"),
+    ("operator", "keyword.operator"),
+    ("property", "variable.other.property"),
+    ("punctuation", "punctuation"),
+    ("punctuation.delimiter", "punctuation.separator"),
+    ("string", "string"),
+    ("string.escape", "constant.character.escape"),
+    ("string.regex", "string.regexp"),
+    ("tag", "entity.name.tag"),
+    ("type", "entity.name.type"),
+    ("type.builtin", "support.type"),
+    ("variable", "variable"),
+    ("variable.builtin", "variable.language"),
+    ("variable.parameter", "variable.parameter"),
+];
+
+#[derive(Clone, Copy, Default)]
+struct FontStyle {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+}
+
+impl FontStyle {
+    fn parse(value: &str) -> Self {
+        let mut style = FontStyle::default();
+        for word in value.split_whitespace() {
+            match word {
+                "bold" => style.bold = true,
+                "italic" => style.italic = true,
+                "underline" => style.underline = true,
+                "strikethrough" => style.strikethrough = true,
+                _ => {}
+            }
+        }
+        style
+    }
+}
+
+// What one rule sets. Each property is resolved separately, so a rule that
+// only sets `fontStyle` keeps the foreground of a less specific rule.
+#[derive(Clone, Default)]
+struct RuleSettings {
+    foreground: Option<u32>,
+    background: Option<u32>,
+    font_style: Option<FontStyle>,
+}
+
+// One alternative of a scope selector: scopes that must appear in order, the
+// last one matching as deep as possible, minus any excluded paths.
+struct Selector {
+    path: Vec<String>,
+    excluded: Vec<Vec<String>>,
+}
+
+// How closely a selector matched: for each selector scope from the last, the
+// depth it matched at and how many of its segments matched.
+type Score = Vec<(usize, usize)>;
+
+impl Selector {
+    fn parse_list(selectors: &str) -> Vec<Self> {
+        selectors
+            .split(',')
+            .filter_map(|alternative| {
+                let mut parts = alternative.split(" - ");
+                let path = scope_path(parts.next()?);
+                (!path.is_empty()).then(|| Selector {
+                    path,
+                    excluded: parts.map(scope_path).collect(),
+                })
+            })
+            .collect()
+    }
+
+    fn score(&self, scopes: &[&str]) -> Option<Score> {
+        let score = match_path(&self.path, scopes)?;
+        let excluded = self
+            .excluded
+            .iter()
+            .any(|path| match_path(path, scopes).is_some());
+
+        (!excluded).then_some(score)
+    }
+}
+
+fn scope_path(selector: &str) -> Vec<String> {
+    selector.split_whitespace().map(str::to_string).collect()
+}
+
+// Matches `path` against the scope stack, outermost scope first. The last
+// selector scope takes the innermost scope it can, and each earlier one the
+// innermost scope above that.
+fn match_path(path: &[String], scopes: &[&str]) -> Option<Score> {
+    let mut score = Vec::new();
+    let mut end = scopes.len();
+
+    for part in path.iter().rev() {
+        let depth = (0..end).rev().find(|&i| scope_matches(part, scopes[i]))?;
+        score.push((depth + 1, part.split('.').count()));
+        end = depth;
+    }
+
+    Some(score)
+}
+
+// A selector scope matches a scope it equals or is a dotted prefix of.
+fn scope_matches(selector: &str, scope: &str) -> bool {
+    scope
+        .strip_prefix(selector)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+struct Rule {
+    selectors: Vec<Selector>,
+    settings: RuleSettings,
+}
+
+#[frb(opaque)]
+pub struct Theme {
+    name: String,
+    kind: ThemeKind,
+    colors: EditorColors,
+    rules: Vec<Rule>,
+}
+
+impl Theme {
+    #[frb(sync)]
+    pub fn bundled_names() -> Vec<String> {
+        BUNDLED_THEMES
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    // A theme shipped with the editor, by the name used in `ui.theme`.
+    #[frb(sync)]
+    pub fn bundled(name: String) -> anyhow::Result<Self> {
+        let (_, contents) = BUNDLED_THEMES
+            .iter()
+            .find(|(bundled, _)| *bundled == name)
+            .ok_or_else(|| anyhow!("no bundled theme named `{name}`"))?;
+
+        Self::parse(contents.to_string(), ThemeFormat::VsCode)
+    }
+
+    // Loads a `.tmTheme` file, or a VS Code theme otherwise. A VS Code theme
+    // may `include` a parent theme by path relative to itself.
+    pub fn load(path: String) -> anyhow::Result<Self> {
+        let path = Path::new(&path);
+        let is_tm_theme = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("tmtheme"));
+
+        let theme = if is_tm_theme {
+            RawTheme::from_tm_theme(&fs::read(path)?)?
+        } else {
+            RawTheme::load_vscode(path, 0)?
+        };
+        let fallback_name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        Ok(theme.build(fallback_name))
+    }
+
+    #[frb(sync)]
+    pub fn parse(contents: String, format: ThemeFormat) -> anyhow::Result<Self> {
+        let theme = match format {
+            ThemeFormat::VsCode => {
+                let file = VsCodeFile::parse(&contents)?;
+                if file.include.is_some() {
+                    bail!("`include` is only supported for themes loaded from a file");
+                }
+                RawTheme::from_vscode(file, None)?
+            }
+            ThemeFormat::TextMate => RawTheme::from_tm_theme(contents.as_bytes())?,
+        };
+
+        Ok(theme.build(String::new()))
+    }
+
+    #[frb(sync)]
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[frb(sync)]
+    pub fn kind(&self) -> ThemeKind {
+        self.kind
+    }
+
+    #[frb(sync)]
+    pub fn colors(&self) -> EditorColors {
+        self.colors
+    }
+
+    // Style for a scope stack given outermost first and separated by spaces,
+    // e.g. `source.rust string.quoted.double`.
+    #[frb(sync)]
+    pub fn style_for_scope(&self, scope: String) -> TokenStyle {
+        let scopes: Vec<&str> = scope.split_whitespace().collect();
+        self.resolve(&scopes)
+    }
+
+    // Style for a highlight capture name such as `function.method`.
+    #[frb(sync)]
+    pub fn style_for_capture(&self, capture: String) -> TokenStyle {
+        let scope = capture_scope(capture.trim_start_matches('@'));
+        self.resolve(&[&scope])
+    }
+
+    #[frb(sync)]
+    pub fn styles_for_captures(&self, captures: Vec<String>) -> Vec<TokenStyle> {
+        captures
+            .into_iter()
+            .map(|capture| self.style_for_capture(capture))
+            .collect()
+    }
+
+    // Picks each property from the most specific rule that sets it, with
+    // later rules winning ties.
+    fn resolve(&self, scopes: &[&str]) -> TokenStyle {
+        let mut foreground: Option<(Score, u32)> = None;
+        let mut background: Option<(Score, u32)> = None;
+        let mut font_style: Option<(Score, FontStyle)> = None;
+
+        fn consider<T>(best: &mut Option<(Score, T)>, score: &Score, value: Option<T>) {
+            let Some(value) = value else {
+                return;
+            };
+            let wins = best
+                .as_ref()
+                .is_none_or(|(best, _)| score.cmp(best) != Ordering::Less);
+            if wins {
+                *best = Some((score.clone(), value));
+            }
+        }
+
+        for rule in &self.rules {
+            let Some(score) = rule
+                .selectors
+                .iter()
+                .filter_map(|selector| selector.score(scopes))
+                .max()
+            else {
+                continue;
+            };
+
+            consider(&mut foreground, &score, rule.settings.foreground);
+            consider(&mut background, &score, rule.settings.background);
+            consider(&mut font_style, &score, rule.settings.font_style);
+        }
+
+        let font_style = font_style.map(|(_, style)| style).unwrap_or_default();
+        TokenStyle {
+            foreground: foreground.map_or(self.colors.foreground, |(_, color)| color),
+            background: background.map(|(_, color)| color),
+            bold: font_style.bold,
+            italic: font_style.italic,
+            underline: font_style.underline,
+            strikethrough: font_style.strikethrough,
+        }
+    }
+}
+
+fn capture_scope(capture: &str) -> String {
+    let mut name = capture;
+    loop {
+        if let Some((_, scope)) = CAPTURE_SCOPES.iter().find(|(known, _)| *known == name) {
+            return scope.to_string();
+        }
+        match name.rsplit_once('.') {
+            Some((parent, _)) => name = parent,
+            // Unknown captures are matched as scopes, so themes can still
+            // target them directly.
+            None => return capture.to_string(),
+        }
+    }
+}
+
+// A theme as read from a file, before defaults are filled in.
+#[derive(Default)]
+struct RawTheme {
+    name: Option<String>,
+    kind: Option<ThemeKind>,
+    colors: HashMap<&'static str, u32>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+struct VsCodeFile {
+    name: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    include: Option<String>,
+    #[serde(default)]
+    colors: HashMap<String, Value>,
+    #[serde(rename = "tokenColors")]
+    token_colors: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct VsCodeRule {
+    scope: Option<Value>,
+    #[serde(default)]
+    settings: RuleFile,
+}
+
+#[derive(Default, Deserialize)]
+struct RuleFile {
+    foreground: Option<String>,
+    background: Option<String>,
+    #[serde(rename = "fontStyle")]
+    font_style: Option<String>,
+}
+
+impl RuleFile {
+    fn settings(&self) -> RuleSettings {
+        RuleSettings {
+            foreground: self.foreground.as_deref().and_then(parse_color),
+            background: self.background.as_deref().and_then(parse_color),
+            font_style: self.font_style.as_deref().map(FontStyle::parse),
+        }
+    }
+}
+
+// VS Code color keys and the editor color each sets.
+const VSCODE_COLORS: &[(&str, &str)] = &[
+    ("editor.background", "background"),
+    ("editor.foreground", "foreground"),
+    ("editorCursor.foreground", "cursor"),
+    ("editor.selectionBackground", "selection"),
+    ("editor.lineHighlightBackground", "line_highlight"),
+    ("editorGutter.background", "gutter_background"),
+    ("editorLineNumber.foreground", "gutter_foreground"),
+    (
+        "editorLineNumber.activeForeground",
+        "gutter_active_foreground",
+    ),
+];
+
+// `.tmTheme` global setting keys and the editor color each sets.
+const TM_THEME_COLORS: &[(&str, &str)] = &[
+    ("background", "background"),
+    ("foreground", "foreground"),
+    ("caret", "cursor"),
+    ("selection", "selection"),
+    ("lineHighlight", "line_highlight"),
+    ("gutter", "gutter_background"),
+    ("gutterForeground", "gutter_foreground"),
+];
+
+impl VsCodeFile {
+    fn parse(contents: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(&strip_json_comments(contents))?)
+    }
+}
+
+impl RawTheme {
+    // Included themes nest at most this deep, which also stops cycles.
+    const MAX_INCLUDE_DEPTH: usize = 8;
+
+    fn load_vscode(path: &Path, depth: usize) -> anyhow::Result<Self> {
+        if depth > Self::MAX_INCLUDE_DEPTH {
+            bail!("too many nested theme includes at {}", path.display());
+        }
+
+        let contents = fs::read_to_string(path)?;
+        let file = VsCodeFile::parse(&contents)
+            .map_err(|err| err.context(format!("invalid theme {}", path.display())))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let mut theme = match &file.include {
+            Some(include) => Self::load_vscode(&dir.join(include), depth + 1)?,
+            None => RawTheme::default(),
+        };
+        theme.merge(Self::from_vscode(file, Some(dir))?);
+
+        Ok(theme)
+    }
+
+    fn from_vscode(file: VsCodeFile, dir: Option<&Path>) -> anyhow::Result<Self> {
+        let mut theme = RawTheme {
+            name: file.name,
+            kind: match file.kind.as_deref() {
+                Some("light" | "hcLight") => Some(ThemeKind::Light),
+                Some("dark" | "hc" | "hcDark") => Some(ThemeKind::Dark),
+                _ => None,
+            },
+            ..RawTheme::default()
+        };
+
+        for &(key, color) in VSCODE_COLORS {
+            if let Some(value) = file.colors.get(key).and_then(Value::as_str) {
+                if let Some(value) = parse_color(value) {
+                    theme.colors.insert(color, value);
+                }
+            }
+        }
+
+        match file.token_colors {
+            // Token colors may live in a separate `.tmTheme` file.
+            Some(Value::String(path)) => {
+                let dir = dir.ok_or_else(|| {
+                    anyhow!("`tokenColors` paths are only supported for themes loaded from a file")
+                })?;
+                theme.rules = Self::from_tm_theme(&fs::read(dir.join(path))?)?.rules;
+            }
+            Some(rules) => {
+                let rules: Vec<VsCodeRule> = serde_json::from_value(rules)?;
+                theme.rules = rules
+                    .into_iter()
+                    .filter_map(|rule| {
+                        let selectors = match rule.scope? {
+                            Value::String(scope) => Selector::parse_list(&scope),
+                            Value::Array(scopes) => scopes
+                                .iter()
+                                .filter_map(Value::as_str)
+                                .flat_map(Selector::parse_list)
+                                .collect(),
+                            _ => return None,
+                        };
+                        Some(Rule {
+                            selectors,
+                            settings: rule.settings.settings(),
+                        })
+                    })
+                    .collect();
+            }
+            None => {}
+        }
+
+        Ok(theme)
+    }
+
+    fn from_tm_theme(contents: &[u8]) -> anyhow::Result<Self> {
+        let root: plist::Dictionary = plist::from_bytes(contents)?;
+        let mut theme = RawTheme {
+            name: root
+                .get("name")
+                .and_then(plist::Value::as_string)
+                .map(str::to_string),
+            ..RawTheme::default()
+        };
+
+        let entries = root
+            .get("settings")
+            .and_then(plist::Value::as_array)
+            .ok_or_else(|| anyhow!("`.tmTheme` has no `settings` array"))?;
+
+        for entry in entries.iter().filter_map(plist::Value::as_dictionary) {
+            let Some(settings) = entry.get("settings").and_then(plist::Value::as_dictionary) else {
+                continue;
+            };
+            let string = |key: &str| {
+                settings
+                    .get(key)
+                    .and_then(plist::Value::as_string)
+                    .map(str::to_string)
+            };
+
+            match entry.get("scope").and_then(plist::Value::as_string) {
+                // The entry without a scope holds the editor colors.
+                None => {
+                    for &(key, color) in TM_THEME_COLORS {
+                        if let Some(value) = string(key).as_deref().and_then(parse_color) {
+                            theme.colors.insert(color, value);
+                        }
+                    }
+                }
+                Some(scope) => theme.rules.push(Rule {
+                    selectors: Selector::parse_list(scope),
+                    settings: RuleFile {
+                        foreground: string("foreground"),
+                        background: string("background"),
+                        font_style: string("fontStyle"),
+                    }
+                    .settings(),
+                }),
+            }
+        }
+
+        Ok(theme)
+    }
+
+    // Applies `other` over this theme, as a theme does over one it includes.
+    fn merge(&mut self, other: RawTheme) {
+        self.name = other.name.or(self.name.take());
+        self.kind = other.kind.or(self.kind);
+        self.colors.extend(other.colors);
+        self.rules.extend(other.rules);
+    }
+
+    fn build(self, fallback_name: String) -> Theme {
+        let kind = self
+            .kind
+            .unwrap_or_else(|| match self.colors.get("background") {
+                Some(&background) if luminance(background) > 0.5 => ThemeKind::Light,
+                _ => ThemeKind::Dark,
+            });
+        let (background, foreground) = match kind {
+            ThemeKind::Dark => (0xff000000, 0xffffffff),
+            ThemeKind::Light => (0xffffffff, 0xff24292e),
+        };
+
+        let color = |key: &str, fallback: u32| self.colors.get(key).copied().unwrap_or(fallback);
+        let background = color("background", background);
+        let foreground = color("foreground", foreground);
+        let with_alpha = |color: u32, alpha: u32| (color & 0x00ffffff) | (alpha << 24);
+
+        Theme {
+            name: self.name.unwrap_or(fallback_name),
+            kind,
+            colors: EditorColors {
+                background,
+                foreground,
+                cursor: color("cursor", foreground),
+                selection: color("selection", with_alpha(foreground, 0x40)),
+                line_highlight: color("line_highlight", with_alpha(foreground, 0x08)),
+                gutter_background: color("gutter_background", background),
+                gutter_foreground: color("gutter_foreground", with_alpha(foreground, 0x80)),
+                gutter_active_foreground: color("gutter_active_foreground", foreground),
+            },
+            rules: self.rules,
+        }
+    }
+}
+
+// Parses `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` into ARGB.
+fn parse_color(value: &str) -> Option<u32> {
+    let hex = value.trim().strip_prefix('#')?;
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let expanded: String = match hex.len() {
+        3 | 4 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 | 8 => hex.to_string(),
+        _ => return None,
+    };
+    let rgb = u32::from_str_radix(&expanded[..6], 16).ok()?;
+    let alpha = match expanded.get(6..8) {
+        Some(alpha) => u32::from_str_radix(alpha, 16).ok()?,
+        None => 0xff,
+    };
+
+    Some((alpha << 24) | rgb)
+}
+
+fn luminance(color: u32) -> f64 {
+    let channel = |shift: u32| f64::from((color >> shift) & 0xff) / 255.0;
+    0.2126 * channel(16) + 0.7152 * channel(8) + 0.0722 * channel(0)
+}
+
+// Removes `//` and `/* */` comments and trailing commas, which VS Code allows
+// in theme files.
+pub(crate) fn strip_json_comments(contents: &str) -> String {
+    let chars: Vec<char> = contents.chars().collect();
+    let mut out = String::with_capacity(contents.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '"' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                let end = (i + 1).min(chars.len());
+                out.extend(&chars[start..end]);
+            }
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            c @ ('}' | ']') => {
+                let content_len = out.trim_end().len();
+                if out[..content_len].ends_with(',') {
+                    out.remove(content_len - 1);
+                }
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+        i += 1;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const THEME: &str = r##"{
+        // Comments and trailing commas are allowed.
+        "name": "Test",
+        "type": "dark",
+        "colors": { "editor.background": "#101010", "editor.foreground": "#eee", },
+        "tokenColors": [
+            { "scope": "string", "settings": { "foreground": "#00ff00" } },
+            { "scope": "string.quoted.double", "settings": { "foreground": "#0000ff" } },
+            { "scope": "source.rust string", "settings": { "fontStyle": "italic" } },
+            { "scope": "comment - comment.block", "settings": { "foreground": "#808080" } },
+            { "scope": ["keyword", "storage"], "settings": { "foreground": "#ff0000", "fontStyle": "bold underline" } },
+            { "scope": "keyword.control", "settings": { "fontStyle": "" } },
+        ],
+    }"##;
+
+    fn theme() -> Theme {
+        Theme::parse(THEME.to_string(), ThemeFormat::VsCode).unwrap()
+    }
+
+    #[test]
+    fn deeper_and_longer_selectors_win() {
+        let theme = theme();
+        let style = |scope: &str| theme.style_for_scope(scope.to_string());
+
+        assert_eq!(
+            style("source.js string.quoted.single").foreground,
+            0xff00ff00
+        );
+        assert_eq!(
+            style("source.js string.quoted.double").foreground,
+            0xff0000ff
+        );
+        // A parent scope in the selector adds to the foreground from elsewhere.
+        let rust = style("source.rust string.quoted.double");
+        assert_eq!((rust.foreground, rust.italic), (0xff0000ff, true));
+        assert!(!style("source.js string").italic);
+        // `stringy` is not a dotted child of `string`.
+        assert_eq!(style("stringy").foreground, 0xffeeeeee);
+
+        // A more specific rule can clear the font style but keeps the color.
+        let control = style("keyword.control");
+        assert_eq!((control.foreground, control.bold), (0xffff0000, false));
+        assert!(style("storage.type").underline);
+    }
+
+    #[test]
+    fn exclusions_remove_a_match() {
+        let theme = theme();
+        let style = |scope: &str| theme.style_for_scope(scope.to_string());
+
+        assert_eq!(style("comment.line.double-slash").foreground, 0xff808080);
+        assert_eq!(style("comment.block").foreground, 0xffeeeeee);
+        assert_eq!(
+            theme.style_for_capture("@comment".to_string()).foreground,
+            0xff808080
+        );
+        assert_eq!(
+            theme
+                .style_for_capture("comment.documentation".to_string())
+                .foreground,
+            0xffeeeeee
+        );
+    }
+
+    #[test]
+    fn parses_tm_themes() {
+        let contents = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>name</key>
+    <string>Paper</string>
+    <key>settings</key>
+    <array>
+        <dict>
+            <key>settings</key>
+            <dict>
+                <key>background</key>
+                <string>#FAFAFA</string>
+                <key>foreground</key>
+                <string>#333333</string>
+                <key>caret</key>
+                <string>#FF000080</string>
+            </dict>
+        </dict>
+        <dict>
+            <key>scope</key>
+            <string>entity.name.function, support.function</string>
+            <key>settings</key>
+            <dict>
+                <key>foreground</key>
+                <string>#123456</string>
+                <key>fontStyle</key>
+                <string>bold</string>
+            </dict>
+        </dict>
+    </array>
+</dict>
+</plist>"#;
+        let theme = Theme::parse(contents.to_string(), ThemeFormat::TextMate).unwrap();
+
+        assert_eq!(theme.name(), "Paper");
+        // Light from the background's luminance, with no `type` to say so.
+        assert_eq!(theme.kind(), ThemeKind::Light);
+        let colors = theme.colors();
+        assert_eq!(
+            (colors.background, colors.foreground, colors.cursor),
+            (0xfffafafa, 0xff333333, 0x80ff0000)
+        );
+        assert_eq!(colors.gutter_background, 0xfffafafa);
+
+        let style = theme.style_for_capture("function.builtin".to_string());
+        assert_eq!((style.foreground, style.bold), (0xff123456, true));
+        assert_eq!(
+            theme
+                .style_for_capture("function.method.call".to_string())
+                .foreground,
+            0xff123456
+        );
+    }
+
+    #[test]
+    fn bundled_themes_parse() {
+        for name in Theme::bundled_names() {
+            Theme::bundled(name).unwrap();
+        }
+        assert!(Theme::bundled("missing".to_string()).is_err());
+    }
+}
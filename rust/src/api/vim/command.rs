@@ -0,0 +1,459 @@
+// Key notation and the grammar of normal and visual mode commands:
+// `["x][count]{operator}[count]{motion|text object}` and friends.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Key {
+    Char(char),
+    Escape,
+    Enter,
+    Backspace,
+    Tab,
+    Ctrl(char),
+}
+
+impl Key {
+    pub fn notation(&self) -> String {
+        match self {
+            Key::Char('<') => "<lt>".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Escape => "<Esc>".to_string(),
+            Key::Enter => "<CR>".to_string(),
+            Key::Backspace => "<BS>".to_string(),
+            Key::Tab => "<Tab>".to_string(),
+            Key::Ctrl(c) => format!("<C-{c}>"),
+        }
+    }
+}
+
+// Parses vim key notation such as `d2w`, `ihello<Esc>` or `<C-v>jd`. A `<`
+// that does not start a known key name is taken literally.
+pub(crate) fn parse_keys(keys: &str) -> Vec<Key> {
+    let mut parsed = Vec::new();
+    let mut rest = keys;
+
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some((key, len)) = rest.find('>').and_then(|end| {
+                let key = named_key(&rest[1..end])?;
+                Some((key, end + 1))
+            }) {
+                parsed.push(key);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+
+        parsed.push(Key::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+
+    parsed
+}
+
+fn named_key(name: &str) -> Option<Key> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "esc" => Key::Escape,
+        "cr" | "enter" | "return" => Key::Enter,
+        "bs" => Key::Backspace,
+        "tab" => Key::Tab,
+        "space" => Key::Char(' '),
+        "lt" => Key::Char('<'),
+        "bar" => Key::Char('|'),
+        lower => {
+            let c = lower.strip_prefix("c-")?;
+            let mut chars = c.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Key::Ctrl(c),
+                _ => return None,
+            }
+        }
+    };
+
+    Some(key)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    WordStart { big: bool },
+    WordEnd { big: bool },
+    WordBack { big: bool },
+    LineStart,
+    FirstNonBlank,
+    LineEnd,
+    FirstLine,
+    LastLine,
+    Find(Find),
+    RepeatFind { reverse: bool },
+    ParagraphForward,
+    ParagraphBack,
+    MatchingPair,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Find {
+    pub target: char,
+    pub forward: bool,
+    // `t` and `T` stop one character short of the target.
+    pub till: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ObjectKind {
+    Word { big: bool },
+    Pair { open: char, close: char },
+    Quote(char),
+    Tag,
+    Paragraph,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TextObject {
+    pub inner: bool,
+    pub kind: ObjectKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Delete,
+    Change,
+    Yank,
+    Indent,
+    Outdent,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Target {
+    Motion(Motion),
+    Object(TextObject),
+    // The visual selection.
+    Selection,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum InsertAt {
+    Cursor,
+    AfterCursor,
+    FirstNonBlank,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum VisualKind {
+    Char,
+    Line,
+    Block,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Action {
+    Move(Motion),
+    Operate(Operator, Target),
+    // A doubled operator such as `dd`, applied to `count` whole lines.
+    Lines(Operator),
+    Insert(InsertAt),
+    Paste { before: bool },
+    Join,
+    Replace(char),
+    ToggleCase,
+    Visual(VisualKind),
+    SwapAnchor,
+    Select(TextObject),
+    // `.`, replaying the last change.
+    RepeatChange,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Command {
+    pub register: Option<char>,
+    pub count: Option<usize>,
+    pub action: Action,
+}
+
+impl Command {
+    pub fn count(&self) -> usize {
+        self.count.unwrap_or(1)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Parse<T> {
+    // More keys are needed; `operator` is set once an operator is waiting
+    // for its motion.
+    Incomplete { operator: bool },
+    Invalid,
+    Done(T),
+}
+
+const INCOMPLETE: Parse<Command> = Parse::Incomplete { operator: false };
+
+pub(crate) fn parse_normal(keys: &[Key]) -> Parse<Command> {
+    parse_command(keys, false)
+}
+
+pub(crate) fn parse_visual(keys: &[Key]) -> Parse<Command> {
+    parse_command(keys, true)
+}
+
+fn parse_command(keys: &[Key], visual: bool) -> Parse<Command> {
+    let (register, keys) = match keys {
+        [Key::Char('"')] => return INCOMPLETE,
+        [Key::Char('"'), Key::Char(register), rest @ ..] => (Some(*register), rest),
+        [Key::Char('"'), ..] => return Parse::Invalid,
+        _ => (None, keys),
+    };
+
+    let (count, keys) = parse_count(keys);
+    let command = |count: Option<usize>, action: Action| {
+        Parse::Done(Command {
+            register,
+            count,
+            action,
+        })
+    };
+
+    let Some(&key) = keys.first() else {
+        return INCOMPLETE;
+    };
+
+    if visual {
+        let action = match key {
+            Key::Char('d' | 'x' | 'X' | 'D') => {
+                Action::Operate(Operator::Delete, Target::Selection)
+            }
+            Key::Char('c' | 's') => Action::Operate(Operator::Change, Target::Selection),
+            Key::Char('y') => Action::Operate(Operator::Yank, Target::Selection),
+            Key::Char('>') => Action::Operate(Operator::Indent, Target::Selection),
+            Key::Char('<') => Action::Operate(Operator::Outdent, Target::Selection),
+            Key::Char('~') => Action::ToggleCase,
+            Key::Char('J') => Action::Join,
+            Key::Char('p' | 'P') => Action::Paste { before: true },
+            Key::Char('o') => Action::SwapAnchor,
+            Key::Char('v') => Action::Visual(VisualKind::Char),
+            Key::Char('V') => Action::Visual(VisualKind::Line),
+            Key::Ctrl('v') => Action::Visual(VisualKind::Block),
+            Key::Char('r') => {
+                return match keys.get(1) {
+                    None => INCOMPLETE,
+                    Some(&Key::Char(c)) => command(count, Action::Replace(c)),
+                    Some(Key::Enter) => command(count, Action::Replace('\n')),
+                    Some(_) => Parse::Invalid,
+                }
+            }
+            Key::Char(c @ ('i' | 'a')) => {
+                return match keys.get(1) {
+                    None => INCOMPLETE,
+                    Some(&key) => match text_object(c == 'i', key) {
+                        Some(object) => command(count, Action::Select(object)),
+                        None => Parse::Invalid,
+                    },
+                }
+            }
+            _ => return parse_move(keys, register, count),
+        };
+
+        return command(count, action);
+    }
+
+    let operator = match key {
+        Key::Char('d') => Some(Operator::Delete),
+        Key::Char('c') => Some(Operator::Change),
+        Key::Char('y') => Some(Operator::Yank),
+        Key::Char('>') => Some(Operator::Indent),
+        Key::Char('<') => Some(Operator::Outdent),
+        _ => None,
+    };
+
+    if let Some(operator) = operator {
+        let (motion_count, rest) = parse_count(&keys[1..]);
+        let count = multiply_counts(count, motion_count);
+
+        return match rest.first() {
+            None => Parse::Incomplete { operator: true },
+            Some(&next) if next == key => command(count, Action::Lines(operator)),
+            Some(&Key::Char(c @ ('i' | 'a'))) => match rest.get(1) {
+                None => Parse::Incomplete { operator: true },
+                Some(&key) => match text_object(c == 'i', key) {
+                    Some(object) => {
+                        command(count, Action::Operate(operator, Target::Object(object)))
+                    }
+                    None => Parse::Invalid,
+                },
+            },
+            Some(_) => match parse_motion(rest) {
+                Parse::Incomplete { .. } => Parse::Incomplete { operator: true },
+                Parse::Invalid => Parse::Invalid,
+                Parse::Done(motion) => {
+                    command(count, Action::Operate(operator, Target::Motion(motion)))
+                }
+            },
+        };
+    }
+
+    let action = match key {
+        Key::Char('x') => Action::Operate(Operator::Delete, Target::Motion(Motion::Right)),
+        Key::Char('X') => Action::Operate(Operator::Delete, Target::Motion(Motion::Left)),
+        Key::Char('D') => Action::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        Key::Char('C') => Action::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        Key::Char('s') => Action::Operate(Operator::Change, Target::Motion(Motion::Right)),
+        Key::Char('S') => Action::Lines(Operator::Change),
+        Key::Char('Y') => Action::Lines(Operator::Yank),
+        Key::Char('i') => Action::Insert(InsertAt::Cursor),
+        Key::Char('a') => Action::Insert(InsertAt::AfterCursor),
+        Key::Char('I') => Action::Insert(InsertAt::FirstNonBlank),
+        Key::Char('A') => Action::Insert(InsertAt::LineEnd),
+        Key::Char('o') => Action::Insert(InsertAt::LineBelow),
+        Key::Char('O') => Action::Insert(InsertAt::LineAbove),
+        Key::Char('p') => Action::Paste { before: false },
+        Key::Char('P') => Action::Paste { before: true },
+        Key::Char('J') => Action::Join,
+        Key::Char('~') => Action::ToggleCase,
+        Key::Char('.') => Action::RepeatChange,
+        Key::Char('v') => Action::Visual(VisualKind::Char),
+        Key::Char('V') => Action::Visual(VisualKind::Line),
+        Key::Ctrl('v') => Action::Visual(VisualKind::Block),
+        Key::Char('r') => {
+            return match keys.get(1) {
+                None => INCOMPLETE,
+                Some(&Key::Char(c)) => command(count, Action::Replace(c)),
+                Some(Key::Enter) => command(count, Action::Replace('\n')),
+                Some(_) => Parse::Invalid,
+            }
+        }
+        _ => return parse_move(keys, register, count),
+    };
+
+    command(count, action)
+}
+
+// The keys of a complete command with its leading count replaced, as `3.`
+// repeats `2dw` as `3dw`.
+pub(crate) fn with_count(keys: &[Key], count: usize) -> Vec<Key> {
+    let split = match keys {
+        [Key::Char('"'), Key::Char(_), ..] => 2,
+        _ => 0,
+    };
+    let (register, rest) = keys.split_at(split);
+    let (_, rest) = parse_count(rest);
+
+    let digits = count.to_string().chars().map(Key::Char).collect::<Vec<_>>();
+    [register, &digits, rest].concat()
+}
+
+fn parse_move(keys: &[Key], register: Option<char>, count: Option<usize>) -> Parse<Command> {
+    match parse_motion(keys) {
+        Parse::Incomplete { .. } => INCOMPLETE,
+        Parse::Invalid => Parse::Invalid,
+        Parse::Done(motion) => Parse::Done(Command {
+            register,
+            count,
+            action: Action::Move(motion),
+        }),
+    }
+}
+
+fn parse_motion(keys: &[Key]) -> Parse<Motion> {
+    let motion = match keys {
+        [] => return Parse::Incomplete { operator: false },
+        [Key::Char('h') | Key::Backspace, ..] => Motion::Left,
+        [Key::Char('l' | ' '), ..] => Motion::Right,
+        [Key::Char('k'), ..] => Motion::Up,
+        [Key::Char('j'), ..] => Motion::Down,
+        [Key::Char('w'), ..] => Motion::WordStart { big: false },
+        [Key::Char('W'), ..] => Motion::WordStart { big: true },
+        [Key::Char('e'), ..] => Motion::WordEnd { big: false },
+        [Key::Char('E'), ..] => Motion::WordEnd { big: true },
+        [Key::Char('b'), ..] => Motion::WordBack { big: false },
+        [Key::Char('B'), ..] => Motion::WordBack { big: true },
+        [Key::Char('0'), ..] => Motion::LineStart,
+        [Key::Char('^'), ..] => Motion::FirstNonBlank,
+        [Key::Char('$'), ..] => Motion::LineEnd,
+        [Key::Char('G'), ..] => Motion::LastLine,
+        [Key::Char('g')] => return Parse::Incomplete { operator: false },
+        [Key::Char('g'), Key::Char('g'), ..] => Motion::FirstLine,
+        [Key::Char(';'), ..] => Motion::RepeatFind { reverse: false },
+        [Key::Char(','), ..] => Motion::RepeatFind { reverse: true },
+        [Key::Char('}'), ..] => Motion::ParagraphForward,
+        [Key::Char('{'), ..] => Motion::ParagraphBack,
+        [Key::Char('%'), ..] => Motion::MatchingPair,
+        [Key::Char('f' | 'F' | 't' | 'T')] => return Parse::Incomplete { operator: false },
+        [Key::Char(c @ ('f' | 'F' | 't' | 'T')), Key::Char(target), ..] => Motion::Find(Find {
+            target: *target,
+            forward: c.is_lowercase(),
+            till: matches!(c, 't' | 'T'),
+        }),
+        _ => return Parse::Invalid,
+    };
+
+    Parse::Done(motion)
+}
+
+fn text_object(inner: bool, key: Key) -> Option<TextObject> {
+    let Key::Char(c) = key else {
+        return None;
+    };
+
+    let kind = match c {
+        'w' => ObjectKind::Word { big: false },
+        'W' => ObjectKind::Word { big: true },
+        '(' | ')' | 'b' => ObjectKind::Pair {
+            open: '(',
+            close: ')',
+        },
+        '{' | '}' | 'B' => ObjectKind::Pair {
+            open: '{',
+            close: '}',
+        },
+        '[' | ']' => ObjectKind::Pair {
+            open: '[',
+            close: ']',
+        },
+        '<' | '>' => ObjectKind::Pair {
+            open: '<',
+            close: '>',
+        },
+        '"' | '\'' | '`' => ObjectKind::Quote(c),
+        't' => ObjectKind::Tag,
+        'p' => ObjectKind::Paragraph,
+        _ => return None,
+    };
+
+    Some(TextObject { inner, kind })
+}
+
+fn parse_count(keys: &[Key]) -> (Option<usize>, &[Key]) {
+    let digits = keys
+        .iter()
+        .enumerate()
+        .take_while(|(idx, key)| match key {
+            Key::Char('0') => *idx > 0,
+            Key::Char(c) => c.is_ascii_digit(),
+            _ => false,
+        })
+        .count();
+
+    if digits == 0 {
+        return (None, keys);
+    }
+
+    let count = keys[..digits].iter().fold(0usize, |count, key| match key {
+        Key::Char(c) => count
+            .saturating_mul(10)
+            .saturating_add(c.to_digit(10).unwrap_or(0) as usize),
+        _ => count,
+    });
+
+    (Some(count), &keys[digits..])
+}
+
+fn multiply_counts(first: Option<usize>, second: Option<usize>) -> Option<usize> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(first.saturating_mul(second)),
+        (first, second) => first.or(second),
+    }
+}
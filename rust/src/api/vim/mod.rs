@@ -0,0 +1,1143 @@
+mod command;
+mod motion;
+
+use flutter_rust_bridge::frb;
+use std::collections::HashMap;
+
+use super::buffer::Buffer;
+use super::clipboard::ClipboardContents;
+use super::cursor::Cursor;
+use super::editorconfig::Indentation;
+use super::selection::Selection;
+use super::text::{byte_column, char_width, floor_char_boundary, visual_width};
+use command::{
+    parse_keys, parse_normal, parse_visual, with_count, Action, Command, Find, InsertAt, Key,
+    Motion, Operator, Parse, Target, VisualKind,
+};
+use motion::{
+    clamp_normal, cursor_at, first_non_blank, last_row, move_cursor, next_boundary, offset_of,
+    previous_boundary, select_object, MotionKind, TextRange,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VimMode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+    VisualBlock,
+    OperatorPending,
+}
+
+// The cursor and selection to show after feeding keys. A visual block is
+// reported one selection per row in `block_selections`.
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta))]
+#[derive(Clone)]
+pub struct VimOutcome {
+    pub mode: VimMode,
+    pub cursor: Cursor,
+    pub selection: Selection,
+    pub block_selections: Vec<Selection>,
+}
+
+struct InsertSession {
+    // How many times the typed text is inserted in total, as in `3ihi<Esc>`.
+    repeat: usize,
+    typed: String,
+    // For a changed visual block, the rows below the first and the block's
+    // visual column, where the typed text is repeated when insert mode ends.
+    block: Option<(Vec<usize>, usize)>,
+}
+
+// Modal editing state for one editor. Keys are fed in vim notation and applied
+// to the buffer directly, so a whole interaction can be replayed from a
+// string such as `"ayiw` or `ci(foo<Esc>`.
+#[frb(opaque)]
+pub struct VimState {
+    mode: VimMode,
+    pending: Vec<Key>,
+    registers: HashMap<char, ClipboardContents>,
+    visual_anchor: Cursor,
+    last_find: Option<Find>,
+    insert: Option<InsertSession>,
+    indentation: Indentation,
+    // The keys of the last change made from normal mode, including what was
+    // typed if it entered insert mode, for `.` to replay.
+    last_change: Vec<Key>,
+    // The keys of a change still being typed in insert mode.
+    recording: Option<Vec<Key>>,
+}
+
+impl Default for VimState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VimState {
+    const UNNAMED_REGISTER: char = '"';
+    const YANK_REGISTER: char = '0';
+    const SMALL_DELETE_REGISTER: char = '-';
+    const BLACK_HOLE_REGISTER: char = '_';
+
+    #[frb(sync)]
+    pub fn new() -> Self {
+        Self {
+            mode: VimMode::Normal,
+            pending: Vec::new(),
+            registers: HashMap::new(),
+            visual_anchor: Cursor::default(),
+            last_find: None,
+            insert: None,
+            indentation: Indentation::default(),
+            last_change: Vec::new(),
+            recording: None,
+        }
+    }
+
+    #[frb(sync)]
+    pub fn mode(&self) -> VimMode {
+        self.mode
+    }
+
+    // Keys typed towards a command that is not complete yet, for display.
+    #[frb(sync)]
+    pub fn pending_keys(&self) -> String {
+        self.pending.iter().map(Key::notation).collect()
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn set_shift_width(&mut self, shift_width: usize) {
+        self.indentation.size = shift_width.max(1);
+    }
+
+    // Sets what `>` and `<` add and remove, e.g. from EditorConfig.
+    #[frb(sync)]
+    pub fn set_indentation(&mut self, indentation: Indentation) {
+        self.indentation = indentation;
+    }
+
+    #[frb(sync)]
+    pub fn register(&self, name: char) -> Option<ClipboardContents> {
+        self.registers.get(&name.to_ascii_lowercase()).cloned()
+    }
+
+    // Lets the host fill registers vim cannot read itself, such as `+` from
+    // the system clipboard.
+    #[frb(sync)]
+    pub fn set_register(&mut self, name: char, contents: ClipboardContents) {
+        self.registers.insert(name.to_ascii_lowercase(), contents);
+    }
+
+    #[frb(sync)]
+    pub fn feed(&mut self, buffer: &mut Buffer, cursor: Cursor, keys: String) -> VimOutcome {
+        let mut cursor = cursor;
+        for key in parse_keys(&keys) {
+            cursor = self.handle_key(buffer, cursor, key);
+        }
+
+        self.outcome(buffer, cursor)
+    }
+
+    fn handle_key(&mut self, buffer: &mut Buffer, cursor: Cursor, key: Key) -> Cursor {
+        if self.mode == VimMode::Insert {
+            if let Some(recording) = &mut self.recording {
+                recording.push(key);
+            }
+            let cursor = self.insert_key(buffer, cursor, key);
+            if self.mode != VimMode::Insert {
+                if let Some(recording) = self.recording.take() {
+                    self.last_change = recording;
+                }
+            }
+            return cursor;
+        }
+
+        if key == Key::Escape {
+            self.pending.clear();
+            self.mode = VimMode::Normal;
+            return clamp_normal(buffer, cursor);
+        }
+
+        self.pending.push(key);
+        let parsed = if self.is_visual() {
+            parse_visual(&self.pending)
+        } else {
+            parse_normal(&self.pending)
+        };
+
+        match parsed {
+            Parse::Incomplete { operator } => {
+                if !self.is_visual() {
+                    self.mode = if operator {
+                        VimMode::OperatorPending
+                    } else {
+                        VimMode::Normal
+                    };
+                }
+                cursor
+            }
+            Parse::Invalid => {
+                self.reset_pending();
+                cursor
+            }
+            Parse::Done(command) => {
+                let keys = std::mem::take(&mut self.pending);
+                self.reset_pending();
+
+                let was_visual = self.is_visual();
+                let version = buffer.version;
+                let cursor = self.execute(buffer, cursor, command);
+
+                // Changes made from visual mode depend on the selection, so
+                // only those started from normal mode are repeatable.
+                if !was_visual && command.action != Action::RepeatChange {
+                    if self.mode == VimMode::Insert {
+                        self.recording = Some(keys);
+                    } else if buffer.version != version {
+                        self.last_change = keys;
+                    }
+                }
+                cursor
+            }
+        }
+    }
+
+    fn reset_pending(&mut self) {
+        self.pending.clear();
+        if self.mode == VimMode::OperatorPending {
+            self.mode = VimMode::Normal;
+        }
+    }
+
+    fn is_visual(&self) -> bool {
+        matches!(
+            self.mode,
+            VimMode::Visual | VimMode::VisualLine | VimMode::VisualBlock
+        )
+    }
+
+    fn execute(&mut self, buffer: &mut Buffer, cursor: Cursor, command: Command) -> Cursor {
+        let count = command.count();
+
+        match command.action {
+            Action::Move(motion) => {
+                match self.motion(buffer, cursor, motion, command.count, false) {
+                    Some((target, _)) => clamp_normal(buffer, target),
+                    None => cursor,
+                }
+            }
+            Action::Operate(operator, Target::Selection) => {
+                let range = self.visual_range(buffer, cursor);
+                let mode = self.mode;
+                self.mode = VimMode::Normal;
+
+                if mode == VimMode::VisualBlock {
+                    self.operate_block(buffer, cursor, operator, command.register)
+                } else {
+                    self.operate(buffer, cursor, operator, range, command.register)
+                }
+            }
+            Action::Operate(operator, target) => {
+                let range = match target {
+                    Target::Object(object) => select_object(buffer, cursor, object),
+                    _ => self.motion_range(buffer, cursor, operator, target, command.count),
+                };
+
+                match range {
+                    Some(range) => self.operate(buffer, cursor, operator, range, command.register),
+                    None => cursor,
+                }
+            }
+            Action::Lines(operator) => {
+                let end_row = cursor.row.saturating_add(count - 1).min(last_row(buffer));
+                let range = TextRange::Lines(cursor.row, end_row);
+                self.operate(buffer, cursor, operator, range, command.register)
+            }
+            Action::Insert(at) => self.begin_insert(buffer, cursor, at, count),
+            Action::Paste { before } => {
+                if self.is_visual() {
+                    self.paste_over_selection(buffer, cursor, command.register)
+                } else {
+                    self.paste(buffer, cursor, before, count, command.register)
+                }
+            }
+            Action::Join => self.join(buffer, cursor, count),
+            Action::Replace(c) => self.replace_chars(buffer, cursor, c, count),
+            Action::ToggleCase => self.toggle_case(buffer, cursor, count),
+            Action::Visual(kind) => {
+                let mode = match kind {
+                    VisualKind::Char => VimMode::Visual,
+                    VisualKind::Line => VimMode::VisualLine,
+                    VisualKind::Block => VimMode::VisualBlock,
+                };
+
+                if self.mode == mode {
+                    self.mode = VimMode::Normal;
+                } else {
+                    if !self.is_visual() {
+                        self.visual_anchor = cursor;
+                    }
+                    self.mode = mode;
+                }
+                cursor
+            }
+            Action::SwapAnchor => std::mem::replace(&mut self.visual_anchor, cursor),
+            Action::RepeatChange => {
+                let keys = match command.count {
+                    Some(count) => with_count(&self.last_change, count),
+                    None => self.last_change.clone(),
+                };
+                keys.into_iter()
+                    .fold(cursor, |cursor, key| self.handle_key(buffer, cursor, key))
+            }
+            Action::Select(object) => match select_object(buffer, cursor, object) {
+                Some(TextRange::Chars(start, end)) if end > start => {
+                    self.mode = VimMode::Visual;
+                    self.visual_anchor = cursor_at(buffer, start);
+                    let end = cursor_at(buffer, end);
+                    let line = buffer.line(end.row);
+                    let column = previous_boundary(&line, end.column);
+
+                    if end.column == 0 && end.row > 0 {
+                        let row = end.row - 1;
+                        Cursor::new(row, buffer.line(row).len(), 0)
+                    } else {
+                        Cursor::new(end.row, column, column)
+                    }
+                }
+                Some(TextRange::Lines(start_row, end_row)) => {
+                    self.mode = VimMode::VisualLine;
+                    self.visual_anchor = Cursor::new(start_row, 0, 0);
+                    Cursor::new(end_row, 0, 0)
+                }
+                _ => cursor,
+            },
+        }
+    }
+
+    fn motion(
+        &mut self,
+        buffer: &Buffer,
+        cursor: Cursor,
+        motion: Motion,
+        count: Option<usize>,
+        for_operator: bool,
+    ) -> Option<(Cursor, MotionKind)> {
+        if let Motion::Find(find) = motion {
+            self.last_find = Some(find);
+        }
+
+        move_cursor(buffer, cursor, motion, count, self.last_find, for_operator)
+    }
+
+    fn motion_range(
+        &mut self,
+        buffer: &Buffer,
+        cursor: Cursor,
+        operator: Operator,
+        target: Target,
+        count: Option<usize>,
+    ) -> Option<TextRange> {
+        let Target::Motion(motion) = target else {
+            return None;
+        };
+
+        // `cw` on a word changes to its end, like `ce`, keeping the blanks
+        // after it.
+        if let (Operator::Change, Motion::WordStart { big }) = (operator, motion) {
+            let line = buffer.line(cursor.row);
+            let on_blank = line[cursor.column.min(line.len())..]
+                .chars()
+                .next()
+                .is_none_or(char::is_whitespace);
+
+            if !on_blank {
+                let end = motion::word_end_from(buffer, cursor, count.unwrap_or(1), big);
+                return Some(inclusive_range(buffer, offset_of(buffer, cursor), end));
+            }
+        }
+
+        let (target, kind) = self.motion(buffer, cursor, motion, count, true)?;
+        let (from, to) = (offset_of(buffer, cursor), offset_of(buffer, target));
+
+        Some(match kind {
+            MotionKind::Linewise => {
+                TextRange::Lines(cursor.row.min(target.row), cursor.row.max(target.row))
+            }
+            MotionKind::Exclusive => TextRange::Chars(from.min(to), from.max(to)),
+            MotionKind::Inclusive => inclusive_range(buffer, from, to),
+        })
+    }
+
+    fn visual_range(&self, buffer: &Buffer, cursor: Cursor) -> TextRange {
+        let anchor = self.visual_anchor;
+
+        match self.mode {
+            VimMode::VisualLine => {
+                TextRange::Lines(anchor.row.min(cursor.row), anchor.row.max(cursor.row))
+            }
+            _ => inclusive_range(buffer, offset_of(buffer, anchor), offset_of(buffer, cursor)),
+        }
+    }
+
+    // The visual columns the block spans, from the left edge of the leftmost
+    // corner to the right edge of the character under the rightmost one.
+    fn block_edges(&self, buffer: &Buffer, cursor: Cursor) -> (usize, usize) {
+        let tab_width = self.indentation.tab_width;
+        let edges = |cursor: Cursor| {
+            let line = buffer.line(cursor.row);
+            let column = floor_char_boundary(&line, cursor.column);
+            let start = visual_width(&line[..column], tab_width);
+            let width = line[column..]
+                .chars()
+                .next()
+                .map_or(1, |c| char_width(c, start, tab_width).max(1));
+            (start, start + width)
+        };
+
+        let (anchor, head) = (edges(self.visual_anchor), edges(cursor));
+        (anchor.0.min(head.0), anchor.1.max(head.1))
+    }
+
+    // Per-row byte columns of the visual block. Tabs and wide characters only
+    // partly inside it are included whole.
+    fn block_columns(&self, buffer: &Buffer, cursor: Cursor) -> Vec<(usize, usize, usize)> {
+        let anchor = self.visual_anchor;
+        let tab_width = self.indentation.tab_width;
+        let (left, right) = self.block_edges(buffer, cursor);
+
+        (anchor.row.min(cursor.row)..=anchor.row.max(cursor.row))
+            .map(|row| {
+                let line = buffer.line(row);
+                (
+                    row,
+                    byte_column(&line, left, tab_width, false),
+                    byte_column(&line, right, tab_width, true),
+                )
+            })
+            .collect()
+    }
+
+    fn operate(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        operator: Operator,
+        range: TextRange,
+        register: Option<char>,
+    ) -> Cursor {
+        match operator {
+            Operator::Yank => {
+                self.store(register, contents_of(buffer, range), true);
+                match range {
+                    TextRange::Chars(start, _) => clamp_normal(buffer, cursor_at(buffer, start)),
+                    TextRange::Lines(start_row, _) => clamp_normal(
+                        buffer,
+                        Cursor::new(start_row.min(cursor.row), cursor.column, cursor.column),
+                    ),
+                }
+            }
+            Operator::Delete => {
+                self.store(register, contents_of(buffer, range), false);
+                match range {
+                    TextRange::Chars(start, end) => {
+                        buffer.replace_bytes(start..end, "");
+                        clamp_normal(buffer, cursor_at(buffer, start))
+                    }
+                    TextRange::Lines(start_row, end_row) => {
+                        delete_rows(buffer, start_row, end_row);
+                        let row = start_row.min(last_row(buffer));
+                        let column = first_non_blank(&buffer.line(row));
+                        Cursor::new(row, column, column)
+                    }
+                }
+            }
+            Operator::Change => {
+                self.store(register, contents_of(buffer, range), false);
+                self.mode = VimMode::Insert;
+                self.insert = Some(InsertSession {
+                    repeat: 1,
+                    typed: String::new(),
+                    block: None,
+                });
+
+                let (row, column) = match range {
+                    TextRange::Chars(start, end) => buffer.replace_bytes(start..end, ""),
+                    TextRange::Lines(start_row, end_row) => {
+                        let line = buffer.line(start_row);
+                        let indent = &line[..first_non_blank(&line)];
+                        let (start, _) = buffer.row_byte_range(start_row, start_row);
+                        let (_, end) = buffer.row_byte_range(end_row, end_row);
+                        buffer.replace_bytes(start..end, indent)
+                    }
+                };
+                Cursor::new(row, column, column)
+            }
+            Operator::Indent | Operator::Outdent => {
+                let (start_row, end_row) = match range {
+                    TextRange::Lines(start_row, end_row) => (start_row, end_row),
+                    TextRange::Chars(start, end) => (
+                        cursor_at(buffer, start).row,
+                        cursor_at(buffer, end.max(start)).row,
+                    ),
+                };
+
+                self.shift_rows(buffer, start_row, end_row, operator == Operator::Indent);
+                let column = first_non_blank(&buffer.line(start_row));
+                Cursor::new(start_row, column, column)
+            }
+        }
+    }
+
+    fn operate_block(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        operator: Operator,
+        register: Option<char>,
+    ) -> Cursor {
+        let columns = self.block_columns(buffer, cursor);
+        let (left_edge, _) = self.block_edges(buffer, cursor);
+        let (top_row, left, _) = columns[0];
+        let bottom_row = columns[columns.len() - 1].0;
+
+        if let Operator::Indent | Operator::Outdent = operator {
+            self.shift_rows(buffer, top_row, bottom_row, operator == Operator::Indent);
+            let column = first_non_blank(&buffer.line(top_row));
+            return Cursor::new(top_row, column, column);
+        }
+
+        let fragments = columns
+            .iter()
+            .map(|&(row, start, end)| buffer.line(row)[start..end].to_string())
+            .collect();
+        self.store(
+            register,
+            ClipboardContents {
+                fragments,
+                line_wise: false,
+            },
+            operator == Operator::Yank,
+        );
+
+        if operator != Operator::Yank {
+            for &(row, start, end) in columns.iter().rev() {
+                let line_start = buffer.byte_of_line(row);
+                buffer.replace_bytes(line_start + start..line_start + end, "");
+            }
+        }
+
+        // Text typed into a changed block is inserted on its first row, then
+        // on the others that reach the block once insert mode ends.
+        if operator == Operator::Change {
+            let rows = columns[1..]
+                .iter()
+                .filter(|&&(_, start, end)| start < end)
+                .map(|&(row, _, _)| row)
+                .collect();
+            self.mode = VimMode::Insert;
+            self.insert = Some(InsertSession {
+                repeat: 1,
+                typed: String::new(),
+                block: Some((rows, left_edge)),
+            });
+            return Cursor::new(top_row, left, left);
+        }
+
+        clamp_normal(buffer, Cursor::new(top_row, left, left))
+    }
+
+    fn shift_rows(&self, buffer: &mut Buffer, start_row: usize, end_row: usize, indent: bool) {
+        buffer.shift_indentation(start_row, end_row, indent, self.indentation);
+    }
+
+    // Writes deleted or yanked text to the named register, or to the unnamed
+    // register and the numbered ones as vim does. An uppercase name appends.
+    fn store(&mut self, register: Option<char>, contents: ClipboardContents, is_yank: bool) {
+        let contents = match register {
+            Some(Self::BLACK_HOLE_REGISTER) => return,
+            Some(name) if name.is_ascii_uppercase() => {
+                let name = name.to_ascii_lowercase();
+                let appended = match self.registers.get(&name) {
+                    Some(existing) => append_contents(existing, &contents),
+                    None => contents,
+                };
+                self.registers.insert(name, appended.clone());
+                appended
+            }
+            Some(name) => {
+                self.registers.insert(name, contents.clone());
+                contents
+            }
+            None if is_yank => {
+                self.registers.insert(Self::YANK_REGISTER, contents.clone());
+                contents
+            }
+            None if contents.line_wise || contents.text().contains('\n') => {
+                for idx in (1..9).rev() {
+                    let from = char::from_digit(idx, 10).unwrap();
+                    let to = char::from_digit(idx + 1, 10).unwrap();
+                    if let Some(shifted) = self.registers.remove(&from) {
+                        self.registers.insert(to, shifted);
+                    }
+                }
+                self.registers.insert('1', contents.clone());
+                contents
+            }
+            None => {
+                self.registers
+                    .insert(Self::SMALL_DELETE_REGISTER, contents.clone());
+                contents
+            }
+        };
+
+        self.registers.insert(Self::UNNAMED_REGISTER, contents);
+    }
+
+    fn read_register(&self, register: Option<char>) -> Option<ClipboardContents> {
+        self.register(register.unwrap_or(Self::UNNAMED_REGISTER))
+    }
+
+    fn paste(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        before: bool,
+        count: usize,
+        register: Option<char>,
+    ) -> Cursor {
+        let Some(contents) = self.read_register(register) else {
+            return cursor;
+        };
+        let text = repeat_text(&contents.text(), count);
+
+        if contents.line_wise {
+            let text = if text.ends_with('\n') {
+                text
+            } else {
+                format!("{text}\n")
+            };
+            let row = if before { cursor.row } else { cursor.row + 1 };
+
+            if row > last_row(buffer) {
+                let end = buffer.rope().byte_len();
+                let ends_with_newline = buffer.to_string().ends_with('\n');
+                if ends_with_newline {
+                    buffer.replace_bytes(end..end, &text);
+                } else {
+                    buffer.replace_bytes(end..end, &format!("\n{}", &text[..text.len() - 1]));
+                }
+            } else {
+                let start = buffer.byte_of_line(row);
+                buffer.replace_bytes(start..start, &text);
+            }
+
+            let row = row.min(last_row(buffer));
+            let column = first_non_blank(&buffer.line(row));
+            return Cursor::new(row, column, column);
+        }
+
+        let line = buffer.line(cursor.row);
+        let column = if before || line.is_empty() {
+            cursor.column
+        } else {
+            next_boundary(&line, cursor.column)
+        };
+        let offset = buffer.byte_of_line(cursor.row) + column.min(line.len());
+        let (row, column) = buffer.replace_bytes(offset..offset, &text);
+        let line = buffer.line(row);
+        let column = previous_boundary(&line, column);
+
+        Cursor::new(row, column, column)
+    }
+
+    fn paste_over_selection(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        register: Option<char>,
+    ) -> Cursor {
+        let contents = self.read_register(register);
+        let range = self.visual_range(buffer, cursor);
+        let replaced = contents_of(buffer, range);
+        self.mode = VimMode::Normal;
+
+        let Some(contents) = contents else {
+            return cursor;
+        };
+
+        let (start, end, text) = match range {
+            TextRange::Chars(start, end) => (start, end, contents.text()),
+            TextRange::Lines(start_row, end_row) => {
+                let (start, _) = buffer.row_byte_range(start_row, start_row);
+                let (_, end) = buffer.row_byte_range(end_row, end_row);
+                let text = contents.text();
+                let text = text.strip_suffix('\n').unwrap_or(&text).to_string();
+                (start, end, text)
+            }
+        };
+
+        buffer.replace_bytes(start..end, &text);
+        self.store(None, replaced, false);
+
+        clamp_normal(buffer, cursor_at(buffer, start))
+    }
+
+    fn join(&mut self, buffer: &mut Buffer, cursor: Cursor, count: usize) -> Cursor {
+        let (start_row, end_row) = if self.is_visual() {
+            let anchor = self.visual_anchor;
+            self.mode = VimMode::Normal;
+            (anchor.row.min(cursor.row), anchor.row.max(cursor.row))
+        } else {
+            (cursor.row, cursor.row.saturating_add(count.max(2) - 1))
+        };
+
+        // Joining one pair at a time leaves the cursor where the last two
+        // lines met.
+        let end_row = end_row.max(start_row + 1).min(last_row(buffer));
+        let mut column = cursor.column;
+        for _ in start_row..end_row {
+            let result = buffer.join_lines(Cursor::new(start_row, 0, 0), Selection::default());
+            column = result.cursor.column;
+        }
+
+        clamp_normal(buffer, Cursor::new(start_row, column, column))
+    }
+
+    fn replace_chars(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        c: char,
+        count: usize,
+    ) -> Cursor {
+        if self.is_visual() {
+            let range = self.visual_range(buffer, cursor);
+            self.mode = VimMode::Normal;
+
+            let (start, end) = match range {
+                TextRange::Chars(start, end) => (start, end),
+                TextRange::Lines(start_row, end_row) => {
+                    let (start, _) = buffer.row_byte_range(start_row, start_row);
+                    let (_, end) = buffer.row_byte_range(end_row, end_row);
+                    (start, end)
+                }
+            };
+
+            let replaced: String = buffer
+                .rope()
+                .byte_slice(start..end)
+                .chars()
+                .map(|old| if old == '\n' { old } else { c })
+                .collect();
+            buffer.replace_bytes(start..end, &replaced);
+            return clamp_normal(buffer, cursor_at(buffer, start));
+        }
+
+        let line = buffer.line(cursor.row);
+        let rest = &line[cursor.column.min(line.len())..];
+        if rest.chars().count() < count {
+            return cursor;
+        }
+
+        let len: usize = rest.chars().take(count).map(char::len_utf8).sum();
+        let start = buffer.byte_of_line(cursor.row) + cursor.column;
+        let text = if c == '\n' {
+            "\n".to_string()
+        } else {
+            c.to_string().repeat(count)
+        };
+
+        let (row, column) = buffer.replace_bytes(start..start + len, &text);
+        let column = if c == '\n' {
+            0
+        } else {
+            previous_boundary(&buffer.line(row), column)
+        };
+
+        Cursor::new(row, column, column)
+    }
+
+    fn toggle_case(&mut self, buffer: &mut Buffer, cursor: Cursor, count: usize) -> Cursor {
+        let (start, end, next) = if self.is_visual() {
+            let range = self.visual_range(buffer, cursor);
+            self.mode = VimMode::Normal;
+
+            match range {
+                TextRange::Chars(start, end) => (start, end, start),
+                TextRange::Lines(start_row, end_row) => {
+                    let (start, _) = buffer.row_byte_range(start_row, start_row);
+                    let (_, end) = buffer.row_byte_range(end_row, end_row);
+                    (start, end, start)
+                }
+            }
+        } else {
+            let line = buffer.line(cursor.row);
+            let column = cursor.column.min(line.len());
+            let len: usize = line[column..].chars().take(count).map(char::len_utf8).sum();
+            let start = buffer.byte_of_line(cursor.row) + column;
+            (start, start + len, start + len)
+        };
+
+        let toggled: String = buffer
+            .rope()
+            .byte_slice(start..end)
+            .chars()
+            .flat_map(|c| -> Box<dyn Iterator<Item = char>> {
+                if c.is_uppercase() {
+                    Box::new(c.to_lowercase())
+                } else {
+                    Box::new(c.to_uppercase())
+                }
+            })
+            .collect();
+        let delta = toggled.len() as isize - (end - start) as isize;
+        buffer.replace_bytes(start..end, &toggled);
+
+        let next = if next == start {
+            start
+        } else {
+            next.saturating_add_signed(delta)
+        };
+        clamp_normal(buffer, cursor_at(buffer, next))
+    }
+
+    fn begin_insert(
+        &mut self,
+        buffer: &mut Buffer,
+        cursor: Cursor,
+        at: InsertAt,
+        count: usize,
+    ) -> Cursor {
+        let line = buffer.line(cursor.row);
+        let indent = line[..first_non_blank(&line)].to_string();
+        let line_start = buffer.byte_of_line(cursor.row);
+
+        let (row, column) = match at {
+            InsertAt::Cursor => (cursor.row, cursor.column.min(line.len())),
+            InsertAt::AfterCursor => (cursor.row, next_boundary(&line, cursor.column)),
+            InsertAt::FirstNonBlank => (cursor.row, indent.len()),
+            InsertAt::LineEnd => (cursor.row, line.len()),
+            InsertAt::LineBelow => {
+                let end = line_start + line.len();
+                buffer.replace_bytes(end..end, &format!("\n{indent}"))
+            }
+            InsertAt::LineAbove => {
+                buffer.replace_bytes(line_start..line_start, &format!("{indent}\n"));
+                (cursor.row, indent.len())
+            }
+        };
+
+        let repeat = match at {
+            InsertAt::LineBelow | InsertAt::LineAbove => 1,
+            _ => count,
+        };
+
+        self.mode = VimMode::Insert;
+        self.insert = Some(InsertSession {
+            repeat,
+            typed: String::new(),
+            block: None,
+        });
+
+        Cursor::new(row, column, column)
+    }
+
+    fn insert_key(&mut self, buffer: &mut Buffer, cursor: Cursor, key: Key) -> Cursor {
+        let text = match key {
+            Key::Escape => {
+                let mut cursor = cursor;
+                if let Some(session) = self.insert.take() {
+                    if session.repeat > 1 && !session.typed.is_empty() {
+                        let (row, column) = buffer.insert(
+                            cursor.row,
+                            cursor.column,
+                            repeat_text(&session.typed, session.repeat - 1),
+                        );
+                        cursor = Cursor::new(row, column, column);
+                    }
+                    if let Some((rows, left)) = session.block {
+                        self.insert_on_block_rows(buffer, &rows, left, &session.typed);
+                    }
+                }
+
+                self.mode = VimMode::Normal;
+                let column = previous_boundary(&buffer.line(cursor.row), cursor.column);
+                return clamp_normal(buffer, Cursor::new(cursor.row, column, column));
+            }
+            Key::Backspace => {
+                if cursor.row == 0 && cursor.column == 0 {
+                    return cursor;
+                }
+                if let Some(session) = &mut self.insert {
+                    session.typed.pop();
+                }
+
+                let (row, column) = buffer.remove_char(cursor.row, cursor.column);
+                return Cursor::new(row, column, column);
+            }
+            Key::Enter => {
+                let line = buffer.line(cursor.row);
+                let indent_len = first_non_blank(&line).min(cursor.column);
+                format!("\n{}", &line[..indent_len])
+            }
+            Key::Tab => "\t".to_string(),
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(_) => return cursor,
+        };
+
+        if let Some(session) = &mut self.insert {
+            session.typed.push_str(&text);
+        }
+
+        let (row, column) = buffer.insert(cursor.row, cursor.column, text);
+        Cursor::new(row, column, column)
+    }
+
+    // Only text typed on one line is repeated, as in vim.
+    fn insert_on_block_rows(&self, buffer: &mut Buffer, rows: &[usize], left: usize, typed: &str) {
+        if typed.is_empty() || typed.contains('\n') {
+            return;
+        }
+
+        let tab_width = self.indentation.tab_width;
+        for &row in rows {
+            let line = buffer.line(row);
+            let column = byte_column(&line, left, tab_width, false);
+            buffer.insert(row, column, typed.to_string());
+        }
+    }
+
+    fn outcome(&self, buffer: &Buffer, cursor: Cursor) -> VimOutcome {
+        let (selection, block_selections) = match self.mode {
+            VimMode::Visual => match self.visual_range(buffer, cursor) {
+                TextRange::Chars(start, end) => (
+                    Selection::new(cursor_at(buffer, start), cursor_at(buffer, end)),
+                    Vec::new(),
+                ),
+                TextRange::Lines(..) => (Selection::default(), Vec::new()),
+            },
+            VimMode::VisualLine => {
+                let anchor = self.visual_anchor;
+                let (start_row, end_row) = (anchor.row.min(cursor.row), anchor.row.max(cursor.row));
+                let end_column = buffer.line(end_row).len();
+                (
+                    Selection::new(
+                        Cursor::new(start_row, 0, 0),
+                        Cursor::new(end_row, end_column, end_column),
+                    ),
+                    Vec::new(),
+                )
+            }
+            VimMode::VisualBlock => {
+                let block: Vec<Selection> = self
+                    .block_columns(buffer, cursor)
+                    .into_iter()
+                    .map(|(row, start, end)| {
+                        Selection::new(Cursor::new(row, start, start), Cursor::new(row, end, end))
+                    })
+                    .collect();
+                let selection = Selection::new(block[0].start, block[block.len() - 1].end);
+                (selection, block)
+            }
+            _ => (Selection::default(), Vec::new()),
+        };
+
+        VimOutcome {
+            mode: self.mode,
+            cursor,
+            selection,
+            block_selections,
+        }
+    }
+}
+
+// `text` repeated `count` times, as for `3p` or `3ihi<Esc>`, but at most as
+// many times as fit in `MAX_REPEATED_BYTES` so a huge count cannot exhaust
+// memory.
+fn repeat_text(text: &str, count: usize) -> String {
+    const MAX_REPEATED_BYTES: usize = 16 << 20;
+    text.repeat(count.min(MAX_REPEATED_BYTES / text.len().max(1)).max(1))
+}
+
+fn inclusive_range(buffer: &Buffer, from: usize, to: usize) -> TextRange {
+    let (start, end) = (from.min(to), from.max(to));
+    let (row, column) = buffer.idx_to_row_column(end);
+    let line = buffer.line(row);
+    let end = end - column + next_boundary(&line, column);
+
+    TextRange::Chars(start, end.max(start))
+}
+
+fn contents_of(buffer: &Buffer, range: TextRange) -> ClipboardContents {
+    match range {
+        TextRange::Chars(start, end) => ClipboardContents {
+            fragments: vec![buffer.rope().byte_slice(start..end).to_string()],
+            line_wise: false,
+        },
+        TextRange::Lines(start_row, end_row) => ClipboardContents {
+            fragments: vec![format!(
+                "{}\n",
+                buffer.lines_in_rows(start_row, end_row).join("\n")
+            )],
+            line_wise: true,
+        },
+    }
+}
+
+fn append_contents(existing: &ClipboardContents, added: &ClipboardContents) -> ClipboardContents {
+    let mut text = existing.text();
+    if added.line_wise && !existing.line_wise {
+        text.push('\n');
+    }
+    text.push_str(&added.text());
+
+    ClipboardContents {
+        fragments: vec![text],
+        line_wise: existing.line_wise || added.line_wise,
+    }
+}
+
+// Deletes whole rows; the last rows take the line break before them instead
+// of the one after, which the buffer may not have.
+fn delete_rows(buffer: &mut Buffer, start_row: usize, end_row: usize) {
+    let (_, end) = buffer.row_byte_range(end_row, end_row);
+
+    let range = if end_row < last_row(buffer) {
+        buffer.byte_of_line(start_row)..buffer.byte_of_line(end_row + 1)
+    } else if start_row > 0 {
+        let (_, previous_end) = buffer.row_byte_range(start_row - 1, start_row - 1);
+        previous_end..end
+    } else {
+        0..end
+    };
+
+    buffer.replace_bytes(range, "");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `keys` to a fresh state over `text` with the cursor at `at`, and
+    // returns the resulting text, cursor and mode.
+    fn feed(text: &str, at: (usize, usize), keys: &str) -> (String, (usize, usize), VimMode) {
+        let mut buffer = Buffer::from(text.to_string());
+        let outcome =
+            VimState::new().feed(&mut buffer, Cursor::new(at.0, at.1, at.1), keys.to_string());
+        (
+            buffer.to_string(),
+            (outcome.cursor.row, outcome.cursor.column),
+            outcome.mode,
+        )
+    }
+
+    #[test]
+    fn deletes_a_word() {
+        assert_eq!(
+            feed("foo bar baz", (0, 0), "dw"),
+            ("bar baz".to_string(), (0, 0), VimMode::Normal)
+        );
+    }
+
+    #[test]
+    fn changes_the_inner_word() {
+        assert_eq!(
+            feed("foo bar baz", (0, 5), "ciwqux<Esc>"),
+            ("foo qux baz".to_string(), (0, 6), VimMode::Normal)
+        );
+    }
+
+    #[test]
+    fn deletes_inside_parentheses() {
+        assert_eq!(
+            feed("f(a, b) x", (0, 3), "di("),
+            ("f() x".to_string(), (0, 2), VimMode::Normal)
+        );
+    }
+
+    #[test]
+    fn deletes_lines() {
+        assert_eq!(
+            feed("a\nb\nc", (1, 0), "dd"),
+            ("a\nc".to_string(), (1, 0), VimMode::Normal)
+        );
+        assert_eq!(
+            feed("a\nb\nc", (0, 0), "2dd"),
+            ("c".to_string(), (0, 0), VimMode::Normal)
+        );
+    }
+
+    #[test]
+    fn yanks_and_puts_a_line() {
+        assert_eq!(
+            feed("a\nb", (0, 0), "yyp"),
+            ("a\na\nb".to_string(), (1, 0), VimMode::Normal)
+        );
+    }
+
+    #[test]
+    fn repeats_inserted_text_by_count() {
+        assert_eq!(
+            feed("x", (0, 0), "3ihi<Esc>"),
+            ("hihihix".to_string(), (0, 5), VimMode::Normal)
+        );
+    }
+
+    #[test]
+    fn dot_repeats_the_last_change() {
+        assert_eq!(feed("a b c d", (0, 0), "dw.").0, "c d");
+        assert_eq!(feed("a b c d e", (0, 0), "dw2.").0, "d e");
+        assert_eq!(feed("x\ny", (0, 0), "Ahi<Esc>j.").0, "xhi\nyhi");
+        // Motions and yanks leave the last change alone.
+        assert_eq!(feed("a b c d", (0, 0), "xwyw.").0, "  c d");
+    }
+
+    #[test]
+    fn reports_pending_and_visual_modes() {
+        assert_eq!(feed("abc", (0, 0), "d").2, VimMode::OperatorPending);
+        assert_eq!(feed("abc", (0, 0), "v").2, VimMode::Visual);
+        assert_eq!(feed("abc", (0, 0), "vd").2, VimMode::Normal);
+    }
+
+    #[test]
+    fn huge_counts_are_clamped() {
+        let huge = "99999999999999999999999";
+        assert_eq!(feed("abc\ndef", (0, 0), &format!("{huge}$")).1, (1, 2));
+        assert_eq!(feed("abc\ndef", (0, 0), &format!("{huge}dd")).0, "");
+        assert_eq!(feed("abc\ndef", (0, 0), &format!("{huge}x")).0, "\ndef");
+        assert_eq!(feed("a b c", (0, 0), &format!("{huge}w")).1, (0, 4));
+        assert_eq!(feed("a\n\nb\n\nc", (0, 0), &format!("{huge}}}")).1, (4, 0));
+        assert_eq!(feed("abc\ndef", (0, 0), &format!("{huge}J")).0, "abc def");
+
+        let (text, _, _) = feed("x", (0, 0), &format!("{huge}ihi<Esc>"));
+        assert!(text.len() <= (16 << 20) + 3);
+        let (text, _, _) = feed("x", (0, 0), &format!("yl{huge}p"));
+        assert!(text.len() <= (16 << 20) + 3);
+    }
+
+    #[test]
+    fn visual_block_columns_are_visual() {
+        // `x` after the tab and `5` both start at visual column 4.
+        assert_eq!(
+            feed("\tx\n12345x", (1, 4), "<C-v>kd"),
+            ("\t\n1234x".to_string(), (0, 0), VimMode::Normal)
+        );
+        // `字` spans columns 2 and 3, so the block below it takes `cd`.
+        assert_eq!(feed("漢字\nabcd", (0, 3), "<C-v>jd").0, "漢\nab");
+    }
+
+    #[test]
+    fn changing_a_block_types_on_every_row() {
+        assert_eq!(
+            feed("abcd\nabcd\nab\nabcd", (0, 1), "<C-v>jjjlcX<Esc>"),
+            ("aXd\naXd\naX\naXd".to_string(), (0, 1), VimMode::Normal)
+        );
+        // Rows too short to reach the block are left alone.
+        assert_eq!(
+            feed("abcd\na\nabcd", (0, 2), "<C-v>jjcYZ<Esc>").0,
+            "abYZd\na\nabYZd"
+        );
+    }
+}
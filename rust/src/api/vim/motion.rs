@@ -0,0 +1,674 @@
+use crop::Rope;
+
+use super::super::buffer::Buffer;
+use super::super::cursor::Cursor;
+use super::super::text::{floor_char_boundary, repeat_motion};
+use super::command::{Find, Motion, ObjectKind, TextObject};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+// Text an operator applies to: a byte range, or whole rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TextRange {
+    Chars(usize, usize),
+    Lines(usize, usize),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+fn class(c: char, big: bool) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if big || c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+fn char_at(text: &Rope, offset: usize) -> Option<char> {
+    if offset >= text.byte_len() {
+        return None;
+    }
+
+    text.byte_slice(offset..).chars().next()
+}
+
+fn char_before(text: &Rope, offset: usize) -> Option<char> {
+    text.byte_slice(..offset).chars().next_back()
+}
+
+// The last row vim shows: a final line break ends the last line rather than
+// starting an empty one.
+pub(crate) fn last_row(buffer: &Buffer) -> usize {
+    buffer.line_count().max(1) - 1
+}
+
+pub(crate) fn offset_of(buffer: &Buffer, cursor: Cursor) -> usize {
+    buffer
+        .row_column_to_idx(cursor.row, cursor.column)
+        .min(buffer.rope().byte_len())
+}
+
+pub(crate) fn cursor_at(buffer: &Buffer, offset: usize) -> Cursor {
+    let (row, column) = buffer.idx_to_row_column(offset);
+    Cursor::new(row, column, column)
+}
+
+pub(crate) fn first_non_blank(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+fn is_blank_row(buffer: &Buffer, row: usize) -> bool {
+    buffer.line(row).trim().is_empty()
+}
+
+pub(crate) fn next_boundary(line: &str, column: usize) -> usize {
+    line[column.min(line.len())..]
+        .chars()
+        .next()
+        .map_or(line.len(), |c| column + c.len_utf8())
+}
+
+pub(crate) fn previous_boundary(line: &str, column: usize) -> usize {
+    line[..column.min(line.len())]
+        .chars()
+        .next_back()
+        .map_or(0, |c| column - c.len_utf8())
+}
+
+// Where a normal mode cursor may rest: on a character, never past the last.
+pub(crate) fn clamp_normal(buffer: &Buffer, cursor: Cursor) -> Cursor {
+    let row = cursor.row.min(last_row(buffer));
+    let line = buffer.line(row);
+    let last_char = previous_boundary(&line, line.len());
+    let column = floor_char_boundary(&line, cursor.column.min(last_char));
+
+    Cursor::new(row, column, cursor.sticky_column)
+}
+
+pub(crate) fn move_cursor(
+    buffer: &Buffer,
+    cursor: Cursor,
+    motion: Motion,
+    count: Option<usize>,
+    last_find: Option<Find>,
+    for_operator: bool,
+) -> Option<(Cursor, MotionKind)> {
+    let text = buffer.rope();
+    let times = count.unwrap_or(1).max(1);
+    let line = buffer.line(cursor.row);
+    let at = |row: usize, column: usize| Cursor::new(row, column, column);
+
+    let target = match motion {
+        Motion::Left => {
+            let column = repeat_motion(cursor.column, times, |column| {
+                previous_boundary(&line, column)
+            });
+            (at(cursor.row, column), MotionKind::Exclusive)
+        }
+        Motion::Right => {
+            let column = repeat_motion(cursor.column, times, |column| next_boundary(&line, column));
+            (at(cursor.row, column), MotionKind::Exclusive)
+        }
+        Motion::Up | Motion::Down => {
+            let row = if motion == Motion::Up {
+                cursor.row.saturating_sub(times)
+            } else {
+                cursor.row.saturating_add(times).min(last_row(buffer))
+            };
+            let target_line = buffer.line(row);
+            let column = floor_char_boundary(&target_line, cursor.sticky_column);
+
+            (
+                Cursor::new(row, column, cursor.sticky_column),
+                MotionKind::Linewise,
+            )
+        }
+        Motion::WordStart { big } => {
+            let start = offset_of(buffer, cursor);
+            let offset = repeat_motion(start, times, |offset| word_start(text, offset, big));
+            let mut target = cursor_at(buffer, offset);
+
+            // The last word on a line ends the operated text at the line end
+            // rather than at the next line's first word.
+            if for_operator && target.row > cursor.row {
+                let target_line = buffer.line(target.row);
+                if target.column <= first_non_blank(&target_line) {
+                    let row = target.row - 1;
+                    target = at(row, buffer.line(row).len());
+                }
+            }
+
+            (target, MotionKind::Exclusive)
+        }
+        Motion::WordEnd { big } => {
+            let start = offset_of(buffer, cursor);
+            let offset = repeat_motion(start, times, |offset| word_end(text, offset, big));
+            (cursor_at(buffer, offset), MotionKind::Inclusive)
+        }
+        Motion::WordBack { big } => {
+            let start = offset_of(buffer, cursor);
+            let offset = repeat_motion(start, times, |offset| word_back(text, offset, big));
+            (cursor_at(buffer, offset), MotionKind::Exclusive)
+        }
+        Motion::LineStart => (at(cursor.row, 0), MotionKind::Exclusive),
+        Motion::FirstNonBlank => (
+            at(cursor.row, first_non_blank(&line)),
+            MotionKind::Exclusive,
+        ),
+        Motion::LineEnd => {
+            let row = cursor.row.saturating_add(times - 1).min(last_row(buffer));
+            let column = buffer.line(row).len();
+            (Cursor::new(row, column, usize::MAX), MotionKind::Exclusive)
+        }
+        Motion::FirstLine | Motion::LastLine => {
+            let row = match (motion, count) {
+                (_, Some(count)) => count.saturating_sub(1).min(last_row(buffer)),
+                (Motion::FirstLine, None) => 0,
+                _ => last_row(buffer),
+            };
+            (
+                at(row, first_non_blank(&buffer.line(row))),
+                MotionKind::Linewise,
+            )
+        }
+        Motion::Find(find) => find_in_line(&line, cursor, find, times, false)?,
+        Motion::RepeatFind { reverse } => {
+            let mut find = last_find?;
+            if reverse {
+                find.forward = !find.forward;
+            }
+            find_in_line(&line, cursor, find, times, true)?
+        }
+        Motion::ParagraphForward => {
+            let last = last_row(buffer);
+            let row = repeat_motion(cursor.row, times, |mut row| {
+                while row < last && is_blank_row(buffer, row) {
+                    row += 1;
+                }
+                while row < last && !is_blank_row(buffer, row) {
+                    row += 1;
+                }
+                row
+            });
+
+            let column = if is_blank_row(buffer, row) {
+                0
+            } else {
+                buffer.line(row).len()
+            };
+            (at(row, column), MotionKind::Exclusive)
+        }
+        Motion::ParagraphBack => {
+            let row = repeat_motion(cursor.row, times, |mut row| {
+                while row > 0 && is_blank_row(buffer, row) {
+                    row -= 1;
+                }
+                while row > 0 && !is_blank_row(buffer, row) {
+                    row -= 1;
+                }
+                row
+            });
+            (at(row, 0), MotionKind::Exclusive)
+        }
+        Motion::MatchingPair => {
+            let (column, c) = line[cursor.column.min(line.len())..]
+                .char_indices()
+                .find(|(_, c)| "()[]{}".contains(*c))?;
+            let offset = buffer.byte_of_line(cursor.row) + cursor.column + column;
+
+            let matched = match c {
+                '(' => find_close(text, offset + 1, '(', ')'),
+                '[' => find_close(text, offset + 1, '[', ']'),
+                '{' => find_close(text, offset + 1, '{', '}'),
+                ')' => find_open(text, offset, '(', ')'),
+                ']' => find_open(text, offset, '[', ']'),
+                _ => find_open(text, offset, '{', '}'),
+            }?;
+            (cursor_at(buffer, matched), MotionKind::Inclusive)
+        }
+    };
+
+    Some(target)
+}
+
+fn find_in_line(
+    line: &str,
+    cursor: Cursor,
+    find: Find,
+    times: usize,
+    repeat: bool,
+) -> Option<(Cursor, MotionKind)> {
+    let column = cursor.column.min(line.len());
+    // Repeating `t` from just before its target would otherwise stay put.
+    let skip = usize::from(repeat && find.till);
+
+    let found = if find.forward {
+        let start = next_boundary(line, column);
+        let (idx, _) = line[start..]
+            .char_indices()
+            .filter(|&(_, c)| c == find.target)
+            .filter(|&(idx, _)| !(skip == 1 && idx == 0))
+            .nth(times - 1)?;
+        let found = start + idx;
+
+        if find.till {
+            previous_boundary(line, found)
+        } else {
+            found
+        }
+    } else {
+        let end = column;
+        let (idx, c) = line[..end]
+            .char_indices()
+            .rev()
+            .filter(|&(_, c)| c == find.target)
+            .filter(|&(idx, c)| !(skip == 1 && idx + c.len_utf8() == end))
+            .nth(times - 1)?;
+
+        if find.till {
+            idx + c.len_utf8()
+        } else {
+            idx
+        }
+    };
+
+    let kind = if find.forward {
+        MotionKind::Inclusive
+    } else {
+        MotionKind::Exclusive
+    };
+
+    Some((Cursor::new(cursor.row, found, found), kind))
+}
+
+// An empty line counts as a word, so word motions stop on it.
+fn word_start(text: &Rope, offset: usize, big: bool) -> usize {
+    let mut offset = offset;
+    let Some(first) = char_at(text, offset) else {
+        return offset;
+    };
+
+    let first_class = class(first, big);
+    if first_class != CharClass::Blank {
+        while let Some(c) = char_at(text, offset) {
+            if class(c, big) != first_class {
+                break;
+            }
+            offset += c.len_utf8();
+        }
+    }
+
+    while let Some(c) = char_at(text, offset) {
+        if c == '\n' {
+            offset += 1;
+            if char_at(text, offset) == Some('\n') {
+                return offset;
+            }
+        } else if c.is_whitespace() {
+            offset += c.len_utf8();
+        } else {
+            break;
+        }
+    }
+
+    offset
+}
+
+fn word_end(text: &Rope, offset: usize, big: bool) -> usize {
+    let Some(c) = char_at(text, offset) else {
+        return offset;
+    };
+    let mut offset = offset + c.len_utf8();
+
+    while let Some(c) = char_at(text, offset) {
+        if !c.is_whitespace() {
+            break;
+        }
+        offset += c.len_utf8();
+    }
+
+    let Some(mut current) = char_at(text, offset) else {
+        return char_before(text, offset).map_or(0, |c| offset - c.len_utf8());
+    };
+    let word_class = class(current, big);
+
+    while let Some(next) = char_at(text, offset + current.len_utf8()) {
+        if class(next, big) != word_class {
+            break;
+        }
+        offset += current.len_utf8();
+        current = next;
+    }
+
+    offset
+}
+
+fn word_back(text: &Rope, offset: usize, big: bool) -> usize {
+    let mut offset = offset;
+
+    while let Some(c) = char_before(text, offset) {
+        if c == '\n' {
+            offset -= 1;
+            if offset == 0 || char_before(text, offset) == Some('\n') {
+                return offset;
+            }
+        } else if c.is_whitespace() {
+            offset -= c.len_utf8();
+        } else {
+            break;
+        }
+    }
+
+    let Some(c) = char_before(text, offset) else {
+        return offset;
+    };
+    let word_class = class(c, big);
+
+    while let Some(c) = char_before(text, offset) {
+        if class(c, big) != word_class {
+            break;
+        }
+        offset -= c.len_utf8();
+    }
+
+    offset
+}
+
+// Finds the `close` balancing an `open` that ends just before `from`.
+fn find_close(text: &Rope, from: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut offset = from;
+
+    for c in text.byte_slice(from..).chars() {
+        if c == close {
+            if depth == 0 {
+                return Some(offset);
+            }
+            depth -= 1;
+        } else if c == open {
+            depth += 1;
+        }
+        offset += c.len_utf8();
+    }
+
+    None
+}
+
+// Finds the `open` balanced by a `close` starting at or after `before`.
+fn find_open(text: &Rope, before: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut offset = before;
+
+    for c in text.byte_slice(..before).chars().rev() {
+        offset -= c.len_utf8();
+        if c == open {
+            if depth == 0 {
+                return Some(offset);
+            }
+            depth -= 1;
+        } else if c == close {
+            depth += 1;
+        }
+    }
+
+    None
+}
+
+pub(crate) fn select_object(
+    buffer: &Buffer,
+    cursor: Cursor,
+    object: TextObject,
+) -> Option<TextRange> {
+    match object.kind {
+        ObjectKind::Word { big } => select_word(buffer, cursor, object.inner, big),
+        ObjectKind::Pair { open, close } => select_pair(buffer, cursor, object.inner, open, close),
+        ObjectKind::Quote(quote) => select_quote(buffer, cursor, object.inner, quote),
+        ObjectKind::Tag => select_tag(buffer, cursor, object.inner),
+        ObjectKind::Paragraph => Some(select_paragraph(buffer, cursor, object.inner)),
+    }
+}
+
+fn select_word(buffer: &Buffer, cursor: Cursor, inner: bool, big: bool) -> Option<TextRange> {
+    let line = buffer.line(cursor.row);
+    if line.is_empty() {
+        return None;
+    }
+
+    let line_start = buffer.byte_of_line(cursor.row);
+    let column = floor_char_boundary(
+        &line,
+        cursor.column.min(previous_boundary(&line, line.len())),
+    );
+    let run = |column: usize| {
+        let run_class = class(line[column..].chars().next()?, big);
+        let start = line[..column]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| class(c, big) == run_class)
+            .last()
+            .map_or(column, |(idx, _)| idx);
+        let end = line[column..]
+            .char_indices()
+            .take_while(|&(_, c)| class(c, big) == run_class)
+            .last()
+            .map_or(column, |(idx, c)| column + idx + c.len_utf8());
+        Some((start, end, run_class))
+    };
+
+    let (start, end, run_class) = run(column)?;
+    if inner {
+        return Some(TextRange::Chars(line_start + start, line_start + end));
+    }
+
+    // `aw` takes the blanks after the word, or before it when there are
+    // none after; on blanks it takes the word that follows.
+    let (start, end) = if run_class == CharClass::Blank {
+        match run(end) {
+            Some((_, word_end, _)) => (start, word_end),
+            None => (start, end),
+        }
+    } else {
+        match run(end) {
+            Some((_, blank_end, CharClass::Blank)) => (start, blank_end),
+            _ => {
+                let leading = line[..start]
+                    .char_indices()
+                    .rev()
+                    .take_while(|&(_, c)| c.is_whitespace())
+                    .last()
+                    .map_or(start, |(idx, _)| idx);
+                (leading, end)
+            }
+        }
+    };
+
+    Some(TextRange::Chars(line_start + start, line_start + end))
+}
+
+fn select_pair(
+    buffer: &Buffer,
+    cursor: Cursor,
+    inner: bool,
+    open: char,
+    close: char,
+) -> Option<TextRange> {
+    let text = buffer.rope();
+    let offset = offset_of(buffer, cursor);
+
+    let open_offset = if char_at(text, offset) == Some(open) {
+        offset
+    } else {
+        find_open(text, offset, open, close)?
+    };
+    let close_offset = find_close(text, open_offset + open.len_utf8(), open, close)?;
+
+    if !inner {
+        return Some(TextRange::Chars(
+            open_offset,
+            close_offset + close.len_utf8(),
+        ));
+    }
+
+    // A block whose brackets sit on their own lines keeps those lines.
+    let mut start = open_offset + open.len_utf8();
+    if char_at(text, start) == Some('\n') {
+        start += 1;
+    }
+
+    let mut end = close_offset;
+    let close_cursor = cursor_at(buffer, close_offset);
+    let close_line = buffer.line(close_cursor.row);
+    if close_line[..close_cursor.column].trim().is_empty() {
+        end = buffer.byte_of_line(close_cursor.row).max(start);
+    }
+
+    Some(TextRange::Chars(start, end))
+}
+
+fn select_quote(buffer: &Buffer, cursor: Cursor, inner: bool, quote: char) -> Option<TextRange> {
+    let line = buffer.line(cursor.row);
+    let line_start = buffer.byte_of_line(cursor.row);
+
+    let mut escaped = false;
+    let quotes: Vec<usize> = line
+        .char_indices()
+        .filter(|&(_, c)| {
+            let is_quote = c == quote && !escaped;
+            escaped = c == '\\' && !escaped;
+            is_quote
+        })
+        .map(|(idx, _)| idx)
+        .collect();
+
+    // The pair around the cursor, or else the first pair after it.
+    let (open, close) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, close)| cursor.column <= close)?;
+
+    let (start, end) = if inner {
+        (open + quote.len_utf8(), close)
+    } else {
+        let end = close + quote.len_utf8();
+        let trailing = line[end..].len() - line[end..].trim_start().len();
+        if trailing > 0 {
+            (open, end + trailing)
+        } else {
+            let leading = line[..open].len() - line[..open].trim_end().len();
+            (open - leading, end)
+        }
+    };
+
+    Some(TextRange::Chars(line_start + start, line_start + end))
+}
+
+fn select_tag(buffer: &Buffer, cursor: Cursor, inner: bool) -> Option<TextRange> {
+    let text = buffer.to_string();
+    let offset = offset_of(buffer, cursor);
+
+    let mut open_tags: Vec<(String, usize, usize)> = Vec::new();
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut search = 0;
+
+    while let Some(tag_start) = text[search..].find('<').map(|idx| search + idx) {
+        let Some(tag_end) = text[tag_start..].find('>').map(|idx| tag_start + idx + 1) else {
+            break;
+        };
+        search = tag_end;
+
+        let tag = &text[tag_start + 1..tag_end - 1];
+        if tag.starts_with('!') || tag.starts_with('?') || tag.ends_with('/') {
+            continue;
+        }
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim();
+            let Some(idx) = open_tags.iter().rposition(|(open, _, _)| open == name) else {
+                continue;
+            };
+
+            let (_, open_start, open_end) = open_tags[idx].clone();
+            open_tags.truncate(idx);
+
+            let encloses = open_start <= offset && offset < tag_end;
+            let is_tighter =
+                best.is_none_or(|(start, _, _, end)| end - start > tag_end - open_start);
+            if encloses && is_tighter {
+                best = Some((open_start, open_end, tag_start, tag_end));
+            }
+        } else {
+            let name = tag
+                .split_whitespace()
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            open_tags.push((name, tag_start, tag_end));
+        }
+    }
+
+    let (open_start, open_end, close_start, close_end) = best?;
+    Some(if inner {
+        TextRange::Chars(open_end, close_start)
+    } else {
+        TextRange::Chars(open_start, close_end)
+    })
+}
+
+fn select_paragraph(buffer: &Buffer, cursor: Cursor, inner: bool) -> TextRange {
+    let last = last_row(buffer);
+    let row = cursor.row.min(last);
+    let blank = is_blank_row(buffer, row);
+
+    let extent = |from: usize, blank: bool| {
+        let mut start = from;
+        while start > 0 && is_blank_row(buffer, start - 1) == blank {
+            start -= 1;
+        }
+        let mut end = from;
+        while end < last && is_blank_row(buffer, end + 1) == blank {
+            end += 1;
+        }
+        (start, end)
+    };
+
+    let (start, mut end) = extent(row, blank);
+    if !inner && end < last {
+        end = extent(end + 1, !blank).1;
+    }
+
+    TextRange::Lines(start, end)
+}
+
+// The end of the word under the cursor, then of `count - 1` more words, as
+// `cw` changes them.
+pub(crate) fn word_end_from(buffer: &Buffer, cursor: Cursor, count: usize, big: bool) -> usize {
+    let text = buffer.rope();
+    let mut offset = offset_of(buffer, cursor);
+
+    if let Some(mut current) = char_at(text, offset) {
+        let word_class = class(current, big);
+        while let Some(next) = char_at(text, offset + current.len_utf8()) {
+            if class(next, big) != word_class {
+                break;
+            }
+            offset += current.len_utf8();
+            current = next;
+        }
+    }
+
+    repeat_motion(offset, count.saturating_sub(1), |offset| {
+        word_end(text, offset, big)
+    })
+}
@@ -0,0 +1,168 @@
+use flutter_rust_bridge::frb;
+
+use super::buffer::{Buffer, TextEdit};
+use super::editorconfig::LineEnding;
+
+impl Buffer {
+    #[frb(sync)]
+    pub fn trim_trailing_whitespace(&mut self) -> Vec<TextEdit> {
+        let edits: Vec<TextEdit> = (0..self.line_count())
+            .filter_map(|row| {
+                let line = self.line(row);
+                let trimmed_len = line.trim_end().len();
+
+                (trimmed_len < line.len()).then(|| TextEdit {
+                    start_row: row,
+                    start_column: trimmed_len,
+                    end_row: row,
+                    end_column: line.len(),
+                    text: String::new(),
+                })
+            })
+            .collect();
+
+        self.apply_edits(edits.clone());
+        edits
+    }
+
+    #[frb(sync)]
+    pub fn ensure_final_newline(&mut self) -> Vec<TextEdit> {
+        let rope = self.rope();
+        let byte_len = rope.byte_len();
+        if byte_len == 0 {
+            return Vec::new();
+        }
+
+        let mut content_end = byte_len;
+        while content_end > 0 && matches!(rope.byte(content_end - 1), b'\n' | b'\r') {
+            content_end -= 1;
+        }
+
+        let newline = if rope
+            .byte_slice(content_end..byte_len)
+            .to_string()
+            .starts_with("\r\n")
+        {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        if byte_len - content_end == newline.len() && rope.byte(byte_len - 1) == b'\n' {
+            return Vec::new();
+        }
+
+        let (start_row, start_column) = self.idx_to_row_column(content_end);
+        let (end_row, end_column) = self.idx_to_row_column(byte_len);
+        let edits = vec![TextEdit {
+            start_row,
+            start_column,
+            end_row,
+            end_column,
+            text: newline.to_string(),
+        }];
+
+        self.apply_edits(edits.clone());
+        edits
+    }
+
+    // Shortens every run of blank lines to at most `max_blank_lines` lines.
+    #[frb(sync, type_64bit_int)]
+    pub fn collapse_blank_lines(&mut self, max_blank_lines: usize) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+        let mut run_start = None;
+
+        for row in 0..=self.line_count() {
+            let is_blank = row < self.line_count() && self.line(row).trim().is_empty();
+
+            match (is_blank, run_start) {
+                (true, None) => run_start = Some(row),
+                (false, Some(start)) => {
+                    if row - start > max_blank_lines {
+                        edits.push(TextEdit {
+                            start_row: start + max_blank_lines,
+                            start_column: 0,
+                            end_row: row,
+                            end_column: 0,
+                            text: String::new(),
+                        });
+                    }
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+
+        self.apply_edits(edits.clone());
+        edits
+    }
+
+    // Rewrites every line break, whether `\n`, `\r\n` or a lone `\r`, as
+    // `line_ending`.
+    #[frb(sync)]
+    pub fn normalize_line_endings(&mut self, line_ending: LineEnding) -> Vec<TextEdit> {
+        let target = line_ending.as_str();
+        let rope = self.rope();
+        let byte_len = rope.byte_len();
+        let mut ranges = Vec::new();
+        let mut idx = 0;
+
+        while idx < byte_len {
+            let len = match rope.byte(idx) {
+                b'\r' if idx + 1 < byte_len && rope.byte(idx + 1) == b'\n' => 2,
+                b'\r' | b'\n' => 1,
+                _ => {
+                    idx += 1;
+                    continue;
+                }
+            };
+            if rope.byte_slice(idx..idx + len) != target {
+                ranges.push(idx..idx + len);
+            }
+            idx += len;
+        }
+
+        let edits: Vec<TextEdit> = ranges
+            .into_iter()
+            .map(|range| {
+                let (start_row, start_column) = self.idx_to_row_column(range.start);
+                let (end_row, end_column) = self.idx_to_row_column(range.end);
+                TextEdit {
+                    start_row,
+                    start_column,
+                    end_row,
+                    end_column,
+                    text: target.to_string(),
+                }
+            })
+            .collect();
+
+        self.apply_edits(edits.clone());
+        edits
+    }
+
+    // Adds or removes the UTF-8 byte order mark at the start of the buffer.
+    #[frb(sync)]
+    pub fn set_byte_order_mark(&mut self, present: bool) -> Vec<TextEdit> {
+        const BOM: &str = "\u{feff}";
+        let has_bom =
+            self.rope().byte_len() >= BOM.len() && self.rope().byte_slice(..BOM.len()) == BOM;
+        if has_bom == present {
+            return Vec::new();
+        }
+
+        let edits = vec![TextEdit {
+            start_row: 0,
+            start_column: 0,
+            end_row: 0,
+            end_column: if present { 0 } else { BOM.len() },
+            text: if present {
+                BOM.to_string()
+            } else {
+                String::new()
+            },
+        }];
+
+        self.apply_edits(edits.clone());
+        edits
+    }
+}
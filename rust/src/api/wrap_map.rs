@@ -0,0 +1,462 @@
+use flutter_rust_bridge::frb;
+
+use super::buffer::Buffer;
+use super::text::{byte_column, char_width, floor_char_boundary, visual_width};
+
+#[frb(dart_metadata=("freezed", "immutable" import "package:meta/meta.dart" as meta), type_64bit_int)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisplayLine {
+    pub buffer_row: usize,
+    pub start_column: usize,
+    pub end_column: usize,
+    pub text: String,
+    pub is_wrapped: bool,
+}
+
+// Display columns are visual: a tab reaches the next tab stop from the start of
+// its display line and wide characters take two columns.
+#[frb(opaque)]
+pub struct WrapMap {
+    wrap_column: usize,
+    tab_width: usize,
+    line_width: Option<Box<dyn Fn(usize) -> usize + Send + Sync>>,
+    // Byte columns at which each buffer row continues on a new display line.
+    breaks: Vec<Vec<usize>>,
+    // Index of the first display line of each buffer row.
+    display_starts: Vec<usize>,
+}
+
+impl WrapMap {
+    #[frb(sync, type_64bit_int)]
+    pub fn new(buffer: &Buffer, wrap_column: usize, tab_width: usize) -> Self {
+        let mut wrap_map = Self {
+            wrap_column,
+            tab_width,
+            line_width: None,
+            breaks: Vec::new(),
+            display_starts: Vec::new(),
+        };
+
+        wrap_map.rewrap(buffer);
+        wrap_map
+    }
+
+    #[frb(ignore)]
+    pub fn with_line_width(
+        buffer: &Buffer,
+        tab_width: usize,
+        line_width: impl Fn(usize) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        let mut wrap_map = Self {
+            wrap_column: 0,
+            tab_width,
+            line_width: Some(Box::new(line_width)),
+            breaks: Vec::new(),
+            display_starts: Vec::new(),
+        };
+
+        wrap_map.rewrap(buffer);
+        wrap_map
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn set_wrap_column(&mut self, buffer: &Buffer, wrap_column: usize) {
+        self.wrap_column = wrap_column;
+        self.line_width = None;
+        self.rewrap(buffer);
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn wrap_column(&self) -> usize {
+        self.wrap_column
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn set_tab_width(&mut self, buffer: &Buffer, tab_width: usize) {
+        self.tab_width = tab_width;
+        self.rewrap(buffer);
+    }
+
+    #[frb(sync)]
+    pub fn rewrap(&mut self, buffer: &Buffer) {
+        let row_count = Self::row_count(buffer);
+
+        self.breaks = (0..row_count)
+            .map(|row| self.wrap_row(buffer, row))
+            .collect();
+        self.display_starts = vec![0; row_count];
+        self.rebuild_display_starts_from(0);
+    }
+
+    // Rows `start_row..=old_end_row` were replaced by `start_row..=new_end_row`.
+    #[frb(sync, type_64bit_int)]
+    pub fn update(
+        &mut self,
+        buffer: &Buffer,
+        start_row: usize,
+        old_end_row: usize,
+        new_end_row: usize,
+    ) {
+        let row_count = Self::row_count(buffer);
+        let start_row = start_row.min(self.breaks.len());
+        let old_end = (old_end_row + 1).clamp(start_row, self.breaks.len());
+        let new_end = (new_end_row + 1).clamp(start_row, row_count);
+
+        let rewrapped: Vec<Vec<usize>> = (start_row..new_end)
+            .map(|row| self.wrap_row(buffer, row))
+            .collect();
+        let old_lines: usize = self.breaks[start_row..old_end]
+            .iter()
+            .map(|breaks| breaks.len() + 1)
+            .sum();
+        let new_lines: usize = rewrapped.iter().map(|breaks| breaks.len() + 1).sum();
+        self.breaks.splice(start_row..old_end, rewrapped);
+
+        // Fall back to a full rewrap if the caller's row spans were out of sync.
+        if self.breaks.len() != row_count {
+            self.rewrap(buffer);
+            return;
+        }
+
+        let mut display_row = self.display_start_of(start_row);
+        let starts: Vec<usize> = self.breaks[start_row..new_end]
+            .iter()
+            .map(|breaks| {
+                let start = display_row;
+                display_row += breaks.len() + 1;
+                start
+            })
+            .collect();
+        self.display_starts.splice(start_row..old_end, starts);
+
+        // Rows below only move when the edit changed how many display lines
+        // it spans, and then by one addition each, which stays cheap next to
+        // rewrapping even for very long files.
+        if new_lines != old_lines {
+            for start in &mut self.display_starts[new_end..] {
+                *start = *start + new_lines - old_lines;
+            }
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn display_line_count(&self) -> usize {
+        match (self.display_starts.last(), self.breaks.last()) {
+            (Some(start), Some(breaks)) => start + breaks.len() + 1,
+            _ => 0,
+        }
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn buffer_to_display(&self, buffer: &Buffer, row: usize, column: usize) -> (usize, usize) {
+        if self.breaks.is_empty() {
+            return (0, 0);
+        }
+
+        let row = row.min(self.breaks.len() - 1);
+        let line = buffer.line(row);
+        let column = floor_char_boundary(&line, column);
+        let breaks = &self.breaks[row];
+        let segment = breaks.partition_point(|&brk| brk <= column);
+        let segment_start = if segment == 0 { 0 } else { breaks[segment - 1] };
+
+        (
+            self.display_starts[row] + segment,
+            visual_width(&line[segment_start..column], self.tab_width),
+        )
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn display_to_buffer(
+        &self,
+        buffer: &Buffer,
+        display_row: usize,
+        display_column: usize,
+    ) -> (usize, usize) {
+        if self.breaks.is_empty() {
+            return (0, 0);
+        }
+
+        let display_row = display_row.min(self.display_line_count() - 1);
+        let (row, segment) = self.display_row_to_segment(display_row);
+        let line = buffer.line(row);
+        let (start, end) = self.segment_bounds(row, segment, line.len());
+        let mut column =
+            start + byte_column(&line[start..end], display_column, self.tab_width, false);
+
+        // A wrapped segment ends where the next one begins, so place the cursor
+        // before the break rather than on the following display line.
+        if segment < self.breaks[row].len() && column == end && end > start {
+            column = Self::prev_char_boundary(&line, end);
+        }
+
+        (row, column)
+    }
+
+    #[frb(sync, type_64bit_int)]
+    pub fn visible_display_lines(
+        &self,
+        buffer: &Buffer,
+        start: usize,
+        count: usize,
+    ) -> Vec<DisplayLine> {
+        let end = (start + count).min(self.display_line_count());
+        if start >= end {
+            return Vec::new();
+        }
+
+        let (mut row, mut segment) = self.display_row_to_segment(start);
+        let mut line = buffer.line(row);
+        let mut lines = Vec::with_capacity(end - start);
+
+        for _ in start..end {
+            if segment > self.breaks[row].len() {
+                row += 1;
+                segment = 0;
+                line = buffer.line(row);
+            }
+
+            let (start_column, end_column) = self.segment_bounds(row, segment, line.len());
+            lines.push(DisplayLine {
+                buffer_row: row,
+                start_column,
+                end_column,
+                text: line[start_column..end_column].to_string(),
+                is_wrapped: segment > 0,
+            });
+
+            segment += 1;
+        }
+
+        lines
+    }
+
+    fn row_count(buffer: &Buffer) -> usize {
+        buffer.line_count_with_trailing_newline().max(1)
+    }
+
+    fn wrap_row(&self, buffer: &Buffer, row: usize) -> Vec<usize> {
+        let width = match &self.line_width {
+            Some(line_width) => line_width(row),
+            None => self.wrap_column,
+        };
+
+        wrap_line(&buffer.line(row), width, self.tab_width)
+    }
+
+    // Where `row` starts, from the rows above it.
+    fn display_start_of(&self, row: usize) -> usize {
+        if row == 0 {
+            0
+        } else {
+            self.display_starts[row - 1] + self.breaks[row - 1].len() + 1
+        }
+    }
+
+    fn rebuild_display_starts_from(&mut self, start_row: usize) {
+        let mut display_row = self.display_start_of(start_row);
+        for row in start_row..self.breaks.len() {
+            self.display_starts[row] = display_row;
+            display_row += self.breaks[row].len() + 1;
+        }
+    }
+
+    fn display_row_to_segment(&self, display_row: usize) -> (usize, usize) {
+        let row = self
+            .display_starts
+            .partition_point(|&start| start <= display_row)
+            .saturating_sub(1);
+
+        (row, display_row - self.display_starts[row])
+    }
+
+    fn segment_bounds(&self, row: usize, segment: usize, line_len: usize) -> (usize, usize) {
+        let breaks = &self.breaks[row];
+        let start = if segment == 0 { 0 } else { breaks[segment - 1] };
+        let end = breaks.get(segment).copied().unwrap_or(line_len);
+
+        (start, end)
+    }
+
+    fn prev_char_boundary(line: &str, column: usize) -> usize {
+        line[..column]
+            .char_indices()
+            .next_back()
+            .map(|(idx, _)| idx)
+            .unwrap_or(0)
+    }
+}
+
+// Returns the byte offsets at which `line` should continue on a new display
+// line, preferring to break after whitespace and hard-breaking long words.
+// `width` is in visual columns.
+fn wrap_line(line: &str, width: usize, tab_width: usize) -> Vec<usize> {
+    let mut breaks = Vec::new();
+    if width == 0 {
+        return breaks;
+    }
+
+    let mut segment_width = 0;
+    let mut last_break: Option<usize> = None;
+    let mut prev_whitespace = false;
+
+    for (idx, ch) in line.char_indices() {
+        let is_whitespace = ch.is_whitespace();
+
+        if prev_whitespace && !is_whitespace {
+            last_break = Some(idx);
+        }
+        prev_whitespace = is_whitespace;
+
+        // Trailing whitespace is allowed to overhang the wrap column.
+        let ch_width = char_width(ch, segment_width, tab_width);
+        if !is_whitespace && segment_width > 0 && segment_width + ch_width > width {
+            let brk = last_break.take().unwrap_or(idx);
+            breaks.push(brk);
+            // Tab stops move with the start of the display line.
+            segment_width = visual_width(&line[brk..idx], tab_width);
+        }
+
+        segment_width += char_width(ch, segment_width, tab_width);
+    }
+
+    breaks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tabs_and_wide_characters_take_their_visual_width() {
+        assert_eq!(wrap_line("\tab cd", 6, 4), vec![4]);
+        assert_eq!(wrap_line("漢字漢字", 5, 4), vec![6]);
+        assert_eq!(wrap_line("abcdef", 3, 4), vec![3]);
+        assert_eq!(wrap_line("ab cd ef", 5, 4), vec![6]);
+    }
+
+    #[test]
+    fn display_columns_are_visual_and_land_on_char_boundaries() {
+        let buffer = Buffer::from("\t漢字x\n".to_string());
+        let wrap_map = WrapMap::new(&buffer, 80, 4);
+
+        // Byte 4 is the start of `字`, four columns for the tab and two for `漢`.
+        assert_eq!(wrap_map.buffer_to_display(&buffer, 0, 4), (0, 6));
+        // Inside `漢`, so the cursor snaps back to its start.
+        assert_eq!(wrap_map.buffer_to_display(&buffer, 0, 2), (0, 4));
+        assert_eq!(wrap_map.display_to_buffer(&buffer, 0, 6), (0, 4));
+        assert_eq!(wrap_map.display_to_buffer(&buffer, 0, 5), (0, 1));
+        assert_eq!(wrap_map.display_to_buffer(&buffer, 0, 2), (0, 0));
+        assert_eq!(wrap_map.display_to_buffer(&buffer, 0, 100), (0, 8));
+    }
+
+    fn assert_matches_rewrap(wrap_map: &WrapMap, buffer: &Buffer) {
+        let fresh = WrapMap::new(buffer, wrap_map.wrap_column, wrap_map.tab_width);
+        assert_eq!(wrap_map.breaks, fresh.breaks, "{:?}", buffer.to_string());
+        assert_eq!(wrap_map.display_starts, fresh.display_starts);
+    }
+
+    fn apply<T>(wrap_map: &mut WrapMap, buffer: &mut Buffer, edit: impl FnOnce(&mut Buffer) -> T) {
+        let version = buffer.version;
+        edit(buffer);
+        for change in buffer.changes_since(version).unwrap() {
+            wrap_map.update(
+                buffer,
+                change.start_row,
+                change.old_end_row,
+                change.new_end_row,
+            );
+        }
+    }
+
+    #[test]
+    fn updates_match_a_full_rewrap() {
+        let mut buffer = Buffer::from("one two three\nfour\n\nfive six seven eight\n".to_string());
+        let mut wrap_map = WrapMap::new(&buffer, 8, 4);
+
+        // Typing that wraps a row onto more display lines.
+        apply(&mut wrap_map, &mut buffer, |b| {
+            b.insert(1, 4, " and more words".to_string())
+        });
+        assert_matches_rewrap(&wrap_map, &buffer);
+
+        // Splitting and joining rows.
+        apply(&mut wrap_map, &mut buffer, |b| {
+            b.insert(0, 3, "\n\n".to_string())
+        });
+        assert_matches_rewrap(&wrap_map, &buffer);
+        apply(&mut wrap_map, &mut buffer, |b| {
+            b.replace_range(0, 1, 3, 2, String::new())
+        });
+        assert_matches_rewrap(&wrap_map, &buffer);
+
+        // Editing after the trailing newline.
+        let last = buffer.last_row();
+        apply(&mut wrap_map, &mut buffer, |b| {
+            b.insert(last, 0, "tail end".to_string())
+        });
+        assert_matches_rewrap(&wrap_map, &buffer);
+    }
+
+    #[test]
+    fn random_updates_match_a_full_rewrap() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(26);
+        let mut buffer = Buffer::from("a b\n".to_string());
+        let mut wrap_map = WrapMap::new(&buffer, 6, 4);
+        let pieces = [
+            "",
+            "\n",
+            "word ",
+            "\tx",
+            "long_unbroken_word",
+            "a\nb c d e f\n",
+        ];
+
+        for _ in 0..300 {
+            let len = buffer.to_string().len();
+            let start = rng.random_range(0..=len);
+            let end = rng.random_range(start..=len.min(start + 10));
+            let text = pieces[rng.random_range(0..pieces.len())];
+            apply(&mut wrap_map, &mut buffer, |b| {
+                b.replace_bytes(start..end, text)
+            });
+            assert_matches_rewrap(&wrap_map, &buffer);
+        }
+    }
+
+    #[test]
+    fn visible_display_lines_span_wrapped_rows() {
+        let buffer = Buffer::from("abc def ghi\nxy\n".to_string());
+        let wrap_map = WrapMap::new(&buffer, 4, 4);
+        assert_eq!(wrap_map.display_line_count(), 5);
+
+        let lines = wrap_map.visible_display_lines(&buffer, 1, 3);
+        let summary: Vec<_> = lines
+            .iter()
+            .map(|line| {
+                (
+                    line.buffer_row,
+                    line.start_column,
+                    line.text.as_str(),
+                    line.is_wrapped,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (0, 4, "def ", true),
+                (0, 8, "ghi", true),
+                (1, 0, "xy", false)
+            ]
+        );
+
+        // The empty row after the trailing newline, and nothing past the end.
+        let lines = wrap_map.visible_display_lines(&buffer, 4, 10);
+        assert_eq!(lines.len(), 1);
+        assert_eq!((lines[0].buffer_row, lines[0].text.as_str()), (2, ""));
+        assert!(wrap_map.visible_display_lines(&buffer, 5, 10).is_empty());
+    }
+}